@@ -0,0 +1,6 @@
+/* QEMU's mps2-an385 board: 4 MiB of SSRAM for code at 0, and another 4 MiB for data. */
+MEMORY
+{
+  FLASH : ORIGIN = 0x00000000, LENGTH = 4M
+  RAM : ORIGIN = 0x20000000, LENGTH = 4M
+}
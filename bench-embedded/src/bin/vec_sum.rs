@@ -0,0 +1,36 @@
+//! Checks `lol_alloc` works as the global allocator of a bare-metal program, with no operating system or wasm runtime,
+//! run under QEMU with `cargo run --release --bin vec_sum`.
+//!
+//! Collects the numbers below 1000 into a `Vec<u32>` allocated from a `FreeListAllocator` growing into a [GlobalArrayGrower],
+//! checks their sum, frees the `Vec`, and checks the freed memory is reused.
+//! Exits QEMU (through semihosting) with a failure status if any check fails.
+
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use cortex_m_rt::entry;
+use cortex_m_semihosting::{debug, hprintln};
+use lol_alloc::{AssumeSingleThreaded, FreeListAllocator, GlobalArrayGrower};
+use panic_semihosting as _;
+
+// Safety: the program runs on one core, and no interrupt handler allocates.
+#[global_allocator]
+static ALLOCATOR: AssumeSingleThreaded<FreeListAllocator<GlobalArrayGrower<16>>> =
+    unsafe { AssumeSingleThreaded::new(FreeListAllocator::new_in(GlobalArrayGrower::new())) };
+
+#[entry]
+fn main() -> ! {
+    let v: Vec<u32> = (0..1000).collect();
+    assert_eq!(v.iter().sum::<u32>(), 499500);
+    let first = v.as_ptr();
+    drop(v);
+    // The same allocation fits where the freed one was.
+    let v: Vec<u32> = Vec::with_capacity(1000);
+    assert_eq!(v.as_ptr(), first);
+    hprintln!("vec_sum: ok");
+    debug::exit(debug::EXIT_SUCCESS);
+    loop {}
+}
@@ -0,0 +1,219 @@
+//! Measures `FreeListAllocator` on a Cortex-M3 (`thumbv7m-none-eabi`), run under QEMU with `cargo run --release`.
+//!
+//! Runs the churn workload shared with `bench-wasm` ([lol_alloc_bench_wasm::churn]) on a `FreeListAllocator` growing into a [GlobalArrayGrower],
+//! and prints (through semihosting) the cycles `alloc` and `dealloc` took, and how much of the arena was used.
+//!
+//! Cycles come from the DWT cycle counter when the core has one which counts.
+//! QEMU does not emulate it, so there SysTick is used instead, which QEMU runs from a virtual clock:
+//! those counts are only comparable with each other, not with real hardware.
+
+#![no_std]
+#![no_main]
+
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    cell::Cell,
+};
+use cortex_m::peripheral::{syst::SystClkSource, DWT, SYST};
+use cortex_m_rt::entry;
+use cortex_m_semihosting::{debug, hprintln};
+use lol_alloc::{AssumeSingleThreaded, FreeListAllocator, GlobalArrayGrower};
+use panic_semihosting as _;
+
+/// Pages (of 64 KiB) in the arena.
+const ARENA_PAGES: usize = 16;
+
+/// Operations (each an `alloc` or a `dealloc`) in each run of the workload.
+const ITERATIONS: u32 = 20_000;
+
+// This crate does not allocate itself, so there is no global allocator: the measured allocator is used directly.
+
+// Safety: the benchmark runs on one core, and no interrupt handler uses the allocator.
+static ARENA: AssumeSingleThreaded<FreeListAllocator<GlobalArrayGrower<ARENA_PAGES>>> =
+    unsafe { AssumeSingleThreaded::new(FreeListAllocator::new_in(GlobalArrayGrower::new())) };
+
+/// SysTick's reload value, and the largest value of its 24 bit counter.
+const SYST_MAX: u32 = 0x00FF_FFFF;
+
+/// Where cycle counts come from.
+#[derive(Clone, Copy)]
+enum Clock {
+    /// The DWT cycle counter, which counts up.
+    Dwt,
+    /// SysTick, counting down from [SYST_MAX] at the core clock.
+    SysTick,
+}
+
+impl Clock {
+    /// Starts the DWT cycle counter if there is one which counts, and SysTick otherwise.
+    fn start(mut core: cortex_m::Peripherals) -> Clock {
+        if DWT::has_cycle_counter() {
+            core.DCB.enable_trace();
+            core.DWT.enable_cycle_counter();
+            let before = DWT::cycle_count();
+            cortex_m::asm::delay(100);
+            if DWT::cycle_count() != before {
+                return Clock::Dwt;
+            }
+        }
+        core.SYST.set_clock_source(SystClkSource::Core);
+        core.SYST.set_reload(SYST_MAX);
+        core.SYST.clear_current();
+        core.SYST.enable_counter();
+        Clock::SysTick
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Clock::Dwt => "DWT cycle counter",
+            Clock::SysTick => "SysTick",
+        }
+    }
+
+    fn now(self) -> u32 {
+        match self {
+            Clock::Dwt => DWT::cycle_count(),
+            Clock::SysTick => SYST::get_current(),
+        }
+    }
+
+    /// Cycles from `start` (an earlier [Clock::now]) to `end`.
+    fn cycles(self, start: u32, end: u32) -> u32 {
+        match self {
+            Clock::Dwt => end.wrapping_sub(start),
+            // Operations take far less than a SysTick period, so the counter wrapped at most once.
+            Clock::SysTick => start.wrapping_sub(end) & SYST_MAX,
+        }
+    }
+
+    /// Cycles a pair of reads take with nothing between them: the smallest of `samples` measurements.
+    fn overhead(self, samples: usize) -> u32 {
+        (0..samples)
+            .map(|_| {
+                let start = self.now();
+                self.cycles(start, self.now())
+            })
+            .min()
+            .unwrap_or(0)
+    }
+}
+
+/// Cycle counts for one kind of operation.
+#[derive(Default)]
+struct Cycles {
+    count: Cell<u32>,
+    total: Cell<u64>,
+    max: Cell<u32>,
+}
+
+impl Cycles {
+    fn record(&self, cycles: u32) {
+        self.count.set(self.count.get() + 1);
+        self.total.set(self.total.get() + u64::from(cycles));
+        self.max.set(self.max.get().max(cycles));
+    }
+
+    fn report(&self, name: &str) {
+        let mean = self.total.get() / u64::from(self.count.get().max(1));
+        hprintln!(
+            "  {}: {} operations, mean {} cycles, max {} cycles",
+            name,
+            self.count.get(),
+            mean,
+            self.max.get()
+        );
+    }
+}
+
+/// Wraps an allocator, counting the cycles each operation takes, and tracking how much memory is used.
+///
+/// Not `Sync`: like the allocator it measures, it is only used from one thread.
+struct Measured<'a, A> {
+    inner: &'a A,
+    clock: Clock,
+    /// Cycles subtracted from each measurement for reading the clock.
+    overhead: u32,
+    alloc: Cycles,
+    dealloc: Cycles,
+    live_bytes: Cell<usize>,
+    peak_live_bytes: Cell<usize>,
+    /// Lowest address and highest end address allocated, bounding the part of the arena used.
+    low: Cell<usize>,
+    high: Cell<usize>,
+}
+
+impl<'a, A> Measured<'a, A> {
+    fn new(inner: &'a A, clock: Clock) -> Self {
+        Measured {
+            inner,
+            clock,
+            overhead: clock.overhead(100),
+            alloc: Cycles::default(),
+            dealloc: Cycles::default(),
+            live_bytes: Cell::new(0),
+            peak_live_bytes: Cell::new(0),
+            low: Cell::new(usize::MAX),
+            high: Cell::new(0),
+        }
+    }
+
+    /// Runs `f`, recording the cycles it took in `cycles`.
+    fn time<R>(&self, cycles: &Cycles, f: impl FnOnce() -> R) -> R {
+        let start = self.clock.now();
+        let result = f();
+        let end = self.clock.now();
+        cycles.record(self.clock.cycles(start, end).saturating_sub(self.overhead));
+        result
+    }
+
+    fn report(&self, run: &str) {
+        hprintln!("{}:", run);
+        self.alloc.report("alloc");
+        self.dealloc.report("dealloc");
+        hprintln!(
+            "  peak live bytes {}, peak arena usage {} of {} bytes",
+            self.peak_live_bytes.get(),
+            self.high.get().saturating_sub(self.low.get()),
+            ARENA_PAGES * 65536
+        );
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for Measured<'_, A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.time(&self.alloc, || self.inner.alloc(layout));
+        if !ptr.is_null() {
+            let live = self.live_bytes.get() + layout.size();
+            self.live_bytes.set(live);
+            self.peak_live_bytes
+                .set(self.peak_live_bytes.get().max(live));
+            self.low.set(self.low.get().min(ptr as usize));
+            self.high
+                .set(self.high.get().max(ptr as usize + layout.size()));
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.time(&self.dealloc, || self.inner.dealloc(ptr, layout));
+        self.live_bytes.set(self.live_bytes.get() - layout.size());
+    }
+}
+
+#[entry]
+fn main() -> ! {
+    let clock = Clock::start(cortex_m::Peripherals::take().unwrap());
+    hprintln!(
+        "FreeListAllocator<GlobalArrayGrower<{}>>, timed with {}",
+        ARENA_PAGES,
+        clock.name()
+    );
+    // The first run grows the heap as it goes, and the second reuses the memory the first freed.
+    for run in ["churn (growing)", "churn (reusing freed memory)"] {
+        let measured = Measured::new(&ARENA, clock);
+        unsafe { lol_alloc_bench_wasm::churn(&measured, ITERATIONS) };
+        measured.report(run);
+    }
+    debug::exit(debug::EXIT_SUCCESS);
+    loop {}
+}
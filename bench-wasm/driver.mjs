@@ -0,0 +1,24 @@
+// Runs the churn workload on each allocator in the bench module, and prints latency percentiles.
+// Usage: node driver.mjs <module.wasm> <iterations>
+
+import fs from "node:fs";
+
+const [wasmPath, iterationsArg] = process.argv.slice(2);
+const iterations = Number(iterationsArg);
+
+const module = new WebAssembly.Module(fs.readFileSync(wasmPath));
+const { run_churn, latency_ns, operation_count } = new WebAssembly.Instance(module, {
+  env: { now: () => performance.now() },
+}).exports;
+
+const allocators = ["FreeListAllocator", "LockedAllocator<FreeListAllocator>"];
+const ops = ["alloc", "dealloc"];
+
+allocators.forEach((name, allocator) => {
+  run_churn(allocator, iterations);
+  ops.forEach((op, opIndex) => {
+    const [p50, p99, max] = [500, 990, 1000].map((permille) => latency_ns(allocator, opIndex, permille));
+    const count = operation_count(allocator, opIndex);
+    console.log(`${name} ${op}: p50 ${p50} ns, p99 ${p99} ns, max ${max} ns (${count} operations)`);
+  });
+});
@@ -0,0 +1,25 @@
+// Runs the churn workload on AssumeSingleThreaded<FreeListAllocator> and LockedAllocator<FreeListAllocator>,
+// and prints the average nanoseconds per operation of each, as JSON.
+// Usage: node lock_overhead.mjs <module.wasm> <iterations>
+
+import fs from "node:fs";
+
+const [wasmPath, iterationsArg] = process.argv.slice(2);
+const iterations = Number(iterationsArg);
+
+const module = new WebAssembly.Module(fs.readFileSync(wasmPath));
+const { ns_per_op, lock_backend_index } = new WebAssembly.Instance(module, {
+  env: { now: () => performance.now() },
+}).exports;
+
+// Matches LOCK_BACKENDS in src/lib.rs.
+const backends = ["spin", "flag", "unchecked flag"];
+const runs = 5;
+
+console.log(
+  JSON.stringify({
+    backend: backends[lock_backend_index()],
+    unlocked_ns_per_op: ns_per_op(0, iterations, runs),
+    locked_ns_per_op: ns_per_op(1, iterations, runs),
+  }),
+);
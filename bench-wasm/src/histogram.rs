@@ -0,0 +1,158 @@
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+/// Values below this each get their own bucket.
+const LINEAR_BUCKETS: usize = 16;
+/// Buckets per power of two, above [LINEAR_BUCKETS].
+const SUB_BUCKET_BITS: u32 = 2;
+const SUB_BUCKETS: usize = 1 << SUB_BUCKET_BITS;
+/// Enough buckets for all `u64` values.
+pub const BUCKETS: usize =
+    LINEAR_BUCKETS + (64 - LINEAR_BUCKETS.trailing_zeros() as usize) * SUB_BUCKETS;
+
+/// Histogram of durations (in nanoseconds) with logarithmic buckets, which records without allocating.
+///
+/// Each power of two range above [LINEAR_BUCKETS] is split into [SUB_BUCKETS] buckets,
+/// so percentile estimates are within 25% of the true value. The maximum is recorded exactly.
+pub struct Histogram {
+    counts: [AtomicU32; BUCKETS],
+    max: AtomicU64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Histogram {
+    pub const fn new() -> Self {
+        Histogram {
+            counts: [const { AtomicU32::new(0) }; BUCKETS],
+            max: AtomicU64::new(0),
+        }
+    }
+
+    pub fn record(&self, value: u64) {
+        self.counts[bucket(value)].fetch_add(1, Ordering::Relaxed);
+        self.max.fetch_max(value, Ordering::Relaxed);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.counts
+            .iter()
+            .map(|count| u64::from(count.load(Ordering::Relaxed)))
+            .sum()
+    }
+
+    pub fn max(&self) -> u64 {
+        self.max.load(Ordering::Relaxed)
+    }
+
+    /// Estimate of the value `fraction` (between 0 and 1) of the recorded values are at most:
+    /// the upper bound of the bucket containing it, but no more than the maximum.
+    /// Returns 0 if nothing was recorded.
+    pub fn percentile(&self, fraction: f64) -> u64 {
+        let total = self.count();
+        if total == 0 {
+            return 0;
+        }
+        // Rank of the value, starting at 1: `f64::ceil` needs std.
+        let exact = fraction * total as f64;
+        let rank = exact as u64 + u64::from((exact as u64 as f64) < exact);
+        let rank = rank.clamp(1, total);
+        let mut seen = 0;
+        for (index, count) in self.counts.iter().enumerate() {
+            seen += u64::from(count.load(Ordering::Relaxed));
+            if seen >= rank {
+                return bucket_max(index).min(self.max());
+            }
+        }
+        self.max()
+    }
+
+    pub fn clear(&self) {
+        for count in &self.counts {
+            count.store(0, Ordering::Relaxed);
+        }
+        self.max.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Index of the bucket holding `value`.
+fn bucket(value: u64) -> usize {
+    if value < LINEAR_BUCKETS as u64 {
+        return value as usize;
+    }
+    let exponent = 63 - value.leading_zeros();
+    let sub_bucket = (value >> (exponent - SUB_BUCKET_BITS)) as usize & (SUB_BUCKETS - 1);
+    LINEAR_BUCKETS
+        + (exponent - LINEAR_BUCKETS.trailing_zeros()) as usize * SUB_BUCKETS
+        + sub_bucket
+}
+
+/// Smallest value in bucket `index`.
+fn bucket_min(index: usize) -> u64 {
+    if index < LINEAR_BUCKETS {
+        return index as u64;
+    }
+    let exponent =
+        ((index - LINEAR_BUCKETS) / SUB_BUCKETS) as u32 + LINEAR_BUCKETS.trailing_zeros();
+    let sub_bucket = ((index - LINEAR_BUCKETS) % SUB_BUCKETS) as u64;
+    (1 << exponent) + (sub_bucket << (exponent - SUB_BUCKET_BITS))
+}
+
+/// Largest value in bucket `index`.
+fn bucket_max(index: usize) -> u64 {
+    if index + 1 == BUCKETS {
+        u64::MAX
+    } else {
+        bucket_min(index + 1) - 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bucket, bucket_max, bucket_min, Histogram, BUCKETS};
+
+    #[test]
+    fn buckets() {
+        assert_eq!(BUCKETS, 256);
+        assert_eq!(bucket(0), 0);
+        assert_eq!(bucket(15), 15);
+        assert_eq!(bucket(16), 16);
+        assert_eq!(bucket(19), 16);
+        assert_eq!(bucket(20), 17);
+        assert_eq!(bucket(32), 20);
+        assert_eq!(bucket(u64::MAX), BUCKETS - 1);
+        for index in 0..BUCKETS {
+            assert_eq!(bucket(bucket_min(index)), index);
+            assert_eq!(bucket(bucket_max(index)), index);
+        }
+        // Buckets are at most 25% wider than their smallest value.
+        for value in [17, 100, 1000, 12345, 1 << 40] {
+            let index = bucket(value);
+            assert!(bucket_max(index) - bucket_min(index) < bucket_min(index) / 4 + 1);
+        }
+    }
+
+    #[test]
+    fn percentiles() {
+        let histogram = Histogram::new();
+        assert_eq!(histogram.percentile(0.5), 0);
+        for value in 1..=1000 {
+            histogram.record(value);
+        }
+        assert_eq!(histogram.count(), 1000);
+        assert_eq!(histogram.max(), 1000);
+        let p50 = histogram.percentile(0.5);
+        assert!((500..=625).contains(&p50), "{p50}");
+        let p99 = histogram.percentile(0.99);
+        assert!((990..=1000).contains(&p99), "{p99}");
+        assert_eq!(histogram.percentile(1.0), 1000);
+        assert_eq!(histogram.percentile(0.0), 1);
+
+        histogram.clear();
+        histogram.record(7);
+        assert_eq!(histogram.percentile(0.99), 7);
+    }
+}
@@ -0,0 +1,340 @@
+//! Measures the latency of individual allocator operations on wasm.
+//!
+//! Built for `wasm32-unknown-unknown` and driven by `driver.mjs`, which provides the clock
+//! (an `env.now() -> f64` import returning milliseconds, like `performance.now`) and prints the results.
+//!
+//! The [churn] workload is also run on a Cortex-M3 by `bench-embedded`, which does not have the 64 bit atomics the rest of this needs.
+//!
+//! [ns_per_operation] times whole runs of it instead, for costs (like locking) too small to see in the latency of one operation.
+//! `lock_overhead.mjs` uses it to compare `AssumeSingleThreaded<FreeListAllocator>` with `LockedAllocator<FreeListAllocator>`,
+//! and `tests/lock_overhead.rs` compares them natively too, recording both in `lock_overhead.json`.
+
+#![cfg_attr(
+    all(any(target_arch = "wasm32", target_os = "none"), not(test)),
+    no_std
+)]
+
+#[cfg(target_has_atomic = "64")]
+mod histogram;
+
+use core::alloc::{GlobalAlloc, Layout};
+#[cfg(target_has_atomic = "64")]
+use core::sync::atomic::{AtomicU64, Ordering};
+#[cfg(target_has_atomic = "64")]
+pub use histogram::Histogram;
+
+/// Wraps an allocator, recording how long each `alloc` and `dealloc` takes.
+///
+/// Each operation reads the clock before and after.
+/// The cost of reading the clock (measured by [TimingAllocator::calibrate]) is subtracted from each duration.
+#[cfg(target_has_atomic = "64")]
+pub struct TimingAllocator<A> {
+    inner: A,
+    /// Current time in milliseconds.
+    now: fn() -> f64,
+    /// Nanoseconds a pair of clock reads take with nothing between them.
+    overhead: AtomicU64,
+    pub alloc: Histogram,
+    pub dealloc: Histogram,
+}
+
+#[cfg(target_has_atomic = "64")]
+impl<A> TimingAllocator<A> {
+    pub const fn new(inner: A, now: fn() -> f64) -> Self {
+        TimingAllocator {
+            inner,
+            now,
+            overhead: AtomicU64::new(0),
+            alloc: Histogram::new(),
+            dealloc: Histogram::new(),
+        }
+    }
+
+    /// Measures the cost of reading the clock, as the smallest of `samples` durations with nothing being timed.
+    pub fn calibrate(&self, samples: usize) {
+        let overhead = (0..samples)
+            .map(|_| {
+                let start = (self.now)();
+                nanoseconds((self.now)() - start)
+            })
+            .min()
+            .unwrap_or(0);
+        self.overhead.store(overhead, Ordering::Relaxed);
+    }
+
+    /// Runs `f`, recording its duration in `histogram`.
+    fn time<R>(&self, histogram: &Histogram, f: impl FnOnce() -> R) -> R {
+        let start = (self.now)();
+        let result = f();
+        let duration = nanoseconds((self.now)() - start);
+        histogram.record(duration.saturating_sub(self.overhead.load(Ordering::Relaxed)));
+        result
+    }
+}
+
+#[cfg(target_has_atomic = "64")]
+fn nanoseconds(milliseconds: f64) -> u64 {
+    (milliseconds * 1e6) as u64
+}
+
+#[cfg(target_has_atomic = "64")]
+unsafe impl<A: GlobalAlloc> GlobalAlloc for TimingAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.time(&self.alloc, || self.inner.alloc(layout))
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.time(&self.dealloc, || self.inner.dealloc(ptr, layout));
+    }
+}
+
+/// Number of allocations [churn] keeps live at once.
+const SLOTS: usize = 256;
+
+/// Allocates and frees randomly sized blocks from `allocator`, keeping up to [SLOTS] of them live.
+///
+/// Most allocations are small (up to 256 bytes), with occasional large ones (up to 16 KiB).
+/// Uses a fixed seed, so every allocator sees the same sequence of operations.
+/// Frees everything before returning, and returns the number of `alloc` and `dealloc` calls made.
+///
+/// # Safety
+///
+/// `allocator` must be a correct allocator.
+pub unsafe fn churn<A: GlobalAlloc>(allocator: &A, iterations: u32) -> u32 {
+    let mut slots: [Option<(*mut u8, Layout)>; SLOTS] = [None; SLOTS];
+    let mut state: u32 = 0x9E37_79B9;
+    let mut random = || {
+        // xorshift32
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state
+    };
+    for _ in 0..iterations {
+        let slot = &mut slots[random() as usize % SLOTS];
+        match slot.take() {
+            Some((ptr, layout)) => allocator.dealloc(ptr, layout),
+            None => {
+                let bits = random();
+                let size = if bits % 64 == 0 {
+                    (bits >> 8) as usize % (16 * 1024) + 1
+                } else {
+                    (bits >> 8) as usize % 256 + 1
+                };
+                let align = 1 << ((bits >> 4) % 4);
+                let layout = Layout::from_size_align(size, align).unwrap();
+                let ptr = allocator.alloc(layout);
+                assert!(!ptr.is_null());
+                *slot = Some((ptr, layout));
+            }
+        }
+    }
+    let live = slots.iter().flatten().count() as u32;
+    for (ptr, layout) in slots.into_iter().flatten() {
+        allocator.dealloc(ptr, layout);
+    }
+    // Each iteration made one call, and each allocation left live is freed by one more.
+    iterations + live
+}
+
+/// Average nanoseconds per operation of the fastest of `runs` runs of [churn], timed with `now` (in milliseconds).
+///
+/// Runs it once more first, untimed, so the timed runs reuse memory instead of growing it.
+/// Timing whole runs means the cost of reading the clock is negligible, unlike in [TimingAllocator].
+///
+/// # Safety
+///
+/// `allocator` must be a correct allocator.
+pub unsafe fn ns_per_operation<A: GlobalAlloc>(
+    allocator: &A,
+    iterations: u32,
+    runs: u32,
+    now: impl Fn() -> f64,
+) -> f64 {
+    churn(allocator, iterations);
+    (0..runs)
+        .map(|_| {
+            let start = now();
+            let operations = churn(allocator, iterations);
+            (now() - start) * 1e6 / f64::from(operations)
+        })
+        .fold(f64::INFINITY, f64::min)
+}
+
+/// Names of the locks `LockedAllocator` can use, indexed by [lock_backend].
+pub const LOCK_BACKENDS: [&str; 3] = ["spin", "flag", "unchecked flag"];
+
+/// Which lock (see [LOCK_BACKENDS]) `LockedAllocator` uses in this build.
+///
+/// A spin lock, except on wasm32 without the `atomics` target feature, where it is a flag which traps on recursive locking,
+/// or (with this crate's `unchecked-lock` feature, which enables lol_alloc's) a flag it never checks.
+pub const fn lock_backend() -> usize {
+    if cfg!(all(target_arch = "wasm32", not(target_feature = "atomics"))) {
+        if cfg!(feature = "unchecked-lock") {
+            2
+        } else {
+            1
+        }
+    } else {
+        0
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+mod wasm {
+    use super::{churn, lock_backend, ns_per_operation, Histogram, TimingAllocator};
+    use lol_alloc::{AssumeSingleThreaded, FailAllocator, FreeListAllocator, LockedAllocator};
+
+    #[link(wasm_import_module = "env")]
+    extern "C" {
+        /// Current time in milliseconds, like `performance.now`.
+        fn now() -> f64;
+    }
+
+    fn imported_now() -> f64 {
+        unsafe { now() }
+    }
+
+    /// This crate does not allocate itself: the measured allocators are used directly.
+    /// `lol_alloc` links the `alloc` crate though, which requires a global allocator, so this is one which always fails.
+    #[global_allocator]
+    static GLOBAL: FailAllocator = FailAllocator;
+
+    // Safety: wasm32-unknown-unknown modules built without the atomics feature are single threaded.
+    static FREE_LIST: TimingAllocator<AssumeSingleThreaded<FreeListAllocator>> =
+        TimingAllocator::new(
+            unsafe { AssumeSingleThreaded::new(FreeListAllocator::new()) },
+            imported_now,
+        );
+    static LOCKED: TimingAllocator<LockedAllocator<FreeListAllocator>> =
+        TimingAllocator::new(LockedAllocator::new(FreeListAllocator::new()), imported_now);
+
+    // Untimed copies, measured by `ns_per_op`, so timing one does not disturb the heap of the others.
+    static UNLOCKED: AssumeSingleThreaded<FreeListAllocator> =
+        unsafe { AssumeSingleThreaded::new(FreeListAllocator::new()) };
+    static UNTIMED_LOCKED: LockedAllocator<FreeListAllocator> =
+        LockedAllocator::new(FreeListAllocator::new());
+
+    /// Calls `f` with the allocator selected by `allocator`: 0 for `FreeListAllocator`, 1 for `LockedAllocator<FreeListAllocator>`.
+    fn with_allocator<R>(allocator: u32, f: impl FnOnce(&dyn Measured) -> R) -> R {
+        match allocator {
+            0 => f(&FREE_LIST),
+            _ => f(&LOCKED),
+        }
+    }
+
+    trait Measured {
+        fn histogram(&self, op: u32) -> &Histogram;
+        fn run(&self, iterations: u32);
+    }
+
+    impl<A: core::alloc::GlobalAlloc> Measured for TimingAllocator<A> {
+        fn histogram(&self, op: u32) -> &Histogram {
+            if op == 0 {
+                &self.alloc
+            } else {
+                &self.dealloc
+            }
+        }
+
+        fn run(&self, iterations: u32) {
+            self.calibrate(1000);
+            self.alloc.clear();
+            self.dealloc.clear();
+            unsafe { churn(self, iterations) };
+        }
+    }
+
+    /// Runs the churn workload on an allocator (see [with_allocator]), replacing its previous results.
+    #[no_mangle]
+    pub extern "C" fn run_churn(allocator: u32, iterations: u32) {
+        with_allocator(allocator, |a| a.run(iterations));
+    }
+
+    /// Average nanoseconds per operation of the fastest of `runs` runs of the churn workload (see [ns_per_operation]),
+    /// on an untimed `AssumeSingleThreaded<FreeListAllocator>` (`allocator` 0) or `LockedAllocator<FreeListAllocator>` (1).
+    #[no_mangle]
+    pub extern "C" fn ns_per_op(allocator: u32, iterations: u32, runs: u32) -> f64 {
+        unsafe {
+            match allocator {
+                0 => ns_per_operation(&UNLOCKED, iterations, runs, imported_now),
+                _ => ns_per_operation(&UNTIMED_LOCKED, iterations, runs, imported_now),
+            }
+        }
+    }
+
+    /// Index in [LOCK_BACKENDS](super::LOCK_BACKENDS) of the lock `LockedAllocator` uses in this build.
+    #[no_mangle]
+    pub extern "C" fn lock_backend_index() -> u32 {
+        lock_backend() as u32
+    }
+
+    /// Estimated latency in nanoseconds which `permille` thousandths of operations took at most.
+    /// `op` is 0 for `alloc` and 1 for `dealloc`. A `permille` of 1000 gives the exact maximum.
+    #[no_mangle]
+    pub extern "C" fn latency_ns(allocator: u32, op: u32, permille: u32) -> f64 {
+        with_allocator(allocator, |a| {
+            let histogram = a.histogram(op);
+            if permille >= 1000 {
+                histogram.max() as f64
+            } else {
+                histogram.percentile(f64::from(permille) / 1000.0) as f64
+            }
+        })
+    }
+
+    /// Number of operations recorded.
+    #[no_mangle]
+    pub extern "C" fn operation_count(allocator: u32, op: u32) -> f64 {
+        with_allocator(allocator, |a| a.histogram(op).count() as f64)
+    }
+
+    // Tests link std, which has its own panic handler.
+    #[cfg(not(test))]
+    #[panic_handler]
+    fn panic(_info: &core::panic::PanicInfo) -> ! {
+        core::arch::wasm32::unreachable()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{churn, TimingAllocator};
+    use core::sync::atomic::{AtomicU64, Ordering};
+    use std::alloc::System;
+
+    /// Fake clock, in nanoseconds, advanced by 10 on each read.
+    static CLOCK: AtomicU64 = AtomicU64::new(0);
+
+    fn fake_now() -> f64 {
+        CLOCK.fetch_add(10, Ordering::Relaxed) as f64 / 1e6
+    }
+
+    #[test]
+    fn timing() {
+        let allocator = TimingAllocator::new(System, fake_now);
+        allocator.calibrate(10);
+        unsafe { churn(&allocator, 10000) };
+        // Everything allocated was freed, and each read's cost was calibrated away.
+        assert!(allocator.alloc.count() > 0);
+        assert_eq!(allocator.alloc.count(), allocator.dealloc.count());
+        assert!(allocator.alloc.max() <= 1, "{}", allocator.alloc.max());
+    }
+
+    #[test]
+    fn churn_counts_operations() {
+        let allocator = TimingAllocator::new(System, fake_now);
+        let operations = unsafe { churn(&allocator, 10000) };
+        assert_eq!(
+            u64::from(operations),
+            allocator.alloc.count() + allocator.dealloc.count()
+        );
+    }
+
+    #[test]
+    fn ns_per_operation_of_fastest_run() {
+        // Each run reads the clock twice, 10 ns apart, and makes more than 10000 operations.
+        let ns = unsafe { super::ns_per_operation(&System, 10000, 3, fake_now) };
+        assert!(ns > 0.0 && ns < 0.001, "{ns}");
+    }
+}
@@ -0,0 +1,183 @@
+//! Measures what `LockedAllocator<FreeListAllocator>` costs over `AssumeSingleThreaded<FreeListAllocator>`,
+//! natively (with a spin lock) and on wasm in Node (with each flag lock), and how much larger the locked demo module is.
+//!
+//! Run `cargo test --release -p lol_alloc_bench_wasm --test lock_overhead -- --nocapture` to print the results next to the baseline in `lock_overhead.json`,
+//! and with `LOCK_OVERHEAD_BASELINE=update` set to replace the baseline with them.
+//!
+//! It runs on the host, driving Node itself: built for wasm32 it would link the library's `no_std` panic handler next to std's.
+#![cfg(not(target_arch = "wasm32"))]
+
+use example_e2e::{
+    build_standalone_wasm_package, build_wasm_package, build_wasm_package_with_features,
+    missing_tools, run_node_driver,
+};
+use lol_alloc::{testing::MockGrower, AssumeSingleThreaded, FreeListAllocator, LockedAllocator};
+use lol_alloc_bench_wasm::{lock_backend, ns_per_operation, LOCK_BACKENDS};
+use std::{fs, path::Path, time::Instant};
+
+/// Timings of one lock backend.
+struct Measurement {
+    target: &'static str,
+    backend: String,
+    unlocked_ns_per_op: f64,
+    locked_ns_per_op: f64,
+}
+
+impl Measurement {
+    fn to_json(&self) -> String {
+        format!(
+            r#"{{"target": "{}", "backend": "{}", "unlocked_ns_per_op": {:.2}, "locked_ns_per_op": {:.2}, "overhead_ns_per_op": {:.2}}}"#,
+            self.target,
+            self.backend,
+            self.unlocked_ns_per_op,
+            self.locked_ns_per_op,
+            self.locked_ns_per_op - self.unlocked_ns_per_op
+        )
+    }
+}
+
+fn baseline_path() -> &'static Path {
+    Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/lock_overhead.json"))
+}
+
+/// The results as the JSON stored in the baseline.
+fn to_json(measurements: &[Measurement], wasm_size_delta: Option<u64>) -> String {
+    let lines: Vec<_> = measurements
+        .iter()
+        .map(|m| format!("    {}", m.to_json()))
+        .collect();
+    let size = wasm_size_delta.map_or("null".to_string(), |size| size.to_string());
+    format!(
+        "{{\n  \"ns_per_op\": [\n{}\n  ],\n  \"wasm_size_delta_bytes\": {size}\n}}\n",
+        lines.join(",\n")
+    )
+}
+
+/// The number after `"key":` in `json`.
+fn number(json: &str, key: &str) -> f64 {
+    let pattern = format!("\"{key}\":");
+    let start = json
+        .find(&pattern)
+        .unwrap_or_else(|| panic!("{key} missing from {json}"))
+        + pattern.len();
+    let rest = json[start..].trim_start();
+    let end = rest
+        .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-' || c == 'e'))
+        .unwrap_or(rest.len());
+    rest[..end]
+        .parse()
+        .unwrap_or_else(|_| panic!("{key} is not a number in {json}"))
+}
+
+fn measure_native(iterations: u32) -> Measurement {
+    let epoch = Instant::now();
+    let now = || epoch.elapsed().as_secs_f64() * 1e3;
+    // Safety: only used on this thread.
+    let unlocked =
+        unsafe { AssumeSingleThreaded::new(FreeListAllocator::new_in(MockGrower::new())) };
+    let locked = LockedAllocator::new(FreeListAllocator::new_in(MockGrower::new()));
+    unsafe {
+        Measurement {
+            target: "native",
+            backend: LOCK_BACKENDS[lock_backend()].to_string(),
+            unlocked_ns_per_op: ns_per_operation(&unlocked, iterations, 5, now),
+            locked_ns_per_op: ns_per_operation(&locked, iterations, 5, now),
+        }
+    }
+}
+
+/// Runs `lock_overhead.mjs` on the bench module built with `features`.
+fn measure_wasm(features: &str, iterations: usize) -> Measurement {
+    let wasm = build_wasm_package_with_features("lol_alloc_bench_wasm", features);
+    let driver = Path::new(env!("CARGO_MANIFEST_DIR")).join("lock_overhead.mjs");
+    let output = run_node_driver(&driver, &wasm, iterations);
+    let backend = output
+        .split("\"backend\":\"")
+        .nth(1)
+        .and_then(|rest| rest.split('"').next())
+        .unwrap_or_else(|| panic!("backend missing from {output}"));
+    Measurement {
+        target: "wasm32",
+        backend: backend.to_string(),
+        unlocked_ns_per_op: number(&output, "unlocked_ns_per_op"),
+        locked_ns_per_op: number(&output, "locked_ns_per_op"),
+    }
+}
+
+/// How many bytes larger the `LockedAllocator` demo module is than the `AssumeSingleThreaded` one, which are otherwise the same.
+fn wasm_size_delta() -> u64 {
+    let size = |wasm| fs::metadata(wasm).unwrap().len();
+    let single_threaded = build_standalone_wasm_package(
+        "examples/demos/single-threaded-free-list",
+        "lol_alloc_demo_single_threaded_free_list",
+    );
+    size(build_wasm_package("lol_alloc_demo_locked_free_list"))
+        .saturating_sub(size(single_threaded))
+}
+
+#[test]
+fn lock_overhead() {
+    // Debug builds are much slower, so only measure enough to check the harness works.
+    let release = !cfg!(debug_assertions);
+    let mut measurements = vec![measure_native(if release { 100_000 } else { 5_000 })];
+    let mut size_delta = None;
+    match missing_tools() {
+        Some(reason) => eprintln!("skipping wasm lock overhead: {reason}"),
+        None => {
+            // The wasm module is always built in release mode.
+            for features in ["", "unchecked-lock"] {
+                measurements.push(measure_wasm(features, 100_000));
+            }
+            size_delta = Some(wasm_size_delta());
+        }
+    }
+
+    for measurement in &measurements {
+        assert!(
+            measurement.unlocked_ns_per_op > 0.0,
+            "{}",
+            measurement.to_json()
+        );
+        assert!(
+            measurement.locked_ns_per_op > 0.0,
+            "{}",
+            measurement.to_json()
+        );
+    }
+    let json = to_json(&measurements, size_delta);
+    println!("measured:\n{json}");
+    println!(
+        "baseline:\n{}",
+        fs::read_to_string(baseline_path()).unwrap()
+    );
+    if std::env::var("LOCK_OVERHEAD_BASELINE").as_deref() == Ok("update") {
+        assert!(release, "record the baseline from a release build");
+        assert!(
+            size_delta.is_some(),
+            "record the baseline with the wasm tools installed"
+        );
+        fs::write(baseline_path(), json).unwrap();
+    }
+}
+
+/// The baseline has numbers for every backend, so a change to the measurement which drops one is noticed.
+#[test]
+fn baseline_covers_each_backend() {
+    let baseline = fs::read_to_string(baseline_path()).unwrap();
+    for (target, backend) in [
+        ("native", "spin"),
+        ("wasm32", "flag"),
+        ("wasm32", "unchecked flag"),
+    ] {
+        let entry = baseline
+            .lines()
+            .find(|line| {
+                line.contains(&format!(r#""target": "{target}""#))
+                    && line.contains(&format!(r#""backend": "{backend}""#))
+            })
+            .unwrap_or_else(|| panic!("no {target} {backend} entry in {baseline}"));
+        assert!(number(entry, "unlocked_ns_per_op") > 0.0);
+        assert!(number(entry, "locked_ns_per_op") > 0.0);
+    }
+    assert!(number(&baseline, "wasm_size_delta_bytes") >= 0.0);
+}
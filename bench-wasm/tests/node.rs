@@ -0,0 +1,15 @@
+use example_e2e::{build_wasm_package, missing_tools, run_node_driver};
+use std::path::Path;
+
+#[test]
+fn latency_percentiles() {
+    if let Some(reason) = missing_tools() {
+        eprintln!("skipping wasm benchmark: {reason}");
+        return;
+    }
+    let wasm = build_wasm_package("lol_alloc_bench_wasm");
+    let driver = Path::new(env!("CARGO_MANIFEST_DIR")).join("driver.mjs");
+    let output = run_node_driver(&driver, &wasm, 100000);
+    println!("{output}");
+    assert_eq!(output.lines().count(), 4, "{output}");
+}
@@ -0,0 +1,47 @@
+// Drives the example module's `hello` and `goodbye` exports.
+// Usage: node driver.mjs <module.wasm> <iterations>
+// Exits with a non-zero status if any check fails.
+
+import fs from "node:fs";
+
+const [wasmPath, iterationsArg] = process.argv.slice(2);
+const iterations = Number(iterationsArg);
+
+function fail(message) {
+  console.log(`FAIL: ${message}`);
+  process.exit(1);
+}
+
+const module = new WebAssembly.Module(fs.readFileSync(wasmPath));
+if (WebAssembly.Module.imports(module).length !== 0) {
+  fail("example module should not have any imports");
+}
+const { hello, goodbye, memory } = new WebAssembly.Instance(module, {}).exports;
+
+// Let the heap reach its steady state before measuring it.
+const warmup = Math.min(1000, iterations);
+let settledSize = null;
+
+for (let i = 0; i < iterations; i++) {
+  if (i === warmup) {
+    settledSize = memory.buffer.byteLength;
+  }
+  const ptr = hello();
+  if (ptr === 0) {
+    fail(`hello returned null on iteration ${i}`);
+  }
+  if (ptr >= memory.buffer.byteLength) {
+    fail(`hello returned ${ptr}, outside memory of ${memory.buffer.byteLength} bytes`);
+  }
+  const value = new Uint8Array(memory.buffer)[ptr];
+  if (value !== 42) {
+    fail(`hello returned box containing ${value} on iteration ${i}`);
+  }
+  goodbye(ptr);
+}
+
+const finalSize = memory.buffer.byteLength;
+if (settledSize !== null && finalSize !== settledSize) {
+  fail(`memory grew from ${settledSize} to ${finalSize} bytes`);
+}
+console.log(`ok: ${iterations} iterations, memory ${finalSize} bytes`);
@@ -0,0 +1,145 @@
+//! Helpers for building the `example` crate (and other wasm modules in the workspace) for wasm32 and running them in Node.
+//! The actual tests are in `tests/`.
+
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// The WebAssembly target the example is built for.
+pub const WASM_TARGET: &str = "wasm32-unknown-unknown";
+
+/// Root of the workspace.
+pub fn workspace_root() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .parent()
+        .unwrap()
+        .to_path_buf()
+}
+
+/// Returns why the end-to-end tests can't run in this environment, if they can't.
+pub fn missing_tools() -> Option<String> {
+    if Command::new("node").arg("--version").output().is_err() {
+        return Some("node is not installed".into());
+    }
+    let output = Command::new("rustc")
+        .args(["--print", "target-libdir", "--target", WASM_TARGET])
+        .output()
+        .ok()?;
+    let libdir = String::from_utf8_lossy(&output.stdout);
+    if !output.status.success() || !Path::new(libdir.trim()).exists() {
+        return Some(format!("the {WASM_TARGET} target is not installed"));
+    }
+    None
+}
+
+/// Builds the example crate in release mode, returning the path to the produced wasm module.
+pub fn build_example() -> PathBuf {
+    build_wasm_package("lol_alloc_example")
+}
+
+/// Builds a workspace package's cdylib in release mode, returning the path to the produced wasm module.
+///
+/// Uses its own target directory so it does not contend with the cargo invocation running the tests.
+pub fn build_wasm_package(package: &str) -> PathBuf {
+    build_wasm_package_with_features(package, "")
+}
+
+/// Like [build_wasm_package], but with the package's `features` (comma separated) enabled.
+///
+/// Each set of features gets its own target directory, so building one does not overwrite the module built with another.
+pub fn build_wasm_package_with_features(package: &str, features: &str) -> PathBuf {
+    let root = workspace_root();
+    let mut target_dir = root.join("target").join("e2e");
+    if !features.is_empty() {
+        target_dir = target_dir.join(features.replace(|c: char| !c.is_alphanumeric(), "-"));
+    }
+    let status = Command::new(env!("CARGO"))
+        .current_dir(&root)
+        .args(["build", "--release", "-p", package, "--target", WASM_TARGET])
+        .args(["--features", features])
+        .arg("--target-dir")
+        .arg(&target_dir)
+        .status()
+        .expect("failed to run cargo");
+    assert!(status.success(), "building {package} failed");
+    module_path(&target_dir, package)
+}
+
+/// Path of the wasm module built for `package` in `target_dir`.
+fn module_path(target_dir: &Path, package: &str) -> PathBuf {
+    target_dir
+        .join(WASM_TARGET)
+        .join("release")
+        .join(format!("{}.wasm", package.replace('-', "_")))
+}
+
+/// Like [build_wasm_package], for a package which is its own workspace (like the `auto-global` demo), in `dir` (relative to the workspace root).
+pub fn build_standalone_wasm_package(dir: &str, package: &str) -> PathBuf {
+    let root = workspace_root();
+    let target_dir = root.join("target").join("e2e");
+    let status = Command::new(env!("CARGO"))
+        .current_dir(&root)
+        .args(["build", "--release", "--target", WASM_TARGET])
+        .arg("--manifest-path")
+        .arg(root.join(dir).join("Cargo.toml"))
+        .arg("--target-dir")
+        .arg(&target_dir)
+        .status()
+        .expect("failed to run cargo");
+    assert!(status.success(), "building {package} failed");
+    module_path(&target_dir, package)
+}
+
+/// Runs `cargo check` on lol_alloc with `features`, for `target` (or the host if `None`), with `rustflags`.
+/// Returns whether it succeeded, with cargo's stderr.
+///
+/// Like [build_wasm_package], uses its own target directory (one per target and flags, so they don't invalidate each other's builds).
+pub fn check_lol_alloc(features: &str, target: Option<&str>, rustflags: &str) -> (bool, String) {
+    let root = workspace_root();
+    let name = format!("{}{rustflags}", target.unwrap_or("host"))
+        .replace(|c: char| !c.is_alphanumeric(), "-");
+    let mut command = Command::new(env!("CARGO"));
+    command
+        .current_dir(&root)
+        .args(["check", "-p", "lol_alloc", "--features", features])
+        .arg("--target-dir")
+        .arg(root.join("target").join("e2e-check").join(name))
+        .env("RUSTFLAGS", rustflags);
+    if let Some(target) = target {
+        command.args(["--target", target]);
+    }
+    let output = command.output().expect("failed to run cargo");
+    (
+        output.status.success(),
+        String::from_utf8_lossy(&output.stderr).into_owned(),
+    )
+}
+
+/// Runs `driver.mjs` on the given wasm module, returning its stdout.
+/// Panics if the driver reports a failure.
+pub fn run_driver(wasm: &Path, iterations: usize) -> String {
+    run_node_driver(
+        &Path::new(env!("CARGO_MANIFEST_DIR")).join("driver.mjs"),
+        wasm,
+        iterations,
+    )
+}
+
+/// Runs a driver script taking the same arguments as `driver.mjs`, returning its stdout.
+/// Panics if the driver fails.
+pub fn run_node_driver(driver: &Path, wasm: &Path, iterations: usize) -> String {
+    let output = Command::new("node")
+        .arg(driver)
+        .arg(wasm)
+        .arg(iterations.to_string())
+        .output()
+        .expect("failed to run node");
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    assert!(
+        output.status.success(),
+        "driver failed:\n{stdout}\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    stdout
+}
@@ -0,0 +1,44 @@
+//! Compile tests for lol_alloc's `auto-global` feature, which is only allowed on wasm32.
+
+use example_e2e::{build_standalone_wasm_package, check_lol_alloc, missing_tools, WASM_TARGET};
+
+#[test]
+fn allowed_on_wasm32() {
+    if let Some(reason) = missing_tools() {
+        eprintln!("skipping end-to-end test: {reason}");
+        return;
+    }
+    let (success, stderr) = check_lol_alloc("auto-global", Some(WASM_TARGET), "");
+    assert!(success, "{stderr}");
+    // The demo which uses it links, so there is exactly one global allocator.
+    assert!(build_standalone_wasm_package(
+        "examples/demos/single-threaded-free-list",
+        "lol_alloc_demo_single_threaded_free_list"
+    )
+    .exists());
+}
+
+/// With threads, the allocator is locked.
+#[test]
+fn allowed_on_wasm32_with_atomics() {
+    if let Some(reason) = missing_tools() {
+        eprintln!("skipping end-to-end test: {reason}");
+        return;
+    }
+    let (success, stderr) = check_lol_alloc(
+        "auto-global",
+        Some(WASM_TARGET),
+        "-C target-feature=+atomics,+bulk-memory",
+    );
+    assert!(success, "{stderr}");
+}
+
+#[test]
+fn forbidden_on_host() {
+    let (success, stderr) = check_lol_alloc("auto-global", None, "");
+    assert!(!success);
+    assert!(
+        stderr.contains("the lol_alloc `auto-global` feature only supports wasm32 targets"),
+        "{stderr}"
+    );
+}
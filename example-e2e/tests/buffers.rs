@@ -0,0 +1,14 @@
+use example_e2e::{build_wasm_package, missing_tools, run_node_driver};
+use std::path::Path;
+
+#[test]
+fn buffer_round_trips() {
+    if let Some(reason) = missing_tools() {
+        eprintln!("skipping end-to-end test: {reason}");
+        return;
+    }
+    let wasm = build_wasm_package("lol_alloc_buffers_example");
+    let harness = Path::new(env!("CARGO_MANIFEST_DIR")).join("../examples/buffers/harness.mjs");
+    let output = run_node_driver(&harness, &wasm, 5000);
+    assert!(output.starts_with("ok:"), "{output}");
+}
@@ -0,0 +1,12 @@
+use example_e2e::{build_example, missing_tools, run_driver};
+
+#[test]
+fn hello_goodbye_loop() {
+    if let Some(reason) = missing_tools() {
+        eprintln!("skipping end-to-end test: {reason}");
+        return;
+    }
+    let wasm = build_example();
+    let output = run_driver(&wasm, 50000);
+    assert!(output.starts_with("ok:"), "{output}");
+}
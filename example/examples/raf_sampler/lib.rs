@@ -0,0 +1,41 @@
+//! Samples heap statistics for charting every frame from JavaScript: see `index.html`.
+#![cfg(target_arch = "wasm32")]
+
+use js_sys::{Object, Reflect};
+use lol_alloc::{AllocatorStats, AssumeSingleThreaded, FreeListAllocator};
+use lol_alloc_diagnostics::CountingAllocator;
+use wasm_bindgen::prelude::*;
+
+lol_alloc::define_global! {
+    static ALLOCATOR: AssumeSingleThreaded<CountingAllocator<FreeListAllocator>> =
+        unsafe { AssumeSingleThreaded::new(CountingAllocator::new(FreeListAllocator::new())) };
+}
+
+/// Returns `{ live_bytes, free_bytes, page_count, free_list_len }`.
+#[wasm_bindgen]
+pub fn sample_memory() -> JsValue {
+    let stats = ALLOCATOR.stats();
+    let sample = Object::new();
+    let set = |key: &str, value: Option<usize>| {
+        let value = value.map_or(JsValue::NULL, |value| JsValue::from(value as f64));
+        Reflect::set(&sample, &key.into(), &value).unwrap();
+    };
+    set("live_bytes", stats.live_bytes);
+    set("free_bytes", stats.free_bytes);
+    set("page_count", Some(core::arch::wasm32::memory_size(0)));
+    set("free_list_len", stats.free_blocks);
+    sample.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    #[wasm_bindgen_test]
+    fn returns_object() {
+        let sample = super::sample_memory();
+        assert!(sample.is_object());
+        let live_bytes = js_sys::Reflect::get(&sample, &"live_bytes".into()).unwrap();
+        assert!(live_bytes.as_f64().is_some());
+    }
+}
@@ -0,0 +1,84 @@
+// Drives the buffers example module's exports, checking buffer contents in both directions.
+// Usage: node harness.mjs <module.wasm> <iterations>
+// Exits with a non-zero status if any check fails.
+
+import fs from "node:fs";
+
+const [wasmPath, iterationsArg] = process.argv.slice(2);
+const iterations = Number(iterationsArg);
+
+function fail(message) {
+  console.log(`FAIL: ${message}`);
+  process.exit(1);
+}
+
+const module = new WebAssembly.Module(fs.readFileSync(wasmPath));
+if (WebAssembly.Module.imports(module).length !== 0) {
+  fail("buffers module should not have any imports");
+}
+const { buffer_alloc, buffer_filled, buffer_sum_and_free, buffer_free, memory } =
+  new WebAssembly.Instance(module, {}).exports;
+
+/// The bytes of the buffer at `ptr`, checking it is in memory.
+function bytes(ptr, len, what) {
+  if (ptr === 0) {
+    fail(`${what} returned null for ${len} bytes`);
+  }
+  if (ptr + len > memory.buffer.byteLength) {
+    fail(`${what} returned ${ptr}, which with ${len} bytes is outside memory of ${memory.buffer.byteLength} bytes`);
+  }
+  // Views must be made after each call, since growing memory detaches the old buffer.
+  return new Uint8Array(memory.buffer, ptr, len);
+}
+
+// Let the heap reach its steady state before measuring it.
+const warmup = Math.min(1000, iterations);
+let settledSize = null;
+
+for (let i = 0; i < iterations; i++) {
+  if (i === warmup) {
+    settledSize = memory.buffer.byteLength;
+  }
+  // A spread of sizes, so buffers of different sizes reuse each other's memory.
+  const len = (i * 7919) % 5000;
+  const seed = i % 256;
+
+  // Rust fills a buffer, JavaScript checks it and hands it back.
+  const filled = buffer_filled(len, seed);
+  let expected = 0;
+  for (const [j, byte] of bytes(filled, len, "buffer_filled").entries()) {
+    if (byte !== (j + seed) % 256) {
+      fail(`buffer_filled byte ${j} is ${byte} on iteration ${i}`);
+    }
+    expected += byte;
+  }
+  const sum = buffer_sum_and_free(filled, len);
+  if (sum !== expected) {
+    fail(`buffer_sum_and_free returned ${sum}, expected ${expected} on iteration ${i}`);
+  }
+
+  // JavaScript fills a buffer, Rust sums it.
+  const view = bytes(buffer_alloc(len), len, "buffer_alloc");
+  if (view.some((byte) => byte !== 0)) {
+    fail(`buffer_alloc returned a buffer which is not zeroed on iteration ${i}`);
+  }
+  view.fill(seed);
+  const ptr = view.byteOffset;
+  // Another allocation while JavaScript holds the buffer must not overlap it.
+  const other = buffer_alloc(len);
+  bytes(other, len, "buffer_alloc");
+  if (len !== 0 && other < ptr + len && ptr < other + len) {
+    fail(`buffer_alloc returned overlapping buffers ${ptr} and ${other} of ${len} bytes`);
+  }
+  buffer_free(other, len);
+  const filledSum = buffer_sum_and_free(ptr, len);
+  if (filledSum !== seed * len) {
+    fail(`buffer_sum_and_free returned ${filledSum}, expected ${seed * len} on iteration ${i}`);
+  }
+}
+
+const finalSize = memory.buffer.byteLength;
+if (settledSize !== null && finalSize !== settledSize) {
+  fail(`memory grew from ${settledSize} to ${finalSize} bytes`);
+}
+console.log(`ok: ${iterations} iterations, memory ${finalSize} bytes`);
@@ -0,0 +1,60 @@
+//! Byte buffers passed between Rust and JavaScript through raw pointers, without wasm-bindgen.
+//!
+//! Every buffer is a [RawBuffer]: it crosses to JavaScript as a pointer and length with [RawBuffer::into_raw_parts],
+//! and comes back with [RawBuffer::from_raw_parts], which frees it when dropped.
+//! JavaScript owns a buffer between the two, and must hand each one back exactly once.
+//! `harness.mjs` drives these exports, and the `example-e2e` crate runs it.
+#![cfg(target_arch = "wasm32")]
+
+use lol_alloc::{FreeListAllocator, LockedAllocator, RawBuffer};
+
+lol_alloc::define_global! {
+    static ALLOCATOR: LockedAllocator<FreeListAllocator> =
+        LockedAllocator::new(FreeListAllocator::new());
+}
+
+/// Gives a buffer to JavaScript, or null if it could not be allocated.
+fn hand_out(buffer: Option<RawBuffer>) -> *mut u8 {
+    buffer.map_or(core::ptr::null_mut(), |buffer| buffer.into_raw_parts().0)
+}
+
+/// Allocates a zeroed buffer of `len` bytes for JavaScript to fill.
+/// Returns null if allocation fails.
+#[no_mangle]
+pub extern "C" fn buffer_alloc(len: usize) -> *mut u8 {
+    hand_out(RawBuffer::alloc(len, 1))
+}
+
+/// Allocates a buffer of `len` bytes, with byte `i` set to `i + seed` (wrapping), for JavaScript to read.
+/// Returns null if allocation fails.
+#[no_mangle]
+pub extern "C" fn buffer_filled(len: usize, seed: u8) -> *mut u8 {
+    let mut buffer = RawBuffer::alloc(len, 1);
+    if let Some(buffer) = &mut buffer {
+        for (i, byte) in buffer.iter_mut().enumerate() {
+            *byte = (i as u8).wrapping_add(seed);
+        }
+    }
+    hand_out(buffer)
+}
+
+/// Takes a buffer back from JavaScript, returning the sum of its bytes, and frees it.
+///
+/// # Safety
+///
+/// `ptr` and `len` must be a buffer from [buffer_alloc] or [buffer_filled] which has not been handed back yet.
+#[no_mangle]
+pub unsafe extern "C" fn buffer_sum_and_free(ptr: *mut u8, len: usize) -> u32 {
+    let buffer = RawBuffer::from_raw_parts(ptr, len, 1);
+    buffer.iter().map(|&byte| byte as u32).sum()
+}
+
+/// Takes a buffer back from JavaScript, and frees it.
+///
+/// # Safety
+///
+/// As for [buffer_sum_and_free].
+#[no_mangle]
+pub unsafe extern "C" fn buffer_free(ptr: *mut u8, len: usize) {
+    drop(RawBuffer::from_raw_parts(ptr, len, 1));
+}
@@ -0,0 +1,39 @@
+//! Demo of `FailAllocator`, which fails every allocation, for modules which never allocate.
+//! Exports the demo API from `lol_alloc_demo_support`: calling `allocate` with a non zero count traps.
+#![cfg(target_arch = "wasm32")]
+
+use lol_alloc::FailAllocator;
+pub use lol_alloc_demo_support::{allocate, free_all};
+
+// The test harness allocates, so tests keep the default global allocator.
+#[cfg_attr(not(test), global_allocator)]
+static ALLOCATOR: FailAllocator = FailAllocator;
+
+lol_alloc_demo_support::export_stats!(ALLOCATOR);
+
+#[cfg(test)]
+mod tests {
+    use super::ALLOCATOR;
+    use core::alloc::{GlobalAlloc, Layout};
+    use lol_alloc_demo_support::{allocate, free_all, stat};
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    /// Allocating no objects does not need the allocator.
+    #[wasm_bindgen_test]
+    fn allocates_nothing() {
+        assert_eq!(allocate(0), 0);
+        free_all();
+    }
+
+    #[wasm_bindgen_test]
+    fn fails() {
+        assert!(unsafe { ALLOCATOR.alloc(Layout::new::<u32>()) }.is_null());
+    }
+
+    #[wasm_bindgen_test]
+    fn reports_stats() {
+        let stats = super::stats();
+        assert_eq!(stat(&stats, "heapBytes"), Some(0.0));
+        assert_eq!(stat(&stats, "liveAllocations"), Some(0.0));
+    }
+}
@@ -0,0 +1,44 @@
+//! Demo of `LeakingPageAllocator`, which allocates whole pages and never frees, for modules which only allocate a few times.
+//! Exports the demo API from `lol_alloc_demo_support`.
+#![cfg(target_arch = "wasm32")]
+
+use lol_alloc::LeakingPageAllocator;
+pub use lol_alloc_demo_support::{allocate, free_all};
+
+lol_alloc::define_global! {
+    static ALLOCATOR: LeakingPageAllocator = LeakingPageAllocator::new();
+}
+
+lol_alloc_demo_support::export_stats!(ALLOCATOR);
+
+#[cfg(test)]
+mod tests {
+    use core::arch::wasm32::memory_size;
+    use lol_alloc_demo_support::{allocate, free_all, stat};
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    #[wasm_bindgen_test]
+    fn allocates_and_frees() {
+        assert_eq!(allocate(10), 10);
+        assert_eq!(allocate(1), 11);
+        free_all();
+        assert_eq!(allocate(0), 0);
+    }
+
+    /// Every object takes at least a page of its own.
+    #[wasm_bindgen_test]
+    fn page_per_allocation() {
+        let pages = memory_size(0);
+        allocate(20);
+        free_all();
+        assert!(memory_size(0) >= pages + 20);
+    }
+
+    #[wasm_bindgen_test]
+    fn reports_stats() {
+        // It keeps no state, so nothing is known.
+        let stats = super::stats();
+        assert_eq!(stat(&stats, "freeBytes"), None);
+        assert_eq!(stat(&stats, "heapBytes"), None);
+    }
+}
@@ -0,0 +1,47 @@
+//! Demo of `LockedAllocator<LeakingAllocator>`, a bump allocator which never frees, for modules which only allocate a little.
+//! Exports the demo API from `lol_alloc_demo_support`.
+#![cfg(target_arch = "wasm32")]
+
+use lol_alloc::{LeakingAllocator, LockedAllocator};
+pub use lol_alloc_demo_support::{allocate, free_all};
+
+lol_alloc::define_global! {
+    static ALLOCATOR: LockedAllocator<LeakingAllocator> =
+        LockedAllocator::new(LeakingAllocator::new());
+}
+
+lol_alloc_demo_support::export_stats!(ALLOCATOR);
+
+#[cfg(test)]
+mod tests {
+    use core::arch::wasm32::memory_size;
+    use lol_alloc_demo_support::{allocate, free_all, stat};
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    #[wasm_bindgen_test]
+    fn allocates_and_frees() {
+        assert_eq!(allocate(1000), 1000);
+        assert_eq!(allocate(10), 1010);
+        free_all();
+        assert_eq!(allocate(0), 0);
+    }
+
+    /// Freeing does nothing, so allocating the same objects again grows memory.
+    #[wasm_bindgen_test]
+    fn leaks_memory() {
+        let pages = memory_size(0);
+        for _ in 0..10 {
+            allocate(10_000);
+            free_all();
+        }
+        assert!(memory_size(0) > pages);
+    }
+
+    #[wasm_bindgen_test]
+    fn reports_stats() {
+        let stats = super::stats();
+        // Only the space left to bump into is known.
+        assert!(stat(&stats, "freeBytes").is_some());
+        assert_eq!(stat(&stats, "liveBytes"), None);
+    }
+}
@@ -0,0 +1,52 @@
+//! Demo of `LockedAllocator<FreeListAllocator>`, the recommended allocator for most modules.
+//! Exports the demo API from `lol_alloc_demo_support`.
+#![cfg(target_arch = "wasm32")]
+
+use lol_alloc::{FreeListAllocator, LockedAllocator};
+pub use lol_alloc_demo_support::{allocate, free_all};
+
+lol_alloc::define_global! {
+    static ALLOCATOR: LockedAllocator<FreeListAllocator> =
+        LockedAllocator::new(FreeListAllocator::new());
+}
+
+lol_alloc_demo_support::export_stats!(ALLOCATOR);
+
+#[cfg(test)]
+mod tests {
+    use core::arch::wasm32::memory_size;
+    use lol_alloc_demo_support::{allocate, free_all, stat};
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    #[wasm_bindgen_test]
+    fn allocates_and_frees() {
+        assert_eq!(allocate(1000), 1000);
+        assert_eq!(allocate(10), 1010);
+        free_all();
+        assert_eq!(allocate(0), 0);
+    }
+
+    /// Freed objects are reused, so allocating the same objects again does not grow memory.
+    #[wasm_bindgen_test]
+    fn reuses_memory() {
+        allocate(10_000);
+        free_all();
+        let pages = memory_size(0);
+        for _ in 0..10 {
+            allocate(10_000);
+            free_all();
+        }
+        assert_eq!(memory_size(0), pages);
+    }
+
+    #[wasm_bindgen_test]
+    fn reports_stats() {
+        allocate(1000);
+        free_all();
+        let stats = super::stats();
+        // The freed objects are in the free list.
+        assert!(stat(&stats, "freeBytes").unwrap() >= 4000.0);
+        assert!(stat(&stats, "freeBlocks").unwrap() >= 1.0);
+        assert_eq!(stat(&stats, "liveBytes"), None);
+    }
+}
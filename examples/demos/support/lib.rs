@@ -0,0 +1,89 @@
+//! Code shared by the allocator demos in `examples/demos`.
+//!
+//! Each demo is a wasm-bindgen module using a different allocator, exporting the same API:
+//! `allocate` and `free_all` (from here), and `stats` (from [export_stats!], since it depends on the allocator).
+#![cfg(target_arch = "wasm32")]
+
+use core::cell::RefCell;
+use lol_alloc::Stats;
+use wasm_bindgen::prelude::*;
+
+pub use js_sys;
+pub use wasm_bindgen;
+
+/// What the demos allocate: a word per object, holding its index.
+type Object = Box<u32>;
+
+thread_local! {
+    static OBJECTS: RefCell<Vec<Object>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Allocates `count` more objects, returning how many are now live.
+#[wasm_bindgen]
+pub fn allocate(count: u32) -> u32 {
+    OBJECTS.with_borrow_mut(|objects| {
+        for _ in 0..count {
+            objects.push(Box::new(objects.len() as u32));
+        }
+        objects.len() as u32
+    })
+}
+
+/// Frees all the objects (and the list of them).
+#[wasm_bindgen]
+pub fn free_all() {
+    OBJECTS.with_borrow_mut(|objects| {
+        for (index, object) in objects.iter().enumerate() {
+            assert_eq!(**object, index as u32);
+        }
+        *objects = Vec::new();
+    });
+}
+
+/// `stats` as a JavaScript object, with a number (or `undefined` if unknown) for each field, in camelCase.
+///
+/// Building the object does not allocate from the Rust heap, so this works with any allocator.
+pub fn stats_object(stats: Stats) -> js_sys::Object {
+    let object = js_sys::Object::new();
+    for (name, value) in [
+        ("liveBytes", stats.live_bytes),
+        ("liveAllocations", stats.live_allocations),
+        ("freeBytes", stats.free_bytes),
+        ("freeBlocks", stats.free_blocks),
+        ("heapBytes", stats.heap_bytes),
+        ("peakBytes", stats.peak_bytes),
+        ("failedAllocations", stats.failed_allocations),
+    ] {
+        let value = value.map_or(JsValue::UNDEFINED, |value| JsValue::from(value as f64));
+        js_sys::Reflect::set(&object, &JsValue::from_str(name), &value).unwrap();
+    }
+    object
+}
+
+/// Reads a field of an object from [stats_object], for tests.
+pub fn stat(stats: &js_sys::Object, name: &str) -> Option<f64> {
+    js_sys::Reflect::get(stats, &JsValue::from_str(name))
+        .unwrap()
+        .as_f64()
+}
+
+/// Exports the statistics of the allocator in the static `$allocator` to JavaScript, as `stats()` (see [stats_object]).
+///
+/// For example:
+///
+/// ```ignore
+/// lol_alloc::define_global! {
+///     static ALLOCATOR: LockedAllocator<FreeListAllocator> = LockedAllocator::new(FreeListAllocator::new());
+/// }
+/// lol_alloc_demo_support::export_stats!(ALLOCATOR);
+/// ```
+#[macro_export]
+macro_rules! export_stats {
+    ($allocator:ident) => {
+        /// The allocator's statistics: see `lol_alloc::Stats`.
+        #[$crate::wasm_bindgen::prelude::wasm_bindgen(wasm_bindgen = $crate::wasm_bindgen)]
+        pub fn stats() -> $crate::js_sys::Object {
+            $crate::stats_object(lol_alloc::AllocatorStats::stats(&$allocator))
+        }
+    };
+}
@@ -0,0 +1,191 @@
+//! A wasm module with an LRU cache which survives a tight memory quota by shedding its least recently used entries, instead of aborting.
+//!
+//! The heap may only grow [QUOTA_PAGES] pages (enforced by a [MaxPagesGrower]), far less than the cache would use if it kept everything.
+//! The global allocator is a [RecoveringAllocator], with [shed] registered as a pressure callback:
+//! when an allocation fails, [shed] frees entries from the cold end of the cache, and the allocation is retried.
+//! So an allocation anywhere in the module (not just in the cache) can make room by shrinking the cache,
+//! and allocations never fail outward (which would abort) while the cache has entries to give up.
+//!
+//! The cache is borrowed while it is changed, and [shed] skips it then, so nothing in the borrow allocates:
+//! values are allocated before it, and the entry list has a fixed capacity.
+#![cfg(target_arch = "wasm32")]
+
+use core::{alloc::Layout, cell::RefCell};
+use lol_alloc::{
+    DefaultGrower, FreeListAllocator, LockedAllocator, MaxPagesGrower, RecoveringAllocator,
+};
+use std::{collections::VecDeque, sync::Once};
+
+/// How many pages the heap may grow: 2 MiB.
+pub const QUOTA_PAGES: usize = 32;
+
+/// Most entries the cache holds, however small they are.
+const MAX_ENTRIES: usize = 1024;
+
+static GROWER: MaxPagesGrower = MaxPagesGrower::new(DefaultGrower, QUOTA_PAGES);
+
+lol_alloc::define_global! {
+    static ALLOCATOR: RecoveringAllocator<LockedAllocator<FreeListAllocator<&'static MaxPagesGrower>>> =
+        RecoveringAllocator::new(LockedAllocator::new(FreeListAllocator::new_in(&GROWER)));
+    export set_limit(GROWER);
+}
+
+/// Cached values by key, most recently used first.
+struct Cache {
+    entries: VecDeque<(u32, Vec<u8>)>,
+    /// Total length of the values.
+    bytes: usize,
+    /// Number of entries shed to free memory.
+    shed: usize,
+}
+
+thread_local! {
+    static CACHE: RefCell<Cache> = const {
+        RefCell::new(Cache {
+            entries: VecDeque::new(),
+            bytes: 0,
+            shed: 0,
+        })
+    };
+}
+
+/// Runs `f` with the cache, setting it up first if this is the first use.
+fn with_cache<R>(f: impl FnOnce(&mut Cache) -> R) -> R {
+    static SETUP: Once = Once::new();
+    SETUP.call_once(|| {
+        CACHE.with_borrow_mut(|cache| cache.entries.reserve_exact(MAX_ENTRIES));
+        assert!(ALLOCATOR.register(shed));
+    });
+    CACHE.with_borrow_mut(f)
+}
+
+/// The [PressureCallback](lol_alloc::PressureCallback): frees the least recently used entries, unless the cache is in use.
+/// Returns whether it freed anything.
+///
+/// Frees at least a quarter of the cache, so recovering is rare, then keeps going until the failed allocation fits:
+/// freed values may not be next to each other in memory, so freeing as many bytes as the allocation needs is not always enough.
+/// It checks by allocating, which (since it runs while recovering) fails instead of recursing.
+pub fn shed(layout: Layout) -> bool {
+    CACHE.with(|cache| {
+        let Ok(mut cache) = cache.try_borrow_mut() else {
+            return false;
+        };
+        let quarter = cache.bytes / 4;
+        let mut freed = 0;
+        while let Some((_, value)) = cache.entries.pop_back() {
+            freed += value.len();
+            cache.bytes -= value.len();
+            cache.shed += 1;
+            drop(value);
+            if freed >= quarter && fits(layout) {
+                break;
+            }
+        }
+        freed > 0
+    })
+}
+
+/// If `layout` can be allocated now.
+fn fits(layout: Layout) -> bool {
+    unsafe {
+        let ptr = std::alloc::alloc(layout);
+        if ptr.is_null() {
+            return false;
+        }
+        std::alloc::dealloc(ptr, layout);
+    }
+    true
+}
+
+/// Caches a value of `len` bytes for `key` (replacing any value it had), evicting the least recently used entry if the cache is full.
+/// Returns the number of entries.
+#[no_mangle]
+pub extern "C" fn cache_put(key: u32, len: usize) -> usize {
+    // Allocated before the cache is borrowed, so shedding can make room for it.
+    let value = vec![key as u8; len];
+    with_cache(|cache| {
+        if let Some(index) = cache.entries.iter().position(|&(k, _)| k == key) {
+            let (_, old) = cache.entries.remove(index).unwrap();
+            cache.bytes -= old.len();
+        } else if cache.entries.len() == MAX_ENTRIES {
+            let (_, evicted) = cache.entries.pop_back().unwrap();
+            cache.bytes -= evicted.len();
+        }
+        cache.bytes += value.len();
+        cache.entries.push_front((key, value));
+        cache.entries.len()
+    })
+}
+
+/// Looks up `key`, marking it most recently used. Returns the length of its value, or `usize::MAX` if it is not cached.
+#[no_mangle]
+pub extern "C" fn cache_get(key: u32) -> usize {
+    with_cache(|cache| {
+        let Some(index) = cache.entries.iter().position(|&(k, _)| k == key) else {
+            return usize::MAX;
+        };
+        let entry = cache.entries.remove(index).unwrap();
+        assert!(entry.1.iter().all(|&b| b == key as u8));
+        let len = entry.1.len();
+        cache.entries.push_front(entry);
+        len
+    })
+}
+
+/// Number of cached entries.
+#[no_mangle]
+pub extern "C" fn cache_len() -> usize {
+    with_cache(|cache| cache.entries.len())
+}
+
+/// Number of entries shed so far to free memory.
+#[no_mangle]
+pub extern "C" fn cache_shed() -> usize {
+    with_cache(|cache| cache.shed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{cache_get, cache_len, cache_put, cache_shed, ALLOCATOR, GROWER, QUOTA_PAGES};
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    /// Fills the cache far past the quota with values of each size, repeatedly.
+    /// Any allocation failing outward would abort, failing the test.
+    #[wasm_bindgen_test]
+    fn survives_quota() {
+        let mut key = 0;
+        for len in [100_000, 1000, 50_000, 300_000, 10] {
+            let shed = cache_shed();
+            let mut largest = 0;
+            let mut shrank = false;
+            // Each round caches more than the whole quota.
+            for _ in 0..(4 * QUOTA_PAGES * lol_alloc::PAGE_SIZE / len).min(4000) {
+                let entries = cache_put(key, len);
+                shrank |= entries < largest;
+                largest = largest.max(entries);
+                key += 1;
+            }
+            // The newest value is always kept.
+            assert_eq!(cache_get(key - 1), len);
+            if len >= 1000 {
+                assert!(shrank, "the cache did not shrink for {len} byte values");
+                assert!(cache_shed() > shed);
+            }
+            assert!(GROWER.grown_pages() <= QUOTA_PAGES);
+        }
+        assert!(ALLOCATOR.recoveries() > 0);
+        assert!(cache_len() > 0);
+    }
+
+    /// Entries which are used survive shedding, since the least recently used go first.
+    #[wasm_bindgen_test]
+    fn keeps_recently_used() {
+        let hot = 1 << 30;
+        cache_put(hot, 10_000);
+        for key in 0..200 {
+            cache_put(key, 100_000);
+            assert_eq!(cache_get(hot), 10_000);
+        }
+        assert!(cache_shed() > 0);
+    }
+}
@@ -0,0 +1,6 @@
+#!/bin/bash
+# Builds the example into ./pkg, as an ES module which loads the wasm itself (for browsers, or Deno), for use from types.ts.
+set -eux -o pipefail
+
+cd "$(dirname "$0")"
+wasm-pack build --release --target web
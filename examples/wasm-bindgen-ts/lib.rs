@@ -0,0 +1,44 @@
+//! A wasm-bindgen module using lol_alloc, called from TypeScript: see `types.ts`, and `build.sh` to build it.
+#![cfg(target_arch = "wasm32")]
+
+use lol_alloc::{FreeListAllocator, LockedAllocator};
+use wasm_bindgen::prelude::*;
+
+#[global_allocator]
+static ALLOC: LockedAllocator<FreeListAllocator> = LockedAllocator::new(FreeListAllocator::new());
+
+/// Returns `input` with each byte repeated twice.
+///
+/// wasm-bindgen copies `input` into memory allocated from `ALLOC`,
+/// and copies the returned `Vec` out to a `Uint8Array` before freeing it.
+#[wasm_bindgen]
+pub fn compute(input: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(input.len() * 2);
+    for &byte in input {
+        output.extend([byte, byte]);
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    #[wasm_bindgen_test]
+    fn doubles_bytes() {
+        assert_eq!(super::compute(&[1, 2, 3]), [1, 1, 2, 2, 3, 3]);
+        assert!(super::compute(&[]).is_empty());
+    }
+
+    /// Results are freed, so repeated calls reuse memory instead of growing it.
+    #[wasm_bindgen_test]
+    fn reuses_memory() {
+        let input = vec![7; 100_000];
+        drop(super::compute(&input));
+        let pages = core::arch::wasm32::memory_size(0);
+        for _ in 0..100 {
+            assert_eq!(super::compute(&input).len(), 200_000);
+        }
+        assert_eq!(core::arch::wasm32::memory_size(0), pages);
+    }
+}
@@ -0,0 +1,13 @@
+// Calls the module built by build.sh (into ./pkg) from TypeScript.
+// wasm-bindgen generates ./pkg/lol_alloc_wasm_bindgen_ts.d.ts, which types `compute` as:
+//   export function compute(input: Uint8Array): Uint8Array;
+import init, { compute } from "./pkg/lol_alloc_wasm_bindgen_ts.js";
+
+// With --target web, the module must be initialized (fetching the .wasm file) before use.
+await init();
+
+const input: Uint8Array = new TextEncoder().encode("lol");
+// The input is copied into the wasm heap, and the result copied out of it:
+// the returned array is owned by JavaScript, so later calls do not change it.
+const output: Uint8Array = compute(input);
+console.log(new TextDecoder().decode(output)); // "llooll"
@@ -0,0 +1,135 @@
+//! Workloads shaped like real applications, for testing allocators where their bugs actually bite users.
+//!
+//! [hash_map_churn] grows a `hashbrown` map through every power of two table size (each rehash allocates a table twice as large, then frees the old one),
+//! and [json_churn] parses a JSON document with `serde_json`, making many small strings, vectors and maps with interleaved lifetimes.
+//! [check_workload] runs them repeatedly, checking the heap stops growing after the first round, and that everything is freed at the end.
+//!
+//! The wasm tests (in `tests/`) run them with `LockedAllocator<FreeListAllocator>` and `AssumeSingleThreaded<FreeListAllocator>` as the global allocator,
+//! and `tests/native.rs` runs them on the host, with the heap in a static array.
+
+use hashbrown::HashMap;
+use serde_json::Value;
+use std::{fmt::Write, hash::RandomState};
+
+/// Inserts `entries` entries into a map, removes every other one, inserts them again, then removes them all.
+///
+/// Growing to `entries` rehashes into each larger power of two table, freeing the old table each time;
+/// the removals leave tombstones which later insertions reuse.
+pub fn hash_map_churn(entries: u64) {
+    let mut map: HashMap<u64, u64, RandomState> = HashMap::with_hasher(RandomState::new());
+    for key in 0..entries {
+        map.insert(key, !key);
+    }
+    for key in (0..entries).step_by(2) {
+        assert_eq!(map.remove(&key), Some(!key));
+    }
+    for key in (0..entries).step_by(2) {
+        map.insert(key, !key);
+    }
+    assert_eq!(map.len() as u64, entries);
+    for key in 0..entries {
+        assert_eq!(map.remove(&key), Some(!key));
+    }
+    assert!(map.is_empty());
+}
+
+/// A JSON document of at least `bytes` bytes: an array of records, each with strings, numbers, a nested array and a nested object.
+///
+/// String lengths vary (up to a few hundred bytes), so parsing it makes allocations of many sizes.
+pub fn json_document(bytes: usize) -> String {
+    let mut document = String::from("[");
+    let mut record = 0u64;
+    while document.len() < bytes {
+        if record > 0 {
+            document.push(',');
+        }
+        let name = "x".repeat((record * 7 % 200) as usize);
+        write!(
+            document,
+            r#"{{"id":{record},"name":"record {record} {name}","score":{},"tags":["#,
+            record as f64 / 3.0
+        )
+        .unwrap();
+        for tag in 0..record % 5 {
+            if tag > 0 {
+                document.push(',');
+            }
+            write!(document, r#""tag{tag}""#).unwrap();
+        }
+        write!(
+            document,
+            r#"],"nested":{{"depth":{},"label":"{}"}}}}"#,
+            record % 3,
+            "y".repeat((record * 13 % 50) as usize)
+        )
+        .unwrap();
+        record += 1;
+    }
+    document.push(']');
+    document
+}
+
+/// Parses `document` (from [json_document]), keeps the names of every other record while dropping the rest of the document,
+/// then frees the names. Returns the number of records.
+pub fn json_churn(document: &str) -> usize {
+    let value: Value = serde_json::from_str(document).unwrap();
+    let records = value.as_array().unwrap();
+    let names: Vec<String> = records
+        .iter()
+        .step_by(2)
+        .map(|record| record["name"].as_str().unwrap().to_owned())
+        .collect();
+    let count = records.len();
+    // The names outlive the document they came from.
+    drop(value);
+    assert!(names.iter().all(|name| name.starts_with("record ")));
+    count
+}
+
+/// Memory grown for the heap, and how much of it is free, in bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HeapSize {
+    pub grown: usize,
+    pub free: usize,
+}
+
+impl HeapSize {
+    /// Bytes of the heap in use, by allocations (and the allocator's own overhead).
+    pub fn used(&self) -> usize {
+        self.grown - self.free
+    }
+}
+
+/// Runs `workload` `rounds` times, checking that the heap (as reported by `heap`) stops growing after the first round,
+/// and that everything the workload allocated has been freed back to the allocator afterwards.
+///
+/// Nothing else may allocate or free while this runs.
+pub fn check_workload(rounds: usize, heap: impl Fn() -> HeapSize, mut workload: impl FnMut()) {
+    let before = heap();
+    workload();
+    let plateau = heap().grown;
+    for round in 1..rounds {
+        workload();
+        assert_eq!(heap().grown, plateau, "heap grew in round {round}");
+    }
+    let after = heap();
+    assert_eq!(
+        after.used(),
+        before.used(),
+        "memory was not freed: {after:?}"
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{json_churn, json_document};
+
+    #[test]
+    fn json_document_size() {
+        for bytes in [100, 1000, 100_000] {
+            let document = json_document(bytes);
+            assert!(document.len() >= bytes);
+            assert!(json_churn(&document) > 0);
+        }
+    }
+}
@@ -0,0 +1,32 @@
+//! The workloads with `LockedAllocator<FreeListAllocator>`, the recommended allocator, as the global allocator.
+#![cfg(target_arch = "wasm32")]
+
+use core::arch::wasm32::memory_size;
+use lol_alloc::{AllocatorStats, FreeListAllocator, LockedAllocator, PAGE_SIZE};
+use lol_alloc_workloads::{check_workload, hash_map_churn, json_churn, json_document, HeapSize};
+use wasm_bindgen_test::wasm_bindgen_test;
+
+lol_alloc::define_global! {
+    static ALLOCATOR: LockedAllocator<FreeListAllocator> =
+        LockedAllocator::new(FreeListAllocator::new());
+}
+
+fn heap() -> HeapSize {
+    HeapSize {
+        grown: memory_size(0) * PAGE_SIZE,
+        free: ALLOCATOR.stats().free_bytes.unwrap(),
+    }
+}
+
+#[wasm_bindgen_test]
+fn hash_map() {
+    check_workload(3, heap, || hash_map_churn(1_000_000));
+}
+
+#[wasm_bindgen_test]
+fn json() {
+    let document = json_document(4 << 20);
+    check_workload(3, heap, || {
+        assert!(json_churn(&document) > 10_000);
+    });
+}
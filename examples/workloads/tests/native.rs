@@ -0,0 +1,56 @@
+//! The workloads on the host, with `LockedAllocator<FreeListAllocator>` as the global allocator, growing into a static array.
+//!
+//! They are smaller than on wasm, so they run quickly in debug builds.
+//! Everything runs in one test, so the test harness is not allocating from other threads at the same time.
+#![cfg(not(target_arch = "wasm32"))]
+
+use lol_alloc::{
+    AllocatorStats, FreeListAllocator, GlobalArrayGrower, LockedAllocator, MemoryGrower, PageCount,
+    ERROR_PAGE_COUNT, PAGE_SIZE,
+};
+use lol_alloc_workloads::{check_workload, hash_map_churn, json_churn, json_document, HeapSize};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Counts the pages grown from a [GlobalArrayGrower].
+struct CountingGrower {
+    inner: GlobalArrayGrower<512>,
+    pages: AtomicUsize,
+}
+
+impl MemoryGrower for CountingGrower {
+    fn memory_grow(&self, delta: PageCount) -> PageCount {
+        let previous = self.inner.memory_grow(delta);
+        if previous != ERROR_PAGE_COUNT {
+            self.pages.fetch_add(delta.0, Ordering::Relaxed);
+        }
+        previous
+    }
+}
+
+static GROWER: CountingGrower = CountingGrower {
+    inner: GlobalArrayGrower::new(),
+    pages: AtomicUsize::new(0),
+};
+
+lol_alloc::define_global! {
+    static ALLOCATOR: LockedAllocator<FreeListAllocator<&CountingGrower>> =
+        LockedAllocator::new(FreeListAllocator::new_in(&GROWER));
+}
+
+fn heap() -> HeapSize {
+    HeapSize {
+        grown: GROWER.pages.load(Ordering::Relaxed) * PAGE_SIZE,
+        free: ALLOCATOR.stats().free_bytes.unwrap(),
+    }
+}
+
+#[test]
+fn workloads() {
+    check_workload(3, heap, || hash_map_churn(100_000));
+    let document = json_document(256 << 10);
+    check_workload(3, heap, || {
+        assert!(json_churn(&document) > 500);
+    });
+    drop(document);
+    ALLOCATOR.lock().validate_heap();
+}
@@ -0,0 +1,33 @@
+//! The workloads with `AssumeSingleThreaded<FreeListAllocator>`, the allocator for modules without threads, as the global allocator.
+#![cfg(target_arch = "wasm32")]
+
+use core::arch::wasm32::memory_size;
+use lol_alloc::{AllocatorStats, AssumeSingleThreaded, FreeListAllocator, PAGE_SIZE};
+use lol_alloc_workloads::{check_workload, hash_map_churn, json_churn, json_document, HeapSize};
+use wasm_bindgen_test::wasm_bindgen_test;
+
+lol_alloc::define_global! {
+    // Safety: wasm32 modules built without the atomics target feature have no threads.
+    static ALLOCATOR: AssumeSingleThreaded<FreeListAllocator> =
+        unsafe { AssumeSingleThreaded::new(FreeListAllocator::new()) };
+}
+
+fn heap() -> HeapSize {
+    HeapSize {
+        grown: memory_size(0) * PAGE_SIZE,
+        free: ALLOCATOR.stats().free_bytes.unwrap(),
+    }
+}
+
+#[wasm_bindgen_test]
+fn hash_map() {
+    check_workload(3, heap, || hash_map_churn(1_000_000));
+}
+
+#[wasm_bindgen_test]
+fn json() {
+    let document = json_document(4 << 20);
+    check_workload(3, heap, || {
+        assert!(json_churn(&document) > 10_000);
+    });
+}
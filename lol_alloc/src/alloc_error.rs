@@ -0,0 +1,11 @@
+use core::fmt;
+
+/// An allocation (or a reservation of memory for later allocations) failed, or its size overflowed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct AllocError;
+
+impl fmt::Display for AllocError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("memory allocation failed")
+    }
+}
@@ -0,0 +1,78 @@
+use crate::{lock::LockGuard, AllocWithExcess, AllocatorStats, Stats};
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    ops::Deref,
+};
+
+/// A locked allocator, from [LockedAllocator::alloc_ref](crate::LockedAllocator::alloc_ref).
+///
+/// Implements [GlobalAlloc] by using the inner allocator directly, so a batch of allocations and frees only locks once.
+/// The lock is released when this is dropped. Until then, other uses of the [LockedAllocator](crate::LockedAllocator) spin,
+/// so do not use it as the global allocator while holding one on the same thread.
+pub struct AllocRef<'a, T> {
+    guard: LockGuard<'a, T>,
+}
+
+impl<'a, T> AllocRef<'a, T> {
+    pub(crate) fn new(guard: LockGuard<'a, T>) -> Self {
+        AllocRef { guard }
+    }
+}
+
+/// For access to functionality beyond [GlobalAlloc].
+impl<T> Deref for AllocRef<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+unsafe impl<T: GlobalAlloc> GlobalAlloc for AllocRef<'_, T> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        nonzero_size_assert!(layout.size());
+        self.guard.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        nonzero_size_assert!(layout.size());
+        self.guard.dealloc(ptr, layout);
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        nonzero_size_assert!(layout.size());
+        self.guard.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        nonzero_size_assert!(layout.size(), new_size);
+        self.guard.realloc(ptr, layout, new_size)
+    }
+}
+
+impl<T: AllocWithExcess> AllocWithExcess for AllocRef<'_, T> {
+    unsafe fn alloc_with_excess(&self, layout: Layout) -> (*mut u8, usize) {
+        self.guard.alloc_with_excess(layout)
+    }
+}
+
+impl<T: AllocatorStats> AllocatorStats for AllocRef<'_, T> {
+    fn stats(&self) -> Stats {
+        self.guard.stats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        testing::{conformance, MockGrower},
+        FreeListAllocator, LockedAllocator,
+    };
+
+    #[test]
+    fn conforms() {
+        let allocator = LockedAllocator::new(FreeListAllocator::new_in(MockGrower::new()));
+        conformance(&allocator.alloc_ref());
+        allocator.alloc_ref().validate_heap();
+    }
+}
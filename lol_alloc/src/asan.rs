@@ -0,0 +1,140 @@
+//! AddressSanitizer annotations for the `asan` feature.
+//!
+//! Poisoned memory is reported as an error by ASAN if instrumented code accesses it.
+//! Without the feature, or on wasm32 (which ASAN does not support), everything here is a no-op.
+//!
+//! Stable Rust can't weakly link symbols, so with the feature the ASAN runtime must be part of the link,
+//! for example from C code built with `-fsanitize=address`.
+
+#[cfg(all(feature = "asan", not(target_arch = "wasm32")))]
+extern "C" {
+    fn __asan_poison_memory_region(addr: *const u8, size: usize);
+    fn __asan_unpoison_memory_region(addr: *const u8, size: usize);
+}
+
+/// Marks the `size` bytes at `ptr` as not accessible.
+#[cfg(all(feature = "asan", not(target_arch = "wasm32")))]
+pub(crate) unsafe fn poison(ptr: *const u8, size: usize) {
+    __asan_poison_memory_region(ptr, size);
+}
+
+/// Marks the `size` bytes at `ptr` as accessible.
+#[cfg(all(feature = "asan", not(target_arch = "wasm32")))]
+pub(crate) unsafe fn unpoison(ptr: *const u8, size: usize) {
+    __asan_unpoison_memory_region(ptr, size);
+}
+
+#[cfg(not(all(feature = "asan", not(target_arch = "wasm32"))))]
+#[inline(always)]
+pub(crate) unsafe fn poison(_ptr: *const u8, _size: usize) {}
+
+#[cfg(not(all(feature = "asan", not(target_arch = "wasm32"))))]
+#[inline(always)]
+pub(crate) unsafe fn unpoison(_ptr: *const u8, _size: usize) {}
+
+/// Test implementation of the ASAN runtime functions, tracking which bytes are poisoned on each thread.
+#[cfg(all(test, feature = "asan"))]
+pub(crate) mod shadow {
+    use std::{
+        cell::{Cell, RefCell},
+        collections::BTreeMap,
+        vec::Vec,
+    };
+
+    std::thread_local! {
+        /// Poisoned ranges, as a map from start to end, with no two ranges overlapping or adjacent.
+        static POISONED: RefCell<BTreeMap<usize, usize>> = const { RefCell::new(BTreeMap::new()) };
+        /// The only addresses tracked, so tests not checking poisoning are not slowed down.
+        static WATCHED: Cell<(usize, usize)> = const { Cell::new((0, 0)) };
+    }
+
+    /// Starts tracking poisoning of the `size` bytes at `ptr` on this thread, instead of any previously watched bytes.
+    pub(crate) fn watch(ptr: *const u8, size: usize) {
+        WATCHED.set((ptr as usize, ptr as usize + size));
+        POISONED.with(|p| p.borrow_mut().clear());
+    }
+
+    /// The part of the `size` bytes at `addr` which is watched.
+    fn watched(addr: *const u8, size: usize) -> (usize, usize) {
+        let (start, end) = WATCHED.get();
+        (
+            (addr as usize).clamp(start, end),
+            (addr as usize + size).clamp(start, end),
+        )
+    }
+
+    /// Removes the poisoned ranges overlapping or adjacent to `start..end`, returning them.
+    fn take(
+        poisoned: &mut BTreeMap<usize, usize>,
+        start: usize,
+        end: usize,
+    ) -> Vec<(usize, usize)> {
+        let mut touching = Vec::new();
+        while let Some((&s, &e)) = poisoned.range(..=end).next_back() {
+            if e < start {
+                break;
+            }
+            poisoned.remove(&s);
+            touching.push((s, e));
+        }
+        touching
+    }
+
+    /// If any of the `size` bytes at `ptr` (which must be watched) are poisoned.
+    pub(crate) fn any_poisoned(ptr: *const u8, size: usize) -> bool {
+        let (start, end) = (ptr as usize, ptr as usize + size);
+        POISONED.with(|p| {
+            p.borrow()
+                .range(..end)
+                .next_back()
+                .is_some_and(|(_, &e)| e > start)
+        })
+    }
+
+    /// If all the `size` bytes at `ptr` (which must be watched) are poisoned.
+    pub(crate) fn all_poisoned(ptr: *const u8, size: usize) -> bool {
+        let (start, end) = (ptr as usize, ptr as usize + size);
+        start == end
+            || POISONED.with(|p| {
+                p.borrow()
+                    .range(..=start)
+                    .next_back()
+                    .is_some_and(|(_, &e)| e >= end)
+            })
+    }
+
+    #[no_mangle]
+    extern "C" fn __asan_poison_memory_region(addr: *const u8, size: usize) {
+        let (start, end) = watched(addr, size);
+        if start == end {
+            return;
+        }
+        POISONED.with(|p| {
+            let mut p = p.borrow_mut();
+            let touching = take(&mut p, start, end);
+            let merged_start = touching.iter().map(|r| r.0).fold(start, usize::min);
+            let merged_end = touching.iter().map(|r| r.1).fold(end, usize::max);
+            p.insert(merged_start, merged_end);
+        });
+    }
+
+    #[no_mangle]
+    extern "C" fn __asan_unpoison_memory_region(addr: *const u8, size: usize) {
+        let (start, end) = watched(addr, size);
+        if start == end {
+            return;
+        }
+        POISONED.with(|p| {
+            let mut p = p.borrow_mut();
+            // Keep the parts of the ranges outside start..end.
+            for (s, e) in take(&mut p, start, end) {
+                if s < start {
+                    p.insert(s, start);
+                }
+                if e > end {
+                    p.insert(end, e);
+                }
+            }
+        });
+    }
+}
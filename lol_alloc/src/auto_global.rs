@@ -0,0 +1,35 @@
+//! The `auto-global` feature: lol_alloc declares the `#[global_allocator]` itself, so using the crate is the entire integration.
+//!
+//! Without the `atomics` target feature wasm32 can't have threads, so the allocator is [AssumeSingleThreaded]:
+//! otherwise it is a [LockedAllocator], like the lock selection in [lock](crate::lock).
+
+#[cfg(not(target_arch = "wasm32"))]
+compile_error!(
+    "the lol_alloc `auto-global` feature only supports wasm32 targets: \
+     enable it only for them (in `[target.'cfg(target_arch = \"wasm32\")'.dependencies]`), \
+     or declare the global allocator yourself"
+);
+
+#[cfg(all(target_arch = "wasm32", not(target_feature = "atomics")))]
+use crate::AssumeSingleThreaded;
+#[cfg(target_arch = "wasm32")]
+use crate::FreeListAllocator;
+#[cfg(all(target_arch = "wasm32", target_feature = "atomics"))]
+use crate::LockedAllocator;
+
+/// The global allocator declared by the `auto-global` feature, for reading its [Stats](crate::Stats).
+///
+/// A program can only have one global allocator, so with this feature it must not declare its own.
+// SAFETY: Without the atomics target feature there are no threads, so using AssumeSingleThreaded is allowed.
+#[cfg(all(target_arch = "wasm32", not(target_feature = "atomics")))]
+#[global_allocator]
+pub static AUTO_GLOBAL: AssumeSingleThreaded<FreeListAllocator> =
+    unsafe { AssumeSingleThreaded::new(FreeListAllocator::new()) };
+
+/// The global allocator declared by the `auto-global` feature, for reading its [Stats](crate::Stats).
+///
+/// A program can only have one global allocator, so with this feature it must not declare its own.
+#[cfg(all(target_arch = "wasm32", target_feature = "atomics"))]
+#[global_allocator]
+pub static AUTO_GLOBAL: LockedAllocator<FreeListAllocator> =
+    LockedAllocator::new(FreeListAllocator::new());
@@ -0,0 +1,190 @@
+use crate::{
+    atomic::{AtomicUsize, Ordering},
+    AllocatorStats, CountsAllocations, Stats,
+};
+use core::alloc::{GlobalAlloc, Layout};
+
+/// Wraps an allocator, aborting if the bytes in live allocations would exceed a budget.
+///
+/// For programs where exceeding a known memory budget is a bug, this reports it at the allocation which caused it,
+/// instead of as an allocation failure once memory runs out.
+/// The budget counts the bytes requested by each [Layout], not any overhead of the inner allocator.
+///
+/// Unwinding out of a global allocator is undefined behavior, so going over budget does not unwind, and does not return null for callers to handle:
+/// it panics in a function which cannot unwind, so the panic hook prints the allocation and the bytes in use, then the program aborts.
+/// On wasm, where panics abort, this traps.
+pub struct BudgetAllocator<T> {
+    inner: T,
+    budget_bytes: usize,
+    live_bytes: AtomicUsize,
+}
+
+impl<T> BudgetAllocator<T> {
+    pub const fn new(inner: T, budget_bytes: usize) -> Self {
+        BudgetAllocator {
+            inner,
+            budget_bytes,
+            live_bytes: AtomicUsize::new(0),
+        }
+    }
+
+    /// Adds `size` bytes to the live bytes, replacing `freed` bytes which are being reallocated.
+    /// If this would exceed the budget, returns the live bytes instead.
+    fn reserve(&self, size: usize, freed: usize) -> Result<(), usize> {
+        self.live_bytes
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |live| {
+                (live - freed)
+                    .checked_add(size)
+                    .filter(|&total| total <= self.budget_bytes)
+            })
+            .map(|_| ())
+    }
+
+    /// [Self::reserve], aborting if this would exceed the budget.
+    fn reserve_or_abort(&self, layout: Layout, size: usize, freed: usize) {
+        if let Err(live) = self.reserve(size, freed) {
+            over_budget(size, layout.align(), live, self.budget_bytes);
+        }
+    }
+}
+
+/// Reports an allocation of `size` bytes going over budget, then aborts.
+///
+/// Panicking in an `extern "C"` function runs the panic hook, then aborts instead of unwinding out of it.
+#[cold]
+#[inline(never)]
+extern "C" fn over_budget(size: usize, align: usize, live: usize, budget: usize) -> ! {
+    panic!(
+        "Allocation of {size} bytes (align {align}) exceeds memory budget: {live} of {budget} bytes in use"
+    );
+}
+
+unsafe impl<T: GlobalAlloc> GlobalAlloc for BudgetAllocator<T> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        nonzero_size_assert!(layout.size());
+        self.reserve_or_abort(layout, layout.size(), 0);
+        let ptr = self.inner.alloc(layout);
+        if ptr.is_null() {
+            self.live_bytes.fetch_sub(layout.size(), Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        nonzero_size_assert!(layout.size());
+        self.inner.dealloc(ptr, layout);
+        self.live_bytes.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        nonzero_size_assert!(layout.size(), new_size);
+        // Only the new size counts against the budget, even if the inner allocator copies.
+        self.reserve_or_abort(layout, new_size, layout.size());
+        let new_ptr = self.inner.realloc(ptr, layout, new_size);
+        if new_ptr.is_null() {
+            // The original allocation is still live.
+            self.live_bytes.fetch_sub(new_size, Ordering::Relaxed);
+            self.live_bytes.fetch_add(layout.size(), Ordering::Relaxed);
+        }
+        new_ptr
+    }
+}
+
+impl<T: AllocatorStats> AllocatorStats for BudgetAllocator<T> {
+    fn stats(&self) -> Stats {
+        Stats {
+            live_bytes: Some(self.live_bytes.load(Ordering::Relaxed)),
+            ..Stats::default()
+        }
+        .or(self.inner.stats())
+    }
+}
+
+impl<T: CountsAllocations> CountsAllocations for BudgetAllocator<T> {}
+
+#[cfg(test)]
+mod tests {
+    use super::BudgetAllocator;
+    use crate::{free_list_allocator::FreeListAllocator, testing::MockGrower, AllocatorStats};
+    use core::alloc::{GlobalAlloc, Layout};
+
+    fn allocator() -> BudgetAllocator<FreeListAllocator<MockGrower>> {
+        BudgetAllocator::new(FreeListAllocator::new_in(MockGrower::new()), 1000)
+    }
+
+    #[test]
+    fn within_budget() {
+        let allocator = allocator();
+        let layout = |size| Layout::from_size_align(size, 1).unwrap();
+        unsafe {
+            let a = allocator.alloc(layout(600));
+            // Exactly reaching the budget is allowed.
+            let b = allocator.alloc(layout(400));
+            assert_eq!(allocator.stats().live_bytes, Some(1000));
+            allocator.dealloc(a, layout(600));
+            // Reallocating only counts the new size.
+            let b = allocator.realloc(b, layout(400), 1000);
+            assert_eq!(allocator.stats().live_bytes, Some(1000));
+            allocator.dealloc(b, layout(1000));
+            assert_eq!(allocator.stats().live_bytes, Some(0));
+            assert!(allocator.stats().free_bytes.is_some());
+        }
+    }
+
+    /// The budget is crossed by the byte after it.
+    #[test]
+    fn exceeding_budget_by_one_byte() {
+        let allocator = allocator();
+        assert_eq!(allocator.reserve(1000, 0), Ok(()));
+        assert_eq!(allocator.reserve(1, 0), Err(1000));
+        assert_eq!(allocator.stats().live_bytes, Some(1000));
+    }
+
+    /// Reallocating can use the bytes it frees, but not one more.
+    #[test]
+    fn realloc_exceeding_budget_by_one_byte() {
+        let allocator = allocator();
+        assert_eq!(allocator.reserve(100, 0), Ok(()));
+        assert_eq!(allocator.reserve(1001, 100), Err(100));
+        assert_eq!(allocator.reserve(1000, 100), Ok(()));
+        assert_eq!(allocator.stats().live_bytes, Some(1000));
+    }
+
+    /// Going over budget in `alloc` aborts, after printing the allocation and the bytes in use.
+    /// Since that ends the process, the test runs itself in a child process.
+    #[test]
+    fn exceeding_budget_aborts() {
+        const CHILD: &str = "LOL_ALLOC_BUDGET_CHILD";
+        if std::env::var_os(CHILD).is_some() {
+            let allocator = allocator();
+            unsafe {
+                allocator.alloc(Layout::from_size_align(1000, 8).unwrap());
+                allocator.alloc(Layout::from_size_align(1, 1).unwrap());
+            }
+            return;
+        }
+        let output = std::process::Command::new(std::env::current_exe().unwrap())
+            .args([
+                "--exact",
+                "budget_allocator::tests::exceeding_budget_aborts",
+                // The panic message is printed just before aborting, so must not be captured.
+                "--nocapture",
+            ])
+            .env(CHILD, "1")
+            .output()
+            .unwrap();
+        let stderr = std::string::String::from_utf8_lossy(&output.stderr);
+        assert!(!output.status.success(), "{stderr}");
+        assert!(
+            stderr.contains(
+                "Allocation of 1 bytes (align 1) exceeds memory budget: 1000 of 1000 bytes in use"
+            ),
+            "{stderr}"
+        );
+        // Aborted, instead of unwinding out of the allocator.
+        assert!(
+            stderr.contains("panic in a function that cannot unwind"),
+            "{stderr}"
+        );
+    }
+}
@@ -0,0 +1,164 @@
+use crate::{AllocWithExcess, AllocatorStats, CountsAllocations, Stats};
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    ptr::null_mut,
+};
+
+/// Wraps an allocator, rounding the size and alignment of every allocation up to a multiple of `CACHE_LINE` bytes,
+/// so no two allocations share a cache line.
+///
+/// This avoids false sharing between allocations used by different threads (for example in concurrent data structures),
+/// at the cost of wasting most of each cache line for small allocations: with the default 64 byte line, a 1 byte allocation uses 64 bytes.
+/// `CACHE_LINE` must be a power of two.
+pub struct CacheIsolatedAllocator<T, const CACHE_LINE: usize = 64> {
+    inner: T,
+}
+
+impl<T, const CACHE_LINE: usize> CacheIsolatedAllocator<T, CACHE_LINE> {
+    pub const fn new(inner: T) -> Self {
+        assert!(
+            CACHE_LINE.is_power_of_two(),
+            "CACHE_LINE must be a power of two"
+        );
+        CacheIsolatedAllocator { inner }
+    }
+
+    /// The allocator this wraps.
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    /// The layout allocated from the inner allocator for `layout`.
+    fn padded(layout: Layout) -> Option<Layout> {
+        let size = layout.size().checked_next_multiple_of(CACHE_LINE)?;
+        Layout::from_size_align(size, layout.align().max(CACHE_LINE)).ok()
+    }
+}
+
+unsafe impl<T: GlobalAlloc, const CACHE_LINE: usize> GlobalAlloc
+    for CacheIsolatedAllocator<T, CACHE_LINE>
+{
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        nonzero_size_assert!(layout.size());
+        match Self::padded(layout) {
+            Some(padded) => self.inner.alloc(padded),
+            None => null_mut(),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        nonzero_size_assert!(layout.size());
+        // Layout was valid when allocated, so this is too.
+        self.inner
+            .dealloc(ptr, Self::padded(layout).unwrap_unchecked());
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        nonzero_size_assert!(layout.size(), new_size);
+        let padded = Self::padded(layout).unwrap_unchecked();
+        let Some(new_padded) = new_size.checked_next_multiple_of(CACHE_LINE) else {
+            return null_mut();
+        };
+        if new_padded == padded.size() {
+            // The padding already has room.
+            return ptr;
+        }
+        self.inner.realloc(ptr, padded, new_padded)
+    }
+}
+
+/// Reports the usable size of the inner allocation, rounded down to whole cache lines
+/// so freeing with it uses a padded size the inner allocator accepts.
+impl<T: AllocWithExcess, const CACHE_LINE: usize> AllocWithExcess
+    for CacheIsolatedAllocator<T, CACHE_LINE>
+{
+    unsafe fn alloc_with_excess(&self, layout: Layout) -> (*mut u8, usize) {
+        let Some(padded) = Self::padded(layout) else {
+            return (null_mut(), 0);
+        };
+        let (ptr, usable) = self.inner.alloc_with_excess(padded);
+        (ptr, usable & CACHE_LINE.wrapping_neg())
+    }
+}
+
+/// Statistics are those of the inner allocator, so count the padding.
+impl<T: AllocatorStats, const CACHE_LINE: usize> AllocatorStats
+    for CacheIsolatedAllocator<T, CACHE_LINE>
+{
+    fn stats(&self) -> Stats {
+        self.inner.stats()
+    }
+}
+
+impl<T: CountsAllocations, const CACHE_LINE: usize> CountsAllocations
+    for CacheIsolatedAllocator<T, CACHE_LINE>
+{
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CacheIsolatedAllocator;
+    use crate::{
+        testing::{block_size, conformance, excess_conformance, MockGrower},
+        FreeListAllocator,
+    };
+    use alloc::vec::Vec;
+    use core::alloc::{GlobalAlloc, Layout};
+
+    #[test]
+    fn conforms() {
+        let allocator =
+            CacheIsolatedAllocator::<_>::new(FreeListAllocator::new_in(MockGrower::new()));
+        conformance(&allocator);
+        excess_conformance(&allocator);
+        allocator.inner().validate_heap();
+    }
+
+    fn check_sequential<const CACHE_LINE: usize>() {
+        let allocator = CacheIsolatedAllocator::<_, CACHE_LINE>::new(FreeListAllocator::new_in(
+            MockGrower::new(),
+        ));
+        let layout = Layout::from_size_align(1, 1).unwrap();
+        let ptrs: Vec<*mut u8> = (0..10)
+            .map(|_| unsafe { allocator.alloc(layout) })
+            .collect();
+        // One line each, or two with the `dealloc-size-check` prefix.
+        let line = Layout::from_size_align(CACHE_LINE, CACHE_LINE).unwrap();
+        for pair in ptrs.windows(2) {
+            assert_eq!(
+                (pair[0] as usize).abs_diff(pair[1] as usize),
+                block_size(line)
+            );
+        }
+        for &ptr in &ptrs {
+            assert_eq!(ptr as usize % CACHE_LINE, 0);
+            unsafe { allocator.dealloc(ptr, layout) };
+        }
+        allocator.inner().validate_heap();
+    }
+
+    #[test]
+    fn sequential_allocations_one_line_apart() {
+        check_sequential::<64>();
+        check_sequential::<128>();
+    }
+
+    #[test]
+    fn realloc_within_line() {
+        let allocator =
+            CacheIsolatedAllocator::<_>::new(FreeListAllocator::new_in(MockGrower::new()));
+        let layout = Layout::from_size_align(1, 1).unwrap();
+        unsafe {
+            let ptr = allocator.alloc(layout);
+            ptr.write(7);
+            // Growing within the padding does not move the allocation.
+            assert_eq!(allocator.realloc(ptr, layout, 64), ptr);
+            let layout = Layout::from_size_align(64, 1).unwrap();
+            let grown = allocator.realloc(ptr, layout, 65);
+            assert_ne!(grown, ptr);
+            assert_eq!(grown.read(), 7);
+            allocator.dealloc(grown, Layout::from_size_align(65, 1).unwrap());
+        }
+        allocator.inner().validate_heap();
+    }
+}
@@ -0,0 +1,226 @@
+use crate::{
+    lock::{Lock, LockGuard},
+    zero::zeroed,
+    AllocatorStats, Stats,
+};
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    ptr::null_mut,
+};
+
+/// A freed allocation, waiting to be passed to the inner allocator.
+#[derive(Clone, Copy)]
+struct Deferred {
+    ptr: *mut u8,
+    layout: Layout,
+}
+
+/// Frees not yet passed to the inner allocator.
+struct Pending<const BATCH: usize> {
+    entries: [Deferred; BATCH],
+    len: usize,
+}
+
+/// The pointers are only passed to the inner allocator, which owns the memory they point to.
+unsafe impl<const BATCH: usize> Send for Pending<BATCH> {}
+
+/// A thread safe allocator, like [LockedAllocator](crate::LockedAllocator), which batches frees.
+///
+/// `dealloc` only records the allocation in a buffer of `BATCH` entries, with its own lock held just long enough to do that.
+/// The inner allocator's lock is taken to free the whole batch at once when the buffer fills, when [DeferredFreeAllocator::flush] is called,
+/// and by `alloc` (which frees the batch first, so the memory can be reused), so workloads which free more than they allocate lock the inner allocator less often.
+/// Until then, the memory stays allocated.
+///
+/// `no_std` has no thread locals, so there is one buffer for all threads.
+/// Dropping the allocator flushes it.
+pub struct DeferredFreeAllocator<T: GlobalAlloc, const BATCH: usize> {
+    inner: Lock<T>,
+    pending: Lock<Pending<BATCH>>,
+}
+
+impl<T: GlobalAlloc, const BATCH: usize> DeferredFreeAllocator<T, BATCH> {
+    pub const fn new(inner: T) -> Self {
+        DeferredFreeAllocator {
+            inner: Lock::new(inner),
+            pending: Lock::new(Pending {
+                entries: [Deferred {
+                    ptr: null_mut(),
+                    layout: Layout::new::<u8>(),
+                }; BATCH],
+                len: 0,
+            }),
+        }
+    }
+
+    /// Frees all the batched allocations.
+    pub fn flush(&self) {
+        let mut pending = self.pending.lock();
+        if pending.len > 0 {
+            Self::free_pending(&mut pending, &mut self.inner.lock());
+        }
+    }
+
+    /// Number of frees batched, and not yet passed to the inner allocator.
+    pub fn pending(&self) -> usize {
+        self.pending.lock().len
+    }
+
+    /// Flushes, then locks the inner allocator, for access to functionality beyond [GlobalAlloc].
+    pub fn lock(&self) -> LockGuard<'_, T> {
+        // Lock in the same order as everything else: pending first.
+        let mut pending = self.pending.lock();
+        let mut inner = self.inner.lock();
+        Self::free_pending(&mut pending, &mut inner);
+        inner
+    }
+
+    /// Frees everything in `pending` to `inner`.
+    fn free_pending(pending: &mut Pending<BATCH>, inner: &mut T) {
+        for deferred in &pending.entries[..pending.len] {
+            // Safety: each entry was passed to `dealloc`, which has the same requirements.
+            unsafe { inner.dealloc(deferred.ptr, deferred.layout) };
+        }
+        pending.len = 0;
+    }
+}
+
+unsafe impl<T: GlobalAlloc, const BATCH: usize> GlobalAlloc for DeferredFreeAllocator<T, BATCH> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        nonzero_size_assert!(layout.size());
+        self.lock().alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        nonzero_size_assert!(layout.size());
+        let mut pending = self.pending.lock();
+        if BATCH == 0 {
+            // Nowhere to batch it.
+            self.inner.lock().dealloc(ptr, layout);
+            return;
+        }
+        let len = pending.len;
+        pending.entries[len] = Deferred { ptr, layout };
+        pending.len += 1;
+        if pending.len == BATCH {
+            Self::free_pending(&mut pending, &mut self.inner.lock());
+        }
+    }
+
+    /// Zeroes after unlocking, so other threads are not kept waiting.
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        zeroed(self.alloc(layout), layout.size())
+    }
+}
+
+/// Flushes first, so batched frees count as free.
+impl<T: GlobalAlloc + AllocatorStats, const BATCH: usize> AllocatorStats
+    for DeferredFreeAllocator<T, BATCH>
+{
+    fn stats(&self) -> Stats {
+        self.lock().stats()
+    }
+}
+
+impl<T: GlobalAlloc, const BATCH: usize> Drop for DeferredFreeAllocator<T, BATCH> {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DeferredFreeAllocator;
+    use crate::{
+        testing::{conformance, Counted, MockGrower},
+        FreeListAllocator,
+    };
+    use alloc::vec::Vec;
+    use core::alloc::{GlobalAlloc, Layout};
+
+    #[test]
+    fn deferred_free_conformance() {
+        conformance(&DeferredFreeAllocator::<_, 8>::new(
+            FreeListAllocator::new_in(MockGrower::new()),
+        ));
+        conformance(&DeferredFreeAllocator::<_, 0>::new(
+            FreeListAllocator::new_in(MockGrower::new()),
+        ));
+    }
+
+    #[test]
+    fn reusable_after_flush() {
+        let allocator =
+            DeferredFreeAllocator::<_, 4>::new(FreeListAllocator::new_in(MockGrower::new()));
+        let layout = Layout::from_size_align(100, 8).unwrap();
+        unsafe {
+            let ptr = allocator.alloc(layout);
+            allocator.dealloc(ptr, layout);
+            assert_eq!(allocator.pending(), 1);
+            // Still allocated until flushed.
+            assert!(!allocator.inner.lock().is_free(ptr, layout.size()));
+            allocator.flush();
+            assert_eq!(allocator.pending(), 0);
+            assert!(allocator.inner.lock().is_free(ptr, layout.size()));
+
+            // Allocating flushes too.
+            let again = allocator.alloc(layout);
+            assert_eq!(again, ptr);
+            allocator.dealloc(again, layout);
+            assert_eq!(allocator.alloc(layout), ptr);
+            assert_eq!(allocator.pending(), 0);
+        }
+    }
+
+    #[test]
+    fn flushes_when_full() {
+        let inner = Counted::new();
+        let allocator = DeferredFreeAllocator::<_, 4>::new(&inner);
+        let layout = Layout::new::<u64>();
+        unsafe {
+            let ptrs: Vec<_> = (0..10).map(|_| allocator.alloc(layout)).collect();
+            for (i, &ptr) in ptrs.iter().enumerate() {
+                allocator.dealloc(ptr, layout);
+                let freed = i + 1;
+                // Each time the buffer fills, the whole batch is freed.
+                assert_eq!(allocator.pending(), freed % 4);
+                assert_eq!(inner.live(), 10 - (freed - freed % 4));
+            }
+        }
+    }
+
+    #[test]
+    fn drop_flushes() {
+        let inner = Counted::new();
+        let allocator = DeferredFreeAllocator::<_, 4>::new(&inner);
+        let layout = Layout::new::<u64>();
+        unsafe {
+            let ptr = allocator.alloc(layout);
+            allocator.dealloc(ptr, layout);
+        }
+        assert_eq!(inner.live(), 1);
+        drop(allocator);
+        assert_eq!(inner.live(), 0);
+    }
+
+    #[test]
+    fn shared_between_threads() {
+        let allocator =
+            DeferredFreeAllocator::<_, 16>::new(FreeListAllocator::new_in(MockGrower::new()));
+        let layout = Layout::from_size_align(32, 8).unwrap();
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for _ in 0..1000 {
+                        unsafe {
+                            let ptr = allocator.alloc(layout);
+                            assert!(!ptr.is_null());
+                            allocator.dealloc(ptr, layout);
+                        }
+                    }
+                });
+            }
+        });
+        allocator.flush();
+        allocator.inner.lock().validate_heap();
+    }
+}
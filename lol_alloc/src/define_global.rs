@@ -0,0 +1,105 @@
+use crate::{CountsAllocations, Stats};
+
+/// Defines the `#[global_allocator]` static, and exports functions for the host to inspect and control it.
+///
+/// Takes the static (like a normal `static` item), followed by any number of `export` lines:
+///
+/// - `export stats;`: exports a `lol_alloc_<field>() -> usize` function for each field of [Stats]
+///   (like `lol_alloc_live_bytes`), returning `usize::MAX` if the allocator does not know it.
+///   Requires an allocator which counts allocations (see [CountsAllocations]),
+///   like one wrapped in `lol_alloc_diagnostics::CountingAllocator`.
+/// - `export reset(free_list);`: exports `lol_alloc_reset_heap`, like [export_reset_heap!](crate::export_reset_heap).
+///   Takes an expression evaluating to the [FreeListAllocator](crate::FreeListAllocator) to reset, and requires the `reset-heap` feature.
+/// - `export set_limit(grower);`: exports `lol_alloc_set_limit_pages`, like [export_set_limit_pages!](crate::export_set_limit_pages).
+///   Takes an expression evaluating to the [MaxPagesGrower](crate::MaxPagesGrower).
+///
+/// Requesting an export the allocator can't support is a compile error. For example:
+///
+/// ```ignore
+/// static GROWER: MaxPagesGrower = MaxPagesGrower::new(DefaultGrower, 100);
+///
+/// lol_alloc::define_global! {
+///     static ALLOCATOR: LockedAllocator<CountingAllocator<FreeListAllocator<&MaxPagesGrower>>> =
+///         LockedAllocator::new(CountingAllocator::new(FreeListAllocator::new_in(&GROWER)));
+///     export stats;
+///     export reset(ALLOCATOR.lock().inner());
+///     export set_limit(GROWER);
+/// }
+/// ```
+#[macro_export]
+macro_rules! define_global {
+    (
+        $(#[$attr:meta])*
+        $vis:vis static $name:ident: $ty:ty = $init:expr;
+        $(export $export:ident $(($($arg:tt)*))?;)*
+    ) => {
+        $(#[$attr])*
+        #[global_allocator]
+        $vis static $name: $ty = $init;
+
+        $($crate::define_global!(@export $name, $export $(($($arg)*))?);)*
+    };
+    (@export $name:ident, stats) => {
+        $crate::define_global!(@stat $name, lol_alloc_live_bytes, live_bytes);
+        $crate::define_global!(@stat $name, lol_alloc_live_allocations, live_allocations);
+        $crate::define_global!(@stat $name, lol_alloc_free_bytes, free_bytes);
+        $crate::define_global!(@stat $name, lol_alloc_free_blocks, free_blocks);
+        $crate::define_global!(@stat $name, lol_alloc_heap_bytes, heap_bytes);
+        $crate::define_global!(@stat $name, lol_alloc_peak_bytes, peak_bytes);
+        $crate::define_global!(@stat $name, lol_alloc_failed_allocations, failed_allocations);
+    };
+    (@export $name:ident, reset($free_list:expr)) => {
+        $crate::__define_global_reset!($free_list);
+    };
+    (@export $name:ident, set_limit($grower:expr)) => {
+        $crate::export_set_limit_pages!($grower);
+    };
+    (@export $name:ident, $export:ident $($args:tt)*) => {
+        ::core::compile_error!(::core::concat!(
+            "unknown define_global! export `",
+            ::core::stringify!($export $($args)*),
+            "`: expected `stats`, `reset(free_list)` or `set_limit(grower)`"
+        ));
+    };
+    (@stat $name:ident, $function:ident, $field:ident) => {
+        #[doc = ::core::concat!(
+            "The global allocator's `Stats::",
+            ::core::stringify!($field),
+            "`, or `usize::MAX` if it is not known."
+        )]
+        #[unsafe(no_mangle)]
+        pub extern "C" fn $function() -> usize {
+            $crate::__counted_stats(&$name)
+                .$field
+                .unwrap_or(usize::MAX)
+        }
+    };
+}
+
+/// `export reset` for [define_global!].
+#[cfg(feature = "reset-heap")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __define_global_reset {
+    ($free_list:expr) => {
+        $crate::export_reset_heap!($free_list);
+    };
+}
+
+/// `export reset` for [define_global!], without the `reset-heap` feature it needs.
+#[cfg(not(feature = "reset-heap"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __define_global_reset {
+    ($free_list:expr) => {
+        ::core::compile_error!(
+            "define_global! `export reset` requires lol_alloc's `reset-heap` feature"
+        );
+    };
+}
+
+/// The stats `export stats` in [define_global!] reports, which only compiles if the allocator counts allocations.
+#[doc(hidden)]
+pub fn __counted_stats<T: CountsAllocations>(allocator: &T) -> Stats {
+    allocator.stats()
+}
@@ -0,0 +1,116 @@
+use crate::{MemoryGrower, PageCount};
+use spin::Once;
+
+/// Grower which forwards to a grower chosen at runtime, for allocators in statics (which must be constructed in a const context).
+///
+/// It starts out forwarding to `default`, and [DynGrower::set] can replace that once,
+/// for example with a [MaxPagesGrower](crate::MaxPagesGrower) whose limit comes from host configuration,
+/// or with a recording grower in debug builds, without changing the allocator's type.
+/// Memory already grown stays in the allocator's heap: only later growth comes from the new grower.
+///
+/// Each grow costs an atomic load and an indirect call (see [MemoryGrower]).
+pub struct DynGrower {
+    default: &'static (dyn MemoryGrower + Sync),
+    chosen: Once<&'static (dyn MemoryGrower + Sync)>,
+}
+
+impl DynGrower {
+    pub const fn new(default: &'static (dyn MemoryGrower + Sync)) -> Self {
+        DynGrower {
+            default,
+            chosen: Once::new(),
+        }
+    }
+
+    /// Makes all future growth use `grower`.
+    ///
+    /// Returns false (changing nothing) if a grower has already been set.
+    pub fn set(&self, grower: &'static (dyn MemoryGrower + Sync)) -> bool {
+        let mut set = false;
+        self.chosen.call_once(|| {
+            set = true;
+            grower
+        });
+        set
+    }
+
+    /// The grower currently forwarded to.
+    fn current(&self) -> &'static (dyn MemoryGrower + Sync) {
+        self.chosen.get().copied().unwrap_or(self.default)
+    }
+}
+
+impl MemoryGrower for DynGrower {
+    fn memory_grow(&self, delta: PageCount) -> PageCount {
+        self.current().memory_grow(delta)
+    }
+
+    fn current_pages(&self) -> PageCount {
+        self.current().current_pages()
+    }
+
+    fn memory_grow_partial(&self, delta: PageCount) -> (PageCount, PageCount) {
+        self.current().memory_grow_partial(delta)
+    }
+
+    /// Memory grown before [DynGrower::set] came from `default`, so both must zero it.
+    fn grows_zeroed(&self) -> bool {
+        self.default.grows_zeroed() && self.current().grows_zeroed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DynGrower;
+    use crate::{
+        testing::{conformance, MockGrower},
+        FreeListAllocator, GlobalArrayGrower, LockedAllocator, MemoryGrower, PageCount,
+        ERROR_PAGE_COUNT,
+    };
+    use alloc::boxed::Box;
+
+    /// Conformance through a `dyn MemoryGrower` chosen at runtime, for each choice.
+    #[test]
+    fn dyn_grower_conformance() {
+        for choose_second in [false, true] {
+            let first: &'static MockGrower = Box::leak(Box::new(MockGrower::new()));
+            let second: &'static MockGrower = Box::leak(Box::new(MockGrower::new()));
+            let grower: &'static dyn MemoryGrower = if choose_second { second } else { first };
+            let allocator = FreeListAllocator::new_in(grower);
+            conformance(&allocator);
+            allocator.validate_heap();
+            let (chosen, other) = if choose_second {
+                (second, first)
+            } else {
+                (first, second)
+            };
+            assert!(chosen.used_pages() > 0);
+            assert_eq!(other.used_pages(), 0);
+        }
+    }
+
+    #[test]
+    fn set_once() {
+        static DEFAULT: GlobalArrayGrower<1> = GlobalArrayGrower::new();
+        static CHOSEN: GlobalArrayGrower<32> = GlobalArrayGrower::new();
+        static GROWER: DynGrower = DynGrower::new(&DEFAULT);
+        static ALLOCATOR: LockedAllocator<FreeListAllocator<&DynGrower>> =
+            LockedAllocator::new(FreeListAllocator::new_in(&GROWER));
+
+        let first_page = CHOSEN.current_pages();
+        assert!(GROWER.set(&CHOSEN));
+        assert!(!GROWER.set(&DEFAULT));
+        conformance(&ALLOCATOR);
+        ALLOCATOR.lock().validate_heap();
+        // All the memory came from the chosen grower.
+        assert_eq!(GROWER.current_pages(), CHOSEN.current_pages());
+        assert!(CHOSEN.current_pages().0 > first_page.0);
+        // The default never grew, so still has its one page.
+        assert_ne!(DEFAULT.memory_grow(PageCount(1)), ERROR_PAGE_COUNT);
+    }
+
+    crate::check_grower!(
+        dyn_grower_conformance_kit,
+        DynGrower::new(Box::leak(Box::new(MockGrower::with_pages(64))))
+    );
+}
@@ -0,0 +1,21 @@
+use core::alloc::{GlobalAlloc, Layout};
+
+/// An allocator which can report how much of an allocation is usable, which is often more than was requested.
+///
+/// This is a stable alternative to the unstable `Allocator` API returning the granted length,
+/// so callers like growable buffers can use the slack instead of reallocating.
+///
+/// Wrappers which track the bytes allocated (like [crate::BudgetAllocator]) do not implement this,
+/// since frees with a larger size than was allocated would throw off their accounting.
+pub trait AllocWithExcess: GlobalAlloc {
+    /// Like [GlobalAlloc::alloc], but also returns the usable size of the allocation,
+    /// which is at least `layout.size()`, or 0 if the allocation failed.
+    ///
+    /// All of the usable size can be written to,
+    /// and the allocation can be freed (or reallocated) using `layout` with its size changed to anything from `layout.size()` up to the usable size.
+    ///
+    /// # Safety
+    ///
+    /// Same as [GlobalAlloc::alloc]: in particular `layout` must not be zero sized.
+    unsafe fn alloc_with_excess(&self, layout: Layout) -> (*mut u8, usize);
+}
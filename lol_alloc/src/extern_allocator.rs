@@ -0,0 +1,184 @@
+use crate::{AllocatorStats, Stats};
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    ffi::c_void,
+    mem::size_of,
+    ptr::null_mut,
+};
+
+/// Signature of C's `malloc`.
+pub type MallocFn = unsafe extern "C" fn(size: usize) -> *mut c_void;
+/// Signature of C's `free`.
+pub type FreeFn = unsafe extern "C" fn(ptr: *mut c_void);
+/// Signature of C's `aligned_alloc`.
+pub type AlignedAllocFn = unsafe extern "C" fn(alignment: usize, size: usize) -> *mut c_void;
+
+/// Allocator which uses a heap owned by someone else (like the host, or a C runtime linked into the module),
+/// through its C style `malloc` and `free` functions.
+///
+/// This allows using this crate's wrappers (and `lol_alloc_diagnostics`) with that heap.
+/// Declare the functions, and pass them in:
+///
+/// ```ignore
+/// extern "C" {
+///     fn malloc(size: usize) -> *mut core::ffi::c_void;
+///     fn free(ptr: *mut core::ffi::c_void);
+/// }
+///
+/// #[global_allocator]
+/// static ALLOCATOR: ExternAllocator = unsafe { ExternAllocator::new(malloc, free) };
+/// ```
+///
+/// `malloc` is assumed to align allocations to [ExternAllocator::MALLOC_ALIGN], like C allocators generally do.
+/// Larger alignments use `aligned_alloc` if one was given to [ExternAllocator::with_aligned_alloc].
+/// Otherwise they over-allocate from `malloc`, and store the pointer `malloc` returned in the word before the allocation, so it can be freed.
+#[derive(Clone, Copy)]
+pub struct ExternAllocator {
+    malloc: MallocFn,
+    free: FreeFn,
+    aligned_alloc: Option<AlignedAllocFn>,
+}
+
+impl ExternAllocator {
+    /// Alignment `malloc` is assumed to provide: two words (8 bytes on wasm32).
+    pub const MALLOC_ALIGN: usize = 2 * size_of::<usize>();
+
+    /// Creates an allocator which allocates with `malloc` and frees with `free`.
+    ///
+    /// # Safety
+    ///
+    /// `malloc` and `free` must behave like C's: `malloc` returns null or a new allocation of at least `size` bytes aligned to [ExternAllocator::MALLOC_ALIGN],
+    /// and `free` frees allocations from `malloc`.
+    /// They must be safe to call from any thread this allocator is used from.
+    pub const unsafe fn new(malloc: MallocFn, free: FreeFn) -> Self {
+        ExternAllocator {
+            malloc,
+            free,
+            aligned_alloc: None,
+        }
+    }
+
+    /// Like [ExternAllocator::new], but uses `aligned_alloc` for allocations needing more alignment than `malloc` provides.
+    ///
+    /// # Safety
+    ///
+    /// As for [ExternAllocator::new], and `aligned_alloc` must behave like C's,
+    /// with `free` also freeing its allocations.
+    pub const unsafe fn with_aligned_alloc(
+        malloc: MallocFn,
+        free: FreeFn,
+        aligned_alloc: AlignedAllocFn,
+    ) -> Self {
+        ExternAllocator {
+            malloc,
+            free,
+            aligned_alloc: Some(aligned_alloc),
+        }
+    }
+
+    /// If `malloc` alone aligns allocations for `layout` well enough.
+    fn malloc_aligns(layout: Layout) -> bool {
+        layout.align() <= Self::MALLOC_ALIGN
+    }
+}
+
+unsafe impl GlobalAlloc for ExternAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        nonzero_size_assert!(layout.size());
+        // C allocators may return null for 0 bytes.
+        let size = layout.size().max(1);
+        if Self::malloc_aligns(layout) {
+            return (self.malloc)(size) as *mut u8;
+        }
+        if let Some(aligned_alloc) = self.aligned_alloc {
+            // C requires the size to be a multiple of the alignment.
+            let Some(size) = size.checked_next_multiple_of(layout.align()) else {
+                return null_mut();
+            };
+            return aligned_alloc(layout.align(), size) as *mut u8;
+        }
+        // Since `malloc` aligns to MALLOC_ALIGN (which divides the alignment),
+        // the first aligned address at least a word in is at most `align` bytes in.
+        let Some(padded) = size.checked_add(layout.align()) else {
+            return null_mut();
+        };
+        let original = (self.malloc)(padded) as *mut u8;
+        if original.is_null() {
+            return original;
+        }
+        let ptr = original.add(size_of::<usize>());
+        let ptr = ptr.add(ptr.align_offset(layout.align()));
+        (ptr as *mut *mut u8).sub(1).write(original);
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        nonzero_size_assert!(layout.size());
+        let original = if Self::malloc_aligns(layout) || self.aligned_alloc.is_some() {
+            ptr
+        } else {
+            (ptr as *mut *mut u8).sub(1).read()
+        };
+        (self.free)(original as *mut c_void);
+    }
+}
+
+/// The external heap does not report anything.
+impl AllocatorStats for ExternAllocator {
+    fn stats(&self) -> Stats {
+        Stats::default()
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::ExternAllocator;
+    use crate::{testing::conformance, LockedAllocator};
+    use core::{
+        alloc::{GlobalAlloc, Layout},
+        ffi::c_void,
+    };
+    use std::sync::Mutex;
+
+    /// Sizes requested from [recording_malloc].
+    static MALLOC_SIZES: Mutex<std::vec::Vec<usize>> = Mutex::new(std::vec::Vec::new());
+
+    unsafe extern "C" fn recording_malloc(size: usize) -> *mut c_void {
+        MALLOC_SIZES.lock().unwrap().push(size);
+        libc::malloc(size)
+    }
+
+    #[test]
+    fn conforms() {
+        let allocator = unsafe { ExternAllocator::new(libc::malloc, libc::free) };
+        conformance(&allocator);
+        conformance(&LockedAllocator::new(allocator));
+    }
+
+    #[test]
+    fn conforms_with_aligned_alloc() {
+        conformance(&unsafe {
+            ExternAllocator::with_aligned_alloc(libc::malloc, libc::free, libc::aligned_alloc)
+        });
+    }
+
+    #[test]
+    fn over_aligned_fallback() {
+        let allocator = unsafe { ExternAllocator::new(recording_malloc, libc::free) };
+        let layout = Layout::from_size_align(100, 64).unwrap();
+        unsafe {
+            let ptrs: std::vec::Vec<*mut u8> = (0..10).map(|_| allocator.alloc(layout)).collect();
+            for &ptr in &ptrs {
+                assert_eq!(ptr as usize % 64, 0);
+                ptr.write_bytes(0xFF, layout.size());
+                // The pointer from malloc is stored just before the allocation, within what was requested.
+                let original = (ptr as *const usize).sub(1).read();
+                assert!(original < ptr as usize && ptr as usize + 100 <= original + 164);
+            }
+            for ptr in ptrs {
+                allocator.dealloc(ptr, layout);
+            }
+        }
+        assert_eq!(*MALLOC_SIZES.lock().unwrap(), [164; 10]);
+    }
+}
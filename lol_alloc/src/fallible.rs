@@ -0,0 +1,52 @@
+//! Collections allocated from the global allocator without panicking or aborting when allocation fails, for the `fallible` feature.
+//!
+//! `Box::new` and `Vec::with_capacity` call `handle_alloc_error` when the global allocator returns null, which aborts.
+//! These allocate with [alloc::alloc::alloc] instead, returning [AllocError] for null, so they work with any lol_alloc stack
+//! which reports failure by returning null (like a [FreeListAllocator](crate::FreeListAllocator) on a [MaxPagesGrower](crate::MaxPagesGrower)).
+//!
+//! Allocators which stop the program instead of returning null (like [BudgetAllocator](crate::BudgetAllocator) over its budget,
+//! or anything trapping on failure) are not made fallible by this: they still stop the program.
+
+use crate::AllocError;
+use alloc::{alloc::alloc as global_alloc, boxed::Box, string::String, vec::Vec};
+use core::alloc::Layout;
+
+/// Like `Box::new`, but returns an error if allocation fails.
+pub fn try_box<T>(value: T) -> Result<Box<T>, AllocError> {
+    let layout = Layout::new::<T>();
+    if layout.size() == 0 {
+        // Zero sized boxes do not allocate.
+        return Ok(Box::new(value));
+    }
+    let ptr = unsafe { global_alloc(layout) } as *mut T;
+    if ptr.is_null() {
+        return Err(AllocError);
+    }
+    unsafe {
+        ptr.write(value);
+        // Safety: allocated by the global allocator with the layout of `T`, as `Box` requires.
+        Ok(Box::from_raw(ptr))
+    }
+}
+
+/// Like `Vec::with_capacity`, but returns an error if allocation fails.
+///
+/// The capacity is exactly `capacity` (unless `T` is zero sized).
+pub fn try_vec_with_capacity<T>(capacity: usize) -> Result<Vec<T>, AllocError> {
+    let layout = Layout::array::<T>(capacity).map_err(|_| AllocError)?;
+    if layout.size() == 0 {
+        return Ok(Vec::new());
+    }
+    let ptr = unsafe { global_alloc(layout) } as *mut T;
+    if ptr.is_null() {
+        return Err(AllocError);
+    }
+    // Safety: allocated by the global allocator with the layout of `capacity` `T`s, as `Vec` requires.
+    Ok(unsafe { Vec::from_raw_parts(ptr, 0, capacity) })
+}
+
+/// Like `String::with_capacity`, but returns an error if allocation fails.
+pub fn try_string_with_capacity(capacity: usize) -> Result<String, AllocError> {
+    // Safety: empty, so valid UTF-8.
+    try_vec_with_capacity(capacity).map(|bytes| unsafe { String::from_utf8_unchecked(bytes) })
+}
@@ -1,4 +1,9 @@
-use super::{DefaultGrower, MemoryGrower, PageCount, ERROR_PAGE_COUNT, PAGE_SIZE};
+#[cfg(feature = "large-objects")]
+use super::ERROR_PAGE_COUNT;
+use super::{
+    AllocWithExcess, AllocatorStats, DefaultGrower, MemoryGrower, PageCount, Stats, PAGE_SIZE,
+};
+use crate::{asan, live_blocks::LiveList, size_check, zero::zeroed};
 use core::{
     alloc::{GlobalAlloc, Layout},
     cell::UnsafeCell,
@@ -9,140 +14,780 @@ use core::{
 /// Allocations and frees have runtime O(length of free list).
 ///
 /// The free list is kept sorted by address, and adjacent blocks of memory are coalesced when inserting new blocks.
-pub struct FreeListAllocator<T = DefaultGrower> {
+///
+/// `SENTINEL` is the address used to mark the end of the free list, so it must never be the address of free memory.
+/// The default of `usize::MAX` is never a multiple of the node alignment, so it can never collide.
+/// Use [FreeListAllocator::with_sentinel] to create an allocator with a different one.
+///
+/// If `SKIP_WASTEFUL` is true, searching the free list skips blocks where aligning an allocation would leave more than half the block unused.
+/// This avoids filling the free list with small fragments from over-aligned allocations, at the cost of growing memory sooner.
+///
+/// With the `jitter` feature, [FreeListAllocator::with_jitter_seed] makes allocations be placed up to [JITTER_MAX_NODES] nodes lower than they otherwise would be,
+/// chosen by a PRNG.
+///
+/// With the `large-objects` feature, [FreeListAllocator::with_large_object_threshold] makes large allocations use whole runs of pages,
+/// kept in a separate free list (the page pool) so they never fragment the free list used by smaller allocations.
+///
+/// With the `asan` feature, free memory is poisoned for AddressSanitizer,
+/// except the first node of each freed block, which may hold the allocator's own bookkeeping.
+///
+/// If `STATIC_CHUNK` is not 0, the allocator holds that many bytes of memory inside itself,
+/// which are added to the free list by the first allocation, so small workloads never need to grow memory.
+/// See [FreeListAllocator::new_with_static_chunk_in].
+///
+/// Alignments larger than [PAGE_SIZE] are supported: growing for them adds enough pages to align the allocation within the grown memory,
+/// and the padding on either side stays in the free list.
+///
+/// If the grower [grows zeroed memory](MemoryGrower::grows_zeroed), `alloc_zeroed` only zeroes the part of the allocation
+/// below the highest address the allocator has used, since everything above that is still zero.
+pub struct FreeListAllocator<
+    T = DefaultGrower,
+    const SENTINEL: usize = DEFAULT_SENTINEL,
+    const SKIP_WASTEFUL: bool = false,
+    const STATIC_CHUNK: usize = 0,
+> {
     free_list: UnsafeCell<*mut FreeListNode>,
-    grower: T,
+    pub(crate) grower: T,
+    /// Largest number of nodes observed in the free list.
+    #[cfg(feature = "peak-free-list-len")]
+    peak_free_list_len: UnsafeCell<usize>,
+    /// Start and end address of the most recent contiguous run of grown memory.
+    #[cfg(feature = "reset-heap")]
+    extent: UnsafeCell<(usize, usize)>,
+    /// State of the PRNG choosing how much to move allocations down by, or 0 if jitter is disabled.
+    #[cfg(feature = "jitter")]
+    jitter_state: UnsafeCell<u64>,
+    /// Free list of page runs freed by large allocations.
+    #[cfg(feature = "large-objects")]
+    page_pool: UnsafeCell<*mut FreeListNode>,
+    /// Allocations of at least this many bytes use the page pool.
+    #[cfg(feature = "large-objects")]
+    large_object_threshold: usize,
+    /// Live allocations, if enabled by [FreeListAllocator::new_with_live_blocks_in].
+    live_blocks: LiveList,
+    /// Memory used before growing, added to the free list by the first allocation.
+    static_chunk: UnsafeCell<[u8; STATIC_CHUNK]>,
+    /// If `static_chunk` has been added to the free list.
+    static_chunk_seeded: UnsafeCell<bool>,
+    /// End of the highest memory which has been allocated or had a free list node written to it.
+    /// Memory above this has not been written since it was grown (or since the allocator was created, for the static chunk).
+    high_water: UnsafeCell<usize>,
+    /// Free list nodes visited by searches, for tests checking how much of the list operations walk.
+    #[cfg(test)]
+    nodes_searched: UnsafeCell<usize>,
 }
 
 #[cfg(target_arch = "wasm32")]
 impl FreeListAllocator<DefaultGrower> {
     pub const fn new() -> Self {
+        FreeListAllocator::new_in(DefaultGrower)
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Default for FreeListAllocator<DefaultGrower> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> FreeListAllocator<T> {
+    /// Creates an allocator which gets its memory from `grower`.
+    pub const fn new_in(grower: T) -> Self {
+        FreeListAllocator::new_with_sentinel_in(grower)
+    }
+}
+
+impl<T> FreeListAllocator<T> {
+    /// Creates an allocator which gets its memory from `grower`,
+    /// and tracks live allocations so they can be enumerated with [FreeListAllocator::for_each_live_block].
+    ///
+    /// Each allocation gets a hidden three word header, holding its size and links to the allocations before and after it in a list.
+    /// Allocating and freeing update the list in O(1).
+    #[cfg(feature = "live-blocks")]
+    pub const fn new_with_live_blocks_in(grower: T) -> Self {
+        let mut allocator = FreeListAllocator::new_in(grower);
+        allocator.live_blocks = LiveList::enabled();
+        allocator
+    }
+}
+
+impl<T, const STATIC_CHUNK: usize> FreeListAllocator<T, DEFAULT_SENTINEL, false, STATIC_CHUNK> {
+    /// Creates an allocator which first uses the `STATIC_CHUNK` bytes stored inside itself,
+    /// and only gets memory from `grower` once those are used up.
+    ///
+    /// Useful for reducing cold start latency: an instance which never allocates more than the static chunk never grows memory.
+    /// When used as a static, the chunk is zero initialized data, so it does not increase the size of the binary.
+    /// Memory from the static chunk is never coalesced with grown memory.
+    ///
+    /// # Safety
+    ///
+    /// The allocator must not be moved after its first allocation. In practice this means only using it in a `static`.
+    pub const unsafe fn new_with_static_chunk_in(grower: T) -> Self {
+        FreeListAllocator::new_with_sentinel_in(grower)
+    }
+}
+
+/// A [FreeListAllocator] with a static chunk of `N` bytes, created by [FreeListAllocator::new_with_static_chunk_in].
+pub type StaticChunkFreeListAllocator<const N: usize, T = DefaultGrower> =
+    FreeListAllocator<T, DEFAULT_SENTINEL, false, N>;
+
+impl<T> FreeListAllocator<T, DEFAULT_SENTINEL, true> {
+    /// Creates an allocator with `SKIP_WASTEFUL` enabled, which gets its memory from `grower`.
+    pub const fn new_skip_wasteful_in(grower: T) -> Self {
+        FreeListAllocator::new_with_sentinel_in(grower)
+    }
+}
+
+impl<T, const SENTINEL: usize, const SKIP_WASTEFUL: bool, const STATIC_CHUNK: usize>
+    FreeListAllocator<T, SENTINEL, SKIP_WASTEFUL, STATIC_CHUNK>
+{
+    /// Marks the end of the free list.
+    const EMPTY: *mut FreeListNode = SENTINEL as *mut FreeListNode;
+
+    /// Creates an allocator which gets its memory from `grower`, using a custom `SENTINEL`.
+    ///
+    /// Checks (in debug builds) that `SENTINEL` is above all the memory `grower` has provided so far.
+    pub fn with_sentinel(grower: T) -> Self
+    where
+        T: MemoryGrower,
+    {
+        lol_assert!(
+            grower
+                .current_pages()
+                .size_in_bytes()
+                .is_some_and(|size| size < SENTINEL),
+            "SENTINEL must not be the address of any memory"
+        );
+        FreeListAllocator::new_with_sentinel_in(grower)
+    }
+
+    const fn new_with_sentinel_in(grower: T) -> Self {
         FreeListAllocator {
             // Use a special value for empty, which is never valid otherwise.
-            free_list: UnsafeCell::new(EMPTY_FREE_LIST),
-            grower: DefaultGrower,
+            free_list: UnsafeCell::new(Self::EMPTY),
+            grower,
+            #[cfg(feature = "peak-free-list-len")]
+            peak_free_list_len: UnsafeCell::new(0),
+            #[cfg(feature = "reset-heap")]
+            extent: UnsafeCell::new((0, 0)),
+            #[cfg(feature = "jitter")]
+            jitter_state: UnsafeCell::new(0),
+            #[cfg(feature = "large-objects")]
+            page_pool: UnsafeCell::new(Self::EMPTY),
+            #[cfg(feature = "large-objects")]
+            large_object_threshold: usize::MAX,
+            live_blocks: LiveList::new(),
+            static_chunk: UnsafeCell::new([0; STATIC_CHUNK]),
+            static_chunk_seeded: UnsafeCell::new(false),
+            high_water: UnsafeCell::new(0),
+            #[cfg(test)]
+            nodes_searched: UnsafeCell::new(0),
         }
     }
+
+    /// Enables jitter, using `seed` to choose where allocations are placed.
+    ///
+    /// Different seeds give different addresses for the same sequence of operations,
+    /// which helps find code that depends on allocation addresses, or on allocations being contiguous.
+    #[cfg(feature = "jitter")]
+    pub const fn with_jitter_seed(mut self, seed: u64) -> Self {
+        self.jitter_state = UnsafeCell::new(jitter_state(seed));
+        self
+    }
+
+    /// Makes allocations of at least `threshold` bytes use whole runs of pages, from the page pool or newly grown,
+    /// instead of the free list. Freeing them returns the whole run to the page pool.
+    ///
+    /// Each large allocation has a one word header, and is rounded up to whole pages,
+    /// so this trades some memory for a shorter and less fragmented free list.
+    /// [LARGE_OBJECT_THRESHOLD] is a reasonable default.
+    #[cfg(feature = "large-objects")]
+    pub const fn with_large_object_threshold(mut self, threshold: usize) -> Self {
+        self.large_object_threshold = threshold;
+        self
+    }
 }
 
-const EMPTY_FREE_LIST: *mut FreeListNode = usize::MAX as *mut FreeListNode;
+/// Default `SENTINEL` for [FreeListAllocator].
+const DEFAULT_SENTINEL: usize = usize::MAX;
+
+/// Suggested threshold for [FreeListAllocator::with_large_object_threshold].
+#[cfg(feature = "large-objects")]
+pub const LARGE_OBJECT_THRESHOLD: usize = 16 * 1024;
+
+/// Size of the header before each large allocation, which holds the number of pages in its run.
+#[cfg(feature = "large-objects")]
+const LARGE_HEADER_SIZE: usize = core::mem::size_of::<usize>();
+
+/// Most nodes an allocation is moved down by with the `jitter` feature.
+///
+/// The space skipped above an allocation stays in the free list (and so is counted in [Stats::free_bytes]),
+/// so this bounds the fragmentation jitter adds to `JITTER_MAX_NODES * NODE_SIZE` bytes per live allocation.
+#[cfg(feature = "jitter")]
+pub const JITTER_MAX_NODES: usize = 3;
+
+/// Initial PRNG state for `seed`: xorshift gets stuck at 0 (which also means disabled), so that seed is remapped.
+#[cfg(feature = "jitter")]
+const fn jitter_state(seed: u64) -> u64 {
+    if seed == 0 {
+        0x6C6F_6C5F_616C_6C6F
+    } else {
+        seed
+    }
+}
 
 /// Stored at the beginning of each free segment.
 /// Note: It would be possible to fit this in 1 word (use the low bit to flag that case,
 /// then only use a second word if the allocation has size greater than 1 word)
+///
+/// `next` is first, so a pointer to a node's `next` field is also a pointer to the node.
+#[repr(C)]
 struct FreeListNode {
     next: *mut FreeListNode,
     size: usize,
+    /// [FreeListNode::expected_checksum], updated whenever the node is changed.
+    #[cfg(feature = "node-checksums")]
+    checksum: usize,
+    /// Keeps [NODE_SIZE] a power of two, since it is used as an alignment.
+    #[cfg(feature = "node-checksums")]
+    _padding: usize,
+}
+
+pub(crate) const NODE_SIZE: usize = core::mem::size_of::<FreeListNode>();
+
+/// Mixed into node checksums, so zeroed memory does not look like a valid node.
+#[cfg(feature = "node-checksums")]
+const CHECKSUM_SALT: usize = 0x6C6F_6C5F_616C_6C6F_u64 as usize;
+
+#[cfg(feature = "node-checksums")]
+impl FreeListNode {
+    unsafe fn expected_checksum(node: *const FreeListNode) -> usize {
+        (*node).next as usize ^ (*node).size ^ CHECKSUM_SALT
+    }
+
+    /// Updates the checksum of `node`, after writing its other fields.
+    unsafe fn seal(node: *mut FreeListNode) {
+        (*node).checksum = Self::expected_checksum(node);
+    }
+
+    /// Checks the checksum of `node`, before using its other fields.
+    ///
+    /// Traps (with [corrupt_node]) if it does not match, which means something other than the allocator wrote to the node.
+    unsafe fn check(node: *const FreeListNode) {
+        if (*node).checksum != Self::expected_checksum(node) {
+            corrupt_node();
+        }
+    }
+
+    /// Points `link` (either `head` or the `next` field of a node) at `node`, updating the checksum of the node it is in.
+    unsafe fn set_link(
+        head: *mut *mut FreeListNode,
+        link: *mut *mut FreeListNode,
+        node: *mut FreeListNode,
+    ) {
+        *link = node;
+        if link != head {
+            Self::seal(link as *mut FreeListNode);
+        }
+    }
+}
+
+#[cfg(not(feature = "node-checksums"))]
+impl FreeListNode {
+    #[inline(always)]
+    unsafe fn seal(_node: *mut FreeListNode) {}
+
+    #[inline(always)]
+    unsafe fn check(_node: *const FreeListNode) {}
+
+    #[inline(always)]
+    unsafe fn set_link(
+        _head: *mut *mut FreeListNode,
+        link: *mut *mut FreeListNode,
+        node: *mut FreeListNode,
+    ) {
+        *link = node;
+    }
 }
 
-const NODE_SIZE: usize = core::mem::size_of::<FreeListNode>();
+/// Stops execution because a free list node's checksum does not match.
+/// On wasm32 this is the `unreachable` instruction.
+/// Other targets panic with a message distinct from other traps, so tests can tell them apart.
+#[cfg(feature = "node-checksums")]
+#[cold]
+fn corrupt_node() -> ! {
+    #[cfg(target_arch = "wasm32")]
+    core::arch::wasm32::unreachable();
+    #[cfg(not(target_arch = "wasm32"))]
+    panic!("lol_alloc free list node checksum mismatch");
+}
 
 // Safety: No one besides us has the raw pointer, so we can safely transfer the
 // FreeListAllocator to another thread.
-unsafe impl<T> Send for FreeListAllocator<T> {}
+unsafe impl<T, const SENTINEL: usize, const SKIP_WASTEFUL: bool, const STATIC_CHUNK: usize> Send
+    for FreeListAllocator<T, SENTINEL, SKIP_WASTEFUL, STATIC_CHUNK>
+{
+}
 
-unsafe impl<T: MemoryGrower> GlobalAlloc for FreeListAllocator<T> {
+unsafe impl<
+        T: MemoryGrower,
+        const SENTINEL: usize,
+        const SKIP_WASTEFUL: bool,
+        const STATIC_CHUNK: usize,
+    > GlobalAlloc for FreeListAllocator<T, SENTINEL, SKIP_WASTEFUL, STATIC_CHUNK>
+{
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        // This assumes PAGE_SIZE is always a multiple of the required alignment, which should be true for all practical use.
-        debug_assert!(PAGE_SIZE % layout.align() == 0);
+        nonzero_size_assert!(layout.size());
+        self.alloc_picking(layout, None::<fn(usize) -> usize>)
+    }
 
-        let size = full_size(layout);
-        let alignment = layout.align().max(NODE_SIZE);
-        let mut free_list: *mut *mut FreeListNode = self.free_list.get();
-        // search freelist
-        loop {
-            if *free_list == EMPTY_FREE_LIST {
-                break;
-            }
-            // Try to allocate from end of block of free space.
-            let size_of_block = (**free_list).size;
-            let start_of_block = *free_list as usize;
-            let end_of_block = start_of_block + size_of_block;
-            if size < end_of_block {
-                let position = multiple_below(end_of_block - size, alignment);
-                if position >= start_of_block {
-                    // Compute if we need a node after used space due to alignment.
-                    let end_of_used = position + size;
-                    if end_of_used < end_of_block {
-                        // Insert new block
-                        let new_block = end_of_used as *mut FreeListNode;
-                        (*new_block).next = *free_list;
-                        (*new_block).size = end_of_block - end_of_used;
-                        *free_list = new_block;
-                        free_list = ptr::addr_of_mut!((*new_block).next);
-                    }
-                    if position == start_of_block {
-                        // Remove current node from free list.
-                        *free_list = (**free_list).next;
-                    } else {
-                        // Shrink free block
-                        (**free_list).size = position - start_of_block;
-                    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        nonzero_size_assert!(layout.size());
+        // Layout was valid when allocated, so these are too.
+        let inner_layout = self.live_blocks.inner_layout(layout).unwrap_unchecked();
+        let checked_layout = size_check::inner_layout(inner_layout).unwrap_unchecked();
+        let raw = size_check::check(self.live_blocks.unlink(ptr, layout), inner_layout);
+        self.dealloc_unlisted(raw, checked_layout);
+    }
 
-                    let ptr = position as *mut u8;
-                    debug_assert!(ptr.align_offset(NODE_SIZE) == 0);
-                    debug_assert!(ptr.align_offset(layout.align()) == 0);
-                    return ptr;
-                }
-            }
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        nonzero_size_assert!(layout.size());
+        let (ptr, fresh) = self.alloc_tracking_fresh(layout, None::<fn(usize) -> usize>);
+        // Blocks from the free list may have been used before, but memory never used since it was grown is still zero.
+        let used = fresh.saturating_sub(ptr as usize).min(layout.size());
+        zeroed(ptr, used)
+    }
 
-            free_list = ptr::addr_of_mut!((**free_list).next);
+    /// Shrinks in place, and grows in place when the free block just after the allocation has room, otherwise moves it.
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        nonzero_size_assert!(layout.size(), new_size);
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        if self.resize_in_place(ptr, layout, new_layout) {
+            return ptr;
+        }
+        let new_ptr = self.alloc(new_layout);
+        if !new_ptr.is_null() {
+            ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+            self.dealloc(ptr, layout);
+        }
+        new_ptr
+    }
+}
+
+/// Reports the rounded up size of the block carved from the free list,
+/// or the rest of the run of pages for large allocations.
+/// With `live-blocks` enabled, frees must use the exact size, so there is no excess.
+impl<
+        T: MemoryGrower,
+        const SENTINEL: usize,
+        const SKIP_WASTEFUL: bool,
+        const STATIC_CHUNK: usize,
+    > AllocWithExcess for FreeListAllocator<T, SENTINEL, SKIP_WASTEFUL, STATIC_CHUNK>
+{
+    unsafe fn alloc_with_excess(&self, layout: Layout) -> (*mut u8, usize) {
+        let ptr = self.alloc(layout);
+        if ptr.is_null() {
+            return (ptr, 0);
+        }
+        (ptr, self.usable_size(layout))
+    }
+}
+
+impl<T, const SENTINEL: usize, const SKIP_WASTEFUL: bool, const STATIC_CHUNK: usize>
+    FreeListAllocator<T, SENTINEL, SKIP_WASTEFUL, STATIC_CHUNK>
+{
+    /// [GlobalAlloc::alloc], but if `pick` is given, and more than one free block fits the allocation,
+    /// uses the `pick(count)`th (modulo `count`) of the `count` blocks which fit, in address order, instead of the first.
+    pub(crate) unsafe fn alloc_picking(
+        &self,
+        layout: Layout,
+        pick: Option<impl FnOnce(usize) -> usize>,
+    ) -> *mut u8
+    where
+        T: MemoryGrower,
+    {
+        self.alloc_tracking_fresh(layout, pick).0
+    }
+
+    /// [Self::alloc_picking], also returning the address from which the allocation is known to still be zero
+    /// (`usize::MAX` if none of it is).
+    unsafe fn alloc_tracking_fresh(
+        &self,
+        layout: Layout,
+        pick: Option<impl FnOnce(usize) -> usize>,
+    ) -> (*mut u8, usize)
+    where
+        T: MemoryGrower,
+    {
+        let Some(inner_layout) = self.live_blocks.inner_layout(layout) else {
+            return (null_mut(), 0);
+        };
+        let Some(checked_layout) = size_check::inner_layout(inner_layout) else {
+            return (null_mut(), 0);
+        };
+        let block = self.alloc_unlisted(checked_layout, pick);
+        if block.is_null() {
+            return (block, 0);
+        }
+        let fresh = self.mark_allocated(block, self.block_size(checked_layout));
+        let fresh = if self.grower.grows_zeroed() {
+            fresh
+        } else {
+            usize::MAX
+        };
+        let raw = size_check::write(block, inner_layout);
+        (self.live_blocks.link(raw, layout), fresh)
+    }
+
+    /// Bytes from the start of an allocation for `layout` (from [Self::alloc_unlisted]) to the end of the memory reserved for it.
+    fn block_size(&self, layout: Layout) -> usize {
+        #[cfg(feature = "large-objects")]
+        if self.is_large(layout) {
+            return large_run(layout).map_or(0, |(offset, run_size)| run_size - offset);
+        }
+        full_size(layout)
+    }
+
+    /// Records that memory up to `end` has been used, so is no longer known to be zero.
+    #[inline(always)]
+    unsafe fn mark_used(&self, end: usize) {
+        let high_water = &mut *self.high_water.get();
+        *high_water = end.max(*high_water);
+    }
+
+    /// Marks the `size` bytes at `block`, just allocated, as used,
+    /// returning the address from which memory in the block has never been used (so is still zero if the grower grows zeroed memory).
+    pub(crate) unsafe fn mark_allocated(&self, block: *mut u8, size: usize) -> usize {
+        // Read before marking the block used, but after any nodes written while allocating it.
+        let fresh = (*self.high_water.get()).max(block as usize);
+        self.mark_used(block as usize + size);
+        fresh
+    }
+
+    /// [Self::alloc_picking], without adding the allocation to the `live-blocks` list.
+    unsafe fn alloc_unlisted(
+        &self,
+        layout: Layout,
+        pick: Option<impl FnOnce(usize) -> usize>,
+    ) -> *mut u8
+    where
+        T: MemoryGrower,
+    {
+        #[cfg(feature = "large-objects")]
+        if self.is_large(layout) {
+            return self.alloc_large(layout);
+        }
+
+        self.seed_static_chunk();
+        let Some(size) = checked_full_size(layout) else {
+            return null_mut();
+        };
+        let alignment = layout.align().max(NODE_SIZE);
+        let ptr = match pick {
+            Some(pick) => self.search_free_list_picking(size, alignment, SKIP_WASTEFUL, pick),
+            None => self.search_free_list(size, alignment, SKIP_WASTEFUL),
+        };
+        if !ptr.is_null() {
+            return ptr;
         }
 
         // Failed to find space in the free list.
         // So allocate more space, and allocate from that.
-        // Simplest way to due that is grow the heap, and "free" the new space then recurse.
-        // This should never need to recurse more than once.
-
-        let requested_bytes = round_up(size, PAGE_SIZE);
-        let previous_page_count = self
-            .grower
-            .memory_grow(PageCount(requested_bytes / PAGE_SIZE));
-        if previous_page_count == ERROR_PAGE_COUNT {
+        // Simplest way to due that is grow the heap, and "free" the new space then search again.
+        // If the grower granted everything requested, the search will succeed.
+        // Unless wasteful blocks were skipped, the search covered every block, which nothing can change before growing.
+        let mut progress = GrowProgress::new(!SKIP_WASTEFUL);
+        loop {
+            let Some(pages) =
+                self.pages_to_grow_for(|| self.grower.current_pages(), size, alignment)
+            else {
+                return null_mut();
+            };
+            let (first_page, granted) = self.grower.memory_grow_partial(pages);
+            if granted.0 == 0 {
+                return null_mut();
+            }
+            if let Some(ptr) =
+                self.alloc_from_grown(first_page, granted, size, alignment, &mut progress)
+            {
+                return ptr;
+            }
+        }
+    }
+
+    /// Number of pages to grow to fit an allocation of `size` bytes aligned to `alignment`.
+    ///
+    /// If the first free block ends at the end of memory (the wilderness), the grown memory extends it,
+    /// so only the part of the allocation which does not fit in it is grown.
+    /// `current_pages` gives the size of memory (as [MemoryGrower::current_pages] does), and is only called if there is a free block.
+    ///
+    /// Returns `None` if the size in bytes overflows, in which case nothing could fit the allocation.
+    pub(crate) unsafe fn pages_to_grow_for(
+        &self,
+        current_pages: impl FnOnce() -> PageCount,
+        size: usize,
+        alignment: usize,
+    ) -> Option<PageCount> {
+        // Grown memory is only page aligned, so allocations aligned to more than a page may need up to this much padding before them.
+        let padding = alignment.saturating_sub(PAGE_SIZE);
+        let needed = size.checked_add(padding)?;
+        let head = *self.free_list.get();
+        if head == Self::EMPTY {
+            return Some(pages_to_grow(needed));
+        }
+        FreeListNode::check(head);
+        let end_of_block = (head as usize).checked_add((*head).size);
+        let end_of_memory = current_pages().size_in_bytes();
+        if end_of_block.is_none() || end_of_block != end_of_memory {
+            return Some(pages_to_grow(needed));
+        }
+        // The end of memory is page aligned, so an allocation at the end of the extended block needs `size` rounded up to `alignment`
+        // (or to a page, plus the padding, for larger alignments).
+        // Always grow something: the block may have only been skipped for being wasteful.
+        let missing = size
+            .checked_next_multiple_of(alignment.min(PAGE_SIZE))?
+            .checked_add(padding)?
+            .saturating_sub((*head).size);
+        Some(PageCount(pages_to_grow(missing).0.max(1)))
+    }
+
+    /// Usable size of an allocation for `layout`: freeing it with any size from `layout.size()` up to this frees the same memory.
+    pub(crate) fn usable_size(&self, layout: Layout) -> usize {
+        if self.live_blocks.is_enabled() || size_check::ENABLED {
+            // The header records the requested size, which frees are checked against.
+            return layout.size();
+        }
+
+        #[cfg(feature = "large-objects")]
+        if self.is_large(layout) {
+            return large_run(layout).map_or(0, |(offset, run_size)| run_size - offset);
+        }
+
+        // Larger sizes would be freed to the page pool instead.
+        #[cfg(feature = "large-objects")]
+        return full_size(layout).min(self.large_object_threshold - 1);
+        #[cfg(not(feature = "large-objects"))]
+        full_size(layout)
+    }
+
+    /// [GlobalAlloc::dealloc], for allocations from [Self::alloc_unlisted].
+    unsafe fn dealloc_unlisted(&self, ptr: *mut u8, layout: Layout) {
+        #[cfg(feature = "large-objects")]
+        if self.is_large(layout) {
+            return self.dealloc_large(ptr, layout);
+        }
+
+        self.free(ptr, full_size(layout));
+    }
+
+    /// Makes the allocation at `ptr` for `layout` hold `new_layout` without moving it, returning false if it can't.
+    ///
+    /// Shrinking always works: the tail of the block is freed (merging with a free block after it),
+    /// unless it is smaller than a node, in which case it stays part of the allocation.
+    /// Growing works when the free block just after the allocation has room for the rest of the larger block,
+    /// which is then taken from its start.
+    /// With `dealloc-size-check`, the size prefix stays where it is, and records the new block size.
+    /// Allocations with `live-blocks` headers and large allocations are always moved.
+    unsafe fn resize_in_place(&self, ptr: *mut u8, layout: Layout, new_layout: Layout) -> bool {
+        if self.live_blocks.is_enabled() {
+            return false;
+        }
+        // Layout was valid when allocated, so this is too.
+        let checked_layout = size_check::inner_layout(layout).unwrap_unchecked();
+        let Some(new_checked_layout) = size_check::inner_layout(new_layout) else {
+            return false;
+        };
+        #[cfg(feature = "large-objects")]
+        if self.is_large(checked_layout) || self.is_large(new_checked_layout) {
+            return false;
+        }
+        let Some(new_size) = checked_full_size(new_checked_layout) else {
+            return false;
+        };
+        let raw = size_check::check(ptr, layout);
+        if !self.resize_block(raw, full_size(checked_layout), new_size) {
+            return false;
+        }
+        size_check::write(raw, new_layout);
+        true
+    }
+
+    /// Resizes the block of `size` bytes at `ptr` to `new_size` bytes (both multiples of [NODE_SIZE]) without moving it, returning false if it can't.
+    unsafe fn resize_block(&self, ptr: *mut u8, size: usize, new_size: usize) -> bool {
+        if new_size <= size {
+            // Block sizes are multiples of NODE_SIZE, so any tail is large enough to be a node.
+            if new_size < size {
+                self.free(ptr.add(new_size), size - new_size);
+            }
+            return true;
+        }
+        let extra = new_size - size;
+        let after = ptr.add(size) as *mut FreeListNode;
+        let head = self.free_list.get();
+        let mut link = head;
+        // The list is in descending order, so only blocks above the allocation need to be walked.
+        while *link != Self::EMPTY && *link > after {
+            self.count_node_searched();
+            link = ptr::addr_of_mut!((**link).next);
+        }
+        if *link != after {
+            return false;
+        }
+        FreeListNode::check(after);
+        let block_size = (*after).size;
+        if block_size < extra {
+            return false;
+        }
+        if block_size == extra {
+            FreeListNode::set_link(head, link, (*after).next);
+        } else {
+            // Move the start of the block up past the grown allocation.
+            let rest = offset_bytes(after, extra);
+            asan::unpoison(rest as *const u8, NODE_SIZE);
+            (*rest).next = (*after).next;
+            (*rest).size = block_size - extra;
+            FreeListNode::seal(rest);
+            FreeListNode::set_link(head, link, rest);
+            self.mark_used(rest as usize + NODE_SIZE);
+        }
+        self.mark_used(after as usize + extra);
+        asan::unpoison(after as *const u8, extra);
+        true
+    }
+}
+
+#[cfg(feature = "large-objects")]
+impl<T, const SENTINEL: usize, const SKIP_WASTEFUL: bool, const STATIC_CHUNK: usize>
+    FreeListAllocator<T, SENTINEL, SKIP_WASTEFUL, STATIC_CHUNK>
+{
+    /// If an allocation for `layout` uses the page pool.
+    /// Runs of pages are only page aligned, so allocations aligned to more than a page use the free list.
+    fn is_large(&self, layout: Layout) -> bool {
+        layout.size() >= self.large_object_threshold && layout.align() <= PAGE_SIZE
+    }
+
+    /// Allocates a run of pages for `layout`, with the header just before the returned pointer.
+    unsafe fn alloc_large(&self, layout: Layout) -> *mut u8
+    where
+        T: MemoryGrower,
+    {
+        let Some((offset, run_size)) = large_run(layout) else {
             return null_mut();
+        };
+        let mut run = self.search_list(self.page_pool.get(), run_size, PAGE_SIZE, false);
+        if run.is_null() {
+            let first_page = self.grower.memory_grow(PageCount(run_size / PAGE_SIZE));
+            if first_page == ERROR_PAGE_COUNT {
+                return null_mut();
+            }
+            let Some(start) = first_page.size_in_bytes() else {
+                return null_mut();
+            };
+            run = start as *mut u8;
+            self.check_not_adjacent_to_static_chunk(run as usize, run_size);
+            self.record_grown(run as usize, run_size);
         }
+        let ptr = run.add(offset);
+        (ptr.sub(LARGE_HEADER_SIZE) as *mut usize).write(run_size / PAGE_SIZE);
+        ptr
+    }
 
-        let ptr = previous_page_count.size_in_bytes() as *mut u8;
-        self.dealloc(
-            ptr,
-            Layout::from_size_align_unchecked(requested_bytes, PAGE_SIZE),
+    /// Returns the run of pages holding a large allocation to the page pool.
+    unsafe fn dealloc_large(&self, ptr: *mut u8, layout: Layout) {
+        // Layout was valid when allocated, so this is too.
+        let (offset, run_size) = large_run(layout).unwrap_unchecked();
+        lol_assert!(
+            *(ptr.sub(LARGE_HEADER_SIZE) as *const usize) == run_size / PAGE_SIZE,
+            "Large allocation header does not match its layout"
         );
-        self.alloc(layout)
+        self.insert_free(self.page_pool.get(), ptr.sub(offset), run_size);
     }
+}
 
-    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        debug_assert!(ptr.align_offset(NODE_SIZE) == 0);
+/// Total size of the nodes in `list` (which ends at `empty`) which are between `start` and `end`.
+#[cfg(feature = "reset-heap")]
+unsafe fn free_bytes_in(
+    mut list: *mut FreeListNode,
+    empty: *mut FreeListNode,
+    start: usize,
+    end: usize,
+) -> usize {
+    let mut bytes = 0;
+    while list != empty {
+        if start <= list as usize && (list as usize) < end {
+            bytes += (*list).size;
+        }
+        list = (*list).next;
+    }
+    bytes
+}
+
+/// Offset of a large allocation from the start of its run of pages, and the size of the run.
+#[cfg(feature = "large-objects")]
+fn large_run(layout: Layout) -> Option<(usize, usize)> {
+    // The header fits in the space before the allocation, since alignments are powers of two.
+    let offset = layout.align().max(LARGE_HEADER_SIZE);
+    let size = offset
+        .checked_add(layout.size())?
+        .checked_add(PAGE_SIZE - 1)?;
+    Some((offset, multiple_below(size, PAGE_SIZE)))
+}
+
+impl<T, const SENTINEL: usize, const SKIP_WASTEFUL: bool, const STATIC_CHUNK: usize>
+    FreeListAllocator<T, SENTINEL, SKIP_WASTEFUL, STATIC_CHUNK>
+{
+    /// Inserts the `size` bytes at `ptr` into the free list.
+    /// `size` must be a multiple of NODE_SIZE.
+    pub(crate) unsafe fn free(&self, ptr: *mut u8, size: usize) {
+        self.insert_free(self.free_list.get(), ptr, size);
+    }
+
+    /// Inserts the `size` bytes at `ptr` into the list starting at `free_list`.
+    unsafe fn insert_free(&self, mut free_list: *mut *mut FreeListNode, ptr: *mut u8, size: usize) {
+        strict_assert!((ptr as usize).is_multiple_of(NODE_SIZE));
+        asan::poison(ptr.add(NODE_SIZE), size - NODE_SIZE);
         let ptr = ptr as *mut FreeListNode;
-        let size = full_size(layout);
+        lol_assert!(ptr != Self::EMPTY, "SENTINEL collided with freed memory");
+        // The node is written at the start of the freed memory, unless it merges into the node before it.
+        self.mark_used(ptr as usize + NODE_SIZE);
         let after_new = offset_bytes(ptr, size); // Used to merge with next node if adjacent.
+        let head = free_list;
 
-        let mut free_list: *mut *mut FreeListNode = self.free_list.get();
         // Insert into freelist which is stored in order of descending pointers.
         loop {
-            if *free_list == EMPTY_FREE_LIST {
-                (*ptr).next = EMPTY_FREE_LIST;
+            if *free_list == Self::EMPTY {
+                (*ptr).next = Self::EMPTY;
                 (*ptr).size = size;
-                *free_list = ptr;
+                FreeListNode::seal(ptr);
+                FreeListNode::set_link(head, free_list, ptr);
+                self.update_peak_free_list_len();
                 return;
             }
+            FreeListNode::check(*free_list);
 
             if *free_list == after_new {
                 // Merge new node into node after this one.
 
                 let new_size = size + (**free_list).size;
                 let next = (**free_list).next;
-                if next != EMPTY_FREE_LIST && offset_bytes(next, (*next).size) == ptr {
+                if next != Self::EMPTY && {
+                    FreeListNode::check(next);
+                    offset_bytes(next, (*next).size) == ptr
+                } {
                     // Merge into node before this one, as well as after it.
                     (*next).size += new_size;
+                    FreeListNode::seal(next);
                     // Sine we are combining 2 existing nodes (with the new one in-between)
                     // remove one from the list.
-                    *free_list = next;
+                    FreeListNode::set_link(head, free_list, next);
                     return;
                 }
                 // Edit node in free list, moving its location and updating its size.
-                *free_list = ptr;
+                FreeListNode::set_link(head, free_list, ptr);
                 (*ptr).size = new_size;
                 (*ptr).next = next;
+                FreeListNode::seal(ptr);
                 return;
             }
 
@@ -151,13 +796,16 @@ unsafe impl<T: MemoryGrower> GlobalAlloc for FreeListAllocator<T> {
                 if offset_bytes(*free_list, (**free_list).size) == ptr {
                     // Merge into node before this one, as well as after it.
                     (**free_list).size += size;
+                    FreeListNode::seal(*free_list);
                     // Sine we are combining the new node into the end of an existing node, no pointer updates, just a size change.
                     return;
                 }
                 // Create a new free list node
                 (*ptr).next = *free_list;
                 (*ptr).size = size;
-                *free_list = ptr;
+                FreeListNode::seal(ptr);
+                FreeListNode::set_link(head, free_list, ptr);
+                self.update_peak_free_list_len();
                 return;
             }
             free_list = ptr::addr_of_mut!((**free_list).next);
@@ -165,150 +813,981 @@ unsafe impl<T: MemoryGrower> GlobalAlloc for FreeListAllocator<T> {
     }
 }
 
-fn full_size(layout: Layout) -> usize {
-    let grown = layout.size().max(NODE_SIZE);
-    round_up(grown, NODE_SIZE)
-}
-
-/// Round up value to the nearest multiple of increment, which must be a
-/// power of 2. If `value` is a multiple of increment, it is returned
-/// unchanged.
-fn round_up(value: usize, increment: usize) -> usize {
-    debug_assert!(increment.is_power_of_two());
-
-    // Compute `value.div_ceil(increment) * increment`,
-    // in a way that takes advantage of the fact that `increment` is
-    // always a power of two to avoid using an integer divide, since that
-    // wouldn't always get optimized out.
-    multiple_below(value + (increment - 1), increment)
-}
-
-/// Round down value to the nearest multiple of increment, which must be a
-/// power of 2. If `value` is a multiple of `increment`, it is returned
-/// unchanged.
-fn multiple_below(value: usize, increment: usize) -> usize {
-    debug_assert!(increment.is_power_of_two());
-
-    // Compute `value / increment * increment` in a way
-    // that takes advantage of the fact that `increment` is always a power of
-    // two to avoid using an integer divide, since that wouldn't always get
-    // optimized out.
-    value & increment.wrapping_neg()
-}
-
-unsafe fn offset_bytes(ptr: *mut FreeListNode, offset: usize) -> *mut FreeListNode {
-    (ptr as *mut u8).add(offset) as *mut FreeListNode
-}
-
-#[cfg(test)]
-mod tests {
-    use super::{
-        multiple_below, round_up, FreeListAllocator, MemoryGrower, PageCount, EMPTY_FREE_LIST,
-        NODE_SIZE,
-    };
-    use crate::{ERROR_PAGE_COUNT, PAGE_SIZE};
-    use alloc::{boxed::Box, vec::Vec};
-    use core::{
-        alloc::{GlobalAlloc, Layout},
-        cell::{RefCell, UnsafeCell},
-        ptr,
-    };
-
-    struct Allocation {
-        layout: Layout,
-        ptr: *mut u8,
+impl<T, const SENTINEL: usize, const SKIP_WASTEFUL: bool, const STATIC_CHUNK: usize>
+    FreeListAllocator<T, SENTINEL, SKIP_WASTEFUL, STATIC_CHUNK>
+{
+    /// Checks the internal consistency of the free list.
+    ///
+    /// # Panics
+    ///
+    /// If the free list is corrupt: nodes are misaligned, too small, out of order, overlapping or adjacent (adjacent nodes should have been coalesced).
+    pub fn validate_heap(&self) {
+        unsafe {
+            Self::validate_list(*self.free_list.get());
+            #[cfg(feature = "large-objects")]
+            Self::validate_list(*self.page_pool.get());
+        }
     }
 
-    #[derive(Clone, Copy)]
-    #[repr(C, align(65536))] // align does not appear to work with the PAGE_SIZE constant
-    struct Page([u8; PAGE_SIZE]);
+    unsafe fn validate_list(mut list: *mut FreeListNode) {
+        let mut previous: Option<*mut FreeListNode> = None;
+        while list != Self::EMPTY {
+            assert!((list as usize).is_multiple_of(NODE_SIZE));
+            FreeListNode::check(list);
+            let size = (*list).size;
+            assert!(size >= NODE_SIZE);
+            assert!(size.is_multiple_of(NODE_SIZE));
+            if let Some(previous) = previous {
+                // The list is sorted by descending address.
+                assert!(
+                    (previous as usize) > list as usize + size,
+                    "Free list nodes should not overlap or be adjacent"
+                );
+            }
+            previous = Some(list);
+            list = (*list).next;
+        }
+    }
 
-    struct Slabby {
-        /// Test array of pages, sequential in memory.
-        pages: Box<[Page]>,
-        used_pages: usize,
+    /// Returns true if all `size` bytes starting at `ptr` are in a single free block.
+    pub fn is_free(&self, ptr: *const u8, size: usize) -> bool {
+        let start = ptr as usize;
+        unsafe {
+            let mut list = *self.free_list.get();
+            while list != Self::EMPTY {
+                let start_of_block = list as usize;
+                if start_of_block <= start {
+                    // Blocks are in descending order, so this is the only block which could contain ptr.
+                    return start + size <= start_of_block + (*list).size;
+                }
+                list = (*list).next;
+            }
+        }
+        false
     }
 
-    impl Slabby {
-        fn new() -> Self {
-            Slabby {
-                pages: vec![Page([0; PAGE_SIZE]); 1000].into_boxed_slice(),
-                used_pages: 0,
+    /// The `(address, size)` of each block in the free list, in list order (descending address).
+    #[cfg(any(test, feature = "testing"))]
+    pub(crate) fn free_list_blocks(&self) -> alloc::vec::Vec<(usize, usize)> {
+        let mut out = alloc::vec::Vec::new();
+        unsafe {
+            let mut list = *self.free_list.get();
+            while list != Self::EMPTY {
+                out.push((list as usize, (*list).size));
+                list = (*list).next;
             }
         }
+        out
     }
 
-    impl MemoryGrower for RefCell<Slabby> {
-        fn memory_grow(&self, delta: PageCount) -> PageCount {
-            let mut slabby = self.borrow_mut();
-            let old_ptr = ptr::addr_of!(slabby.pages[slabby.used_pages]);
-            if slabby.used_pages + delta.0 > slabby.pages.len() {
-                return ERROR_PAGE_COUNT;
+    /// Size of the largest allocation aligned to `align` (a power of two) which can be made without growing memory,
+    /// or 0 if there is none.
+    ///
+    /// Useful to avoid growing memory from latency sensitive code,
+    /// by checking if an allocation will fit before making it.
+    /// This is O(length of free list).
+    pub fn largest_allocatable_block(&self, align: usize) -> usize {
+        // Allocations tracked as live blocks (or with size checks) have a header first, which takes space from the block.
+        let header = self.live_blocks.header_space(align) + size_check::prefix_space(align);
+        let mut largest = 0;
+        unsafe {
+            let mut list = *self.free_list.get();
+            while list != Self::EMPTY {
+                largest = largest.max(Self::aligned_space(list, align));
+                list = (*list).next;
+            }
+        }
+        #[cfg(feature = "large-objects")]
+        if align <= PAGE_SIZE {
+            let threshold = self.large_object_threshold;
+            // Allocations at least the threshold come from the page pool instead.
+            largest = largest.min(threshold.saturating_sub(1));
+            let mut run = unsafe { *self.page_pool.get() };
+            let offset = align.max(LARGE_HEADER_SIZE);
+            while run != Self::EMPTY {
+                unsafe {
+                    let size = (*run).size.saturating_sub(offset);
+                    if size >= threshold {
+                        largest = largest.max(size);
+                    }
+                    run = (*run).next;
+                }
             }
-            slabby.used_pages += delta.0;
-            debug_assert!(old_ptr.align_offset(PAGE_SIZE) == 0);
-            PageCount(old_ptr as usize / PAGE_SIZE)
         }
+        largest.saturating_sub(header)
     }
 
-    #[derive(Debug, PartialEq, Eq)]
-    struct FreeListContent {
-        size: usize,
-        /// Offset from beginning of Slabby.
-        offset: usize,
+    /// Fraction of free list nodes which are too small (or badly aligned) to serve an allocation of `size` bytes aligned to `align` (a power of two),
+    /// from 0 when every free block could serve it to 1 when none can.
+    ///
+    /// This measures external fragmentation: free memory split into pieces too small to use.
+    /// It helps diagnose growing memory for a large allocation while lots of memory is free.
+    /// An empty free list is not fragmented, so reports 0.
+    /// With the `large-objects` feature, the page pool is not included.
+    /// This is O(length of free list).
+    pub fn external_fragmentation_for_size(&self, size: usize, align: usize) -> f64 {
+        let header = self.live_blocks.header_space(align) + size_check::prefix_space(align);
+        let mut total = 0;
+        let mut can_serve = 0;
+        unsafe {
+            let mut list = *self.free_list.get();
+            while list != Self::EMPTY {
+                total += 1;
+                if Self::aligned_space(list, align) >= size.saturating_add(header) {
+                    can_serve += 1;
+                }
+                list = (*list).next;
+            }
+        }
+        if total == 0 {
+            return 0.0;
+        }
+        1.0 - can_serve as f64 / total as f64
     }
 
-    /// Enumerate and validate free list content
-    fn free_list_content(allocator: &FreeListAllocator<RefCell<Slabby>>) -> Vec<FreeListContent> {
-        let mut out: Vec<FreeListContent> = vec![];
-        let grower = allocator.grower.borrow();
-        let base = grower.pages.as_ptr() as usize;
+    /// Total size of the free list nodes smaller than two nodes:
+    /// an approximation of the memory lost to alignment padding.
+    ///
+    /// Aligning an allocation to the end of a block can leave a fragment after it smaller than the alignment,
+    /// which is too small to serve most allocations, so is effectively wasted until its neighbours are freed.
+    /// Small frees also make such nodes, so this is an upper bound on the padding in the free list.
+    /// With the `large-objects` feature, the page pool is not included.
+    /// This is O(length of free list).
+    pub fn alignment_waste_bytes(&self) -> usize {
+        let mut total = 0;
         unsafe {
-            let mut list = *(allocator.free_list.get());
-            while list != EMPTY_FREE_LIST {
-                debug_assert_eq!(list.align_offset(NODE_SIZE), 0);
-                debug_assert!(list as usize >= base);
-                debug_assert!(
-                    (list as usize)
-                        < ptr::addr_of!(grower.pages[grower.used_pages]) as usize + PAGE_SIZE
-                );
-                let offset = list as usize - base;
-                let size = (*list).size;
-                debug_assert!(offset + size <= grower.used_pages * PAGE_SIZE);
-                debug_assert!(size >= NODE_SIZE);
-                match out.last() {
-                    Some(previous) => {
-                        debug_assert!(
-                            previous.offset > offset + size,
-                            "Free list nodes should not overlap or be adjacent"
-                        );
-                    }
-                    None => {}
+            let mut list = *self.free_list.get();
+            while list != Self::EMPTY {
+                if (*list).size < NODE_SIZE * 2 {
+                    total += (*list).size;
                 }
-                out.push(FreeListContent { size, offset });
                 list = (*list).next;
             }
         }
-        out
+        total
     }
 
-    #[test]
-    fn round_up_works() {
-        assert_eq!(round_up(0, 8), 0);
-        assert_eq!(round_up(7, 8), 8);
-        assert_eq!(round_up(8, 8), 8);
-        assert_eq!(round_up(9, 8), 16);
-        assert_eq!(round_up(15, 8), 16);
-        assert_eq!(round_up(16, 8), 16);
+    /// Bytes in the free block `node` from its first address aligned to `align` to its end:
+    /// since allocations are placed at the end of blocks, this is the largest it can serve.
+    unsafe fn aligned_space(node: *const FreeListNode, align: usize) -> usize {
+        let start_of_block = node as usize;
+        let end_of_block = start_of_block + (*node).size;
+        end_of_block.saturating_sub(round_up(start_of_block, align.max(NODE_SIZE)))
+    }
 
-        assert_eq!(round_up(127, 128), 128);
-        assert_eq!(round_up(100223, 128), 100224);
+    /// Reports information about what the allocator knows of each pointer in `ptrs`,
+    /// which should be the application's list of live allocations.
+    ///
+    /// Useful to detect use after free: live allocations should never be in the free list.
+    pub fn live_allocation_info<'a>(
+        &'a self,
+        ptrs: &'a [*const u8],
+    ) -> impl Iterator<Item = LiveInfo> + 'a {
+        ptrs.iter().map(move |&ptr| {
+            let address = ptr as usize;
+            // The lowest free block starting above ptr, and the free block containing ptr, if any.
+            let mut above: Option<usize> = None;
+            let mut containing: Option<usize> = None;
+            unsafe {
+                let mut list = *self.free_list.get();
+                while list != Self::EMPTY {
+                    let start_of_block = list as usize;
+                    if start_of_block <= address {
+                        if address < start_of_block + (*list).size {
+                            containing = Some(start_of_block + (*list).size);
+                        }
+                        break;
+                    }
+                    above = Some(start_of_block);
+                    list = (*list).next;
+                }
+            }
+            match containing {
+                Some(end_of_block) => LiveInfo {
+                    ptr,
+                    usable_size: Some(end_of_block - address),
+                    is_in_free_list: true,
+                },
+                None => LiveInfo {
+                    ptr,
+                    usable_size: above.map(|start_of_block| start_of_block - address),
+                    is_in_free_list: false,
+                },
+            }
+        })
     }
 
-    #[test]
-    fn multiple_below_works() {
-        assert_eq!(multiple_below(0, 8), 0);
-        assert_eq!(multiple_below(7, 8), 0);
+    /// Calls `f` with the pointer and size of each live allocation, most recent first.
+    /// Only allocators created with [FreeListAllocator::new_with_live_blocks_in] track allocations: others report none.
+    ///
+    /// Useful for conservative garbage collectors, which treat everything in live allocations as potential roots.
+    /// Does not allocate.
+    ///
+    /// # Safety
+    ///
+    /// `f` must not allocate or free memory from this allocator.
+    #[cfg(feature = "live-blocks")]
+    pub unsafe fn for_each_live_block(&self, f: impl FnMut(*mut u8, usize)) {
+        self.live_blocks.for_each(f);
+    }
+
+    /// Largest number of nodes the free list has had.
+    ///
+    /// Useful for evaluating worst case performance of a workload, since allocations and frees are O(length of free list).
+    #[cfg(feature = "peak-free-list-len")]
+    pub fn peak_free_list_len(&self) -> usize {
+        unsafe { *self.peak_free_list_len.get() }
+    }
+
+    /// Call after any change which may have increased the length of the free list.
+    /// This is O(length of free list), so it only does anything with the `peak-free-list-len` feature.
+    #[cfg(feature = "peak-free-list-len")]
+    unsafe fn update_peak_free_list_len(&self) {
+        let mut len = 0;
+        let mut list = *self.free_list.get();
+        while list != Self::EMPTY {
+            len += 1;
+            list = (*list).next;
+        }
+        let peak = &mut *self.peak_free_list_len.get();
+        *peak = len.max(*peak);
+    }
+
+    #[cfg(not(feature = "peak-free-list-len"))]
+    #[inline(always)]
+    unsafe fn update_peak_free_list_len(&self) {}
+
+    /// Call with each newly grown region of memory, to track the extent of the heap.
+    #[cfg(feature = "reset-heap")]
+    unsafe fn record_grown(&self, start: usize, size: usize) {
+        let extent = &mut *self.extent.get();
+        if extent.1 == start && extent.0 != extent.1 {
+            extent.1 += size;
+        } else {
+            // Not contiguous with the previous memory, which will no longer be tracked.
+            *extent = (start, start + size);
+        }
+    }
+
+    #[cfg(not(feature = "reset-heap"))]
+    #[inline(always)]
+    unsafe fn record_grown(&self, _start: usize, _size: usize) {}
+
+    /// Frees all memory the allocator has grown, making the free list a single block covering all of it.
+    ///
+    /// Useful for reusing a wasm instance for multiple independent invocations (for example in plugin hosts),
+    /// where memory leaked by one invocation would otherwise accumulate.
+    ///
+    /// Only the most recent contiguous run of grown memory is tracked:
+    /// if other code grew memory in between this allocator's grows, memory from before that is not reclaimed.
+    ///
+    /// # Safety
+    ///
+    /// Every allocation from this allocator becomes invalid: no Rust heap object allocated from it may survive the reset,
+    /// including ones which would be freed (or just dropped) later.
+    /// In practice this means it must only be called when nothing (in any thread) holds any allocation from this allocator.
+    #[cfg(feature = "reset-heap")]
+    pub unsafe fn reset_heap(&self) {
+        *self.free_list.get() = Self::EMPTY;
+        #[cfg(feature = "live-blocks")]
+        self.live_blocks.clear();
+        #[cfg(feature = "large-objects")]
+        {
+            *self.page_pool.get() = Self::EMPTY;
+        }
+        // Added back to the free list by the next allocation.
+        *self.static_chunk_seeded.get() = false;
+        let (start, end) = *self.extent.get();
+        if start != end {
+            self.free(start as *mut u8, end - start);
+        }
+    }
+
+    /// Frees all memory the allocator has grown, like [Self::reset_heap], and returns how many bytes were in live allocations.
+    ///
+    /// The live allocations are found as the parts of the heap not in the free list,
+    /// so the count includes the padding and headers the allocator adds to each allocation.
+    /// Calling this at shutdown works as a leak check: anything other than 0 was never freed.
+    ///
+    /// # Safety
+    ///
+    /// Same as [Self::reset_heap].
+    #[cfg(feature = "reset-heap")]
+    pub unsafe fn dealloc_all(&self) -> usize {
+        let mut regions = [*self.extent.get(), (0, 0)];
+        if *self.static_chunk_seeded.get() {
+            regions[1] = self.static_chunk_bounds();
+        }
+        let mut live = 0;
+        for (start, end) in regions {
+            if start >= end {
+                continue;
+            }
+            live += end - start - free_bytes_in(*self.free_list.get(), Self::EMPTY, start, end);
+            #[cfg(feature = "large-objects")]
+            {
+                live -= free_bytes_in(*self.page_pool.get(), Self::EMPTY, start, end);
+            }
+        }
+        self.reset_heap();
+        live
+    }
+
+    /// Start and end of the part of the static chunk which is aligned for the free list.
+    fn static_chunk_bounds(&self) -> (usize, usize) {
+        let start = self.static_chunk.get() as usize;
+        (
+            round_up(start, NODE_SIZE),
+            multiple_below(start + STATIC_CHUNK, NODE_SIZE),
+        )
+    }
+
+    /// Adds the static chunk to the free list, if it has not been already.
+    #[inline(always)]
+    unsafe fn seed_static_chunk(&self) {
+        if STATIC_CHUNK < NODE_SIZE || *self.static_chunk_seeded.get() {
+            return;
+        }
+        *self.static_chunk_seeded.get() = true;
+        let (start, end) = self.static_chunk_bounds();
+        if start < end {
+            self.free(start as *mut u8, end - start);
+        }
+    }
+
+    /// Checks newly grown memory is not adjacent to the static chunk, so they are never coalesced into one free block.
+    fn check_not_adjacent_to_static_chunk(&self, start: usize, size: usize) {
+        if STATIC_CHUNK < NODE_SIZE {
+            return;
+        }
+        let (chunk_start, chunk_end) = self.static_chunk_bounds();
+        lol_assert!(
+            chunk_start >= chunk_end || (start != chunk_end && start + size != chunk_start),
+            "Grown memory is adjacent to the static chunk"
+        );
+    }
+
+    /// Adds newly grown memory to the free list, and retries allocating `size` bytes aligned to `alignment`.
+    ///
+    /// Returns `None` if the allocation should be retried after growing again,
+    /// which happens when only part of the request was granted.
+    /// Returns null when growing again would not make progress.
+    pub(crate) unsafe fn alloc_from_grown(
+        &self,
+        first_page: PageCount,
+        granted: PageCount,
+        size: usize,
+        alignment: usize,
+        progress: &mut GrowProgress,
+    ) -> Option<*mut u8> {
+        let (Some(start), Some(grown_size)) = (first_page.size_in_bytes(), granted.size_in_bytes())
+        else {
+            return Some(null_mut());
+        };
+        self.check_not_adjacent_to_static_chunk(start, grown_size);
+        self.record_grown(start, grown_size);
+        let head = self.free_list.get();
+        let block_size = if *head == Self::EMPTY || (*head as usize) < start {
+            // The grown memory is above all the free memory (as it usually is),
+            // so it makes (or extends) the first block, which is the only one that changed.
+            if *head != Self::EMPTY && {
+                FreeListNode::check(*head);
+                offset_bytes(*head, (**head).size) as usize == start
+            } {
+                // Extending the block directly (instead of freeing the memory) writes nothing to it,
+                // so it stays above the high water mark.
+                (**head).size += grown_size;
+                FreeListNode::seal(*head);
+                asan::poison(start as *mut u8, grown_size);
+            } else {
+                self.free(start as *mut u8, grown_size);
+            }
+            let mut ptr = self.alloc_from_node(head, head, size, alignment, false);
+            if ptr.is_null() && !progress.searched {
+                ptr = self.search_list_from(
+                    head,
+                    ptr::addr_of_mut!((**head).next),
+                    size,
+                    alignment,
+                    false,
+                );
+            }
+            if !ptr.is_null() {
+                return Some(ptr);
+            }
+            (**head).size
+        } else {
+            self.free(start as *mut u8, grown_size);
+            let ptr = self.alloc_from_free_list(size, alignment);
+            if !ptr.is_null() {
+                return Some(ptr);
+            }
+            self.free_block_size_containing(start)
+        };
+
+        // Only part of the request was granted, so searching again failed.
+        // Growing again then only helps if the new memory keeps extending the same free block,
+        // otherwise a grower which keeps granting too little would make this loop forever.
+        if block_size <= progress.grown_block_size {
+            return Some(null_mut());
+        }
+        progress.grown_block_size = block_size;
+        None
+    }
+
+    /// Allocates `size` bytes aligned to `alignment` from the free list.
+    /// Returns null if no free block can fit it.
+    ///
+    /// Never skips wasteful blocks, so newly grown memory is always used.
+    pub(crate) unsafe fn alloc_from_free_list(&self, size: usize, alignment: usize) -> *mut u8 {
+        self.search_free_list(size, alignment, false)
+    }
+
+    /// Like [Self::alloc_from_free_list], but if `skip_wasteful`,
+    /// blocks where more than half the block would be left unused after the allocation due to alignment are skipped.
+    unsafe fn search_free_list(
+        &self,
+        size: usize,
+        alignment: usize,
+        skip_wasteful: bool,
+    ) -> *mut u8 {
+        self.search_list(self.free_list.get(), size, alignment, skip_wasteful)
+    }
+
+    /// Like [Self::search_free_list], but allocates from the `pick(count)`th (modulo `count`) of the `count` blocks which fit.
+    unsafe fn search_free_list_picking(
+        &self,
+        size: usize,
+        alignment: usize,
+        skip_wasteful: bool,
+        pick: impl FnOnce(usize) -> usize,
+    ) -> *mut u8 {
+        let head = self.free_list.get();
+        let mut count = 0;
+        let mut node = *head;
+        while node != Self::EMPTY {
+            self.count_node_searched();
+            FreeListNode::check(node);
+            if fit_position(node, size, alignment, skip_wasteful).is_some() {
+                count += 1;
+            }
+            node = (*node).next;
+        }
+        if count == 0 {
+            return null_mut();
+        }
+        let mut remaining = pick(count) % count;
+        let mut link = head;
+        loop {
+            if fit_position(*link, size, alignment, skip_wasteful).is_some() {
+                if remaining == 0 {
+                    return self.alloc_from_node(head, link, size, alignment, skip_wasteful);
+                }
+                remaining -= 1;
+            }
+            link = ptr::addr_of_mut!((**link).next);
+        }
+    }
+
+    /// Like [Self::search_free_list], but searches the list starting at `free_list`.
+    unsafe fn search_list(
+        &self,
+        free_list: *mut *mut FreeListNode,
+        size: usize,
+        alignment: usize,
+        skip_wasteful: bool,
+    ) -> *mut u8 {
+        self.search_list_from(free_list, free_list, size, alignment, skip_wasteful)
+    }
+
+    /// Like [Self::search_list] on the list starting at `head`, but only searches from `link`
+    /// (either `head` or the `next` field of one of its nodes).
+    unsafe fn search_list_from(
+        &self,
+        head: *mut *mut FreeListNode,
+        mut link: *mut *mut FreeListNode,
+        size: usize,
+        alignment: usize,
+        skip_wasteful: bool,
+    ) -> *mut u8 {
+        loop {
+            if *link == Self::EMPTY {
+                return null_mut();
+            }
+            let ptr = self.alloc_from_node(head, link, size, alignment, skip_wasteful);
+            if !ptr.is_null() {
+                return ptr;
+            }
+            link = ptr::addr_of_mut!((**link).next);
+        }
+    }
+
+    /// Allocates `size` bytes aligned to `alignment` from the end of the block `*free_list` points to, in the list starting at `head`.
+    /// Returns null if it does not fit, or if `skip_wasteful` and more than half the block would be left unused due to alignment.
+    unsafe fn alloc_from_node(
+        &self,
+        head: *mut *mut FreeListNode,
+        mut free_list: *mut *mut FreeListNode,
+        size: usize,
+        alignment: usize,
+        skip_wasteful: bool,
+    ) -> *mut u8 {
+        self.count_node_searched();
+        FreeListNode::check(*free_list);
+        // Try to allocate from end of block of free space.
+        let start_of_block = *free_list as usize;
+        let end_of_block = start_of_block + (**free_list).size;
+        let Some(position) = fit_position(*free_list, size, alignment, skip_wasteful) else {
+            return null_mut();
+        };
+        let position = self.jitter(position, start_of_block, alignment);
+        // Compute if we need a node after used space due to alignment.
+        let end_of_used = position + size;
+        if end_of_used < end_of_block {
+            // Insert new block
+            let new_block = end_of_used as *mut FreeListNode;
+            asan::unpoison(new_block as *const u8, NODE_SIZE);
+            self.mark_used(end_of_used + NODE_SIZE);
+            (*new_block).next = *free_list;
+            (*new_block).size = end_of_block - end_of_used;
+            FreeListNode::seal(new_block);
+            FreeListNode::set_link(head, free_list, new_block);
+            free_list = ptr::addr_of_mut!((*new_block).next);
+        }
+        if position == start_of_block {
+            // Remove current node from free list.
+            FreeListNode::set_link(head, free_list, (**free_list).next);
+        } else {
+            // Shrink free block
+            (**free_list).size = position - start_of_block;
+            FreeListNode::seal(*free_list);
+        }
+        self.update_peak_free_list_len();
+
+        let ptr = position as *mut u8;
+        lol_assert!(ptr.align_offset(NODE_SIZE) == 0);
+        lol_assert!(ptr.align_offset(alignment) == 0);
+        asan::unpoison(ptr, size);
+        ptr
+    }
+
+    /// Counts a node visited by a search, for [Self::nodes_searched].
+    #[cfg(test)]
+    fn count_node_searched(&self) {
+        unsafe { *self.nodes_searched.get() += 1 };
+    }
+
+    #[cfg(not(test))]
+    #[inline(always)]
+    fn count_node_searched(&self) {}
+
+    /// Number of free list nodes searches have visited.
+    #[cfg(test)]
+    pub(crate) fn nodes_searched(&self) -> usize {
+        unsafe { *self.nodes_searched.get() }
+    }
+
+    /// Moves an allocation at `position` down by a random number of nodes (keeping it `alignment` aligned),
+    /// if that keeps it above `start_of_block`.
+    #[cfg(feature = "jitter")]
+    unsafe fn jitter(&self, position: usize, start_of_block: usize, alignment: usize) -> usize {
+        // xorshift64
+        let state = &mut *self.jitter_state.get();
+        if *state == 0 {
+            return position;
+        }
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        let nodes = (*state >> 32) as usize % (JITTER_MAX_NODES + 1);
+        // Over-aligned allocations can only move by multiples of their alignment, so move less.
+        let offset = multiple_below(nodes * NODE_SIZE, alignment);
+        if position - start_of_block >= offset {
+            position - offset
+        } else {
+            position
+        }
+    }
+
+    #[cfg(not(feature = "jitter"))]
+    #[inline(always)]
+    unsafe fn jitter(&self, position: usize, _start_of_block: usize, _alignment: usize) -> usize {
+        position
+    }
+
+    /// Size of the free block containing `address`, or 0 if it is not free.
+    unsafe fn free_block_size_containing(&self, address: usize) -> usize {
+        let mut list = *self.free_list.get();
+        while list != Self::EMPTY {
+            self.count_node_searched();
+            FreeListNode::check(list);
+            let start = list as usize;
+            if start <= address && address < start + (*list).size {
+                return (*list).size;
+            }
+            list = (*list).next;
+        }
+        0
+    }
+
+    /// Converts this into an allocator which never frees memory.
+    /// All memory currently in the free list remains available for future allocations.
+    ///
+    /// Useful when an application enters a phase where all allocations are permanent,
+    /// since it makes `dealloc` O(1) instead of O(length of free list).
+    pub fn into_leaking(
+        self,
+    ) -> LeakingAllocatorFromFreeList<T, SENTINEL, SKIP_WASTEFUL, STATIC_CHUNK> {
+        LeakingAllocatorFromFreeList { inner: self }
+    }
+}
+
+impl<T, const SENTINEL: usize, const SKIP_WASTEFUL: bool, const STATIC_CHUNK: usize> AllocatorStats
+    for FreeListAllocator<T, SENTINEL, SKIP_WASTEFUL, STATIC_CHUNK>
+{
+    fn stats(&self) -> Stats {
+        // Total size and number of nodes.
+        let list_bytes = |mut list: *mut FreeListNode| {
+            let (mut bytes, mut blocks) = (0, 0);
+            while list != Self::EMPTY {
+                unsafe {
+                    bytes += (*list).size;
+                    blocks += 1;
+                    list = (*list).next;
+                }
+            }
+            (bytes, blocks)
+        };
+        let (free_bytes, free_blocks) = list_bytes(unsafe { *self.free_list.get() });
+        // Pages in the page pool are free too.
+        #[cfg(feature = "large-objects")]
+        let (free_bytes, free_blocks) = {
+            let (pool_bytes, pool_blocks) = list_bytes(unsafe { *self.page_pool.get() });
+            (free_bytes + pool_bytes, free_blocks + pool_blocks)
+        };
+        Stats {
+            free_bytes: Some(free_bytes),
+            free_blocks: Some(free_blocks),
+            ..Stats::default()
+        }
+    }
+}
+
+/// Information about a pointer, from [FreeListAllocator::live_allocation_info].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LiveInfo {
+    pub ptr: *const u8,
+    /// The most bytes starting at `ptr` which could be usable.
+    ///
+    /// If `ptr` is in the free list, this is the number of free bytes from `ptr` to the end of its free block.
+    /// Otherwise it is the distance to the next free block above `ptr`, which bounds how large the allocation at `ptr` can be,
+    /// or `None` if there is no free memory above `ptr`.
+    pub usable_size: Option<usize>,
+    /// If `ptr` is in free memory. This should never be true for live allocations.
+    pub is_in_free_list: bool,
+}
+
+/// A [FreeListAllocator] which has stopped freeing memory.
+/// Allocations are still served from the free list, but `dealloc` is a no-op.
+/// Created by [FreeListAllocator::into_leaking].
+pub struct LeakingAllocatorFromFreeList<
+    T = DefaultGrower,
+    const SENTINEL: usize = DEFAULT_SENTINEL,
+    const SKIP_WASTEFUL: bool = false,
+    const STATIC_CHUNK: usize = 0,
+> {
+    inner: FreeListAllocator<T, SENTINEL, SKIP_WASTEFUL, STATIC_CHUNK>,
+}
+
+unsafe impl<
+        T: MemoryGrower,
+        const SENTINEL: usize,
+        const SKIP_WASTEFUL: bool,
+        const STATIC_CHUNK: usize,
+    > GlobalAlloc for LeakingAllocatorFromFreeList<T, SENTINEL, SKIP_WASTEFUL, STATIC_CHUNK>
+{
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        nonzero_size_assert!(layout.size());
+        self.inner.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, _ptr: *mut u8, layout: Layout) {
+        nonzero_size_assert!(layout.size());
+    }
+}
+
+impl<
+        T: MemoryGrower,
+        const SENTINEL: usize,
+        const SKIP_WASTEFUL: bool,
+        const STATIC_CHUNK: usize,
+    > AllocWithExcess for LeakingAllocatorFromFreeList<T, SENTINEL, SKIP_WASTEFUL, STATIC_CHUNK>
+{
+    unsafe fn alloc_with_excess(&self, layout: Layout) -> (*mut u8, usize) {
+        self.inner.alloc_with_excess(layout)
+    }
+}
+
+impl<T, const SENTINEL: usize, const SKIP_WASTEFUL: bool, const STATIC_CHUNK: usize> AllocatorStats
+    for LeakingAllocatorFromFreeList<T, SENTINEL, SKIP_WASTEFUL, STATIC_CHUNK>
+{
+    fn stats(&self) -> Stats {
+        self.inner.stats()
+    }
+}
+
+/// State used to detect when growing memory stops making progress for an allocation.
+pub(crate) struct GrowProgress {
+    /// Size of the free block containing the most recently grown memory.
+    grown_block_size: usize,
+    /// If every free block, other than the one grown memory is added to, is known not to fit the allocation,
+    /// so growing only needs to search that block.
+    searched: bool,
+}
+
+impl GrowProgress {
+    pub(crate) fn new(searched: bool) -> Self {
+        GrowProgress {
+            grown_block_size: 0,
+            searched,
+        }
+    }
+}
+
+/// Where an allocation of `size` bytes aligned to `alignment` goes at the end of the free block `node`.
+/// None if it does not fit, or if `skip_wasteful` and more than half the block would be left unused due to alignment.
+unsafe fn fit_position(
+    node: *mut FreeListNode,
+    size: usize,
+    alignment: usize,
+    skip_wasteful: bool,
+) -> Option<usize> {
+    let size_of_block = (*node).size;
+    let start_of_block = node as usize;
+    let end_of_block = start_of_block + size_of_block;
+    if size >= end_of_block {
+        return None;
+    }
+    let position = multiple_below(end_of_block - size, alignment);
+    // Space left after the allocation due to alignment.
+    let waste = end_of_block - size - position;
+    (position >= start_of_block && !(skip_wasteful && waste > size_of_block / 2))
+        .then_some(position)
+}
+
+/// Number of pages to grow to fit an allocation of `size` bytes.
+///
+/// Unlike rounding `size` up to a multiple of [PAGE_SIZE], this can't overflow.
+pub(crate) fn pages_to_grow(size: usize) -> PageCount {
+    PageCount(size.div_ceil(PAGE_SIZE))
+}
+
+/// Size of the free list block used by an allocation for `layout`, or `None` if it overflows (in which case it can't be allocated).
+///
+/// Every size from `layout.size()` up to the result has the same full size,
+/// which is what lets [AllocWithExcess] allocations be freed with any size up to their usable size.
+///
+/// The alignment is not included, even when it is larger than the size: allocations are placed at aligned positions,
+/// which (like the full size) are multiples of [NODE_SIZE], so what is freed is always a whole number of aligned nodes.
+pub(crate) fn checked_full_size(layout: Layout) -> Option<usize> {
+    layout
+        .size()
+        .max(NODE_SIZE)
+        .checked_next_multiple_of(NODE_SIZE)
+}
+
+/// [checked_full_size] of the layout of an allocation, which could only be made if it did not overflow.
+pub(crate) fn full_size(layout: Layout) -> usize {
+    let size = checked_full_size(layout);
+    lol_assert!(size.is_some());
+    size.unwrap_or(usize::MAX)
+}
+
+/// Round up value to the nearest multiple of increment, which must be a
+/// power of 2. If `value` is a multiple of increment, it is returned
+/// unchanged.
+pub(crate) fn round_up(value: usize, increment: usize) -> usize {
+    lol_assert!(increment.is_power_of_two());
+
+    // Compute `value.div_ceil(increment) * increment`,
+    // in a way that takes advantage of the fact that `increment` is
+    // always a power of two to avoid using an integer divide, since that
+    // wouldn't always get optimized out.
+    multiple_below(value + (increment - 1), increment)
+}
+
+/// Round down value to the nearest multiple of increment, which must be a
+/// power of 2. If `value` is a multiple of `increment`, it is returned
+/// unchanged.
+pub(crate) fn multiple_below(value: usize, increment: usize) -> usize {
+    lol_assert!(increment.is_power_of_two());
+
+    // Compute `value / increment * increment` in a way
+    // that takes advantage of the fact that `increment` is always a power of
+    // two to avoid using an integer divide, since that wouldn't always get
+    // optimized out.
+    value & increment.wrapping_neg()
+}
+
+unsafe fn offset_bytes(ptr: *mut FreeListNode, offset: usize) -> *mut FreeListNode {
+    (ptr as *mut u8).add(offset) as *mut FreeListNode
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        full_size, multiple_below, round_up, FreeListAllocator, LeakingAllocatorFromFreeList,
+        LiveInfo, MemoryGrower, PageCount, StaticChunkFreeListAllocator, DEFAULT_SENTINEL,
+        NODE_SIZE,
+    };
+    #[cfg(unix)]
+    use crate::test_utils::{Reservation, RESERVATION_PAGES};
+    use crate::{
+        size_check,
+        testing::{
+            block_layout, block_size, block_start, excess_conformance, free_blocks,
+            run_alloc_scenario, AllocScenario, Allocation, AllocationTracker, FreeBlock,
+            MockGrower, TestGrower,
+        },
+        AllocatorStats, ERROR_PAGE_COUNT, PAGE_SIZE,
+    };
+    use alloc::vec::Vec;
+    use core::{
+        alloc::{GlobalAlloc, Layout},
+        cell::Cell,
+    };
+
+    /// Adversarial grower which grants at most one page per call,
+    /// optionally leaving a gap before each grant so they are never contiguous.
+    struct OnePageGrower {
+        grower: MockGrower,
+        gap: bool,
+        calls: Cell<usize>,
+    }
+
+    impl OnePageGrower {
+        fn new(gap: bool) -> Self {
+            OnePageGrower {
+                grower: MockGrower::new(),
+                gap,
+                calls: Cell::new(0),
+            }
+        }
+    }
+
+    impl MemoryGrower for OnePageGrower {
+        fn memory_grow(&self, delta: PageCount) -> PageCount {
+            let (first, granted) = self.memory_grow_partial(delta);
+            if granted != delta {
+                return ERROR_PAGE_COUNT;
+            }
+            first
+        }
+
+        fn memory_grow_partial(&self, delta: PageCount) -> (PageCount, PageCount) {
+            self.calls.set(self.calls.get() + 1);
+            if self.gap {
+                self.grower.memory_grow(PageCount(1));
+            }
+            let granted = PageCount(delta.0.min(1));
+            let first = self.grower.memory_grow(granted);
+            if first == ERROR_PAGE_COUNT {
+                return (ERROR_PAGE_COUNT, PageCount(0));
+            }
+            (first, granted)
+        }
+
+        fn current_pages(&self) -> PageCount {
+            self.grower.current_pages()
+        }
+    }
+
+    /// Adversarial grower which never grants any pages, but reports this as a partial grant instead of an error.
+    struct ZeroPageGrower {
+        calls: Cell<usize>,
+    }
+
+    impl MemoryGrower for ZeroPageGrower {
+        fn memory_grow(&self, _delta: PageCount) -> PageCount {
+            ERROR_PAGE_COUNT
+        }
+
+        fn memory_grow_partial(&self, _delta: PageCount) -> (PageCount, PageCount) {
+            self.calls.set(self.calls.get() + 1);
+            (PageCount(0), PageCount(0))
+        }
+    }
+
+    /// Grower which grants pages from the top of its region down, so grown memory is below the free memory.
+    struct DescendingGrower {
+        grower: MockGrower,
+        remaining: Cell<usize>,
+    }
+
+    impl DescendingGrower {
+        fn new(pages: usize) -> Self {
+            DescendingGrower {
+                grower: MockGrower::with_pages(pages),
+                remaining: Cell::new(pages),
+            }
+        }
+    }
+
+    impl MemoryGrower for DescendingGrower {
+        fn memory_grow(&self, delta: PageCount) -> PageCount {
+            if delta.0 > self.remaining.get() {
+                return ERROR_PAGE_COUNT;
+            }
+            self.remaining.set(self.remaining.get() - delta.0);
+            PageCount(self.grower.base() / PAGE_SIZE + self.remaining.get())
+        }
+    }
+
+    /// If any of the `size` bytes at `ptr` are in a free block.
+    fn overlaps_free<G: TestGrower, const SKIP_WASTEFUL: bool>(
+        allocator: &FreeListAllocator<G, DEFAULT_SENTINEL, SKIP_WASTEFUL>,
+        ptr: *const u8,
+        size: usize,
+    ) -> bool {
+        let start = ptr as usize;
+        unsafe {
+            let mut list = *(allocator.free_list.get());
+            while list as usize != DEFAULT_SENTINEL {
+                let block = list as usize;
+                if block < start + size && start < block + (*list).size {
+                    return true;
+                }
+                list = (*list).next;
+            }
+        }
+        false
+    }
+
+    #[test]
+    fn round_up_works() {
+        assert_eq!(round_up(0, 8), 0);
+        assert_eq!(round_up(7, 8), 8);
+        assert_eq!(round_up(8, 8), 8);
+        assert_eq!(round_up(9, 8), 16);
+        assert_eq!(round_up(15, 8), 16);
+        assert_eq!(round_up(16, 8), 16);
+
+        assert_eq!(round_up(127, 128), 128);
+        assert_eq!(round_up(100223, 128), 100224);
+    }
+
+    #[cfg(all(feature = "tiny-asserts", debug_assertions))]
+    #[test]
+    #[should_panic(expected = "lol_alloc trap")]
+    fn tiny_asserts_trap() {
+        round_up(1, 3);
+    }
+
+    #[test]
+    fn multiple_below_works() {
+        assert_eq!(multiple_below(0, 8), 0);
+        assert_eq!(multiple_below(7, 8), 0);
         assert_eq!(multiple_below(8, 8), 8);
         assert_eq!(multiple_below(9, 8), 8);
         assert_eq!(multiple_below(15, 8), 8);
@@ -318,276 +1797,1929 @@ mod tests {
         assert_eq!(multiple_below(100223, 128), 100096);
     }
 
-    /// Test performing frees populates the free list, correctly coalescing adjacent pages.
+    /// Test performing frees populates the free list, correctly coalescing adjacent pages.
+    #[test]
+    fn populates_free_list() {
+        let allocator = FreeListAllocator::new_in(MockGrower::new());
+        allocator.grower.set_used_pages(1); // Fake used pages large enough to we don't fail free list validation.
+        let mut scenario = AllocScenario::new();
+        scenario.expect_free_list(vec![]);
+        scenario.free_range(NODE_SIZE * 3, NODE_SIZE);
+        scenario.expect_free_list(vec![(NODE_SIZE * 3, NODE_SIZE)]);
+        // Free before, not contiguous
+        scenario.free_range(NODE_SIZE, NODE_SIZE);
+        scenario.expect_free_list(vec![(NODE_SIZE * 3, NODE_SIZE), (NODE_SIZE, NODE_SIZE)]);
+        // Free before, contiguous
+        scenario.free_range(0, NODE_SIZE);
+        scenario.expect_free_list(vec![(NODE_SIZE * 3, NODE_SIZE), (0, NODE_SIZE * 2)]);
+        // Free between, contiguous
+        scenario.free_range(NODE_SIZE * 2, NODE_SIZE);
+        scenario.expect_free_list(vec![(0, NODE_SIZE * 4)]);
+        // Free after, contiguous
+        scenario.free_range(NODE_SIZE * 4, NODE_SIZE);
+        scenario.expect_free_list(vec![(0, NODE_SIZE * 5)]);
+        // Free after, not contiguous
+        scenario.free_range(NODE_SIZE * 6, NODE_SIZE);
+        scenario.expect_free_list(vec![(NODE_SIZE * 6, NODE_SIZE), (0, NODE_SIZE * 5)]);
+        run_alloc_scenario(&allocator, &scenario);
+    }
+
+    #[test]
+    fn conformance() {
+        let allocator = FreeListAllocator::new_in(MockGrower::new());
+        crate::testing::conformance(&allocator);
+        allocator.validate_heap();
+    }
+
+    #[test]
+    fn alloc_with_excess() {
+        use crate::{AllocWithExcess, AllocatorStats};
+        let allocator = FreeListAllocator::new_in(MockGrower::new());
+        excess_conformance(&allocator);
+        allocator.validate_heap();
+        // Everything was freed, even though the frees used the larger sizes.
+        let used = allocator.grower.used_pages() * PAGE_SIZE;
+        assert_eq!(allocator.stats().free_bytes, Some(used));
+
+        unsafe {
+            // Sizes are rounded up to whole nodes, unless frees are checked against the exact size.
+            let layout = Layout::from_size_align(NODE_SIZE + 1, 1).unwrap();
+            let (ptr, usable) = allocator.alloc_with_excess(layout);
+            let expected = if size_check::ENABLED {
+                layout.size()
+            } else {
+                2 * NODE_SIZE
+            };
+            assert_eq!(usable, expected);
+            // The excess is not handed out to other allocations.
+            let next = allocator.alloc(Layout::from_size_align(1, 1).unwrap());
+            assert!(next as usize >= ptr as usize + usable || (next as usize) < ptr as usize);
+            allocator.dealloc(next, Layout::from_size_align(1, 1).unwrap());
+            allocator.dealloc(ptr, Layout::from_size_align(usable, 1).unwrap());
+        }
+        allocator.validate_heap();
+        assert_eq!(allocator.stats().free_bytes, Some(used));
+    }
+
+    #[test]
+    fn full_size_agrees_up_to_usable() {
+        for size in 0..200 {
+            for align in [1, 8, 16, 64] {
+                let layout = Layout::from_size_align(size, align).unwrap();
+                let full = full_size(layout);
+                assert!(full >= size);
+                for larger in size..=full {
+                    let larger = Layout::from_size_align(larger, align).unwrap();
+                    assert_eq!(full_size(larger), full);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn it_works() {
+        let allocator = FreeListAllocator::new_in(MockGrower::new());
+        let small = block_size(Layout::from_size_align(1, 1).unwrap());
+        let big_layout = Layout::from_size_align(NODE_SIZE * 2, NODE_SIZE * 2).unwrap();
+        let big = block_size(big_layout);
+        let multi = block_size(Layout::from_size_align(PAGE_SIZE + 1, 1).unwrap());
+        let mut scenario = AllocScenario::new();
+        scenario.expect_free_list(vec![]);
+        let alloc = scenario.alloc(1, 1);
+        scenario.expect_pages(1);
+        // Expect allocation at the end of first page.
+        scenario.expect_free_list(vec![(0, PAGE_SIZE - small)]);
+        // Merge into end of existing chunk
+        scenario.free(alloc);
+        scenario.expect_free_list(vec![(0, PAGE_SIZE)]);
+
+        // Allocate small value to impact alignment
+        let alloc = scenario.alloc(1, 1);
+        // Allocate larger aligned value to cause a hole after it
+        let alloc_big = scenario.alloc(NODE_SIZE * 2, NODE_SIZE * 2);
+        let big_start = multiple_below(PAGE_SIZE - small - big, big_layout.align());
+        scenario.expect_free_list(vec![
+            (big_start + big, PAGE_SIZE - small - big_start - big),
+            (0, big_start),
+        ]);
+
+        // Free second allocation, causing 3 way join
+        scenario.free(alloc_big);
+        scenario.expect_free_list(vec![(0, PAGE_SIZE - small)]);
+
+        // Multi-page allocation
+        scenario.expect_pages(1);
+        let multi_page = scenario.alloc(PAGE_SIZE + 1, 1);
+        scenario.expect_pages(3);
+        scenario.expect_free_list(vec![
+            (PAGE_SIZE, PAGE_SIZE * 2 - multi),
+            (0, PAGE_SIZE - small),
+        ]);
+
+        // Free everything
+        scenario.free(alloc);
+        scenario.expect_free_list(vec![(0, PAGE_SIZE * 3 - multi)]);
+        scenario.free(multi_page);
+        scenario.expect_free_list(vec![(0, PAGE_SIZE * 3)]);
+        run_alloc_scenario(&allocator, &scenario);
+    }
+
+    #[test]
+    fn fuzz() {
+        fuzz_with(MockGrower::new, 100);
+    }
+
+    /// Like [fuzz], in a wasm32 sized address space.
+    #[cfg(unix)]
+    #[test]
+    fn fuzz_reservation() {
+        fuzz_with(Reservation::new, 10);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn reservation_conformance() {
+        let allocator = FreeListAllocator::new_in(Reservation::new());
+        crate::testing::conformance(&allocator);
+        excess_conformance(&allocator);
+        assert_eq!(
+            free_blocks(&allocator),
+            [FreeBlock {
+                size: allocator.grower.used_pages() * PAGE_SIZE,
+                offset: 0,
+            }]
+        );
+    }
+
+    /// Memory can be used right up to the end of the wasm32 address space,
+    /// where address arithmetic in wasm has no room left before wrapping around to 0.
+    /// Testing this with [MockGrower] would need 4 GiB of real memory.
+    #[cfg(unix)]
+    #[test]
+    fn grows_to_end_of_address_space() {
+        let reservation = Reservation::new();
+        reservation.skip_pages(RESERVATION_PAGES - 2);
+        let allocator = FreeListAllocator::new_in(reservation);
+        let end = RESERVATION_PAGES * PAGE_SIZE;
+        let layout = Layout::from_size_align(PAGE_SIZE + PAGE_SIZE / 2, 8).unwrap();
+        unsafe {
+            let first = allocator.alloc(layout);
+            assert!(!first.is_null());
+            first.write_bytes(1, layout.size());
+            assert_eq!(allocator.grower.used_pages(), RESERVATION_PAGES);
+            let free = free_blocks(&allocator);
+            assert_eq!(free.len(), 1);
+            assert_eq!(
+                free[0].size + block_size(layout),
+                2 * PAGE_SIZE,
+                "the rest of the last page is free"
+            );
+
+            // Nothing is left to grow, and failing leaves the heap intact.
+            let too_large = Layout::from_size_align(PAGE_SIZE, 8).unwrap();
+            assert!(allocator.alloc(too_large).is_null());
+            assert_eq!(free_blocks(&allocator), free);
+
+            // Freeing coalesces into one block ending exactly at 4 GiB.
+            allocator.dealloc(first, layout);
+            assert_eq!(
+                free_blocks(&allocator),
+                [FreeBlock {
+                    size: 2 * PAGE_SIZE,
+                    offset: end - 2 * PAGE_SIZE,
+                }]
+            );
+            assert_eq!(
+                allocator.largest_allocatable_block(1),
+                2 * PAGE_SIZE - size_check::prefix_space(1)
+            );
+
+            // All of it, including the last byte of the address space, can be allocated again.
+            let whole = block_layout(2 * PAGE_SIZE, 8);
+            let ptr = allocator.alloc(whole);
+            assert_eq!(
+                allocator.grower.offset(block_start(ptr, whole)),
+                end - 2 * PAGE_SIZE
+            );
+            let last = allocator.grower.address(end - 1);
+            last.write(7);
+            assert_eq!(*ptr.add(whole.size() - 1), 7);
+            allocator.dealloc(ptr, whole);
+        }
+        allocator.validate_heap();
+    }
+
+    /// Runs `iterations` random workloads, each with a new allocator growing into `new_grower()`.
+    fn fuzz_with<G: TestGrower + MemoryGrower>(new_grower: impl Fn() -> G, iterations: usize) {
+        use rand::Rng;
+        use rand_core::SeedableRng;
+        use rand_pcg::Pcg32;
+
+        let mut rng = Pcg32::seed_from_u64(0);
+
+        for _ in 0..iterations {
+            let allocator = FreeListAllocator::new_in(new_grower());
+
+            // The block carved from the free list for an allocation, which starts with the prefix if there is one.
+            let block = |alloc: &Allocation| {
+                let prefix = size_check::prefix_space(alloc.layout.align());
+                let size = full_size(size_check::inner_layout(alloc.layout).unwrap());
+                (alloc.ptr.wrapping_sub(prefix), size)
+            };
+            let mut tracker = AllocationTracker::new(&allocator);
+            let allocate = |tracker: &mut AllocationTracker<_>, size: usize, align: usize| {
+                let alloc = tracker.alloc(Layout::from_size_align(size, align).unwrap());
+                // Allocated memory is exclusively the caller's: none of it is still in the free list.
+                let (start, size) = block(&alloc);
+                assert!(!allocator.is_free(start, size));
+                assert!(!overlaps_free(&allocator, start, size));
+                alloc
+            };
+            let free = |tracker: &mut AllocationTracker<_>, alloc: Allocation| {
+                tracker.free(alloc);
+                let (start, size) = block(&alloc);
+                assert!(allocator.is_free(start, size));
+            };
+
+            let mut allocations = vec![];
+            for _ in 0..5000 {
+                // Randomly free some allocations.
+                while !allocations.is_empty() {
+                    if rng.gen_bool(0.45) {
+                        let alloc = allocations.swap_remove(rng.gen_range(0..allocations.len()));
+                        free(&mut tracker, alloc);
+                    } else {
+                        break;
+                    }
+                }
+                // Do a random small allocation
+                let size = rng.gen_range(1..100);
+                allocations.push(allocate(&mut tracker, size, 1 << rng.gen_range(0..7)));
+                if rng.gen_bool(0.05) {
+                    // Do a random large allocation
+                    let size = rng.gen_range(1..(PAGE_SIZE * 10));
+                    allocations.push(allocate(&mut tracker, size, 1 << rng.gen_range(0..16)));
+                }
+            }
+            tracker.check();
+            free_blocks(&allocator);
+            while !allocations.is_empty() {
+                let alloc = allocations.swap_remove(rng.gen_range(0..allocations.len()));
+                free(&mut tracker, alloc);
+            }
+            assert_eq!(
+                free_blocks(&allocator),
+                [FreeBlock {
+                    size: allocator.grower.used_pages() * PAGE_SIZE,
+                    offset: 0,
+                }]
+            );
+        }
+    }
+
+    /// Pathological pattern for address ordered free lists:
+    /// alternating small and large allocations where only the small ones are freed,
+    /// followed by another round of large allocations which can't use the small holes left behind.
+    ///
+    /// Small allocations sit between large ones, so their holes can't coalesce:
+    /// expect one free node per freed small allocation,
+    /// and the second round of large allocations to need about as much memory as the first.
+    ///
+    /// Each round of `PAIRS` allocations uses about `PAIRS * (SMALL + LARGE)` bytes: 16 pages.
+    /// The limits below have headroom over the current behavior (32 pages, 1015 nodes),
+    /// but fail if the large allocations stopped being packed or the node count blows up.
+    #[test]
+    fn fragmentation_alternating_small_large() {
+        const PAIRS: usize = 1000;
+        const SMALL: usize = NODE_SIZE;
+        const LARGE: usize = 1000;
+        let allocator = FreeListAllocator::new_in(MockGrower::new());
+        let small = Layout::from_size_align(SMALL, 1).unwrap();
+        let large = Layout::from_size_align(LARGE, 1).unwrap();
+        unsafe {
+            let mut large_allocations = vec![];
+            let mut small_allocations = vec![];
+            for _ in 0..PAIRS {
+                small_allocations.push(allocator.alloc(small));
+                large_allocations.push(allocator.alloc(large));
+            }
+            for ptr in small_allocations.drain(..) {
+                allocator.dealloc(ptr, small);
+            }
+            for _ in 0..PAIRS {
+                large_allocations.push(allocator.alloc(large));
+            }
+            // Small allocations fit in existing free space, so should not grow.
+            let pages_before_small = allocator.grower.used_pages();
+            for _ in 0..PAIRS {
+                small_allocations.push(allocator.alloc(small));
+            }
+            assert_eq!(allocator.grower.used_pages(), pages_before_small);
+
+            let pages = allocator.grower.used_pages();
+            let nodes = free_blocks(&allocator).len();
+            assert!(pages <= 40, "grew {pages} pages");
+            assert!(nodes <= 1200, "{nodes} free list nodes");
+
+            for ptr in large_allocations {
+                allocator.dealloc(ptr, large);
+            }
+            for ptr in small_allocations {
+                allocator.dealloc(ptr, small);
+            }
+            assert_eq!(free_blocks(&allocator).len(), 1);
+        }
+    }
+
+    /// Pathological pattern for address ordered free lists:
+    /// freeing every other element of a large array of boxes,
+    /// then allocating larger boxes which don't fit in any of the holes.
+    ///
+    /// Expect one free node for each freed box,
+    /// and the larger boxes to be served from newly grown memory (`COUNT * NODE_SIZE` bytes each round: 2.5 pages with 16 byte nodes).
+    /// The limits below have headroom over the current behavior (5 pages with 16 byte nodes, 5001 nodes),
+    /// but fail if memory use or node count blows up (for example from failing to coalesce).
+    #[test]
+    fn fragmentation_free_every_other() {
+        const COUNT: usize = 10000;
+        let allocator = FreeListAllocator::new_in(MockGrower::new());
+        let small = Layout::from_size_align(NODE_SIZE, NODE_SIZE).unwrap();
+        let larger = Layout::from_size_align(NODE_SIZE * 2, NODE_SIZE).unwrap();
+        unsafe {
+            let mut boxes: Vec<*mut u8> = (0..COUNT).map(|_| allocator.alloc(small)).collect();
+            let mut kept = vec![];
+            for (i, ptr) in boxes.drain(..).enumerate() {
+                if i % 2 == 0 {
+                    allocator.dealloc(ptr, small);
+                } else {
+                    kept.push(ptr);
+                }
+            }
+            let larger_boxes: Vec<*mut u8> =
+                (0..COUNT / 2).map(|_| allocator.alloc(larger)).collect();
+
+            let pages = allocator.grower.used_pages();
+            let nodes = free_blocks(&allocator).len();
+            let live = COUNT * block_size(small) + COUNT / 2 * block_size(larger);
+            assert!(pages <= live / PAGE_SIZE + 3, "grew {pages} pages");
+            assert!(nodes <= 5100, "{nodes} free list nodes");
+
+            for ptr in kept {
+                allocator.dealloc(ptr, small);
+            }
+            for ptr in larger_boxes {
+                allocator.dealloc(ptr, larger);
+            }
+            assert_eq!(free_blocks(&allocator).len(), 1);
+        }
+    }
+
+    /// Allocates an over-aligned block from a free list where the first block would be mostly wasted by alignment,
+    /// returning the free list afterwards.
+    fn alloc_over_aligned<const SKIP_WASTEFUL: bool>(
+        allocator: FreeListAllocator<MockGrower, DEFAULT_SENTINEL, SKIP_WASTEFUL>,
+    ) -> Vec<FreeBlock> {
+        allocator.grower.set_used_pages(1);
+        let base = allocator.grower.base() as *mut u8;
+        unsafe {
+            // Placing NODE_SIZE bytes aligned to NODE_SIZE * 4 at the end of this block leaves 3/4 of it unused.
+            allocator.free(base.add(NODE_SIZE * 28), NODE_SIZE * 4);
+            // The end of this block minus NODE_SIZE is aligned, so it fits without waste.
+            allocator.free(base, NODE_SIZE * 17);
+            // Allocates the block directly: the `dealloc-size-check` prefix is at least as large as the alignment,
+            // which would make the allocation too large for any block to be wasteful.
+            let layout = Layout::from_size_align(NODE_SIZE, NODE_SIZE * 4).unwrap();
+            let ptr = allocator.alloc_unlisted(layout, None::<fn(usize) -> usize>);
+            assert!(!ptr.is_null());
+        }
+        assert_eq!(allocator.grower.used_pages(), 1);
+        free_blocks(&allocator)
+    }
+
+    #[test]
+    fn skip_wasteful() {
+        // By default the first block that fits is used, leaving a small fragment.
+        assert_eq!(
+            alloc_over_aligned(FreeListAllocator::new_in(MockGrower::new())),
+            [
+                FreeBlock {
+                    size: NODE_SIZE * 3,
+                    offset: NODE_SIZE * 29,
+                },
+                FreeBlock {
+                    size: NODE_SIZE * 17,
+                    offset: 0,
+                }
+            ]
+        );
+        // Skipping the wasteful block leaves no fragments.
+        assert_eq!(
+            alloc_over_aligned(FreeListAllocator::new_skip_wasteful_in(MockGrower::new())),
+            [
+                FreeBlock {
+                    size: NODE_SIZE * 4,
+                    offset: NODE_SIZE * 28,
+                },
+                FreeBlock {
+                    size: NODE_SIZE * 16,
+                    offset: 0,
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn skip_wasteful_uses_grown_memory() {
+        let allocator = FreeListAllocator::new_skip_wasteful_in(MockGrower::new());
+        // A whole page is still a wasteful block for this allocation,
+        // but it must be used once it has been grown.
+        let layout = Layout::from_size_align(NODE_SIZE, PAGE_SIZE).unwrap();
+        unsafe {
+            assert!(!allocator.alloc(layout).is_null());
+        }
+        assert_eq!(
+            allocator.grower.used_pages(),
+            block_size(layout).div_ceil(PAGE_SIZE)
+        );
+    }
+
+    #[test]
+    fn static_chunk_avoids_growing() {
+        let allocator: StaticChunkFreeListAllocator<1024, MockGrower> =
+            unsafe { FreeListAllocator::new_with_static_chunk_in(MockGrower::new()) };
+        let chunk =
+            allocator.static_chunk.get() as usize..allocator.static_chunk.get() as usize + 1024;
+        unsafe {
+            let mut allocations = vec![];
+            for size in [1, 8, 100, 200, 16] {
+                let layout = Layout::from_size_align(size, 8).unwrap();
+                let ptr = allocator.alloc(layout);
+                assert!(chunk.contains(&(ptr as usize)));
+                allocations.push((ptr, layout));
+            }
+            for (ptr, layout) in allocations {
+                allocator.dealloc(ptr, layout);
+            }
+        }
+        assert_eq!(allocator.grower.used_pages(), 0);
+        allocator.validate_heap();
+    }
+
+    #[test]
+    fn static_chunk_then_grown() {
+        use crate::AllocatorStats;
+        let allocator: StaticChunkFreeListAllocator<1024, MockGrower> =
+            unsafe { FreeListAllocator::new_with_static_chunk_in(MockGrower::new()) };
+        let chunk =
+            allocator.static_chunk.get() as usize..allocator.static_chunk.get() as usize + 1024;
+        let small = Layout::from_size_align(512, 8).unwrap();
+        let large = Layout::from_size_align(1024, 8).unwrap();
+        unsafe {
+            let a = allocator.alloc(small);
+            assert!(chunk.contains(&(a as usize)));
+            // Does not fit in the rest of the static chunk.
+            let b = allocator.alloc(large);
+            assert!(!chunk.contains(&(b as usize)));
+            assert_eq!(allocator.grower.used_pages(), 1);
+            allocator.dealloc(a, small);
+            allocator.dealloc(b, large);
+        }
+        allocator.validate_heap();
+        // The static chunk and the grown page stay separate free blocks.
+        let (start, end) = allocator.static_chunk_bounds();
+        assert!(allocator.is_free(start as *const u8, end - start));
+        let mut blocks = vec![];
+        unsafe {
+            let mut list = *allocator.free_list.get();
+            while list as usize != DEFAULT_SENTINEL {
+                blocks.push((list as usize, (*list).size));
+                list = (*list).next;
+            }
+        }
+        let page = allocator.grower.base();
+        blocks.sort();
+        let mut expected = vec![(start, end - start), (page, PAGE_SIZE)];
+        expected.sort();
+        assert_eq!(blocks, expected);
+        assert_eq!(allocator.stats().free_blocks, Some(2));
+    }
+
+    #[cfg(feature = "reset-heap")]
+    #[test]
+    fn reset_heap() {
+        let allocator = FreeListAllocator::new_in(MockGrower::new());
+        let small = Layout::from_size_align(100, 8).unwrap();
+        let large = block_layout(PAGE_SIZE * 2, 8);
+        let invocation = || unsafe {
+            let ptrs = [
+                allocator.alloc(small),
+                allocator.alloc(large),
+                allocator.alloc(small),
+            ];
+            // Deliberately leak the last allocation.
+            allocator.dealloc(ptrs[0], small);
+            allocator.dealloc(ptrs[1], large);
+            ptrs
+        };
+        invocation();
+        assert_eq!(free_blocks(&allocator).len(), 2);
+        unsafe { allocator.reset_heap() };
+        assert_eq!(
+            free_blocks(&allocator),
+            [FreeBlock {
+                size: PAGE_SIZE * 3,
+                offset: 0,
+            }]
+        );
+        // Later invocations reuse the same memory, at the same addresses.
+        let used_pages = allocator.grower.used_pages();
+        let second = invocation();
+        unsafe { allocator.reset_heap() };
+        assert_eq!(invocation(), second);
+        assert_eq!(allocator.grower.used_pages(), used_pages);
+    }
+
+    #[cfg(feature = "reset-heap")]
+    #[test]
+    fn dealloc_all() {
+        let allocator = FreeListAllocator::new_in(MockGrower::new());
+        let layouts = [
+            Layout::from_size_align(100, 8).unwrap(),
+            block_layout(PAGE_SIZE * 2, 8),
+            Layout::from_size_align(1, 1).unwrap(),
+            Layout::from_size_align(300, 64).unwrap(),
+        ];
+        unsafe {
+            let ptrs = layouts.map(|layout| allocator.alloc(layout));
+            allocator.dealloc(ptrs[1], layouts[1]);
+            allocator.dealloc(ptrs[2], layouts[2]);
+            let leaked = block_size(layouts[0]) + block_size(layouts[3]);
+            assert_eq!(allocator.dealloc_all(), leaked);
+            assert_eq!(allocator.dealloc_all(), 0);
+        }
+        let used_pages = allocator.grower.used_pages();
+        assert_eq!(
+            free_blocks(&allocator),
+            [FreeBlock {
+                size: used_pages * PAGE_SIZE,
+                offset: 0,
+            }]
+        );
+    }
+
+    #[cfg(feature = "reset-heap")]
+    #[test]
+    fn export_reset_heap() {
+        use crate::{GlobalArrayGrower, LockedAllocator};
+        static ALLOCATOR: LockedAllocator<FreeListAllocator<GlobalArrayGrower<2>>> =
+            LockedAllocator::new(FreeListAllocator::new_in(GlobalArrayGrower::new()));
+        crate::export_reset_heap!(ALLOCATOR.lock());
+
+        let layout = block_layout(PAGE_SIZE, 8);
+        unsafe {
+            // Leak all the memory.
+            assert!(!ALLOCATOR.alloc(layout).is_null());
+            let second = ALLOCATOR.alloc(layout);
+            assert!(!second.is_null());
+            assert!(ALLOCATOR.alloc(layout).is_null());
+            lol_alloc_reset_heap();
+            assert_eq!(ALLOCATOR.alloc(layout), second);
+            assert!(!ALLOCATOR.alloc(layout).is_null());
+        }
+        ALLOCATOR.lock().validate_heap();
+    }
+
+    #[cfg(feature = "jitter")]
+    #[test]
+    fn jitter() {
+        let addresses = |seed| {
+            let allocator = FreeListAllocator::new_in(MockGrower::new()).with_jitter_seed(seed);
+            crate::testing::conformance(&allocator);
+            allocator.validate_heap();
+            let layout = Layout::from_size_align(24, 8).unwrap();
+            let ptrs: Vec<*mut u8> = (0..20)
+                .map(|_| unsafe { allocator.alloc(layout) })
+                .collect();
+            let base = allocator.grower.base();
+            let offsets: Vec<usize> = ptrs
+                .iter()
+                .map(|&p| block_start(p, layout) as usize - base)
+                .collect();
+            // Allocations are still aligned, and do not overlap.
+            for (i, &offset) in offsets.iter().enumerate() {
+                assert_eq!(offset % NODE_SIZE, 0);
+                for &other in &offsets[..i] {
+                    assert!(offset + 32 <= other || other + 32 <= offset);
+                }
+            }
+            for ptr in ptrs {
+                unsafe { allocator.dealloc(ptr, layout) };
+            }
+            // Jitter gaps are reclaimed on free.
+            let free = free_blocks(&allocator);
+            assert_eq!(
+                free.iter().map(|node| node.size).sum::<usize>(),
+                allocator.grower.used_pages() * PAGE_SIZE
+            );
+            offsets
+        };
+        assert_eq!(addresses(1), addresses(1));
+        assert_ne!(addresses(1), addresses(2));
+    }
+
+    #[cfg(feature = "large-objects")]
+    fn page_pool_content(allocator: &FreeListAllocator<MockGrower>) -> Vec<FreeBlock> {
+        let base = allocator.grower.base();
+        let mut out = vec![];
+        unsafe {
+            let mut list = *allocator.page_pool.get();
+            while list as usize != DEFAULT_SENTINEL {
+                out.push(FreeBlock {
+                    size: (*list).size,
+                    offset: list as usize - base,
+                });
+                list = (*list).next;
+            }
+        }
+        out
+    }
+
+    #[cfg(feature = "large-objects")]
+    #[test]
+    fn large_objects() {
+        use crate::{AllocatorStats, LARGE_OBJECT_THRESHOLD};
+        let allocator = FreeListAllocator::new_in(MockGrower::new())
+            .with_large_object_threshold(LARGE_OBJECT_THRESHOLD);
+        crate::testing::conformance(&allocator);
+        allocator.validate_heap();
+
+        let layouts: Vec<Layout> = [
+            (LARGE_OBJECT_THRESHOLD - 1, 8),
+            (LARGE_OBJECT_THRESHOLD, 8),
+            (LARGE_OBJECT_THRESHOLD + 1, 1),
+            (PAGE_SIZE - 8, 8),
+            (PAGE_SIZE, 4096),
+            (PAGE_SIZE * 3 + 5, 16),
+            (100, 8),
+        ]
+        .into_iter()
+        .map(|(size, align)| Layout::from_size_align(size, align).unwrap())
+        .collect();
+        // Bytes of the heap each allocation uses, including any `dealloc-size-check` prefix.
+        let footprint = |layout: &Layout| {
+            let layout = size_check::inner_layout(*layout).unwrap();
+            if layout.size() >= LARGE_OBJECT_THRESHOLD {
+                round_up(layout.size() + layout.align().max(8), PAGE_SIZE)
+            } else {
+                super::full_size(layout)
+            }
+        };
+        let heap_bytes = || allocator.grower.used_pages() * PAGE_SIZE;
+        unsafe {
+            let ptrs: Vec<*mut u8> = layouts.iter().map(|&l| allocator.alloc(l)).collect();
+            for (&ptr, layout) in ptrs.iter().zip(&layouts) {
+                assert!(!ptr.is_null());
+                assert_eq!(ptr as usize % layout.align(), 0);
+                ptr.write_bytes(0xFF, layout.size());
+                // Large allocations are never in the free list, even at their ends.
+                assert!(!allocator.is_free(ptr, 1));
+                assert!(!allocator.is_free(ptr.add(layout.size() - 1), 1));
+            }
+            allocator.validate_heap();
+            // All pages are either free or in use by an allocation.
+            let live: usize = layouts.iter().map(footprint).sum();
+            assert_eq!(allocator.stats().free_bytes.unwrap() + live, heap_bytes());
+
+            let pages = allocator.grower.used_pages();
+            for (&ptr, layout) in ptrs.iter().zip(&layouts) {
+                allocator.dealloc(ptr, *layout);
+            }
+            allocator.validate_heap();
+            assert_eq!(allocator.stats().free_bytes, Some(heap_bytes()));
+            // Freed page runs went to the page pool, not the free list.
+            let pooled: usize = page_pool_content(&allocator).iter().map(|n| n.size).sum();
+            let large: usize = layouts[1..6].iter().map(footprint).sum();
+            assert!(pooled >= large);
+            assert!(pooled.is_multiple_of(PAGE_SIZE));
+
+            // The largest run in the page pool is the largest allocation.
+            let largest = allocator.largest_allocatable_block(16);
+            assert!(largest >= PAGE_SIZE * 3 + 5);
+            // A run of pages, less the header and any `dealloc-size-check` prefix.
+            assert_eq!((largest + 16 + size_check::prefix_space(16)) % PAGE_SIZE, 0);
+
+            // Large allocations are served from the page pool without growing.
+            let ptrs: Vec<*mut u8> = layouts.iter().map(|&l| allocator.alloc(l)).collect();
+            assert_eq!(allocator.grower.used_pages(), pages);
+            for (&ptr, layout) in ptrs.iter().zip(&layouts) {
+                allocator.dealloc(ptr, *layout);
+            }
+        }
+    }
+
+    /// Alternates small and large allocations, then frees the large ones,
+    /// returning the number of free list nodes and the pages grown after allocating more small objects.
+    #[cfg(feature = "large-objects")]
+    fn small_fragmentation(allocator: FreeListAllocator<MockGrower>) -> (usize, usize) {
+        const PAIRS: usize = 100;
+        let small = Layout::from_size_align(NODE_SIZE, 1).unwrap();
+        let large = Layout::from_size_align(crate::LARGE_OBJECT_THRESHOLD, 1).unwrap();
+        unsafe {
+            let mut large_allocations = vec![];
+            let mut small_allocations = vec![];
+            for _ in 0..PAIRS {
+                small_allocations.push(allocator.alloc(small));
+                large_allocations.push(allocator.alloc(large));
+            }
+            for ptr in large_allocations {
+                allocator.dealloc(ptr, large);
+            }
+            for _ in 0..PAIRS {
+                small_allocations.push(allocator.alloc(small));
+            }
+            let result = (free_blocks(&allocator).len(), allocator.grower.used_pages());
+            for ptr in small_allocations {
+                allocator.dealloc(ptr, small);
+            }
+            result
+        }
+    }
+
+    #[cfg(feature = "large-objects")]
+    #[test]
+    fn large_objects_excess() {
+        use crate::{AllocWithExcess, AllocatorStats, LARGE_OBJECT_THRESHOLD};
+        let allocator = FreeListAllocator::new_in(MockGrower::new())
+            .with_large_object_threshold(LARGE_OBJECT_THRESHOLD);
+        excess_conformance(&allocator);
+        allocator.validate_heap();
+        let free = allocator.stats().free_bytes;
+
+        unsafe {
+            // Large allocations can use the rest of their run of pages, unless frees are checked against the exact size.
+            let large = Layout::from_size_align(LARGE_OBJECT_THRESHOLD, 8).unwrap();
+            let (ptr, usable) = allocator.alloc_with_excess(large);
+            let expected = if size_check::ENABLED {
+                large.size()
+            } else {
+                PAGE_SIZE - 8
+            };
+            assert_eq!(usable, expected);
+            ptr.write_bytes(1, usable);
+            allocator.dealloc(ptr, Layout::from_size_align(usable, 8).unwrap());
+
+            // Small allocations are not given enough excess to be freed as large ones.
+            let small = Layout::from_size_align(LARGE_OBJECT_THRESHOLD - 1, 1).unwrap();
+            let (ptr, usable) = allocator.alloc_with_excess(small);
+            assert_eq!(usable, LARGE_OBJECT_THRESHOLD - 1);
+            allocator.dealloc(ptr, Layout::from_size_align(usable, 1).unwrap());
+        }
+        allocator.validate_heap();
+        assert_eq!(allocator.stats().free_bytes, free);
+    }
+
+    /// The free list stays compact with large objects enabled (1 node, instead of 101 without).
+    #[cfg(feature = "large-objects")]
+    #[test]
+    fn large_objects_fragmentation() {
+        let (nodes, pages) = small_fragmentation(FreeListAllocator::new_in(MockGrower::new()));
+        let (large_objects_nodes, large_objects_pages) = small_fragmentation(
+            FreeListAllocator::new_in(MockGrower::new())
+                .with_large_object_threshold(crate::LARGE_OBJECT_THRESHOLD),
+        );
+        // Without the page pool, freed large allocations leave a hole between each pair of small ones.
+        assert!(nodes > 100, "{nodes} free list nodes");
+        assert!(pages < 40, "grew {pages} pages");
+        // With it, the small allocations are packed together, at the cost of rounding each large one up to a page.
+        assert_eq!(large_objects_nodes, 1);
+        assert!(
+            large_objects_pages <= 102,
+            "grew {large_objects_pages} pages"
+        );
+    }
+
+    /// Checks [FreeListAllocator::for_each_live_block] exactly matches a shadow set of allocations,
+    /// through a random workload of allocations, frees and reallocs.
+    #[cfg(feature = "live-blocks")]
+    #[test]
+    fn live_blocks() {
+        use rand::Rng;
+        use rand_core::SeedableRng;
+        use rand_pcg::Pcg32;
+        use std::collections::BTreeMap;
+
+        let mut rng = Pcg32::seed_from_u64(0);
+        let allocator = FreeListAllocator::new_with_live_blocks_in(MockGrower::new());
+        let live_blocks = || {
+            let mut blocks = BTreeMap::new();
+            unsafe {
+                allocator.for_each_live_block(|ptr, size| {
+                    assert!(blocks.insert(ptr as usize, size).is_none());
+                })
+            };
+            blocks
+        };
+        // Layout of each live allocation, by address.
+        let mut shadow: BTreeMap<usize, Layout> = BTreeMap::new();
+        for _ in 0..5000 {
+            unsafe {
+                match rng.gen_range(0..3) {
+                    0 if !shadow.is_empty() => {
+                        let (&ptr, &layout) =
+                            shadow.iter().nth(rng.gen_range(0..shadow.len())).unwrap();
+                        shadow.remove(&ptr);
+                        allocator.dealloc(ptr as *mut u8, layout);
+                    }
+                    1 if !shadow.is_empty() => {
+                        let (&ptr, &layout) =
+                            shadow.iter().nth(rng.gen_range(0..shadow.len())).unwrap();
+                        let new_size = rng.gen_range(1..300);
+                        let new_ptr = allocator.realloc(ptr as *mut u8, layout, new_size);
+                        assert!(!new_ptr.is_null());
+                        shadow.remove(&ptr);
+                        shadow.insert(
+                            new_ptr as usize,
+                            Layout::from_size_align(new_size, layout.align()).unwrap(),
+                        );
+                    }
+                    _ => {
+                        let layout = Layout::from_size_align(
+                            rng.gen_range(1..300),
+                            1 << rng.gen_range(0..8),
+                        )
+                        .unwrap();
+                        let ptr = allocator.alloc(layout);
+                        assert!(!ptr.is_null());
+                        assert_eq!(ptr as usize % layout.align(), 0);
+                        shadow.insert(ptr as usize, layout);
+                    }
+                }
+            }
+            let expected: BTreeMap<usize, usize> = shadow
+                .iter()
+                .map(|(&ptr, layout)| (ptr, layout.size()))
+                .collect();
+            assert_eq!(live_blocks(), expected);
+        }
+        for (&ptr, &layout) in &shadow {
+            unsafe { allocator.dealloc(ptr as *mut u8, layout) };
+        }
+        assert!(live_blocks().is_empty());
+        assert_eq!(free_blocks(&allocator).len(), 1);
+    }
+
+    /// The live block headers record the exact size, so there is no excess.
+    #[cfg(feature = "live-blocks")]
+    #[test]
+    fn live_blocks_excess() {
+        use crate::AllocWithExcess;
+        let allocator = FreeListAllocator::new_with_live_blocks_in(MockGrower::new());
+        excess_conformance(&allocator);
+        let layout = Layout::from_size_align(1, 1).unwrap();
+        unsafe {
+            let (ptr, usable) = allocator.alloc_with_excess(layout);
+            assert_eq!(usable, 1);
+            allocator.dealloc(ptr, layout);
+        }
+        allocator.validate_heap();
+    }
+
+    #[cfg(feature = "live-blocks")]
+    #[test]
+    fn live_blocks_through_wrapper() {
+        let allocator = crate::LockedAllocator::new(FreeListAllocator::new_with_live_blocks_in(
+            MockGrower::new(),
+        ));
+        let layout = Layout::from_size_align(10, 4).unwrap();
+        unsafe {
+            let ptr = allocator.alloc(layout);
+            let mut blocks = vec![];
+            allocator
+                .lock()
+                .for_each_live_block(|ptr, size| blocks.push((ptr, size)));
+            assert_eq!(blocks, [(ptr, 10)]);
+            allocator.dealloc(ptr, layout);
+        }
+    }
+
+    #[cfg(feature = "peak-free-list-len")]
+    #[test]
+    fn peak_free_list_len() {
+        let allocator = FreeListAllocator::new_in(MockGrower::new());
+        let layout = Layout::from_size_align(NODE_SIZE, 1).unwrap();
+        assert_eq!(allocator.peak_free_list_len(), 0);
+        unsafe {
+            let allocations: Vec<*mut u8> = (0..5).map(|_| allocator.alloc(layout)).collect();
+            assert_eq!(allocator.peak_free_list_len(), 1);
+            let mut peaks = vec![];
+            // Free in an order which first fragments the free list, then coalesces it back into one node.
+            for i in [0, 2, 4, 1, 3] {
+                allocator.dealloc(allocations[i], layout);
+                peaks.push(allocator.peak_free_list_len());
+            }
+            assert_eq!(free_blocks(&allocator).len(), 1);
+            assert_eq!(peaks, [2, 3, 3, 3, 3]);
+        }
+    }
+
+    #[test]
+    fn partial_grants_contiguous() {
+        let allocator = FreeListAllocator::new_in(OnePageGrower::new(false));
+        let layout = block_layout(PAGE_SIZE * 3, 1);
+        unsafe {
+            let ptr = allocator.alloc(layout);
+            assert!(!ptr.is_null());
+            // Each grant extends the same free block, so growing keeps going until it fits.
+            assert_eq!(allocator.grower.calls.get(), 3);
+            allocator.validate_heap();
+            allocator.dealloc(ptr, layout);
+            assert_eq!(
+                allocator.free_block_size_containing(ptr as usize),
+                PAGE_SIZE * 3
+            );
+        }
+    }
+
+    /// Growing only searches the block the grown memory was added to, not the whole free list again.
+    #[test]
+    fn partial_grants_search_grown_block() {
+        let allocator = FreeListAllocator::new_in(OnePageGrower::new(false));
+        let small = Layout::from_size_align(NODE_SIZE, NODE_SIZE).unwrap();
+        unsafe {
+            // Fill the first page, then free every other allocation, to make a long free list.
+            let mut ptrs = vec![allocator.alloc(small)];
+            while allocator.largest_allocatable_block(NODE_SIZE) >= NODE_SIZE {
+                ptrs.push(allocator.alloc(small));
+            }
+            for &ptr in ptrs.iter().step_by(2) {
+                allocator.dealloc(ptr, small);
+            }
+            let free_nodes = allocator.stats().free_blocks.unwrap();
+            let searched = allocator.nodes_searched();
+            let layout = block_layout(PAGE_SIZE * 3, 1);
+            let ptr = allocator.alloc(layout);
+            assert!(!ptr.is_null());
+            assert_eq!(allocator.grower.calls.get(), 1 + 3);
+            // The search before growing, then one node for each grant.
+            assert_eq!(allocator.nodes_searched() - searched, free_nodes + 3);
+            allocator.validate_heap();
+            allocator.dealloc(ptr, layout);
+        }
+    }
+
+    /// Memory grown just after the first free block extends it, leaving one block at every step,
+    /// without writing a node into the grown memory.
+    #[test]
+    fn grown_memory_extends_trailing_block() {
+        let allocator = FreeListAllocator::new_in(MockGrower::new());
+        let base = allocator.grower.base() as *mut u8;
+        unsafe {
+            let first = Layout::from_size_align(NODE_SIZE, 1).unwrap();
+            allocator.dealloc(allocator.alloc(first), first);
+            for _ in 0..3 {
+                let used = allocator.grower.used_pages();
+                let free = used * PAGE_SIZE;
+                assert_eq!(
+                    free_blocks(&allocator),
+                    [FreeBlock {
+                        size: free,
+                        offset: 0
+                    }]
+                );
+                // Does not fit in the free block, so grows the one missing page, which is still zero.
+                let layout = block_layout(free + PAGE_SIZE, 1);
+                spoil(base.add(free), PAGE_SIZE);
+                let searched = allocator.nodes_searched();
+                let ptr = allocator.alloc_zeroed(layout);
+                assert_eq!(block_start(ptr, layout), base);
+                assert_eq!(allocator.grower.used_pages(), used + 1);
+                // The search before growing, then the extended block.
+                assert_eq!(allocator.nodes_searched() - searched, 2);
+                let bytes = core::slice::from_raw_parts(ptr, layout.size());
+                let used = free - size_check::prefix_space(1);
+                assert!(bytes[..used].iter().all(|&byte| byte == 0));
+                assert!(bytes[used..].iter().all(|&byte| byte == 0xFF));
+                assert_eq!(free_blocks(&allocator), []);
+                allocator.dealloc(ptr, layout);
+            }
+        }
+    }
+
+    /// A free block at the end of memory counts towards growing, even when it is a single node,
+    /// so an allocation just over a page grows one page instead of two.
+    #[test]
+    fn grows_only_missing_pages() {
+        for (align, pages) in [(1, 1), (NODE_SIZE, 1), (NODE_SIZE * 2, 2)] {
+            let allocator = FreeListAllocator::new_in(MockGrower::new());
+            let node = block_layout(NODE_SIZE, 1);
+            let rest = block_layout(PAGE_SIZE - NODE_SIZE, 1);
+            unsafe {
+                // Fill the first page, then free its last node.
+                let last = allocator.alloc(node);
+                allocator.alloc(rest);
+                allocator.dealloc(last, node);
+                assert_eq!(
+                    free_blocks(&allocator),
+                    [FreeBlock {
+                        size: NODE_SIZE,
+                        offset: PAGE_SIZE - NODE_SIZE,
+                    }]
+                );
+
+                let layout = block_layout(PAGE_SIZE + NODE_SIZE, align);
+                let ptr = allocator.alloc(layout);
+                assert_eq!(allocator.grower.used_pages(), 1 + pages, "align {align}");
+                if pages == 1 {
+                    // Exactly fills the free node and the grown page.
+                    assert_eq!(block_start(ptr, layout), block_start(last, node));
+                    assert_eq!(free_blocks(&allocator), []);
+                } else {
+                    // Aligning it leaves the free node unused.
+                    assert_eq!(ptr as usize % align, 0);
+                    assert_eq!(free_blocks(&allocator).len(), 2);
+                }
+            }
+        }
+    }
+
+    /// Allocations aligned to more than a page grow enough to align them in one grow, and leave the padding free.
+    #[test]
+    fn alignment_above_page_size() {
+        for pages in [2, 4, 8] {
+            let allocator = FreeListAllocator::new_in(MockGrower::new());
+            let align = pages * PAGE_SIZE;
+            let layout = Layout::from_size_align(PAGE_SIZE, align).unwrap();
+            // With `dealloc-size-check`, the prefix takes a whole alignment before the page.
+            let size = block_size(layout);
+            let used = pages - 1 + size / PAGE_SIZE;
+            unsafe {
+                let ptr = allocator.alloc(layout);
+                assert_eq!(ptr as usize % align, 0, "align {pages} pages");
+                // Grown memory is page aligned, so the aligned block starts within `pages - 1` pages of its start.
+                assert_eq!(allocator.grower.used_pages(), used);
+                let free = free_blocks(&allocator);
+                assert_eq!(
+                    free.iter().map(|block| block.size).sum::<usize>(),
+                    (pages - 1) * PAGE_SIZE,
+                    "all the padding is free"
+                );
+                let offset = block_start(ptr, layout) as usize - allocator.grower.base();
+                assert!(free
+                    .iter()
+                    .all(|block| block.offset + block.size <= offset
+                        || block.offset >= offset + size));
+
+                // Freeing merges the padding back into one block, which fits the allocation again without growing.
+                allocator.dealloc(ptr, layout);
+                assert_eq!(
+                    free_blocks(&allocator),
+                    [FreeBlock {
+                        size: used * PAGE_SIZE,
+                        offset: 0,
+                    }]
+                );
+                assert_eq!(allocator.alloc(layout), ptr);
+                assert_eq!(allocator.grower.used_pages(), used);
+                allocator.dealloc(ptr, layout);
+            }
+        }
+    }
+
+    #[test]
+    fn grow_below_free_memory() {
+        let allocator = FreeListAllocator::new_in(DescendingGrower::new(2));
+        let half = block_layout(PAGE_SIZE / 2, 1);
+        let whole = block_layout(PAGE_SIZE, 1);
+        unsafe {
+            let first = allocator.alloc(half);
+            // Does not fit in the free half of the top page, so grows the page below it, which merges with that half.
+            let second = allocator.alloc(whole);
+            assert!(!first.is_null() && !second.is_null());
+            assert_eq!(second as usize, first as usize - PAGE_SIZE);
+            allocator.validate_heap();
+            allocator.dealloc(first, half);
+            allocator.dealloc(second, whole);
+            assert_eq!(
+                allocator.free_block_size_containing(second as usize),
+                PAGE_SIZE * 2
+            );
+        }
+    }
+
+    #[test]
+    fn partial_grants_non_contiguous() {
+        let allocator = FreeListAllocator::new_in(OnePageGrower::new(true));
+        unsafe {
+            // Grants never join into a large enough block, so this gives up once a grant fails to make progress.
+            assert!(allocator
+                .alloc(Layout::from_size_align(PAGE_SIZE * 2, 1).unwrap())
+                .is_null());
+            assert_eq!(allocator.grower.calls.get(), 2);
+            allocator.validate_heap();
+
+            // The granted memory is still usable.
+            assert!(!allocator.alloc(block_layout(PAGE_SIZE, 1)).is_null());
+            assert!(!allocator.alloc(block_layout(PAGE_SIZE, 1)).is_null());
+            assert_eq!(allocator.grower.calls.get(), 2);
+        }
+    }
+
+    #[test]
+    fn zero_page_grants() {
+        let allocator = FreeListAllocator::new_in(ZeroPageGrower {
+            calls: Cell::new(0),
+        });
+        unsafe {
+            assert!(allocator
+                .alloc(Layout::from_size_align(1, 1).unwrap())
+                .is_null());
+        }
+        assert_eq!(allocator.grower.calls.get(), 1);
+        allocator.validate_heap();
+    }
+
+    #[cfg(feature = "asan")]
     #[test]
-    fn populates_free_list() {
-        let allocator = FreeListAllocator {
-            free_list: UnsafeCell::new(EMPTY_FREE_LIST),
-            grower: RefCell::new(Slabby::new()),
+    fn asan_poisons_free_memory() {
+        use crate::asan::shadow::{all_poisoned, any_poisoned, watch};
+        let allocator = FreeListAllocator::new_in(MockGrower::with_pages(2));
+        let base = allocator.grower.base() as *const u8;
+        watch(base, PAGE_SIZE * 2);
+        // The allocator only accesses free memory through the nodes at the start of free blocks.
+        // Aligning the second allocation leaves a free block between it and the first, which needs a new node.
+        let check_nodes = || {
+            for node in free_blocks(&allocator) {
+                assert!(!any_poisoned(base.wrapping_add(node.offset), NODE_SIZE));
+            }
         };
-        allocator.grower.borrow_mut().used_pages = 1; // Fake used pages large enough to we don't fail free list validation.
-        assert_eq!(free_list_content(&allocator), []);
-        unsafe {
-            let free = |alloc: FreeListContent| {
-                allocator.dealloc(
-                    (allocator.grower.borrow().pages.as_ptr() as *mut u8).add(alloc.offset),
-                    Layout::from_size_align(alloc.size, 1).unwrap(),
-                )
-            };
-            assert_eq!(free_list_content(&allocator), []);
+        let layouts: Vec<Layout> = [(100, 1), (64, 64), (1000, 8), (24, 8), (300, 16)]
+            .into_iter()
+            .map(|(size, align)| Layout::from_size_align(size, align).unwrap())
+            .collect();
+        unsafe {
+            let ptrs: Vec<*mut u8> = layouts.iter().map(|&l| allocator.alloc(l)).collect();
+            for (&ptr, layout) in ptrs.iter().zip(&layouts) {
+                assert!(!any_poisoned(ptr, layout.size()));
+            }
+            check_nodes();
+            // Below the allocations, the rest of the page is free.
+            let lowest = ptrs
+                .iter()
+                .zip(&layouts)
+                .map(|(&ptr, &layout)| block_start(ptr, layout))
+                .min()
+                .unwrap();
+            assert!(all_poisoned(
+                base.add(NODE_SIZE),
+                lowest as usize - base as usize - NODE_SIZE
+            ));
+
+            for i in [0, 2, 3] {
+                allocator.dealloc(ptrs[i], layouts[i]);
+                let start = block_start(ptrs[i], layouts[i]);
+                let size = block_size(layouts[i]);
+                assert!(all_poisoned(start.add(NODE_SIZE), size - NODE_SIZE));
+            }
+            for i in [1, 4] {
+                assert!(!any_poisoned(ptrs[i], layouts[i].size()));
+            }
+            check_nodes();
+
+            // Reusing the freed memory unpoisons it.
+            for i in [0, 2, 3] {
+                let ptr = allocator.alloc(layouts[i]);
+                assert!(!any_poisoned(ptr, layouts[i].size()));
+            }
+            check_nodes();
+        }
+    }
 
-            free(FreeListContent {
-                size: NODE_SIZE,
-                offset: NODE_SIZE * 3,
-            });
+    #[test]
+    fn failed_alloc_changes_nothing() {
+        let allocator = FreeListAllocator::new_in(MockGrower::with_pages(1));
+        unsafe {
+            // Fill the only page, except for a small free block.
+            let fill = block_layout(PAGE_SIZE - NODE_SIZE * 4, 1);
+            assert!(!allocator.alloc(fill).is_null());
+            let before = free_blocks(&allocator);
             assert_eq!(
-                free_list_content(&allocator),
-                [FreeListContent {
-                    size: NODE_SIZE,
-                    offset: NODE_SIZE * 3,
+                before,
+                [FreeBlock {
+                    size: NODE_SIZE * 4,
+                    offset: 0,
                 }]
             );
 
-            // Free before, not contiguous
-            free(FreeListContent {
-                size: NODE_SIZE,
-                offset: NODE_SIZE,
-            });
-            assert_eq!(
-                free_list_content(&allocator),
-                [
-                    FreeListContent {
-                        size: NODE_SIZE,
-                        offset: NODE_SIZE * 3,
-                    },
-                    FreeListContent {
-                        size: NODE_SIZE,
-                        offset: NODE_SIZE,
-                    }
-                ]
-            );
+            // Too large for the free block, and the grower is out of memory.
+            let too_large = block_layout(NODE_SIZE * 5, 1);
+            assert!(allocator.alloc(too_large).is_null());
+            assert_eq!(free_blocks(&allocator), before);
+            assert_eq!(allocator.grower.used_pages(), 1);
 
-            // Free before, contiguous
-            free(FreeListContent {
-                size: NODE_SIZE,
-                offset: 0,
-            });
+            // Smaller allocations can still use the free block.
+            let small = block_layout(NODE_SIZE * 2, 1);
+            let ptr = allocator.alloc(small);
             assert_eq!(
-                free_list_content(&allocator),
-                [
-                    FreeListContent {
-                        size: NODE_SIZE,
-                        offset: NODE_SIZE * 3,
-                    },
-                    FreeListContent {
-                        size: NODE_SIZE * 2,
-                        offset: 0,
-                    }
-                ]
+                block_start(ptr, small) as usize - allocator.grower.base(),
+                NODE_SIZE * 2
             );
+        }
+    }
+
+    /// The largest sizes a [Layout] can have fail cleanly, without overflowing.
+    ///
+    /// Sizes like `usize::MAX` can't be tested: `Layout::from_size_align_unchecked` checks its preconditions in debug builds.
+    #[test]
+    fn largest_layouts_fail() {
+        let max = isize::MAX as usize;
+        let half = max / 2 + 1;
+        let layouts = [
+            (max, 1),
+            (max - 15, 16),
+            (max - PAGE_SIZE + 1, PAGE_SIZE),
+            // Aligned to more than a page, so growing adds padding.
+            (half, half),
+            (PAGE_SIZE, half),
+        ]
+        .map(|(size, align)| Layout::from_size_align(size, align).unwrap());
+        let check = |allocator: FreeListAllocator<MockGrower>| unsafe {
+            let small = Layout::from_size_align(100, 8).unwrap();
+            let ptr = allocator.alloc(small);
+            // With `small` allocated, then with all the memory free, so growing would extend the free block.
+            for live in [true, false] {
+                if !live {
+                    allocator.dealloc(ptr, small);
+                }
+                let before = free_blocks(&allocator);
+                for layout in layouts {
+                    assert!(allocator.alloc(layout).is_null(), "{layout:?}");
+                    assert_eq!(free_blocks(&allocator), before);
+                    assert_eq!(allocator.grower.used_pages(), 1);
+                }
+            }
+        };
+        check(FreeListAllocator::new_in(MockGrower::new()));
+        #[cfg(feature = "large-objects")]
+        check(
+            FreeListAllocator::new_in(MockGrower::new())
+                .with_large_object_threshold(crate::LARGE_OBJECT_THRESHOLD),
+        );
+    }
+
+    /// Fragments the single page `allocator` has, then checks [FreeListAllocator::largest_allocatable_block] is tight for a range of alignments.
+    fn check_largest_allocatable_block(allocator: FreeListAllocator<MockGrower>) {
+        use crate::AllocatorStats;
+        assert_eq!(allocator.largest_allocatable_block(1), 0);
+        let layout = |size| Layout::from_size_align(size, 1).unwrap();
+        unsafe {
+            // Holes of different sizes between small allocations, above a small block left at the start of the page.
+            let holes: Vec<(*mut u8, Layout)> = [3000, 5000, 700]
+                .into_iter()
+                .map(|size| {
+                    let hole = allocator.alloc(layout(size));
+                    assert!(!allocator.alloc(layout(100)).is_null());
+                    (hole, layout(size))
+                })
+                .collect();
+            let remaining = allocator.stats().free_bytes.unwrap();
+            assert!(!allocator.alloc(layout(remaining - 2048)).is_null());
+            for &(ptr, layout) in &holes {
+                allocator.dealloc(ptr, layout);
+            }
+        }
+        assert!(allocator.largest_allocatable_block(1) >= 5000);
+        for align in [1, 8, 64, 256, 1024, 4096, PAGE_SIZE] {
+            let largest = allocator.largest_allocatable_block(align);
+            let before = free_blocks(&allocator);
+            unsafe {
+                if largest > 0 {
+                    let fits = Layout::from_size_align(largest, align).unwrap();
+                    let ptr = allocator.alloc(fits);
+                    assert!(!ptr.is_null(), "{align}");
+                    allocator.dealloc(ptr, fits);
+                    assert_eq!(free_blocks(&allocator), before);
+                }
+                // The grower is out of memory, so one byte more fails.
+                let too_large = Layout::from_size_align(largest + 1, align).unwrap();
+                assert!(allocator.alloc(too_large).is_null(), "{align}");
+            }
+            assert_eq!(free_blocks(&allocator), before);
+            assert_eq!(allocator.grower.used_pages(), 1);
+        }
+    }
+
+    #[test]
+    fn largest_allocatable_block() {
+        check_largest_allocatable_block(FreeListAllocator::new_in(MockGrower::with_pages(1)));
+    }
+
+    #[cfg(feature = "live-blocks")]
+    #[test]
+    fn largest_allocatable_block_with_live_blocks() {
+        check_largest_allocatable_block(FreeListAllocator::new_with_live_blocks_in(
+            MockGrower::with_pages(1),
+        ));
+    }
 
-            // Free between, contiguous
-            free(FreeListContent {
-                size: NODE_SIZE,
-                offset: NODE_SIZE * 2,
-            });
+    #[test]
+    fn external_fragmentation_unfragmented() {
+        let allocator = FreeListAllocator::new_in(MockGrower::with_pages(1));
+        // Nothing has been freed yet.
+        assert_eq!(allocator.external_fragmentation_for_size(NODE_SIZE, 1), 0.0);
+        let layout = Layout::from_size_align(NODE_SIZE * 4, NODE_SIZE).unwrap();
+        unsafe {
+            let ptr = allocator.alloc(layout);
+            assert_eq!(free_blocks(&allocator).len(), 1);
             assert_eq!(
-                free_list_content(&allocator),
-                [FreeListContent {
-                    size: NODE_SIZE * 4,
-                    offset: 0,
-                },]
+                allocator.external_fragmentation_for_size(NODE_SIZE * 8, 1),
+                0.0
             );
+            allocator.dealloc(ptr, layout);
+        }
+        assert_eq!(
+            allocator.external_fragmentation_for_size(NODE_SIZE * 8, 1),
+            0.0
+        );
+        // Nothing can serve more than is free.
+        assert_eq!(
+            allocator.external_fragmentation_for_size(PAGE_SIZE * 2, 1),
+            1.0
+        );
+    }
+
+    /// Alignments larger than the size only align where the block is placed, and the block frees back into the free list.
+    #[test]
+    fn align_larger_than_size() {
+        let allocator = FreeListAllocator::new_in(MockGrower::with_pages(1));
+        let aligned = Layout::from_size_align(1, 1024).unwrap();
+        let small = Layout::from_size_align(8, 8).unwrap();
+        unsafe {
+            let mut ptrs = Vec::new();
+            for _ in 0..3 {
+                let ptr = allocator.alloc(aligned);
+                assert!(!ptr.is_null());
+                assert_eq!(ptr as usize % 1024, 0);
+                ptrs.push((ptr, aligned));
+                ptrs.push((allocator.alloc(small), small));
+            }
+            allocator.validate_heap();
+            for (ptr, layout) in ptrs {
+                allocator.dealloc(ptr, layout);
+                allocator.validate_heap();
+            }
+        }
+        assert_eq!(
+            free_blocks(&allocator),
+            [FreeBlock {
+                offset: 0,
+                size: PAGE_SIZE
+            }]
+        );
+    }
+
+    #[test]
+    fn alignment_waste_bytes_aligned() {
+        let allocator = FreeListAllocator::new_in(MockGrower::with_pages(1));
+        assert_eq!(allocator.alignment_waste_bytes(), 0);
+        let layout = Layout::from_size_align(NODE_SIZE * 2, NODE_SIZE).unwrap();
+        unsafe {
+            let ptrs: Vec<_> = (0..10).map(|_| allocator.alloc(layout)).collect();
+            assert_eq!(allocator.alignment_waste_bytes(), 0);
+            for ptr in ptrs {
+                allocator.dealloc(ptr, layout);
+            }
+        }
+        assert_eq!(allocator.alignment_waste_bytes(), 0);
+    }
+
+    #[test]
+    fn alignment_waste_bytes_misaligned() {
+        let allocator = FreeListAllocator::new_in(MockGrower::with_pages(1));
+        // Each allocation is placed a node before the end of an aligned span, leaving a one node fragment after it.
+        let layout = Layout::from_size_align(NODE_SIZE * 7, NODE_SIZE * 8).unwrap();
+        unsafe {
+            let first = allocator.alloc(layout);
+            let second = allocator.alloc(layout);
+            assert!(allocator.alignment_waste_bytes() > 0);
+            assert_eq!(allocator.alignment_waste_bytes() % NODE_SIZE, 0);
+            allocator.dealloc(first, layout);
+            allocator.dealloc(second, layout);
+        }
+        // Freeing their neighbours merges the fragments back into one block.
+        assert_eq!(allocator.alignment_waste_bytes(), 0);
+    }
 
-            // Free after, contiguous
-            free(FreeListContent {
-                size: NODE_SIZE,
-                offset: NODE_SIZE * 4,
-            });
+    #[test]
+    fn external_fragmentation_checkerboard() {
+        let allocator = FreeListAllocator::new_in(MockGrower::with_pages(1));
+        let layout = Layout::from_size_align(NODE_SIZE, NODE_SIZE).unwrap();
+        unsafe {
+            // The first allocation grows the heap, which the rest fill.
+            let mut ptrs = vec![allocator.alloc(layout)];
+            while allocator.largest_allocatable_block(NODE_SIZE) >= NODE_SIZE {
+                ptrs.push(allocator.alloc(layout));
+            }
+            // Free every other block, leaving free blocks of one node separated by live ones.
+            for (i, &ptr) in ptrs.iter().enumerate() {
+                if i % 2 == 0 {
+                    allocator.dealloc(ptr, layout);
+                }
+            }
+            assert_eq!(free_blocks(&allocator).len(), ptrs.len().div_ceil(2));
             assert_eq!(
-                free_list_content(&allocator),
-                [FreeListContent {
-                    size: NODE_SIZE * 5,
-                    offset: 0,
-                },]
+                allocator.external_fragmentation_for_size(NODE_SIZE * 2, 1),
+                1.0
             );
+            // Which is unusable for larger requests, but fine for small ones.
+            assert_eq!(allocator.external_fragmentation_for_size(NODE_SIZE, 1), 0.0);
+            for (i, &ptr) in ptrs.iter().enumerate() {
+                if i % 2 == 1 {
+                    allocator.dealloc(ptr, layout);
+                }
+            }
+        }
+        assert_eq!(
+            allocator.external_fragmentation_for_size(NODE_SIZE * 2, 1),
+            0.0
+        );
+    }
+
+    #[test]
+    fn live_allocation_info() {
+        let allocator = FreeListAllocator::new_in(MockGrower::new());
+        let layout = Layout::from_size_align(NODE_SIZE, 1).unwrap();
+        unsafe {
+            let live: Vec<*const u8> = (0..10).map(|_| allocator.alloc(layout) as _).collect();
+            let info: Vec<LiveInfo> = allocator.live_allocation_info(&live).collect();
+            assert_eq!(info.len(), 10);
+            for i in &info {
+                assert!(!i.is_in_free_list);
+                assert!(!allocator.is_free(i.ptr, layout.size()));
+                // Nothing above the allocations is free.
+                assert_eq!(i.usable_size, None);
+            }
+
+            // Free one allocation, and report all of them, as an application with a use after free bug would.
+            allocator.dealloc(live[3] as *mut u8, layout);
+            let info: Vec<LiveInfo> = allocator.live_allocation_info(&live).collect();
+            for (i, info) in info.iter().enumerate() {
+                assert_eq!(info.ptr, live[i]);
+                assert_eq!(info.is_in_free_list, i == 3);
+                assert_eq!(
+                    info.is_in_free_list,
+                    allocator.is_free(info.ptr, layout.size())
+                );
+            }
+            // Usable sizes are measured from the pointer, which is after the `dealloc-size-check` prefix if there is one.
+            let block = block_size(layout);
+            let prefix = size_check::prefix_space(layout.align());
+            assert_eq!(info[3].usable_size, Some(block - prefix));
+            assert_eq!(info[2].usable_size, None);
+            // Allocations are made from the top of the heap down, so later allocations are below the freed one.
+            assert_eq!(info[4].usable_size, Some(block - prefix));
+            assert_eq!(info[9].usable_size, Some(block * 6 - prefix));
+        }
+    }
+
+    #[test]
+    fn custom_sentinel() {
+        // Aligned like a real node, but above all memory.
+        const SENTINEL: usize = usize::MAX - (NODE_SIZE - 1);
+        let allocator: FreeListAllocator<_, SENTINEL> =
+            FreeListAllocator::with_sentinel(MockGrower::new());
+        let layout = Layout::from_size_align(NODE_SIZE * 3, 1).unwrap();
+        unsafe {
+            let a = allocator.alloc(layout);
+            let b = allocator.alloc(layout);
+            allocator.dealloc(a, layout);
+            assert!(allocator.is_free(a, layout.size()));
+            allocator.dealloc(b, layout);
+            allocator.validate_heap();
+            let base = allocator.grower.base() as *const u8;
+            assert!(allocator.is_free(base, PAGE_SIZE));
+        }
+    }
+
+    #[cfg(all(debug_assertions, not(feature = "tiny-asserts")))]
+    #[test]
+    #[should_panic(expected = "SENTINEL must not be the address of any memory")]
+    fn invalid_sentinel() {
+        let _: FreeListAllocator<_, 0> = FreeListAllocator::with_sentinel(MockGrower::new());
+    }
 
-            // Free after, not contiguous
-            free(FreeListContent {
-                size: NODE_SIZE,
-                offset: NODE_SIZE * 6,
-            });
+    #[test]
+    fn into_leaking() {
+        let allocator = FreeListAllocator::new_in(MockGrower::new());
+        let layout = block_layout(NODE_SIZE, 1);
+        let leaking: LeakingAllocatorFromFreeList<_> = unsafe {
+            let a = allocator.alloc(layout);
+            let b = allocator.alloc(layout);
+            allocator.alloc(layout);
+            allocator.dealloc(a, layout);
+            allocator.dealloc(b, layout);
+            allocator.into_leaking()
+        };
+        let before = free_blocks(&leaking.inner);
+        assert_eq!(
+            before,
+            [
+                FreeBlock {
+                    size: NODE_SIZE * 2,
+                    offset: PAGE_SIZE - NODE_SIZE * 2,
+                },
+                FreeBlock {
+                    size: PAGE_SIZE - NODE_SIZE * 3,
+                    offset: 0,
+                }
+            ]
+        );
+        unsafe {
+            // Allocations are still served from the existing free list.
+            let ptr = leaking.alloc(layout);
+            assert!(!ptr.is_null());
+            assert_eq!(leaking.inner.grower.used_pages(), 1);
+            let after_alloc = free_blocks(&leaking.inner);
             assert_eq!(
-                free_list_content(&allocator),
+                after_alloc,
                 [
-                    FreeListContent {
+                    FreeBlock {
                         size: NODE_SIZE,
-                        offset: NODE_SIZE * 6,
+                        offset: PAGE_SIZE - NODE_SIZE * 2,
                     },
-                    FreeListContent {
-                        size: NODE_SIZE * 5,
+                    FreeBlock {
+                        size: PAGE_SIZE - NODE_SIZE * 3,
                         offset: 0,
                     }
                 ]
             );
+            // Freeing does not modify the free list.
+            leaking.dealloc(ptr, layout);
+            assert_eq!(free_blocks(&leaking.inner), after_alloc);
         }
     }
 
     #[test]
-    fn it_works() {
-        let allocator = FreeListAllocator {
-            free_list: UnsafeCell::new(EMPTY_FREE_LIST),
-            grower: RefCell::new(Slabby::new()),
-        };
-        assert_eq!(free_list_content(&allocator), []);
+    fn alloc_zeroed_reuses_dirty_blocks() {
+        let allocator = FreeListAllocator::new_in(MockGrower::new());
+        for size in [1, 15, 16, 17, 100, 1000] {
+            let layout = Layout::from_size_align(size, 8).unwrap();
+            unsafe {
+                let dirty = allocator.alloc(layout);
+                dirty.write_bytes(0xFF, size);
+                allocator.dealloc(dirty, layout);
+                let bytes = core::slice::from_raw_parts(allocator.alloc_zeroed(layout), size);
+                assert!(bytes.iter().all(|&b| b == 0));
+            }
+        }
+    }
+
+    /// Fills the `len` bytes at `ptr` with 0xFF, as if a grower which claims to zero memory had not.
+    /// Bytes which stay 0xFF after `alloc_zeroed` show which it skipped zeroing.
+    unsafe fn spoil(ptr: *mut u8, len: usize) {
+        ptr.write_bytes(0xFF, len);
+    }
+
+    /// Memory never used since it was grown is not zeroed again.
+    #[test]
+    fn alloc_zeroed_skips_fresh_memory() {
+        let allocator = FreeListAllocator::new_in(MockGrower::new());
+        let base = allocator.grower.base() as *mut u8;
+        let layout = block_layout(PAGE_SIZE * 2, 8);
         unsafe {
-            let allocate = |size: usize, align: usize| {
-                let layout = Layout::from_size_align(size, align).unwrap();
-                Allocation {
-                    layout,
-                    ptr: allocator.alloc(layout),
-                }
-            };
-            let free = |alloc: Allocation| allocator.dealloc(alloc.ptr, alloc.layout);
-            let alloc = allocate(1, 1);
-            assert_eq!(allocator.grower.borrow().used_pages, 1);
+            spoil(base, PAGE_SIZE * 2);
+            let ptr = allocator.alloc_zeroed(layout);
+            assert_eq!(block_start(ptr, layout), base);
+            // Only the free list node written when the memory was grown is zeroed.
+            let bytes = core::slice::from_raw_parts(ptr, layout.size());
+            let (node, rest) = bytes.split_at(NODE_SIZE - size_check::prefix_space(8));
+            assert!(node.iter().all(|&byte| byte == 0));
+            assert!(rest.iter().all(|&byte| byte == 0xFF));
+
+            // Once used, it is zeroed.
+            allocator.dealloc(ptr, layout);
+            let bytes = core::slice::from_raw_parts(allocator.alloc_zeroed(layout), layout.size());
+            assert!(bytes.iter().all(|&byte| byte == 0));
+        }
+    }
+
+    /// Only the part of a block below the high water mark is zeroed,
+    /// including a stale free list node left inside it by coalescing.
+    #[test]
+    fn alloc_zeroed_straddling_high_water() {
+        let allocator = FreeListAllocator::new_in(MockGrower::new());
+        let base = allocator.grower.base() as *mut u8;
+        let small = block_layout(NODE_SIZE * 4, 8);
+        unsafe {
+            // Used memory ends at the end of the first page.
+            let dirty = allocator.alloc(small);
             assert_eq!(
-                free_list_content(&allocator),
-                [FreeListContent {
-                    size: PAGE_SIZE - NODE_SIZE,
-                    offset: 0, // Expect allocation at the end of first page.
-                }]
+                block_start(dirty, small),
+                base.add(PAGE_SIZE - NODE_SIZE * 4)
             );
-            // Merge into end of existing chunk
-            free(alloc);
+            spoil(dirty, small.size());
+
+            // A second page, whose node raises the mark past its start, then merges with the rest when `dirty` is freed.
+            allocator.grower.set_used_pages(2);
+            allocator.free(base.add(PAGE_SIZE), PAGE_SIZE);
+            allocator.dealloc(dirty, small);
+            let mark = PAGE_SIZE + NODE_SIZE;
+            spoil(base.add(mark), PAGE_SIZE * 2 - mark);
+
+            let layout = block_layout(PAGE_SIZE + NODE_SIZE * 8, 8);
+            let ptr = allocator.alloc_zeroed(layout);
+            let start = PAGE_SIZE * 2 - block_size(layout);
+            assert_eq!(block_start(ptr, layout), base.add(start));
+            let bytes = core::slice::from_raw_parts(ptr, layout.size());
+            let (below, above) = bytes.split_at(mark - start - size_check::prefix_space(8));
+            assert!(below.iter().all(|&byte| byte == 0));
+            assert!(above.iter().all(|&byte| byte == 0xFF));
+        }
+    }
+
+    /// Growers which don't promise zeroed memory always get allocations zeroed.
+    #[test]
+    fn alloc_zeroed_without_zeroing_grower() {
+        let allocator = FreeListAllocator::new_in(OnePageGrower::new(false));
+        let base = allocator.grower.grower.base() as *mut u8;
+        let layout = Layout::from_size_align(100, 8).unwrap();
+        unsafe {
+            spoil(base, PAGE_SIZE);
+            let bytes = core::slice::from_raw_parts(allocator.alloc_zeroed(layout), layout.size());
+            assert!(bytes.iter().all(|&byte| byte == 0));
+        }
+    }
+
+    #[cfg(all(feature = "dealloc-size-check", debug_assertions))]
+    #[test]
+    fn dealloc_size_check() {
+        let allocator = FreeListAllocator::new_in(MockGrower::new());
+        unsafe {
+            for align in [1, 8, 64] {
+                let ptr = allocator.alloc(Layout::from_size_align(9, align).unwrap());
+                assert_eq!(ptr as usize % align, 0);
+                // Sizes which free the same block are allowed.
+                allocator.dealloc(ptr, Layout::from_size_align(10, align).unwrap());
+            }
+        }
+        assert_eq!(free_blocks(&allocator).len(), 1);
+    }
+
+    #[cfg(all(
+        feature = "dealloc-size-check",
+        debug_assertions,
+        not(feature = "tiny-asserts")
+    ))]
+    #[test]
+    #[should_panic(expected = "Freed with size 16 (block size")]
+    fn dealloc_size_check_mismatch() {
+        let allocator = FreeListAllocator::new_in(MockGrower::new());
+        unsafe {
+            let ptr = allocator.alloc(Layout::from_size_align(64, 8).unwrap());
+            allocator.dealloc(ptr, Layout::from_size_align(16, 8).unwrap());
+        }
+    }
+
+    #[cfg(feature = "strict-checks")]
+    #[test]
+    #[should_panic(expected = "lol_alloc trap")]
+    fn strict_checks_misaligned_dealloc() {
+        let allocator = FreeListAllocator::new_in(MockGrower::new());
+        unsafe {
+            let ptr = allocator.alloc(Layout::from_size_align(NODE_SIZE, 1).unwrap());
+            allocator.dealloc(ptr.add(1), Layout::from_size_align(1, 1).unwrap());
+        }
+    }
+
+    /// Allocates then frees a block, so the free list has a node at the end of the heap and one after the block,
+    /// then overwrites the size of the node at the head of the free list.
+    #[cfg(feature = "node-checksums")]
+    unsafe fn corrupted_allocator(layout: Layout) -> (FreeListAllocator<MockGrower>, *mut u8) {
+        let allocator = FreeListAllocator::new_in(MockGrower::new());
+        let a = allocator.alloc(layout);
+        let b = allocator.alloc(layout);
+        allocator.dealloc(b, layout);
+        let node = *allocator.free_list.get();
+        // The size is the second word of the node.
+        (node as *mut usize).add(1).write(NODE_SIZE);
+        (allocator, a)
+    }
+
+    #[cfg(feature = "node-checksums")]
+    #[test]
+    #[should_panic(expected = "lol_alloc free list node checksum mismatch")]
+    fn corrupt_node_traps_on_alloc() {
+        let layout = Layout::from_size_align(NODE_SIZE * 3, 1).unwrap();
+        unsafe {
+            let (allocator, _) = corrupted_allocator(layout);
+            allocator.alloc(layout);
+        }
+    }
+
+    #[cfg(feature = "node-checksums")]
+    #[test]
+    #[should_panic(expected = "lol_alloc free list node checksum mismatch")]
+    fn corrupt_node_traps_on_dealloc() {
+        let layout = Layout::from_size_align(NODE_SIZE * 3, 1).unwrap();
+        unsafe {
+            let (allocator, a) = corrupted_allocator(layout);
+            allocator.dealloc(a, layout);
+        }
+    }
+
+    #[cfg(feature = "node-checksums")]
+    #[test]
+    #[should_panic(expected = "lol_alloc free list node checksum mismatch")]
+    fn corrupt_node_fails_validation() {
+        let layout = Layout::from_size_align(NODE_SIZE, 1).unwrap();
+        unsafe { corrupted_allocator(layout).0.validate_heap() };
+    }
+
+    /// Allocates `a` then `b` (which is placed just below `a`), fills `b`, then frees `a`, leaving a free block just after `b`.
+    unsafe fn realloc_neighbors(
+        allocator: &FreeListAllocator<MockGrower>,
+        a: Layout,
+        b: Layout,
+    ) -> *mut u8 {
+        let a_ptr = allocator.alloc(a);
+        let b_ptr = allocator.alloc(b);
+        assert_eq!(
+            block_start(b_ptr, b).add(block_size(b)),
+            block_start(a_ptr, a)
+        );
+        b_ptr.write_bytes(7, b.size());
+        allocator.dealloc(a_ptr, a);
+        b_ptr
+    }
+
+    /// Growing into a free neighbor of exactly the missing size removes it from the free list.
+    #[test]
+    fn realloc_into_exact_neighbor() {
+        let allocator = FreeListAllocator::new_in(MockGrower::new());
+        let layout = block_layout(NODE_SIZE * 2, 8);
+        let new_layout = block_layout(NODE_SIZE * 4, 8);
+        unsafe {
+            let ptr = realloc_neighbors(&allocator, layout, layout);
+            let grown = allocator.realloc(ptr, layout, new_layout.size());
+            assert_eq!(grown, ptr);
+            assert!(core::slice::from_raw_parts(grown, layout.size())
+                .iter()
+                .all(|&byte| byte == 7));
             assert_eq!(
-                free_list_content(&allocator),
-                [FreeListContent {
-                    size: PAGE_SIZE,
+                free_blocks(&allocator),
+                [FreeBlock {
+                    size: PAGE_SIZE - NODE_SIZE * 4,
                     offset: 0,
                 }]
             );
+            allocator.dealloc(grown, new_layout);
+        }
+        assert_eq!(
+            free_blocks(&allocator),
+            [FreeBlock {
+                size: PAGE_SIZE,
+                offset: 0,
+            }]
+        );
+    }
 
-            // Allocate small value to impact alignment
-            let alloc = allocate(1, 1);
-            // Allocate larger aligned value to cause a hole after it
-            let alloc_big = allocate(NODE_SIZE * 2, NODE_SIZE * 2);
+    /// Growing into a larger free neighbor leaves the rest of it in the free list.
+    #[test]
+    fn realloc_into_larger_neighbor() {
+        let allocator = FreeListAllocator::new_in(MockGrower::new());
+        let a = block_layout(NODE_SIZE * 4, 8);
+        let b = block_layout(NODE_SIZE * 2, 8);
+        unsafe {
+            let ptr = realloc_neighbors(&allocator, a, b);
+            // Rounds up to 3 nodes, taking one of the neighbor's 4.
+            let new_size = block_layout(NODE_SIZE * 2, 8).size() + 1;
+            let grown = allocator.realloc(ptr, b, new_size);
+            assert_eq!(grown, ptr);
+            assert!(core::slice::from_raw_parts(grown, b.size())
+                .iter()
+                .all(|&byte| byte == 7));
             assert_eq!(
-                free_list_content(&allocator),
+                free_blocks(&allocator),
                 [
-                    FreeListContent {
-                        size: NODE_SIZE,
-                        offset: PAGE_SIZE - NODE_SIZE * 2,
+                    FreeBlock {
+                        size: NODE_SIZE * 3,
+                        offset: PAGE_SIZE - NODE_SIZE * 3,
                     },
-                    FreeListContent {
-                        size: PAGE_SIZE - NODE_SIZE * 4,
+                    FreeBlock {
+                        size: PAGE_SIZE - NODE_SIZE * 6,
                         offset: 0,
                     },
                 ]
             );
+            allocator.dealloc(grown, Layout::from_size_align(new_size, 8).unwrap());
+        }
+        assert_eq!(free_blocks(&allocator).len(), 1);
+    }
 
-            // Free second allocation, causing 3 way join
-            free(alloc_big);
+    /// Without a free neighbor, growing moves the allocation, and sizes which round to the same block stay put.
+    #[test]
+    fn realloc_without_neighbor_moves() {
+        let allocator = FreeListAllocator::new_in(MockGrower::new());
+        let layout = block_layout(NODE_SIZE, 8);
+        let layout = Layout::from_size_align(layout.size() + 1, 8).unwrap();
+        let new_layout = block_layout(NODE_SIZE * 4, 8);
+        unsafe {
+            let a = allocator.alloc(layout);
+            let b = allocator.alloc(layout);
+            b.write_bytes(7, layout.size());
+            // Still 2 nodes.
             assert_eq!(
-                free_list_content(&allocator),
-                [FreeListContent {
-                    size: PAGE_SIZE - NODE_SIZE,
-                    offset: 0,
-                }]
+                allocator.realloc(b, layout, block_layout(NODE_SIZE * 2, 8).size()),
+                b
             );
 
-            // Multi-page allocation
-            assert_eq!(allocator.grower.borrow().used_pages, 1);
-            let multi_page = allocate(PAGE_SIZE + 1, 1);
-            assert_eq!(allocator.grower.borrow().used_pages, 3);
+            let moved = allocator.realloc(b, layout, new_layout.size());
+            assert_ne!(moved, b);
+            assert!(core::slice::from_raw_parts(moved, layout.size())
+                .iter()
+                .all(|&byte| byte == 7));
+            // The old block is freed, but can't merge with `a` (still allocated) or the moved allocation.
             assert_eq!(
-                free_list_content(&allocator),
+                free_blocks(&allocator),
                 [
-                    FreeListContent {
-                        size: PAGE_SIZE - NODE_SIZE,
-                        offset: PAGE_SIZE,
+                    FreeBlock {
+                        size: NODE_SIZE * 2,
+                        offset: PAGE_SIZE - NODE_SIZE * 4,
                     },
-                    FreeListContent {
-                        size: PAGE_SIZE - NODE_SIZE,
+                    FreeBlock {
+                        size: PAGE_SIZE - NODE_SIZE * 8,
                         offset: 0,
-                    }
+                    },
                 ]
             );
+            allocator.dealloc(moved, new_layout);
+            allocator.dealloc(a, layout);
+        }
+        assert_eq!(free_blocks(&allocator).len(), 1);
+    }
 
-            // Free everything
-            free(alloc);
-            assert_eq!(
-                free_list_content(&allocator),
-                [FreeListContent {
-                    size: PAGE_SIZE * 2 - NODE_SIZE,
-                    offset: 0,
-                }]
-            );
-            free(multi_page);
+    /// Shrinking frees the tail of the block, which merges into the free neighbor after it.
+    #[test]
+    fn realloc_shrink_merges_tail() {
+        let allocator = FreeListAllocator::new_in(MockGrower::new());
+        let a = block_layout(NODE_SIZE * 2, 8);
+        let b = block_layout(NODE_SIZE * 4, 8);
+        unsafe {
+            let ptr = realloc_neighbors(&allocator, a, b);
+            let new_size = block_layout(NODE_SIZE, 8).size() + 1;
+            let shrunk = allocator.realloc(ptr, b, new_size);
+            assert_eq!(shrunk, ptr);
+            assert!(core::slice::from_raw_parts(shrunk, new_size)
+                .iter()
+                .all(|&byte| byte == 7));
             assert_eq!(
-                free_list_content(&allocator),
-                [FreeListContent {
-                    size: PAGE_SIZE * 3,
-                    offset: 0,
-                }]
+                free_blocks(&allocator),
+                [
+                    FreeBlock {
+                        size: NODE_SIZE * 4,
+                        offset: PAGE_SIZE - NODE_SIZE * 4,
+                    },
+                    FreeBlock {
+                        size: PAGE_SIZE - NODE_SIZE * 6,
+                        offset: 0,
+                    },
+                ]
             );
+            allocator.dealloc(shrunk, Layout::from_size_align(new_size, 8).unwrap());
         }
+        assert_eq!(free_blocks(&allocator).len(), 1);
     }
 
+    /// Shrinking between allocations frees the tail as its own block, and tails smaller than a node stay allocated.
     #[test]
-    fn fuzz() {
-        use rand::Rng;
-        use rand_core::SeedableRng;
-        use rand_pcg::Pcg32;
-
-        let mut rng = Pcg32::seed_from_u64(0);
-
-        for _ in 0..100 {
-            let allocator = FreeListAllocator {
-                free_list: UnsafeCell::new(EMPTY_FREE_LIST),
-                grower: RefCell::new(Slabby::new()),
-            };
+    fn realloc_shrink_without_neighbor() {
+        let allocator = FreeListAllocator::new_in(MockGrower::new());
+        let layout = block_layout(NODE_SIZE * 4, 8);
+        let small = block_layout(NODE_SIZE * 2, 8);
+        let smaller = Layout::from_size_align(block_layout(NODE_SIZE, 8).size() + 1, 8).unwrap();
+        unsafe {
+            let a = allocator.alloc(layout);
+            let b = allocator.alloc(layout);
+            let shrunk = allocator.realloc(b, layout, small.size());
+            assert_eq!(shrunk, b);
+            let free = [
+                FreeBlock {
+                    size: NODE_SIZE * 2,
+                    offset: PAGE_SIZE - NODE_SIZE * 6,
+                },
+                FreeBlock {
+                    size: PAGE_SIZE - NODE_SIZE * 8,
+                    offset: 0,
+                },
+            ];
+            assert_eq!(free_blocks(&allocator), free);
 
-            let allocate = |size: usize, align: usize| {
-                let layout = Layout::from_size_align(size, align).unwrap();
-                let ptr = unsafe { allocator.alloc(layout) };
-                assert!(!ptr.is_null(), "Slab Full");
-                Allocation { layout, ptr }
-            };
-            let free = |alloc: Allocation| unsafe { allocator.dealloc(alloc.ptr, alloc.layout) };
+            // Less than a node smaller, which still rounds up to 2 nodes.
+            assert_eq!(allocator.realloc(shrunk, small, smaller.size()), b);
+            assert_eq!(free_blocks(&allocator), free);
 
-            let mut allocations = vec![];
-            for _ in 0..5000 {
-                // Randomly free some allocations.
-                while !allocations.is_empty() {
-                    if rng.gen_bool(0.45) {
-                        let alloc = allocations.swap_remove(rng.gen_range(0..allocations.len()));
-                        free(alloc);
-                    } else {
-                        break;
-                    }
-                }
-                // Do a random small allocation
-                let size = rng.gen_range(1..100);
-                allocations.push(allocate(size, 1 << rng.gen_range(0..7)));
-                if rng.gen_bool(0.05) {
-                    // Do a random large allocation
-                    let size = rng.gen_range(1..(PAGE_SIZE * 10));
-                    allocations.push(allocate(size, 1 << rng.gen_range(0..16)));
-                }
-            }
-            free_list_content(&allocator);
-            while !allocations.is_empty() {
-                let alloc = allocations.swap_remove(rng.gen_range(0..allocations.len()));
-                free(alloc);
-            }
-            assert_eq!(
-                free_list_content(&allocator),
-                [FreeListContent {
-                    size: allocator.grower.borrow().used_pages * PAGE_SIZE,
-                    offset: 0,
-                }]
-            );
+            allocator.dealloc(b, smaller);
+            allocator.dealloc(a, layout);
         }
+        assert_eq!(free_blocks(&allocator).len(), 1);
     }
 }
@@ -0,0 +1,80 @@
+use crate::{
+    atomic::{AtomicUsize, Ordering},
+    MemoryGrower, PageCount, ERROR_PAGE_COUNT, PAGE_SIZE,
+};
+use core::cell::UnsafeCell;
+
+#[repr(C, align(65536))] // align does not appear to work with the PAGE_SIZE constant
+struct Page([u8; PAGE_SIZE]);
+
+impl Page {
+    const ZERO: Page = Page([0; PAGE_SIZE]);
+}
+
+/// Grower which hands out pages from a fixed size array it contains, instead of growing the wasm memory.
+///
+/// Intended to be placed in a static (as part of a global allocator),
+/// for use where there is no `memory.grow`, such as no_std targets without an operating system.
+/// Allocations fail once all `PAGES` pages have been handed out.
+pub struct GlobalArrayGrower<const PAGES: usize> {
+    pages: UnsafeCell<[Page; PAGES]>,
+    used_pages: AtomicUsize,
+}
+
+/// The pages are only ever accessed through the pointers handed out by `memory_grow`.
+unsafe impl<const PAGES: usize> Sync for GlobalArrayGrower<PAGES> {}
+
+impl<const PAGES: usize> GlobalArrayGrower<PAGES> {
+    pub const fn new() -> Self {
+        GlobalArrayGrower {
+            pages: UnsafeCell::new([Page::ZERO; PAGES]),
+            used_pages: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl<const PAGES: usize> Default for GlobalArrayGrower<PAGES> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const PAGES: usize> MemoryGrower for GlobalArrayGrower<PAGES> {
+    fn memory_grow(&self, delta: PageCount) -> PageCount {
+        match self
+            .used_pages
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                used.checked_add(delta.0).filter(|&total| total <= PAGES)
+            }) {
+            Ok(used) => PageCount(self.pages.get() as usize / PAGE_SIZE + used),
+            Err(_) => ERROR_PAGE_COUNT,
+        }
+    }
+
+    /// The array starts zeroed, and each page is only handed out once.
+    fn grows_zeroed(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GlobalArrayGrower;
+    use crate::{MemoryGrower, PageCount, ERROR_PAGE_COUNT};
+
+    #[test]
+    fn grows_until_full() {
+        static GROWER: GlobalArrayGrower<3> = GlobalArrayGrower::new();
+        let first = GROWER.memory_grow(PageCount(1));
+        assert_eq!(first.size_in_bytes().unwrap(), GROWER.pages.get() as usize);
+        assert_eq!(GROWER.memory_grow(PageCount(2)), PageCount(first.0 + 1));
+        assert_eq!(GROWER.memory_grow(PageCount(1)), ERROR_PAGE_COUNT);
+        assert_eq!(GROWER.current_pages(), PageCount(first.0 + 3));
+        assert_eq!(GROWER.memory_grow(PageCount(usize::MAX)), ERROR_PAGE_COUNT);
+    }
+
+    crate::check_grower!(global_array_grower_conformance, {
+        static GROWER: GlobalArrayGrower<16> = GlobalArrayGrower::new();
+        &GROWER
+    });
+}
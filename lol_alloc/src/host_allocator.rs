@@ -0,0 +1,94 @@
+use crate::{AllocWithExcess, AllocatorStats, Stats};
+use core::alloc::{GlobalAlloc, Layout};
+use std::alloc::System;
+
+/// Allocator which uses the host's system allocator ([System]).
+///
+/// Useful as the inner allocator when testing wrapping allocators (like `lol_alloc_diagnostics::CountingAllocator`) on targets other than wasm.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HostAllocator;
+
+impl HostAllocator {
+    pub const fn new() -> Self {
+        HostAllocator
+    }
+}
+
+unsafe impl GlobalAlloc for HostAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        nonzero_size_assert!(layout.size());
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        nonzero_size_assert!(layout.size());
+        System.dealloc(ptr, layout);
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        nonzero_size_assert!(layout.size());
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        nonzero_size_assert!(layout.size(), new_size);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+/// The system allocator does not report its excess.
+impl AllocWithExcess for HostAllocator {
+    unsafe fn alloc_with_excess(&self, layout: Layout) -> (*mut u8, usize) {
+        let ptr = self.alloc(layout);
+        if ptr.is_null() {
+            return (ptr, 0);
+        }
+        (ptr, layout.size())
+    }
+}
+
+/// The system allocator does not report anything.
+impl AllocatorStats for HostAllocator {
+    fn stats(&self) -> Stats {
+        Stats::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HostAllocator;
+    use crate::{
+        testing::conformance, AllocatorStats, AssumeSingleThreaded, LockedAllocator, SafeHeap,
+    };
+    use core::alloc::{GlobalAlloc, Layout};
+
+    #[test]
+    fn conformance_and_realloc() {
+        let allocator = HostAllocator::new();
+        conformance(&allocator);
+        let layout = Layout::from_size_align(8, 8).unwrap();
+        unsafe {
+            let ptr = allocator.alloc_zeroed(layout);
+            assert_eq!(*(ptr as *const u64), 0);
+            ptr.write_bytes(7, 8);
+            let ptr = allocator.realloc(ptr, layout, 1000);
+            assert_eq!(*ptr.add(7), 7);
+            allocator.dealloc(ptr, Layout::from_size_align(1000, 8).unwrap());
+        }
+    }
+
+    #[test]
+    fn wrappers() {
+        let locked = LockedAllocator::new(HostAllocator::new());
+        conformance(&locked);
+        assert_eq!(locked.stats().free_bytes, None);
+        conformance(&unsafe { AssumeSingleThreaded::new(HostAllocator::new()) });
+
+        let heap = SafeHeap::new(&locked);
+        let value = heap.allocate::<u64>().unwrap();
+        unsafe {
+            value.as_ptr().write(7);
+            heap.deallocate(value);
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use crate::{DefaultGrower, MemoryGrower, PageCount, ERROR_PAGE_COUNT};
+
+#[cfg(target_arch = "wasm32")]
+#[link(wasm_import_module = "env")]
+extern "C" {
+    /// Maximum number of pages the memory may have, provided by the host.
+    fn memory_limit() -> i32;
+}
+
+#[cfg(target_arch = "wasm32")]
+fn imported_memory_limit() -> i32 {
+    unsafe { memory_limit() }
+}
+
+/// Grower which asks the host for the maximum memory size (in pages) before each grow,
+/// and refuses to grow memory beyond it.
+///
+/// Unlike [crate::MaxPagesGrower], the limit applies to the total size of memory (including memory other code grew),
+/// and is controlled by the host.
+///
+/// On wasm32 [ImportLimitedGrower::new] uses the `env.memory_limit() -> i32` import, which the host must provide:
+/// Rust can't weakly import functions, so there is no fallback for hosts which don't provide it.
+/// Negative limits are treated as 0.
+///
+/// The limit is checked before growing, so if multiple threads grow at once, each checks the limit separately.
+pub struct ImportLimitedGrower<T = DefaultGrower> {
+    inner: T,
+    memory_limit: fn() -> i32,
+}
+
+#[cfg(target_arch = "wasm32")]
+impl<T> ImportLimitedGrower<T> {
+    /// Wraps `inner`, limiting memory to the size returned by the `env.memory_limit` import.
+    pub const fn new(inner: T) -> Self {
+        ImportLimitedGrower::new_with_limit_fn(inner, imported_memory_limit)
+    }
+}
+
+impl<T> ImportLimitedGrower<T> {
+    /// Wraps `inner`, limiting memory to the size returned by `memory_limit`, in pages.
+    pub const fn new_with_limit_fn(inner: T, memory_limit: fn() -> i32) -> Self {
+        ImportLimitedGrower {
+            inner,
+            memory_limit,
+        }
+    }
+
+    /// The current limit, in pages.
+    pub fn limit_pages(&self) -> usize {
+        (self.memory_limit)().max(0) as usize
+    }
+
+    /// Number of pages memory can grow by without exceeding the limit.
+    fn available_pages(&self) -> usize
+    where
+        T: MemoryGrower,
+    {
+        self.limit_pages()
+            .saturating_sub(self.inner.current_pages().0)
+    }
+}
+
+impl<T: MemoryGrower> MemoryGrower for ImportLimitedGrower<T> {
+    fn memory_grow(&self, delta: PageCount) -> PageCount {
+        if delta.0 > self.available_pages() {
+            return ERROR_PAGE_COUNT;
+        }
+        self.inner.memory_grow(delta)
+    }
+
+    fn current_pages(&self) -> PageCount {
+        self.inner.current_pages()
+    }
+
+    fn memory_grow_partial(&self, delta: PageCount) -> (PageCount, PageCount) {
+        let available = self.available_pages().min(delta.0);
+        if available == 0 {
+            return (ERROR_PAGE_COUNT, PageCount(0));
+        }
+        self.inner.memory_grow_partial(PageCount(available))
+    }
+
+    fn grows_zeroed(&self) -> bool {
+        self.inner.grows_zeroed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ImportLimitedGrower;
+    use crate::{MemoryGrower, PageCount, ERROR_PAGE_COUNT};
+    use core::{
+        cell::Cell,
+        sync::atomic::{AtomicI32, Ordering},
+    };
+
+    /// Grower which tracks the size of memory like wasm does, without providing any actual memory.
+    struct FakeMemory {
+        pages: Cell<usize>,
+    }
+
+    impl MemoryGrower for FakeMemory {
+        fn memory_grow(&self, delta: PageCount) -> PageCount {
+            let previous = self.pages.get();
+            self.pages.set(previous + delta.0);
+            PageCount(previous)
+        }
+    }
+
+    static LIMIT: AtomicI32 = AtomicI32::new(4);
+
+    fn mock_memory_limit() -> i32 {
+        LIMIT.load(Ordering::Relaxed)
+    }
+
+    #[test]
+    fn limits_memory_size() {
+        let grower = ImportLimitedGrower::new_with_limit_fn(
+            FakeMemory {
+                pages: Cell::new(1),
+            },
+            mock_memory_limit,
+        );
+        assert_eq!(grower.limit_pages(), 4);
+        assert_eq!(grower.memory_grow(PageCount(2)), PageCount(1));
+        assert_eq!(grower.memory_grow(PageCount(2)), ERROR_PAGE_COUNT);
+        assert_eq!(grower.current_pages(), PageCount(3));
+
+        // Partial grants are limited to what fits.
+        assert_eq!(
+            grower.memory_grow_partial(PageCount(2)),
+            (PageCount(3), PageCount(1))
+        );
+        assert_eq!(grower.memory_grow_partial(PageCount(1)).1, PageCount(0));
+
+        // The limit is read again each time.
+        LIMIT.store(5, Ordering::Relaxed);
+        assert_eq!(grower.memory_grow(PageCount(1)), PageCount(4));
+        LIMIT.store(-1, Ordering::Relaxed);
+        assert_eq!(grower.limit_pages(), 0);
+        assert_eq!(grower.memory_grow(PageCount(0)), PageCount(5));
+        assert_eq!(grower.memory_grow(PageCount(1)), ERROR_PAGE_COUNT);
+    }
+}
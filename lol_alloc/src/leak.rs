@@ -0,0 +1,131 @@
+//! Allocating buffers which are used for the rest of the program, like ones set up once at startup.
+//!
+//! The buffers are never freed, so the allocator never reuses their memory,
+//! and it stays valid for as long as the allocator's memory does.
+//! Requiring a `'static` allocator makes that forever, which is what makes returning `'static` references sound for any allocator.
+//! Allocators which would free their memory when dropped (like a [FreeListAllocator](crate::FreeListAllocator) using a buffer owned by something else) can't be dropped while borrowed for `'static`.
+//!
+//! With a [LeakingAllocator](crate::LeakingAllocator), which never reuses memory anyway, this costs nothing over a plain allocation.
+//! With other allocators the memory is simply lost to them.
+
+use crate::safe_heap::dangling;
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    mem::MaybeUninit,
+    slice,
+};
+
+/// Allocates `len` zeroed bytes aligned to `align` from `allocator`, which are never freed.
+///
+/// Returns `None` if the allocation fails, or `align` is not a power of two (or too large for `len`).
+/// Zero length buffers do not allocate.
+// Each call returns a new allocation, so the mutable borrows do not alias.
+#[allow(clippy::mut_from_ref)]
+pub fn leak_bytes<A: GlobalAlloc>(
+    allocator: &'static A,
+    len: usize,
+    align: usize,
+) -> Option<&'static mut [u8]> {
+    let layout = Layout::from_size_align(len, align).ok()?;
+    // Safety: the bytes are zeroed, so initialized.
+    unsafe { leak_layout(allocator, layout, true) }
+        .map(|ptr| unsafe { slice::from_raw_parts_mut(ptr, len) })
+}
+
+/// Allocates uninitialized space for `n` `T`s from `allocator`, which is never freed.
+///
+/// Returns `None` if the allocation fails, or the size overflows.
+/// Zero sized arrays do not allocate.
+///
+/// The contents are not dropped, since they are never freed.
+#[allow(clippy::mut_from_ref)]
+pub fn leak_array<T, A: GlobalAlloc>(
+    allocator: &'static A,
+    n: usize,
+) -> Option<&'static mut [MaybeUninit<T>]> {
+    let layout = Layout::array::<T>(n).ok()?;
+    // Safety: MaybeUninit does not need to be initialized.
+    unsafe { leak_layout(allocator, layout, false) }
+        .map(|ptr| unsafe { slice::from_raw_parts_mut(ptr as *mut MaybeUninit<T>, n) })
+}
+
+/// Allocates `layout` from `allocator` (zeroed if `zeroed`), or gets a dangling pointer for zero sized layouts.
+///
+/// # Safety
+///
+/// The allocation must never be freed.
+unsafe fn leak_layout<A: GlobalAlloc>(
+    allocator: &A,
+    layout: Layout,
+    zeroed: bool,
+) -> Option<*mut u8> {
+    if layout.size() == 0 {
+        // GlobalAlloc does not allow zero sized allocations.
+        return Some(dangling(layout).as_ptr());
+    }
+    let ptr = if zeroed {
+        allocator.alloc_zeroed(layout)
+    } else {
+        allocator.alloc(layout)
+    };
+    (!ptr.is_null()).then_some(ptr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{leak_array, leak_bytes};
+    use crate::{testing::MockGrower, trivial_allocators::LeakingAllocator, NoGrower, PAGE_SIZE};
+    use alloc::boxed::Box;
+    use core::mem::MaybeUninit;
+
+    /// A leaked [LeakingAllocator] over `pages` pages of memory.
+    fn allocator(pages: usize) -> &'static LeakingAllocator<NoGrower> {
+        let grower = Box::leak(Box::new(MockGrower::with_pages(pages)));
+        let buffer =
+            unsafe { core::slice::from_raw_parts_mut(grower.base() as *mut u8, pages * PAGE_SIZE) };
+        Box::leak(Box::new(LeakingAllocator::from_slice(buffer)))
+    }
+
+    #[test]
+    fn alignment() {
+        let allocator = allocator(1);
+        for align in [1, 2, 8, 64, 4096] {
+            // Dirty the next bytes, to check they are zeroed.
+            leak_array::<u8, _>(allocator, 3)
+                .unwrap()
+                .fill(MaybeUninit::new(0xFF));
+            let bytes = leak_bytes(allocator, 5, align).unwrap();
+            assert_eq!(bytes.as_ptr() as usize % align, 0);
+            assert_eq!(bytes, [0; 5]);
+        }
+        let array = leak_array::<u64, _>(allocator, 10).unwrap();
+        assert_eq!(array.len(), 10);
+        assert!(array.as_ptr().is_aligned());
+        assert!(leak_bytes(allocator, 1, 3).is_none());
+    }
+
+    #[test]
+    fn zero_length() {
+        // Zero length buffers work even with no memory.
+        let allocator = allocator(0);
+        let bytes = leak_bytes(allocator, 0, 16).unwrap();
+        assert!(bytes.is_empty());
+        assert_eq!(bytes.as_ptr() as usize % 16, 0);
+        assert!(leak_array::<u32, _>(allocator, 0).unwrap().is_empty());
+        assert!(leak_array::<(), _>(allocator, 1000).unwrap().len() == 1000);
+    }
+
+    #[test]
+    fn out_of_memory() {
+        let allocator = allocator(1);
+        assert!(leak_bytes(allocator, PAGE_SIZE + 1, 1).is_none());
+        assert!(leak_array::<u64, _>(allocator, PAGE_SIZE).is_none());
+        assert!(leak_array::<u64, _>(allocator, usize::MAX).is_none());
+        // Failures do not use up the memory.
+        assert_eq!(
+            leak_bytes(allocator, PAGE_SIZE, 1).unwrap().len(),
+            PAGE_SIZE
+        );
+        assert!(leak_bytes(allocator, 1, 1).is_none());
+    }
+}
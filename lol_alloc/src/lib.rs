@@ -1,33 +1,106 @@
 #![no_std]
+// Some code is only used on wasm32 (with DefaultGrower) or by tests, but is still built elsewhere.
+#![cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
 
-#[cfg(test)]
-#[macro_use]
+#[cfg(any(
+    test,
+    feature = "pointer-registry",
+    feature = "fallible",
+    feature = "raw-buffer",
+    feature = "testing"
+))]
+#[cfg_attr(test, macro_use)]
 extern crate alloc;
 
+#[cfg(any(test, feature = "std"))]
+extern crate std;
+
 extern crate spin;
 
 /// A number of WebAssembly memory pages.
-#[derive(Eq, PartialEq)]
-struct PageCount(usize);
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PageCount(pub usize);
 
 impl PageCount {
-    fn size_in_bytes(self) -> usize {
-        self.0 * PAGE_SIZE
+    /// `None` if the size does not fit in a `usize`
+    /// (which on wasm32 only happens for all 65536 pages, or a misbehaving grower's count).
+    ///
+    /// Allocators treat that as running out of memory, instead of panicking (which would unwind out of `GlobalAlloc`).
+    pub fn size_in_bytes(self) -> Option<usize> {
+        self.0.checked_mul(PAGE_SIZE)
     }
 }
 
 /// The WebAssembly page size, in bytes.
-const PAGE_SIZE: usize = 65536;
+pub const PAGE_SIZE: usize = 65536;
 
 /// Invalid number of pages used to indicate out of memory errors.
-const ERROR_PAGE_COUNT: PageCount = PageCount(usize::MAX);
+pub const ERROR_PAGE_COUNT: PageCount = PageCount(usize::MAX);
 
 /// Wrapper for core::arch::wasm::memory_grow.
 /// Adding this level of indirection allows for improved testing,
-/// especially on non wasm platforms.
-trait MemoryGrower {
+/// especially on non wasm platforms, and getting memory from somewhere other than `memory.grow` (like [GlobalArrayGrower]).
+///
+/// Growers hand out memory in whole pages, and the caller owns granted pages forever.
+/// Granted pages need not be adjacent to previously granted ones
+/// (for example other code may have grown the memory in between).
+///
+/// The trait is object safe, so the grower can be chosen at runtime
+/// with a `&'static dyn MemoryGrower` (or [DynGrower]) as an allocator's grower, like `FreeListAllocator<&'static dyn MemoryGrower>`.
+/// Growing then costs an indirect call, which is rarely noticeable: allocators only grow when they run out of free memory.
+/// Use `&'static (dyn MemoryGrower + Sync)` for allocators which must be `Sync`, like those in a [LockedAllocator].
+pub trait MemoryGrower {
     /// See core::arch::wasm::memory_grow for semantics.
+    ///
+    /// Either grants exactly `delta` pages, returning the page index of the first one,
+    /// or grants nothing and returns [ERROR_PAGE_COUNT].
+    /// Growers which can't grant a whole request must fail it, and should implement [MemoryGrower::memory_grow_partial].
     fn memory_grow(&self, delta: PageCount) -> PageCount;
+
+    /// The current size of memory, in pages.
+    /// Like `memory_grow(0)` for wasm, which is what the default implementation does.
+    fn current_pages(&self) -> PageCount {
+        self.memory_grow(PageCount(0))
+    }
+
+    /// Like [MemoryGrower::memory_grow], but may grant fewer than `delta` pages (for example when hitting a quota).
+    ///
+    /// Returns the page index of the first granted page, and the number of pages granted.
+    /// Granting zero pages indicates failure, in which case the first value is not meaningful.
+    fn memory_grow_partial(&self, delta: PageCount) -> (PageCount, PageCount) {
+        let previous_page_count = self.memory_grow(delta);
+        if previous_page_count == ERROR_PAGE_COUNT {
+            (ERROR_PAGE_COUNT, PageCount(0))
+        } else {
+            (previous_page_count, delta)
+        }
+    }
+
+    /// If granted pages are always zeroed, as `memory.grow` guarantees.
+    ///
+    /// Allocators use this to skip zeroing memory which has not been used since it was grown, in `alloc_zeroed`.
+    /// The default of false is always correct.
+    fn grows_zeroed(&self) -> bool {
+        false
+    }
+}
+
+impl<T: MemoryGrower + ?Sized> MemoryGrower for &T {
+    fn memory_grow(&self, delta: PageCount) -> PageCount {
+        (**self).memory_grow(delta)
+    }
+
+    fn current_pages(&self) -> PageCount {
+        (**self).current_pages()
+    }
+
+    fn memory_grow_partial(&self, delta: PageCount) -> (PageCount, PageCount) {
+        (**self).memory_grow_partial(delta)
+    }
+
+    fn grows_zeroed(&self) -> bool {
+        (**self).grows_zeroed()
+    }
 }
 
 /// Stateless heap grower.
@@ -42,16 +115,227 @@ impl MemoryGrower for DefaultGrower {
         // See https://github.com/Craig-Macomber/lol_alloc/issues/1
         PageCount(core::arch::wasm32::memory_grow(0, delta.0))
     }
+
+    fn grows_zeroed(&self) -> bool {
+        true
+    }
+}
+
+/// Grower which never provides any memory.
+/// Used by allocators which are given all their memory up front, like [LeakingAllocator::from_slice].
+pub struct NoGrower;
+
+impl MemoryGrower for NoGrower {
+    fn memory_grow(&self, delta: PageCount) -> PageCount {
+        // Growing nothing succeeds, returning the (empty) size, like `memory.grow` does.
+        if delta.0 == 0 {
+            PageCount(0)
+        } else {
+            ERROR_PAGE_COUNT
+        }
+    }
+
+    fn current_pages(&self) -> PageCount {
+        PageCount(0)
+    }
+}
+
+/// The `debug_assert!` used throughout this crate.
+///
+/// With the `tiny-asserts` feature, failures [trap] instead of panicking,
+/// so builds with debug assertions enabled do not include panic formatting code from this crate.
+macro_rules! lol_assert {
+    ($cond:expr $(, $($message:tt)+)?) => {{
+        #[cfg(feature = "tiny-asserts")]
+        if cfg!(debug_assertions) && !$cond {
+            $crate::trap();
+        }
+        #[cfg(not(feature = "tiny-asserts"))]
+        debug_assert!($cond $(, $($message)+)?);
+    }};
+}
+
+/// Checks a condition the allocators rely on for soundness.
+///
+/// By default this is just a [lol_assert].
+/// With the `strict-checks` feature it is also checked in release builds,
+/// and failures [trap] instead of panicking so no panic formatting code is included.
+macro_rules! strict_assert {
+    ($cond:expr) => {{
+        #[cfg(feature = "strict-checks")]
+        if !$cond {
+            $crate::trap();
+        }
+        #[cfg(not(feature = "strict-checks"))]
+        lol_assert!($cond);
+    }};
 }
 
+/// Checks sizes passed to a `GlobalAlloc` method are not zero, which `GlobalAlloc` forbids.
+///
+/// Every `GlobalAlloc` implementation in this crate checks this (with debug assertions), so callers passing zero sizes are caught
+/// instead of getting whatever each allocator happens to do (like [LeakingPageAllocator] returning the end of memory).
+/// Zero sized allocations belong in the APIs which handle them, like [SafeHeap] and the `fallible` and `raw-buffer` helpers,
+/// which use [dangling](crate::safe_heap::dangling) pointers without calling the allocator.
+macro_rules! nonzero_size_assert {
+    ($($size:expr),+) => {{
+        $(lol_assert!($size != 0, "zero sized allocation");)+
+    }};
+}
+
+/// Stops execution.
+/// On wasm32 this is the `unreachable` instruction.
+/// Other targets panic (with a fixed message) so the behavior can be tested natively.
+#[cfg(any(feature = "strict-checks", feature = "tiny-asserts"))]
+#[cold]
+fn trap() -> ! {
+    #[cfg(target_arch = "wasm32")]
+    core::arch::wasm32::unreachable();
+    #[cfg(not(target_arch = "wasm32"))]
+    panic!("lol_alloc trap");
+}
+
+/// Atomics used by the allocators (but not the tests).
+/// All uses go through here so the implementation can be changed in one place.
+/// They are always `core`'s: targets without native atomics are not supported.
+mod atomic {
+    #[cfg(target_has_atomic = "64")]
+    pub(crate) use core::sync::atomic::AtomicU64;
+    pub(crate) use core::sync::atomic::{
+        fence, AtomicBool, AtomicPtr, AtomicU8, AtomicUsize, Ordering,
+    };
+}
+
+/// Exports a `lol_alloc_reset_heap` function, which the host can call to reset a [FreeListAllocator] (see [FreeListAllocator::reset_heap]).
+///
+/// Takes an expression evaluating to the `FreeListAllocator` (or something which dereferences to it), for example:
+///
+/// ```ignore
+/// #[global_allocator]
+/// static ALLOCATOR: LockedAllocator<FreeListAllocator> = LockedAllocator::new(FreeListAllocator::new());
+///
+/// lol_alloc::export_reset_heap!(ALLOCATOR.lock());
+/// ```
+///
+/// The host must only call `lol_alloc_reset_heap` when it is safe to call `reset_heap`:
+/// no allocations may survive it.
+#[cfg(feature = "reset-heap")]
+#[macro_export]
+macro_rules! export_reset_heap {
+    ($free_list:expr) => {
+        /// Frees all memory in the heap.
+        ///
+        /// # Safety
+        ///
+        /// No allocation from the heap may be used after this is called.
+        #[unsafe(no_mangle)]
+        pub unsafe extern "C" fn lol_alloc_reset_heap() {
+            $free_list.reset_heap();
+        }
+    };
+}
+
+mod alloc_error;
+mod alloc_ref;
+mod asan;
+#[cfg(feature = "auto-global")]
+mod auto_global;
+mod budget_allocator;
+mod cache_isolated_allocator;
+mod deferred_free;
+mod define_global;
+mod dyn_grower;
+mod excess;
+mod extern_allocator;
+#[cfg(feature = "fallible")]
+mod fallible;
 mod free_list_allocator;
+mod global_array_grower;
+#[cfg(feature = "std")]
+mod host_allocator;
+mod import_limited_grower;
+mod leak;
+mod live_blocks;
+mod lock;
 mod locked_allocator;
+mod max_pages_grower;
+mod mpsc_free;
+mod naturally_aligned_allocator;
+mod oom_handling;
+mod phase_switch;
+#[cfg(feature = "pointer-registry")]
+mod pointer_registry;
+#[cfg(feature = "randomized")]
+mod randomized_free_list;
+#[cfg(feature = "raw-buffer")]
+mod raw_buffer;
+mod rcu_arena_allocator;
+mod safe_heap;
 mod single_threaded_allocator;
+mod size_check;
+mod size_skip_free_list_allocator;
+mod slab_arc;
+// Its bitmap of used slots is an AtomicU64, which targets like Cortex-M3 do not have.
+#[cfg(target_has_atomic = "64")]
+mod stack_pool;
+mod stats;
+#[cfg(all(test, unix))]
+mod test_utils;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 mod trivial_allocators;
-#[cfg(target_arch = "wasm32")]
-pub use crate::free_list_allocator::FreeListAllocator;
-pub use crate::locked_allocator::LockedAllocator;
+mod zero;
+pub use crate::alloc_error::AllocError;
+pub use crate::alloc_ref::AllocRef;
+#[cfg(all(feature = "auto-global", target_arch = "wasm32"))]
+pub use crate::auto_global::AUTO_GLOBAL;
+pub use crate::budget_allocator::BudgetAllocator;
+pub use crate::cache_isolated_allocator::CacheIsolatedAllocator;
+pub use crate::deferred_free::DeferredFreeAllocator;
+#[doc(hidden)]
+pub use crate::define_global::__counted_stats;
+pub use crate::dyn_grower::DynGrower;
+pub use crate::excess::AllocWithExcess;
+pub use crate::extern_allocator::{AlignedAllocFn, ExternAllocator, FreeFn, MallocFn};
+#[cfg(feature = "fallible")]
+pub use crate::fallible::{try_box, try_string_with_capacity, try_vec_with_capacity};
+#[cfg(feature = "jitter")]
+pub use crate::free_list_allocator::JITTER_MAX_NODES;
+#[cfg(feature = "large-objects")]
+pub use crate::free_list_allocator::LARGE_OBJECT_THRESHOLD;
+pub use crate::free_list_allocator::{
+    FreeListAllocator, LeakingAllocatorFromFreeList, LiveInfo, StaticChunkFreeListAllocator,
+};
+pub use crate::global_array_grower::GlobalArrayGrower;
+#[cfg(feature = "std")]
+pub use crate::host_allocator::HostAllocator;
+pub use crate::import_limited_grower::ImportLimitedGrower;
+pub use crate::leak::{leak_array, leak_bytes};
+#[cfg(all(target_arch = "wasm32", not(target_feature = "atomics")))]
+pub use crate::lock::FlagGuard;
+pub use crate::lock::LockGuard;
+pub use crate::locked_allocator::{LockedAllocator, LockedFreeListAllocator};
+pub use crate::max_pages_grower::MaxPagesGrower;
+pub use crate::mpsc_free::MpscFreeAllocator;
+pub use crate::naturally_aligned_allocator::NaturallyAlignedAllocator;
+pub use crate::oom_handling::{PressureCallback, RecoveringAllocator};
+pub use crate::phase_switch::PhaseSwitch;
+#[cfg(all(feature = "pointer-registry", debug_assertions))]
+pub use crate::pointer_registry::GlobalPointerRegistry;
+#[cfg(feature = "pointer-registry")]
+pub use crate::pointer_registry::RegisteredAllocator;
+#[cfg(feature = "randomized")]
+pub use crate::randomized_free_list::RandomizedFreeListAllocator;
+#[cfg(feature = "buffer-exports")]
+pub use crate::raw_buffer::{lol_alloc_buffer_alloc, lol_alloc_buffer_free};
+#[cfg(feature = "raw-buffer")]
+pub use crate::raw_buffer::{Global, RawBuffer};
+pub use crate::rcu_arena_allocator::{RcuArenaAllocator, RcuReadGuard};
+pub use crate::safe_heap::SafeHeap;
 pub use crate::single_threaded_allocator::AssumeSingleThreaded;
-pub use crate::trivial_allocators::FailAllocator;
-#[cfg(target_arch = "wasm32")]
-pub use crate::trivial_allocators::{LeakingAllocator, LeakingPageAllocator};
+pub use crate::size_skip_free_list_allocator::SizeSkipFreeListAllocator;
+pub use crate::slab_arc::SlabArc;
+#[cfg(target_has_atomic = "64")]
+pub use crate::stack_pool::StackPoolAllocator;
+pub use crate::stats::{AllocatorStats, CountsAllocations, Stats};
+pub use crate::trivial_allocators::{FailAllocator, LeakingAllocator, LeakingPageAllocator};
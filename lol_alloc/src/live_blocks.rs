@@ -0,0 +1,174 @@
+//! Tracking of live allocations for the `live-blocks` feature.
+//!
+//! Without the feature, or unless enabled with [LiveList::enabled], everything here is a no-op.
+
+use core::alloc::Layout;
+#[cfg(feature = "live-blocks")]
+use core::{cell::UnsafeCell, ptr::null_mut};
+
+/// Stored immediately before each live allocation.
+#[cfg(feature = "live-blocks")]
+#[repr(C)]
+struct LiveHeader {
+    prev: *mut LiveHeader,
+    next: *mut LiveHeader,
+    /// Size of the allocation, from its [Layout].
+    size: usize,
+}
+
+#[cfg(feature = "live-blocks")]
+const HEADER_SIZE: usize = core::mem::size_of::<LiveHeader>();
+
+/// Intrusive doubly linked list of live allocations, so they can be enumerated without allocating.
+///
+/// Not thread safe: the allocator containing it must provide synchronization.
+pub(crate) struct LiveList {
+    /// If allocations have headers and are tracked.
+    #[cfg(feature = "live-blocks")]
+    enabled: bool,
+    #[cfg(feature = "live-blocks")]
+    head: UnsafeCell<*mut LiveHeader>,
+}
+
+#[cfg(feature = "live-blocks")]
+impl LiveList {
+    /// A list which does not track anything.
+    pub(crate) const fn new() -> Self {
+        LiveList {
+            enabled: false,
+            head: UnsafeCell::new(null_mut()),
+        }
+    }
+
+    pub(crate) const fn enabled() -> Self {
+        LiveList {
+            enabled: true,
+            head: UnsafeCell::new(null_mut()),
+        }
+    }
+
+    /// If allocations have headers and are tracked.
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Bytes [LiveList::inner_layout] adds before allocations aligned to `align`.
+    pub(crate) fn header_space(&self, align: usize) -> usize {
+        if !self.enabled {
+            return 0;
+        }
+        HEADER_SIZE.next_multiple_of(align)
+    }
+
+    /// The layout to allocate for `layout`, with space for the header before it.
+    pub(crate) fn inner_layout(&self, layout: Layout) -> Option<Layout> {
+        if !self.enabled {
+            return Some(layout);
+        }
+        let size = layout.size().checked_add(header_offset(layout))?;
+        let align = layout.align().max(core::mem::align_of::<LiveHeader>());
+        Layout::from_size_align(size, align).ok()
+    }
+
+    /// Adds the allocation for `layout` to the list, given `raw` allocated with [LiveList::inner_layout].
+    /// Returns the pointer to give to the user (null if `raw` is null).
+    pub(crate) unsafe fn link(&self, raw: *mut u8, layout: Layout) -> *mut u8 {
+        if !self.enabled || raw.is_null() {
+            return raw;
+        }
+        let ptr = raw.add(header_offset(layout));
+        let header = header(ptr);
+        let head = &mut *self.head.get();
+        header.write(LiveHeader {
+            prev: null_mut(),
+            next: *head,
+            size: layout.size(),
+        });
+        if !head.is_null() {
+            (**head).prev = header;
+        }
+        *head = header;
+        ptr
+    }
+
+    /// Removes the allocation at `ptr` from the list, returning the pointer originally allocated with [LiveList::inner_layout].
+    pub(crate) unsafe fn unlink(&self, ptr: *mut u8, layout: Layout) -> *mut u8 {
+        if !self.enabled {
+            return ptr;
+        }
+        let header = header(ptr);
+        let LiveHeader { prev, next, size } = header.read();
+        lol_assert!(
+            size == layout.size(),
+            "Freed layout does not match allocation"
+        );
+        if prev.is_null() {
+            *self.head.get() = next;
+        } else {
+            (*prev).next = next;
+        }
+        if !next.is_null() {
+            (*next).prev = prev;
+        }
+        ptr.sub(header_offset(layout))
+    }
+
+    /// Forgets all allocations.
+    pub(crate) unsafe fn clear(&self) {
+        *self.head.get() = null_mut();
+    }
+
+    /// Calls `f` with the pointer and size of each live allocation, most recent first.
+    pub(crate) unsafe fn for_each(&self, mut f: impl FnMut(*mut u8, usize)) {
+        let mut header = *self.head.get();
+        while !header.is_null() {
+            f((header as *mut u8).add(HEADER_SIZE), (*header).size);
+            header = (*header).next;
+        }
+    }
+}
+
+#[cfg(not(feature = "live-blocks"))]
+impl LiveList {
+    pub(crate) const fn new() -> Self {
+        LiveList {}
+    }
+
+    #[inline(always)]
+    pub(crate) fn is_enabled(&self) -> bool {
+        false
+    }
+
+    #[inline(always)]
+    pub(crate) fn header_space(&self, _align: usize) -> usize {
+        0
+    }
+
+    #[inline(always)]
+    pub(crate) fn inner_layout(&self, layout: Layout) -> Option<Layout> {
+        Some(layout)
+    }
+
+    #[inline(always)]
+    pub(crate) unsafe fn link(&self, raw: *mut u8, _layout: Layout) -> *mut u8 {
+        raw
+    }
+
+    #[inline(always)]
+    pub(crate) unsafe fn unlink(&self, ptr: *mut u8, _layout: Layout) -> *mut u8 {
+        ptr
+    }
+}
+
+/// The header for the allocation returned at `ptr`.
+#[cfg(feature = "live-blocks")]
+fn header(ptr: *mut u8) -> *mut LiveHeader {
+    ptr.wrapping_sub(HEADER_SIZE) as *mut LiveHeader
+}
+
+/// Offset from the start of the inner allocation to the pointer given to the user.
+#[cfg(feature = "live-blocks")]
+fn header_offset(layout: Layout) -> usize {
+    // Alignments are powers of two, so this is a multiple of both the alignment and the header's alignment.
+    HEADER_SIZE.next_multiple_of(layout.align())
+}
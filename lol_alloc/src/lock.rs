@@ -0,0 +1,140 @@
+//! The lock used by [LockedAllocator](crate::LockedAllocator) and [LockedFreeListAllocator](crate::LockedFreeListAllocator).
+//!
+//! wasm32 builds without the `atomics` target feature can't have threads, so there the lock is just a flag,
+//! which catches recursive use (which would deadlock a real lock) by trapping.
+//! With the `unchecked-lock` feature, it does not even do that, so it costs the same as [AssumeSingleThreaded](crate::AssumeSingleThreaded).
+//! Everywhere else it is a spin lock.
+
+#[cfg(any(test, all(target_arch = "wasm32", not(target_feature = "atomics"))))]
+use core::{
+    cell::UnsafeCell,
+    ops::{Deref, DerefMut},
+};
+
+#[cfg(any(not(target_arch = "wasm32"), target_feature = "atomics"))]
+pub(crate) use spin::Mutex as Lock;
+
+/// Guard which allows access to the allocator inside a locked allocator, and releases the lock when dropped.
+#[cfg(any(not(target_arch = "wasm32"), target_feature = "atomics"))]
+pub type LockGuard<'a, T> = spin::MutexGuard<'a, T>;
+
+#[cfg(all(target_arch = "wasm32", not(target_feature = "atomics")))]
+pub(crate) use FlagLock as Lock;
+
+/// Guard which allows access to the allocator inside a locked allocator, and releases the lock when dropped.
+#[cfg(all(target_arch = "wasm32", not(target_feature = "atomics")))]
+pub type LockGuard<'a, T> = FlagGuard<'a, T>;
+
+/// A lock for targets without threads, which only tracks if it is held.
+#[cfg(any(test, all(target_arch = "wasm32", not(target_feature = "atomics"))))]
+pub struct FlagLock<T> {
+    #[cfg(not(feature = "unchecked-lock"))]
+    locked: core::cell::Cell<bool>,
+    value: UnsafeCell<T>,
+}
+
+/// There are no other threads to share with.
+#[cfg(all(target_arch = "wasm32", not(target_feature = "atomics")))]
+unsafe impl<T: Send> Sync for FlagLock<T> {}
+
+#[cfg(any(test, all(target_arch = "wasm32", not(target_feature = "atomics"))))]
+impl<T> FlagLock<T> {
+    pub(crate) const fn new(value: T) -> Self {
+        FlagLock {
+            #[cfg(not(feature = "unchecked-lock"))]
+            locked: core::cell::Cell::new(false),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Locks, trapping if already locked (unless the `unchecked-lock` feature is enabled, in which case that is undefined behavior).
+    pub(crate) fn lock(&self) -> FlagGuard<'_, T> {
+        #[cfg(not(feature = "unchecked-lock"))]
+        if self.locked.replace(true) {
+            recursive_lock();
+        }
+        FlagGuard { lock: self }
+    }
+
+    #[cfg(all(test, not(feature = "unchecked-lock")))]
+    pub(crate) fn is_locked(&self) -> bool {
+        self.locked.get()
+    }
+}
+
+/// Guard for a [FlagLock].
+#[cfg(any(test, all(target_arch = "wasm32", not(target_feature = "atomics"))))]
+pub struct FlagGuard<'a, T> {
+    lock: &'a FlagLock<T>,
+}
+
+#[cfg(any(test, all(target_arch = "wasm32", not(target_feature = "atomics"))))]
+impl<T> Deref for FlagGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+#[cfg(any(test, all(target_arch = "wasm32", not(target_feature = "atomics"))))]
+impl<T> DerefMut for FlagGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+#[cfg(any(test, all(target_arch = "wasm32", not(target_feature = "atomics"))))]
+impl<T> Drop for FlagGuard<'_, T> {
+    fn drop(&mut self) {
+        #[cfg(not(feature = "unchecked-lock"))]
+        self.lock.locked.set(false);
+    }
+}
+
+/// Stops execution when a [FlagLock] is locked while held.
+#[cfg(all(
+    any(test, all(target_arch = "wasm32", not(target_feature = "atomics"))),
+    not(feature = "unchecked-lock")
+))]
+#[cold]
+fn recursive_lock() -> ! {
+    #[cfg(target_arch = "wasm32")]
+    core::arch::wasm32::unreachable();
+    #[cfg(not(target_arch = "wasm32"))]
+    panic!("lock is already held");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FlagLock, LockGuard};
+
+    /// Tests run natively, where there can be threads.
+    #[test]
+    fn uses_spin_lock() {
+        assert!(core::any::type_name::<LockGuard<'static, u8>>().starts_with("spin::"));
+    }
+
+    #[test]
+    fn flag_lock() {
+        let lock = FlagLock::new(1);
+        {
+            let mut guard = lock.lock();
+            *guard += 1;
+            #[cfg(not(feature = "unchecked-lock"))]
+            assert!(lock.is_locked());
+        }
+        #[cfg(not(feature = "unchecked-lock"))]
+        assert!(!lock.is_locked());
+        assert_eq!(*lock.lock(), 2);
+    }
+
+    #[cfg(not(feature = "unchecked-lock"))]
+    #[test]
+    #[should_panic(expected = "lock is already held")]
+    fn flag_lock_recursive() {
+        let lock = FlagLock::new(());
+        let _guard = lock.lock();
+        let _ = lock.lock();
+    }
+}
@@ -1,24 +1,480 @@
-use core::alloc::{GlobalAlloc, Layout};
+use crate::{
+    alloc_ref::AllocRef,
+    free_list_allocator::{
+        checked_full_size, full_size, FreeListAllocator, GrowProgress, NODE_SIZE,
+    },
+    lock::{Lock, LockGuard},
+    size_check,
+    zero::zeroed,
+    AllocWithExcess, AllocatorStats, CountsAllocations, DefaultGrower, MemoryGrower, Stats,
+};
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    ptr::null_mut,
+};
 
 /// A thread safe allocator created by wrapping a (possible not thread-safe) allocator in a spin-lock.
+///
+/// On wasm32 without the `atomics` target feature there can't be threads, so the lock is only a flag catching recursive use
+/// (see the `unchecked-lock` feature).
 pub struct LockedAllocator<T> {
-    spin: spin::Mutex<T>,
+    spin: Lock<T>,
 }
 
 impl<T> LockedAllocator<T> {
     pub const fn new(t: T) -> Self {
-        LockedAllocator {
-            spin: spin::Mutex::new(t),
-        }
+        LockedAllocator { spin: Lock::new(t) }
+    }
+
+    /// Locks the inner allocator, for access to functionality beyond [GlobalAlloc].
+    pub fn lock(&self) -> LockGuard<'_, T> {
+        self.spin.lock()
+    }
+
+    /// Locks the inner allocator, returning an allocator which uses it until dropped.
+    ///
+    /// Useful for making a batch of allocations or frees while only locking once.
+    pub fn alloc_ref(&self) -> AllocRef<'_, T> {
+        AllocRef::new(self.spin.lock())
     }
 }
 
 unsafe impl<T: GlobalAlloc> GlobalAlloc for LockedAllocator<T> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        nonzero_size_assert!(layout.size());
         self.spin.lock().alloc(layout)
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        nonzero_size_assert!(layout.size());
         self.spin.lock().dealloc(ptr, layout);
     }
+
+    /// Holds the lock while the inner allocator zeroes, so it can skip memory it knows is still zero.
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        nonzero_size_assert!(layout.size());
+        self.spin.lock().alloc_zeroed(layout)
+    }
+
+    /// Holds the lock for the whole `realloc`, so the inner allocator can resize in place.
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        nonzero_size_assert!(layout.size(), new_size);
+        self.spin.lock().realloc(ptr, layout, new_size)
+    }
+}
+
+impl<T: AllocWithExcess> AllocWithExcess for LockedAllocator<T> {
+    unsafe fn alloc_with_excess(&self, layout: Layout) -> (*mut u8, usize) {
+        self.spin.lock().alloc_with_excess(layout)
+    }
+}
+
+impl<T: AllocatorStats> AllocatorStats for LockedAllocator<T> {
+    fn stats(&self) -> Stats {
+        self.spin.lock().stats()
+    }
+}
+
+impl<T: CountsAllocations> CountsAllocations for LockedAllocator<T> {}
+
+/// A thread safe [FreeListAllocator].
+///
+/// Like `LockedAllocator<FreeListAllocator>`, but grows memory without holding the lock,
+/// so other threads are not stalled while one thread is growing memory (which can be slow).
+/// If multiple threads grow at the same time, memory one of them grew might not end up being needed for its allocation:
+/// it remains in the free list for future allocations.
+pub struct LockedFreeListAllocator<T = DefaultGrower> {
+    /// The free list, which never grows itself: growing is done outside the lock, using `grower`.
+    free_list: Lock<FreeListAllocator<()>>,
+    grower: T,
+}
+
+#[cfg(target_arch = "wasm32")]
+impl LockedFreeListAllocator<DefaultGrower> {
+    pub const fn new() -> Self {
+        LockedFreeListAllocator::new_in(DefaultGrower)
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Default for LockedFreeListAllocator<DefaultGrower> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> LockedFreeListAllocator<T> {
+    /// Creates an allocator which gets its memory from `grower`.
+    pub const fn new_in(grower: T) -> Self {
+        LockedFreeListAllocator {
+            free_list: Lock::new(FreeListAllocator::new_in(())),
+            grower,
+        }
+    }
+
+    /// Locks the free list, for access to functionality beyond [GlobalAlloc].
+    pub fn lock(&self) -> LockGuard<'_, FreeListAllocator<()>> {
+        self.free_list.lock()
+    }
+}
+
+unsafe impl<T: MemoryGrower + Sync> GlobalAlloc for LockedFreeListAllocator<T> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        nonzero_size_assert!(layout.size());
+        let Some(block) = size_check::inner_layout(layout) else {
+            return null_mut();
+        };
+        size_check::write(self.alloc_block(block).0, layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        nonzero_size_assert!(layout.size());
+        // Layout was valid when allocated, so this is too.
+        let block = size_check::inner_layout(layout).unwrap_unchecked();
+        let raw = size_check::check(ptr, layout);
+        self.free_list.lock().free(raw, full_size(block));
+    }
+
+    /// Like [FreeListAllocator], memory never used since it was grown is not zeroed again.
+    /// Zeroes after unlocking, so other threads are not kept waiting.
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        nonzero_size_assert!(layout.size());
+        let Some(block) = size_check::inner_layout(layout) else {
+            return null_mut();
+        };
+        let (raw, fresh) = self.alloc_block(block);
+        let ptr = size_check::write(raw, layout);
+        if !self.grower.grows_zeroed() {
+            return zeroed(ptr, layout.size());
+        }
+        let used = fresh.saturating_sub(ptr as usize).min(layout.size());
+        zeroed(ptr, used)
+    }
+}
+
+impl<T: MemoryGrower + Sync> LockedFreeListAllocator<T> {
+    /// Allocates a free list block for `layout` (which includes any `dealloc-size-check` prefix), growing memory if needed.
+    ///
+    /// Also returns the address from which the block has never been used (see [FreeListAllocator::mark_allocated]).
+    unsafe fn alloc_block(&self, layout: Layout) -> (*mut u8, usize) {
+        let Some(size) = checked_full_size(layout) else {
+            return (null_mut(), 0);
+        };
+        let alignment = layout.align().max(NODE_SIZE);
+        {
+            let free_list = self.free_list.lock();
+            let ptr = free_list.alloc_from_free_list(size, alignment);
+            if !ptr.is_null() {
+                return (ptr, free_list.mark_allocated(ptr, size));
+            }
+        }
+
+        // Other threads can free memory while the lock is not held, so growing must search the whole free list again.
+        let mut progress = GrowProgress::new(false);
+        loop {
+            // Other threads may change the free list or grow memory before this grows, so this may grow too little (making this retry) or too much.
+            // The size of memory is read before locking, since the grower may be slow.
+            let current_pages = self.grower.current_pages();
+            let Some(pages) =
+                self.free_list
+                    .lock()
+                    .pages_to_grow_for(|| current_pages, size, alignment)
+            else {
+                return (null_mut(), 0);
+            };
+            // Grow without the lock held.
+            let (first_page, granted) = self.grower.memory_grow_partial(pages);
+            if granted.0 == 0 {
+                return (null_mut(), 0);
+            }
+            let free_list = self.free_list.lock();
+            match free_list.alloc_from_grown(first_page, granted, size, alignment, &mut progress) {
+                Some(ptr) if ptr.is_null() => return (ptr, 0),
+                Some(ptr) => return (ptr, free_list.mark_allocated(ptr, size)),
+                None => {}
+            }
+        }
+    }
+}
+
+/// Reports the rounded up size of the block carved from the free list.
+/// With `dealloc-size-check`, frees must use the exact size, so there is no excess.
+impl<T: MemoryGrower + Sync> AllocWithExcess for LockedFreeListAllocator<T> {
+    unsafe fn alloc_with_excess(&self, layout: Layout) -> (*mut u8, usize) {
+        let ptr = self.alloc(layout);
+        if ptr.is_null() {
+            return (ptr, 0);
+        }
+        if size_check::ENABLED {
+            return (ptr, layout.size());
+        }
+        (ptr, full_size(layout))
+    }
+}
+
+impl<T> AllocatorStats for LockedFreeListAllocator<T> {
+    fn stats(&self) -> Stats {
+        self.free_list.lock().stats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LockedAllocator, LockedFreeListAllocator};
+    use crate::{
+        free_list_allocator::NODE_SIZE,
+        size_check,
+        testing::{
+            block_layout, block_size, conformance, excess_conformance, fresh_zeroed_bytes,
+            MockGrower,
+        },
+        AllocatorStats, FreeListAllocator, MemoryGrower, PageCount, PAGE_SIZE,
+    };
+    use alloc::vec::Vec;
+    use core::{
+        alloc::{GlobalAlloc, Layout},
+        sync::atomic::{AtomicBool, Ordering},
+    };
+    use std::thread;
+
+    /// Grower which can be made to block until released, to simulate a slow `memory.grow`.
+    struct SlowGrower {
+        grower: MockGrower,
+        block: AtomicBool,
+        growing: AtomicBool,
+    }
+
+    impl MemoryGrower for SlowGrower {
+        fn memory_grow(&self, delta: PageCount) -> PageCount {
+            self.growing.store(true, Ordering::SeqCst);
+            while self.block.load(Ordering::SeqCst) {
+                thread::yield_now();
+            }
+            let result = self.grower.memory_grow(delta);
+            self.growing.store(false, Ordering::SeqCst);
+            result
+        }
+    }
+
+    #[test]
+    fn locked_free_list_allocator_conformance() {
+        let allocator = LockedFreeListAllocator::new_in(MockGrower::new());
+        conformance(&allocator);
+        allocator.lock().validate_heap();
+    }
+
+    #[test]
+    fn locked_free_list_allocator_excess() {
+        let allocator = LockedFreeListAllocator::new_in(MockGrower::new());
+        excess_conformance(&allocator);
+        allocator.lock().validate_heap();
+        let used = allocator.grower.used_pages() * PAGE_SIZE;
+        assert_eq!(allocator.stats().free_bytes, Some(used));
+    }
+
+    #[test]
+    fn locked_allocator_excess() {
+        let allocator = LockedAllocator::new(FreeListAllocator::new_in(MockGrower::new()));
+        excess_conformance(&allocator);
+        allocator.lock().validate_heap();
+    }
+
+    /// `alloc_zeroed` is forwarded, so memory never used since it was grown is not zeroed again.
+    #[test]
+    fn locked_allocator_alloc_zeroed_skips_fresh_memory() {
+        let allocator = LockedAllocator::new(FreeListAllocator::new_in(MockGrower::new()));
+        let base = allocator.lock().grower.base() as *mut u8;
+        let zeroed = unsafe { fresh_zeroed_bytes(&allocator, base) };
+        assert_eq!(zeroed, NODE_SIZE - size_check::prefix_space(8));
+    }
+
+    /// Like FreeListAllocator, memory never used since it was grown is not zeroed again.
+    #[test]
+    fn locked_free_list_alloc_zeroed_skips_fresh_memory() {
+        let allocator = LockedFreeListAllocator::new_in(MockGrower::new());
+        let base = allocator.grower.base() as *mut u8;
+        let zeroed = unsafe { fresh_zeroed_bytes(&allocator, base) };
+        assert_eq!(zeroed, NODE_SIZE - size_check::prefix_space(8));
+        // Once used, it is zeroed.
+        let layout = Layout::from_size_align(PAGE_SIZE * 2 - NODE_SIZE, 8).unwrap();
+        unsafe {
+            let ptr = allocator.alloc_zeroed(layout);
+            assert!(core::slice::from_raw_parts(ptr, layout.size())
+                .iter()
+                .all(|&byte| byte == 0));
+        }
+    }
+
+    /// `realloc` is forwarded, so growing into a free neighbor keeps the allocation where it is.
+    #[test]
+    fn locked_allocator_realloc_grows_in_place() {
+        let allocator = LockedAllocator::new(FreeListAllocator::new_in(MockGrower::new()));
+        let layout = block_layout(NODE_SIZE * 2, 8);
+        let grown = block_layout(NODE_SIZE * 4, 8);
+        unsafe {
+            // `b` is placed just below `a`, so freeing `a` leaves a free block just after `b`.
+            let a = allocator.alloc(layout);
+            let b = allocator.alloc(layout);
+            allocator.dealloc(a, layout);
+            assert_eq!(allocator.realloc(b, layout, grown.size()), b);
+            allocator.dealloc(b, grown);
+        }
+        allocator.lock().validate_heap();
+    }
+
+    /// Shrinking through the lock keeps the allocation where it is, and frees its tail.
+    #[test]
+    fn locked_allocator_realloc_shrinks_in_place() {
+        let allocator = LockedAllocator::new(FreeListAllocator::new_in(MockGrower::new()));
+        let layout = block_layout(NODE_SIZE * 4, 8);
+        let shrunk = block_layout(NODE_SIZE * 2, 8);
+        unsafe {
+            let ptr = allocator.alloc(layout);
+            let free = allocator.stats().free_bytes.unwrap();
+            assert_eq!(allocator.realloc(ptr, layout, shrunk.size()), ptr);
+            assert_eq!(allocator.stats().free_bytes, Some(free + NODE_SIZE * 2));
+            allocator.dealloc(ptr, shrunk);
+        }
+        allocator.lock().validate_heap();
+    }
+
+    #[test]
+    fn alloc_ref_holds_lock_until_dropped() {
+        let allocator = LockedAllocator::new(FreeListAllocator::new_in(MockGrower::new()));
+        let layout = Layout::from_size_align(32, 8).unwrap();
+        let alloc_ref = allocator.alloc_ref();
+        let batch: Vec<*mut u8> = (0..10)
+            .map(|_| unsafe { alloc_ref.alloc(layout) })
+            .collect();
+        assert!(batch.iter().all(|ptr| !ptr.is_null()));
+        assert!(allocator.spin.is_locked());
+        for &ptr in &batch {
+            unsafe { alloc_ref.dealloc(ptr, layout) };
+        }
+        drop(alloc_ref);
+        assert!(!allocator.spin.is_locked());
+        allocator.lock().validate_heap();
+    }
+
+    #[test]
+    fn allocations_proceed_during_grow() {
+        let allocator = LockedFreeListAllocator::new_in(SlowGrower {
+            grower: MockGrower::new(),
+            block: AtomicBool::new(false),
+            growing: AtomicBool::new(false),
+        });
+        let small = Layout::from_size_align(16, 1).unwrap();
+        let large = Layout::from_size_align(PAGE_SIZE * 2, 1).unwrap();
+        unsafe {
+            // Put some memory in the free list.
+            allocator.dealloc(allocator.alloc(small), small);
+        }
+        allocator.grower.block.store(true, Ordering::SeqCst);
+        thread::scope(|scope| {
+            let grow = scope.spawn(|| unsafe { allocator.alloc(large) as usize });
+            while !allocator.grower.growing.load(Ordering::SeqCst) {
+                thread::yield_now();
+            }
+            // The other thread is now stuck growing: allocations from the existing free memory still work.
+            unsafe {
+                let ptr = allocator.alloc(small);
+                assert!(!ptr.is_null());
+                allocator.dealloc(ptr, small);
+            }
+            assert!(allocator.grower.growing.load(Ordering::SeqCst));
+            allocator.grower.block.store(false, Ordering::SeqCst);
+            let ptr = grow.join().unwrap();
+            assert_ne!(ptr, 0);
+            unsafe { allocator.dealloc(ptr as *mut u8, large) };
+        });
+        allocator.lock().validate_heap();
+    }
+
+    #[test]
+    fn concurrent_grows() {
+        let allocator = LockedFreeListAllocator::new_in(MockGrower::new());
+        thread::scope(|scope| {
+            for thread_index in 0..4u8 {
+                let allocator = &allocator;
+                scope.spawn(move || {
+                    let layout = Layout::from_size_align(PAGE_SIZE / 2 + 1, 8).unwrap();
+                    let allocations: Vec<*mut u8> = (0..20)
+                        .map(|_| unsafe {
+                            let ptr = allocator.alloc(layout);
+                            assert!(!ptr.is_null());
+                            ptr.write_bytes(thread_index, layout.size());
+                            ptr
+                        })
+                        .collect();
+                    for ptr in allocations {
+                        unsafe {
+                            let bytes = core::slice::from_raw_parts(ptr, layout.size());
+                            assert!(bytes.iter().all(|&b| b == thread_index));
+                            allocator.dealloc(ptr, layout);
+                        }
+                    }
+                });
+            }
+        });
+        allocator.lock().validate_heap();
+        // Everything grown is back in the free list.
+        let grown = allocator.grower.used_pages() * PAGE_SIZE;
+        let base = allocator.grower.base() as *const u8;
+        assert!(allocator.lock().is_free(base, grown));
+    }
+
+    /// Like FreeListAllocator, growing for allocations aligned to more than a page includes the padding needed to align them.
+    #[test]
+    fn locked_free_list_alignment_above_page_size() {
+        for pages in [2, 4, 8] {
+            let allocator = LockedFreeListAllocator::new_in(MockGrower::new());
+            let align = pages * PAGE_SIZE;
+            let layout = Layout::from_size_align(PAGE_SIZE, align).unwrap();
+            // With `dealloc-size-check`, the prefix takes a whole alignment before the page.
+            let used = pages - 1 + block_size(layout) / PAGE_SIZE;
+            unsafe {
+                let ptr = allocator.alloc(layout);
+                assert_eq!(ptr as usize % align, 0, "align {pages} pages");
+                assert_eq!(allocator.grower.used_pages(), used);
+                allocator.dealloc(ptr, layout);
+                assert_eq!(allocator.alloc(layout), ptr);
+            }
+            allocator.lock().validate_heap();
+        }
+    }
+
+    /// Like FreeListAllocator, growing extends a free block at the end of memory by only the missing pages.
+    #[test]
+    fn locked_free_list_grows_only_missing_pages() {
+        let allocator = LockedFreeListAllocator::new_in(MockGrower::new());
+        let half = block_layout(PAGE_SIZE / 2, 1);
+        unsafe {
+            // Leaves the lower half of the first page free, which is not at the end of memory.
+            allocator.alloc(half);
+            let layout = block_layout(PAGE_SIZE, 1);
+            let low = allocator.alloc(layout);
+            assert_eq!(allocator.grower.used_pages(), 2);
+            // Freeing it leaves a free page at the end of memory, so growing one more page fits twice its size.
+            allocator.dealloc(low, layout);
+            let double = block_layout(2 * PAGE_SIZE, 1);
+            assert!(!allocator.alloc(double).is_null());
+            assert_eq!(allocator.grower.used_pages(), 3);
+        }
+        allocator.lock().validate_heap();
+    }
+
+    #[cfg(all(
+        feature = "dealloc-size-check",
+        debug_assertions,
+        not(feature = "tiny-asserts")
+    ))]
+    #[test]
+    #[should_panic(expected = "Freed with size 16 (block size")]
+    fn locked_free_list_dealloc_size_check_mismatch() {
+        let allocator = LockedFreeListAllocator::new_in(MockGrower::new());
+        unsafe {
+            let ptr = allocator.alloc(Layout::from_size_align(64, 8).unwrap());
+            allocator.dealloc(ptr, Layout::from_size_align(16, 8).unwrap());
+        }
+    }
 }
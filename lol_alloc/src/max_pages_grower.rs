@@ -0,0 +1,181 @@
+use crate::{
+    atomic::{AtomicUsize, Ordering},
+    DefaultGrower, MemoryGrower, PageCount, ERROR_PAGE_COUNT,
+};
+
+/// Grower which limits the number of pages another grower grows, with a limit which can be changed at runtime.
+///
+/// Lowering the limit below the number of pages already grown does not affect existing memory:
+/// it just makes growing fail until the limit is raised again.
+///
+/// Allocators hold their grower, so to adjust the limit of an allocator in a static,
+/// put the `MaxPagesGrower` in its own static and give the allocator a reference to it.
+pub struct MaxPagesGrower<T = DefaultGrower> {
+    inner: T,
+    limit: AtomicUsize,
+    grown: AtomicUsize,
+}
+
+impl<T> MaxPagesGrower<T> {
+    /// Wraps `inner`, allowing it to grow at most `limit` pages.
+    pub const fn new(inner: T, limit: usize) -> Self {
+        MaxPagesGrower {
+            inner,
+            limit: AtomicUsize::new(limit),
+            grown: AtomicUsize::new(0),
+        }
+    }
+
+    /// The current limit, in pages.
+    pub fn limit_pages(&self) -> usize {
+        self.limit.load(Ordering::Relaxed)
+    }
+
+    /// Changes the limit, returning the previous one.
+    pub fn set_limit_pages(&self, pages: usize) -> usize {
+        self.limit.swap(pages, Ordering::Relaxed)
+    }
+
+    /// Number of pages grown through this grower.
+    pub fn grown_pages(&self) -> usize {
+        self.grown.load(Ordering::Relaxed)
+    }
+
+    /// Reserves up to `delta` pages within the limit, returning how many were reserved.
+    fn reserve(&self, delta: usize) -> usize {
+        let mut reserved = 0;
+        // Only fails if the closure returns None, which it never does.
+        let _ = self
+            .grown
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |grown| {
+                reserved = self.limit_pages().saturating_sub(grown).min(delta);
+                Some(grown + reserved)
+            });
+        reserved
+    }
+
+    /// Returns reserved pages which were not grown.
+    fn release(&self, pages: usize) {
+        self.grown.fetch_sub(pages, Ordering::Relaxed);
+    }
+}
+
+impl<T: MemoryGrower> MemoryGrower for MaxPagesGrower<T> {
+    fn memory_grow(&self, delta: PageCount) -> PageCount {
+        if self.reserve(delta.0) != delta.0 {
+            self.release(delta.0);
+            return ERROR_PAGE_COUNT;
+        }
+        let result = self.inner.memory_grow(delta);
+        if result == ERROR_PAGE_COUNT {
+            self.release(delta.0);
+        }
+        result
+    }
+
+    fn current_pages(&self) -> PageCount {
+        self.inner.current_pages()
+    }
+
+    fn memory_grow_partial(&self, delta: PageCount) -> (PageCount, PageCount) {
+        let reserved = self.reserve(delta.0);
+        if reserved == 0 {
+            return (ERROR_PAGE_COUNT, PageCount(0));
+        }
+        let (first, granted) = self.inner.memory_grow_partial(PageCount(reserved));
+        self.release(reserved - granted.0);
+        (first, granted)
+    }
+
+    fn grows_zeroed(&self) -> bool {
+        self.inner.grows_zeroed()
+    }
+}
+
+/// Exports a `lol_alloc_set_limit_pages(pages: usize) -> usize` function,
+/// which the host can call to change the limit of a [MaxPagesGrower], returning the previous limit.
+///
+/// Takes an expression evaluating to the `MaxPagesGrower` (or something which dereferences to it), for example:
+///
+/// ```ignore
+/// static GROWER: MaxPagesGrower = MaxPagesGrower::new(DefaultGrower, 100);
+///
+/// #[global_allocator]
+/// static ALLOCATOR: LockedAllocator<FreeListAllocator<&MaxPagesGrower>> =
+///     LockedAllocator::new(FreeListAllocator::new_in(&GROWER));
+///
+/// lol_alloc::export_set_limit_pages!(GROWER);
+/// ```
+#[macro_export]
+macro_rules! export_set_limit_pages {
+    ($grower:expr) => {
+        /// Sets the maximum number of pages the heap can grow, returning the previous maximum.
+        #[unsafe(no_mangle)]
+        pub extern "C" fn lol_alloc_set_limit_pages(pages: usize) -> usize {
+            $grower.set_limit_pages(pages)
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MaxPagesGrower;
+    use crate::{
+        free_list_allocator::FreeListAllocator,
+        testing::{block_layout, MockGrower},
+        GlobalArrayGrower, PAGE_SIZE,
+    };
+    use alloc::vec::Vec;
+    use core::alloc::{GlobalAlloc, Layout};
+
+    #[test]
+    fn adjust_limit() {
+        let grower = MaxPagesGrower::new(MockGrower::new(), 4);
+        let allocator = FreeListAllocator::new_in(&grower);
+        let layout = block_layout(PAGE_SIZE, 1);
+        unsafe {
+            let mut live: Vec<*mut u8> = (0..3).map(|_| allocator.alloc(layout)).collect();
+            assert!(live.iter().all(|ptr| !ptr.is_null()));
+            assert_eq!(grower.grown_pages(), 3);
+
+            // Lowering the limit below current use only stops growth.
+            assert_eq!(grower.set_limit_pages(1), 4);
+            assert!(allocator.alloc(layout).is_null());
+            allocator.dealloc(live.pop().unwrap(), layout);
+            live.push(allocator.alloc(layout));
+            assert!(!live[2].is_null());
+            assert_eq!(grower.grown_pages(), 3);
+
+            // Raising it allows growing again.
+            assert_eq!(grower.set_limit_pages(5), 1);
+            live.push(allocator.alloc(layout));
+            assert!(!live[3].is_null());
+            assert_eq!(grower.grown_pages(), 4);
+
+            // Allocations which would only partly fit still fail.
+            assert!(allocator
+                .alloc(Layout::from_size_align(PAGE_SIZE * 2, 1).unwrap())
+                .is_null());
+            assert_eq!(grower.grown_pages(), 5);
+            for ptr in live {
+                allocator.dealloc(ptr, layout);
+            }
+        }
+        allocator.validate_heap();
+        assert_eq!(grower.inner.used_pages(), 5);
+    }
+
+    #[test]
+    fn export_set_limit_pages() {
+        static GROWER: MaxPagesGrower<GlobalArrayGrower<4>> =
+            MaxPagesGrower::new(GlobalArrayGrower::new(), 2);
+        crate::export_set_limit_pages!(GROWER);
+        assert_eq!(lol_alloc_set_limit_pages(3), 2);
+        assert_eq!(GROWER.limit_pages(), 3);
+    }
+
+    crate::check_grower!(
+        max_pages_grower_conformance,
+        MaxPagesGrower::new(MockGrower::new(), 16)
+    );
+}
@@ -0,0 +1,260 @@
+use crate::atomic::{AtomicPtr, Ordering};
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    mem::{align_of, size_of},
+    ptr::null_mut,
+};
+
+/// A freed allocation waiting in the queue, written over the start of its own memory.
+struct Freed {
+    next: *mut Freed,
+    /// The layout the inner allocator allocated it with.
+    layout: Layout,
+}
+
+/// An allocator where one thread allocates, and any thread can free without waiting for it.
+///
+/// For wasm with threads, where memory allocated on one thread is often freed on others (like buffers handed to workers):
+/// `dealloc` only pushes the allocation onto a lock-free queue (a compare-and-swap loop, so frees never block on each other or on the allocating thread),
+/// and the allocating thread frees everything queued to the inner allocator at the start of each `alloc`, before it looks for free space.
+/// So the inner allocator is only ever used by the allocating thread, and does not need to be thread safe.
+///
+/// The queue is intrusive (each queued allocation's first bytes hold the link to the next), so queuing never allocates,
+/// but allocations are padded to at least the size of a pointer and a [Layout] (three words), aligned to a word.
+/// `no_std` has no thread locals, so all threads push onto one queue.
+/// Until the allocating thread next allocates (or calls [MpscFreeAllocator::drain]), the memory stays allocated.
+pub struct MpscFreeAllocator<T: GlobalAlloc> {
+    inner: T,
+    /// Most recently freed allocation, or null.
+    queue: AtomicPtr<Freed>,
+}
+
+/// Only the allocating thread uses `inner` (see [MpscFreeAllocator::new]): other threads only push to the queue.
+unsafe impl<T: GlobalAlloc + Send> Sync for MpscFreeAllocator<T> {}
+
+impl<T: GlobalAlloc> MpscFreeAllocator<T> {
+    /// Wraps `inner`, which is only used from the allocating thread.
+    ///
+    /// # Safety
+    ///
+    /// All uses other than `dealloc` (`alloc`, `alloc_zeroed`, `realloc` and [MpscFreeAllocator::drain]) must be from a single thread.
+    pub const unsafe fn new(inner: T) -> Self {
+        MpscFreeAllocator {
+            inner,
+            queue: AtomicPtr::new(null_mut()),
+        }
+    }
+
+    /// Frees everything queued to the inner allocator.
+    ///
+    /// `alloc` does this first, so this is only needed to return memory before the next allocation.
+    /// Like `alloc`, this must only be called from the allocating thread.
+    pub fn drain(&self) {
+        // Taking the whole queue at once means nothing is ever popped while another thread pushes, so there is no ABA problem.
+        let mut freed = self.queue.swap(null_mut(), Ordering::Acquire);
+        while !freed.is_null() {
+            // Safety: queued by `dealloc`, which wrote the node over an allocation from `inner`.
+            unsafe {
+                let Freed { next, layout } = freed.read();
+                self.inner.dealloc(freed as *mut u8, layout);
+                freed = next;
+            }
+        }
+    }
+
+    /// If no frees are queued.
+    pub fn is_drained(&self) -> bool {
+        self.queue.load(Ordering::Relaxed).is_null()
+    }
+
+    /// The layout allocated from the inner allocator for `layout`, with room for a [Freed] node.
+    fn padded(layout: Layout) -> Option<Layout> {
+        let size = layout.size().max(size_of::<Freed>());
+        Layout::from_size_align(size, layout.align().max(align_of::<Freed>())).ok()
+    }
+}
+
+unsafe impl<T: GlobalAlloc> GlobalAlloc for MpscFreeAllocator<T> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        nonzero_size_assert!(layout.size());
+        self.drain();
+        match Self::padded(layout) {
+            Some(padded) => self.inner.alloc(padded),
+            None => null_mut(),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        nonzero_size_assert!(layout.size());
+        // Layout was valid when allocated, so this is too.
+        let padded = Self::padded(layout).unwrap_unchecked();
+        let node = ptr as *mut Freed;
+        let mut head = self.queue.load(Ordering::Relaxed);
+        loop {
+            node.write(Freed {
+                next: head,
+                layout: padded,
+            });
+            // Release, so the node is written before the allocating thread can take it.
+            match self
+                .queue
+                .compare_exchange_weak(head, node, Ordering::Release, Ordering::Relaxed)
+            {
+                Ok(_) => return,
+                Err(current) => head = current,
+            }
+        }
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        nonzero_size_assert!(layout.size());
+        self.drain();
+        match Self::padded(layout) {
+            Some(padded) => self.inner.alloc_zeroed(padded),
+            None => null_mut(),
+        }
+    }
+}
+
+impl<T: GlobalAlloc> Drop for MpscFreeAllocator<T> {
+    fn drop(&mut self) {
+        self.drain();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MpscFreeAllocator;
+    use crate::{
+        free_list_allocator::NODE_SIZE,
+        size_check,
+        testing::{conformance, fresh_zeroed_bytes, Counted, MockGrower},
+        AllocatorStats, FreeListAllocator,
+    };
+    use alloc::vec::Vec;
+    use core::alloc::{GlobalAlloc, Layout};
+    use std::{sync::mpsc, thread};
+
+    /// A pointer which can be sent to the thread freeing it.
+    struct SendPtr(*mut u8);
+
+    unsafe impl Send for SendPtr {}
+
+    #[test]
+    fn mpsc_free_conformance() {
+        let allocator =
+            unsafe { MpscFreeAllocator::new(FreeListAllocator::new_in(MockGrower::new())) };
+        conformance(&allocator);
+        allocator.drain();
+        allocator.inner.validate_heap();
+    }
+
+    #[test]
+    fn small_allocations_hold_a_node() {
+        let inner = Counted::new();
+        let allocator = unsafe { MpscFreeAllocator::new(&inner) };
+        let layout = Layout::from_size_align(1, 1).unwrap();
+        unsafe {
+            let ptrs: Vec<_> = (0..10).map(|_| allocator.alloc(layout)).collect();
+            for &ptr in &ptrs {
+                ptr.write(0xFF);
+                allocator.dealloc(ptr, layout);
+            }
+        }
+        // Queued, not yet freed.
+        assert!(!allocator.is_drained());
+        assert_eq!(inner.live(), 10);
+        allocator.drain();
+        assert!(allocator.is_drained());
+        assert_eq!(inner.live(), 0);
+    }
+
+    #[test]
+    fn alloc_drains_first() {
+        let allocator =
+            unsafe { MpscFreeAllocator::new(FreeListAllocator::new_in(MockGrower::new())) };
+        let layout = Layout::from_size_align(100, 8).unwrap();
+        unsafe {
+            let ptr = allocator.alloc(layout);
+            allocator.dealloc(ptr, layout);
+            assert!(!allocator.inner.is_free(ptr, layout.size()));
+            // The queued free happens before the search, so its memory is reused.
+            assert_eq!(allocator.alloc(layout), ptr);
+            assert!(allocator.is_drained());
+            allocator.dealloc(ptr, layout);
+        }
+    }
+
+    /// `alloc_zeroed` is forwarded, so memory never used since it was grown is not zeroed again.
+    #[test]
+    fn alloc_zeroed_skips_fresh_memory() {
+        let allocator =
+            unsafe { MpscFreeAllocator::new(FreeListAllocator::new_in(MockGrower::new())) };
+        let base = allocator.inner.grower.base() as *mut u8;
+        let zeroed = unsafe { fresh_zeroed_bytes(&allocator, base) };
+        assert_eq!(zeroed, NODE_SIZE - size_check::prefix_space(8));
+    }
+
+    #[test]
+    fn drop_drains() {
+        let inner = Counted::new();
+        let allocator = unsafe { MpscFreeAllocator::new(&inner) };
+        let layout = Layout::new::<u64>();
+        unsafe {
+            let ptr = allocator.alloc(layout);
+            allocator.dealloc(ptr, layout);
+        }
+        assert_eq!(inner.live(), 1);
+        drop(allocator);
+        assert_eq!(inner.live(), 0);
+    }
+
+    /// Allocations made on this thread and freed on others are all freed by later allocations, with nothing lost or freed twice.
+    #[test]
+    fn cross_thread_frees() {
+        const THREADS: usize = 4;
+        const ROUNDS: usize = 200;
+        let allocator =
+            unsafe { MpscFreeAllocator::new(FreeListAllocator::new_in(MockGrower::new())) };
+        let layout = Layout::from_size_align(48, 8).unwrap();
+        thread::scope(|scope| {
+            let senders: Vec<mpsc::Sender<SendPtr>> = (0..THREADS)
+                .map(|_| {
+                    let (sender, receiver) = mpsc::channel::<SendPtr>();
+                    let allocator = &allocator;
+                    scope.spawn(move || {
+                        for ptr in receiver {
+                            unsafe {
+                                // Nothing else uses the allocation.
+                                assert!(core::slice::from_raw_parts(ptr.0, layout.size())
+                                    .iter()
+                                    .all(|&b| b == ptr.0 as u8));
+                                allocator.dealloc(ptr.0, layout);
+                            }
+                        }
+                    });
+                    sender
+                })
+                .collect();
+            for round in 0..ROUNDS {
+                for sender in &senders {
+                    let ptr = unsafe { allocator.alloc(layout) };
+                    assert!(!ptr.is_null());
+                    unsafe { ptr.write_bytes(ptr as u8, layout.size()) };
+                    sender.send(SendPtr(ptr)).unwrap();
+                }
+                if round % 50 == 0 {
+                    allocator.inner.validate_heap();
+                }
+            }
+        });
+        // Every thread has finished freeing.
+        allocator.drain();
+        allocator.inner.validate_heap();
+        let stats = allocator.inner.stats();
+        assert_eq!(
+            stats.free_bytes,
+            Some(allocator.inner.grower.used_pages() * crate::PAGE_SIZE)
+        );
+    }
+}
@@ -0,0 +1,132 @@
+use crate::{
+    free_list_allocator::checked_full_size, AllocatorStats, CountsAllocations, Stats, PAGE_SIZE,
+};
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    ptr::null_mut,
+};
+
+/// Wraps an allocator (normally a [FreeListAllocator](crate::FreeListAllocator)), aligning every allocation to its size:
+/// the [FreeListAllocator](crate::FreeListAllocator) block size for its layout, rounded up to a power of two.
+///
+/// For example, a 24 byte allocation uses a 32 byte block (with 16 byte nodes), so is 32 byte aligned,
+/// like the small size classes of allocators such as jemalloc.
+/// This lets values be accessed with instructions which need natural alignment (like wide atomics),
+/// and keeps small allocations from straddling cache lines, at the cost of alignment gaps in the heap
+/// (which the free list reuses for smaller allocations).
+///
+/// Alignment is capped at `PAGE_SIZE`, since that is all memory from `memory.grow` guarantees:
+/// allocations larger than a page are page aligned.
+pub struct NaturallyAlignedAllocator<T> {
+    inner: T,
+}
+
+impl<T> NaturallyAlignedAllocator<T> {
+    pub const fn new(inner: T) -> Self {
+        NaturallyAlignedAllocator { inner }
+    }
+
+    /// The allocator this wraps.
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    /// The layout allocated from the inner allocator for `layout`.
+    fn aligned(layout: Layout) -> Option<Layout> {
+        let natural = checked_full_size(layout)?
+            .checked_next_power_of_two()?
+            .min(PAGE_SIZE);
+        Layout::from_size_align(layout.size(), layout.align().max(natural)).ok()
+    }
+}
+
+unsafe impl<T: GlobalAlloc> GlobalAlloc for NaturallyAlignedAllocator<T> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        nonzero_size_assert!(layout.size());
+        match Self::aligned(layout) {
+            Some(aligned) => self.inner.alloc(aligned),
+            None => null_mut(),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        nonzero_size_assert!(layout.size());
+        // Layout was valid when allocated, so this is too.
+        self.inner
+            .dealloc(ptr, Self::aligned(layout).unwrap_unchecked());
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        nonzero_size_assert!(layout.size());
+        match Self::aligned(layout) {
+            Some(aligned) => self.inner.alloc_zeroed(aligned),
+            None => null_mut(),
+        }
+    }
+}
+
+/// Statistics are those of the inner allocator, which sees the same sizes.
+impl<T: AllocatorStats> AllocatorStats for NaturallyAlignedAllocator<T> {
+    fn stats(&self) -> Stats {
+        self.inner.stats()
+    }
+}
+
+impl<T: CountsAllocations> CountsAllocations for NaturallyAlignedAllocator<T> {}
+
+#[cfg(test)]
+mod tests {
+    use super::NaturallyAlignedAllocator;
+    use crate::{
+        free_list_allocator::full_size,
+        testing::{conformance, MockGrower},
+        FreeListAllocator, PAGE_SIZE,
+    };
+    use alloc::vec::Vec;
+    use core::alloc::{GlobalAlloc, Layout};
+
+    fn allocator() -> NaturallyAlignedAllocator<FreeListAllocator<MockGrower>> {
+        NaturallyAlignedAllocator::new(FreeListAllocator::new_in(MockGrower::new()))
+    }
+
+    #[test]
+    fn conforms() {
+        let allocator = allocator();
+        conformance(&allocator);
+        allocator.inner().validate_heap();
+    }
+
+    #[test]
+    fn naturally_aligned() {
+        let allocator = allocator();
+        let mut allocations = Vec::new();
+        for size in [1, 7, 16, 17, 24, 33, 48, 100, 1000, 4097, PAGE_SIZE] {
+            for align in [1, 8] {
+                let layout = Layout::from_size_align(size, align).unwrap();
+                let ptr = unsafe { allocator.alloc(layout) };
+                assert!(!ptr.is_null());
+                assert_eq!(
+                    ptr.align_offset(full_size(layout).next_power_of_two()),
+                    0,
+                    "{layout:?}"
+                );
+                allocations.push((ptr, layout));
+            }
+        }
+        for (ptr, layout) in allocations {
+            unsafe { allocator.dealloc(ptr, layout) };
+        }
+        allocator.inner().validate_heap();
+    }
+
+    #[test]
+    fn larger_than_a_page() {
+        let allocator = allocator();
+        let layout = Layout::from_size_align(PAGE_SIZE * 2 + 1, 1).unwrap();
+        unsafe {
+            let ptr = allocator.alloc(layout);
+            assert_eq!(ptr.align_offset(PAGE_SIZE), 0);
+            allocator.dealloc(ptr, layout);
+        }
+    }
+}
@@ -0,0 +1,287 @@
+use crate::{
+    atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering},
+    AllocatorStats, CountsAllocations, Stats,
+};
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    ptr::null_mut,
+};
+
+/// A function which frees memory the application can do without (like cached data), called by a [RecoveringAllocator] when an allocation fails.
+///
+/// Takes the layout which could not be allocated, so it can decide how much to free,
+/// and returns whether it freed anything.
+///
+/// It runs inside the failed allocation, so it must not assume allocating will succeed (allocations it makes are not recovered),
+/// and must cope with being called while the application is in the middle of using what it frees
+/// (for example by skipping a cache which is already borrowed).
+pub type PressureCallback = fn(Layout) -> bool;
+
+/// Wraps an allocator, responding to allocation failure by running registered [PressureCallback]s (which free application level caches),
+/// then retrying the allocation once.
+///
+/// Returning null is only useful if something frees memory in response: this is a place to do that for every allocation
+/// (including those made by collections, which abort on failure), instead of around each `try_reserve`.
+/// Up to `N` callbacks can be registered, with [RecoveringAllocator::register], and run in the order they were registered,
+/// until one frees something.
+///
+/// Callbacks run with no lock held, so they can free (and allocate) through the global allocator.
+/// Allocations failing while callbacks run (on any thread) are not recovered, so callbacks never recurse.
+/// Recovering never allocates.
+pub struct RecoveringAllocator<T, const N: usize = 4> {
+    inner: T,
+    /// Registered callbacks, as pointers, with null for unused slots.
+    callbacks: [AtomicPtr<()>; N],
+    /// Set while callbacks run.
+    recovering: AtomicBool,
+    recoveries: AtomicUsize,
+}
+
+impl<T, const N: usize> RecoveringAllocator<T, N> {
+    pub const fn new(inner: T) -> Self {
+        RecoveringAllocator {
+            inner,
+            callbacks: [const { AtomicPtr::new(null_mut()) }; N],
+            recovering: AtomicBool::new(false),
+            recoveries: AtomicUsize::new(0),
+        }
+    }
+
+    /// Adds `callback` to the callbacks run when an allocation fails.
+    ///
+    /// Returns false (registering nothing) if `N` callbacks are already registered.
+    pub fn register(&self, callback: PressureCallback) -> bool {
+        self.callbacks.iter().any(|slot| {
+            slot.compare_exchange(
+                null_mut(),
+                callback as *mut (),
+                Ordering::Release,
+                Ordering::Relaxed,
+            )
+            .is_ok()
+        })
+    }
+
+    /// Number of failed allocations a callback freed memory for (whether or not the retry then succeeded).
+    pub fn recoveries(&self) -> usize {
+        self.recoveries.load(Ordering::Relaxed)
+    }
+
+    /// Runs the callbacks for `layout` until one frees something, returning whether one did.
+    ///
+    /// Does nothing (returning false) if callbacks are already running.
+    fn recover(&self, layout: Layout) -> bool {
+        if self.recovering.swap(true, Ordering::Acquire) {
+            return false;
+        }
+        let freed = self
+            .callbacks
+            .iter()
+            .map(|slot| slot.load(Ordering::Acquire))
+            .take_while(|callback| !callback.is_null())
+            .any(|callback| {
+                // Safety: only `register` stores non-null pointers, which are `PressureCallback`s.
+                let callback: PressureCallback = unsafe { core::mem::transmute(callback) };
+                callback(layout)
+            });
+        self.recovering.store(false, Ordering::Release);
+        if freed {
+            self.recoveries.fetch_add(1, Ordering::Relaxed);
+        }
+        freed
+    }
+
+    /// Runs `allocate`, and if it fails, recovers and runs it again.
+    fn with_retry(&self, layout: Layout, allocate: impl Fn() -> *mut u8) -> *mut u8 {
+        let ptr = allocate();
+        if ptr.is_null() && self.recover(layout) {
+            return allocate();
+        }
+        ptr
+    }
+}
+
+unsafe impl<T: GlobalAlloc, const N: usize> GlobalAlloc for RecoveringAllocator<T, N> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        nonzero_size_assert!(layout.size());
+        self.with_retry(layout, || self.inner.alloc(layout))
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        nonzero_size_assert!(layout.size());
+        self.inner.dealloc(ptr, layout);
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        nonzero_size_assert!(layout.size());
+        self.with_retry(layout, || self.inner.alloc_zeroed(layout))
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        nonzero_size_assert!(layout.size(), new_size);
+        // A failed realloc leaves the allocation where it was, so it can be retried.
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        self.with_retry(new_layout, || self.inner.realloc(ptr, layout, new_size))
+    }
+}
+
+impl<T: AllocatorStats, const N: usize> AllocatorStats for RecoveringAllocator<T, N> {
+    fn stats(&self) -> Stats {
+        self.inner.stats()
+    }
+}
+
+impl<T: CountsAllocations, const N: usize> CountsAllocations for RecoveringAllocator<T, N> {}
+
+#[cfg(test)]
+mod tests {
+    use super::RecoveringAllocator;
+    use crate::{
+        testing::{conformance, MockGrower},
+        FreeListAllocator, MaxPagesGrower, PAGE_SIZE,
+    };
+    use alloc::vec::Vec;
+    use core::{
+        alloc::{GlobalAlloc, Layout},
+        cell::RefCell,
+    };
+
+    type Allocator = RecoveringAllocator<FreeListAllocator<MaxPagesGrower<MockGrower>>, 2>;
+
+    std::thread_local! {
+        /// A cache of allocations from `ALLOCATOR`, oldest first, for the callbacks to shed.
+        static CACHE: RefCell<Vec<*mut u8>> = const { RefCell::new(Vec::new()) };
+        static ALLOCATOR: &'static Allocator = allocator(4);
+    }
+
+    /// Each cached allocation fills a page.
+    const ENTRY: Layout = match Layout::from_size_align(PAGE_SIZE, 1) {
+        Ok(layout) => layout,
+        Err(_) => panic!(),
+    };
+
+    fn allocator(pages: usize) -> &'static Allocator {
+        alloc::boxed::Box::leak(alloc::boxed::Box::new(RecoveringAllocator::new(
+            FreeListAllocator::new_in(MaxPagesGrower::new(MockGrower::new(), pages)),
+        )))
+    }
+
+    /// Frees the oldest cached allocations, at least as many bytes as `layout` (if there are enough), unless the cache is in use.
+    fn shed_oldest(layout: Layout) -> bool {
+        CACHE.with(|cache| {
+            let Ok(mut cache) = cache.try_borrow_mut() else {
+                return false;
+            };
+            let count = layout.size().div_ceil(ENTRY.size()).min(cache.len());
+            for ptr in cache.drain(..count) {
+                ALLOCATOR.with(|allocator| unsafe { allocator.dealloc(ptr, ENTRY) });
+            }
+            count > 0
+        })
+    }
+
+    fn never_frees(_layout: Layout) -> bool {
+        false
+    }
+
+    #[test]
+    fn recovering_conformance() {
+        conformance(&RecoveringAllocator::<_, 1>::new(
+            FreeListAllocator::new_in(MockGrower::new()),
+        ));
+    }
+
+    #[test]
+    fn register_until_full() {
+        let allocator = allocator(1);
+        assert!(allocator.register(never_frees));
+        assert!(allocator.register(shed_oldest));
+        assert!(!allocator.register(never_frees));
+    }
+
+    /// Filling the cache past the quota sheds the oldest entries instead of failing.
+    #[test]
+    fn sheds_under_pressure() {
+        ALLOCATOR.with(|&allocator| {
+            // The first callback never frees anything, so the second runs too.
+            assert!(allocator.register(never_frees));
+            assert!(allocator.register(shed_oldest));
+            for i in 0..20 {
+                let ptr = unsafe { allocator.alloc(ENTRY) };
+                assert!(!ptr.is_null(), "allocation {i} failed");
+                CACHE.with(|cache| cache.borrow_mut().push(ptr));
+                // Only 4 pages fit, and each entry needs a bit over a page, with the free list's overhead.
+                assert!(CACHE.with(|cache| cache.borrow().len()) <= 4);
+            }
+            assert!(allocator.recoveries() >= 16);
+            allocator.inner.validate_heap();
+        });
+    }
+
+    /// Allocations which fail while the cache is borrowed (so can't be shed from) fail outward.
+    #[test]
+    fn callbacks_skip_busy_cache() {
+        ALLOCATOR.with(|&allocator| {
+            assert!(allocator.register(shed_oldest));
+            CACHE.with(|cache| {
+                let mut cache = cache.borrow_mut();
+                loop {
+                    let ptr = unsafe { allocator.alloc(ENTRY) };
+                    if ptr.is_null() {
+                        break;
+                    }
+                    cache.push(ptr);
+                }
+                assert!(!cache.is_empty());
+            });
+            assert_eq!(allocator.recoveries(), 0);
+            // Once the cache is free again, recovery works.
+            assert!(!unsafe { allocator.alloc(ENTRY) }.is_null());
+            assert_eq!(allocator.recoveries(), 1);
+        });
+    }
+
+    /// Failures while recovering are not recovered, so callbacks never recurse.
+    #[test]
+    fn no_recursion() {
+        std::thread_local! {
+            static NESTED: RefCell<Option<&'static Allocator>> = const { RefCell::new(None) };
+        }
+        fn allocates(layout: Layout) -> bool {
+            let allocator = NESTED.with(|nested| nested.borrow().unwrap());
+            // Fails, since memory is full, and does not run this again.
+            assert!(unsafe { allocator.alloc(layout) }.is_null());
+            false
+        }
+        let allocator = allocator(1);
+        NESTED.with(|nested| *nested.borrow_mut() = Some(allocator));
+        assert!(allocator.register(allocates));
+        let large = Layout::from_size_align(2 * PAGE_SIZE, 1).unwrap();
+        assert!(unsafe { allocator.alloc(large) }.is_null());
+        assert_eq!(allocator.recoveries(), 0);
+    }
+
+    #[test]
+    fn realloc_retries() {
+        ALLOCATOR.with(|&allocator| {
+            let small = Layout::from_size_align(100, 1).unwrap();
+            let ptr = unsafe { allocator.alloc(small) };
+            unsafe { ptr.write_bytes(7, small.size()) };
+            // Fill memory before there is anything to recover with.
+            loop {
+                let entry = unsafe { allocator.alloc(ENTRY) };
+                if entry.is_null() {
+                    break;
+                }
+                CACHE.with(|cache| cache.borrow_mut().push(entry));
+            }
+            assert!(allocator.register(shed_oldest));
+            let grown = unsafe { allocator.realloc(ptr, small, 2 * PAGE_SIZE) };
+            assert!(!grown.is_null());
+            assert!(unsafe { core::slice::from_raw_parts(grown, small.size()) }
+                .iter()
+                .all(|&b| b == 7));
+            assert_eq!(allocator.recoveries(), 1);
+        });
+    }
+}
@@ -0,0 +1,284 @@
+use crate::{
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+    AllocWithExcess, AllocatorStats, CountsAllocations, Stats,
+};
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    ptr,
+};
+
+/// Allocator which uses `A` until [PhaseSwitch::switch] is called, then `B`.
+///
+/// Intended for programs which make many permanent allocations during startup,
+/// like `PhaseSwitch<LeakingAllocator, FreeListAllocator>`, which avoids the free list's overhead for them.
+///
+/// Allocations made before the switch (identified by being within the range of addresses `A` returned) are still freed through `A`,
+/// and so are leaked if `A` does not free memory.
+/// Reallocating them after the switch moves them to `B`.
+pub struct PhaseSwitch<A, B> {
+    a: A,
+    b: B,
+    switched: AtomicBool,
+    /// Start of the range of addresses allocated by `a`.
+    a_start: AtomicUsize,
+    /// End of the range of addresses allocated by `a`.
+    a_end: AtomicUsize,
+}
+
+impl<A, B> PhaseSwitch<A, B> {
+    pub const fn new(a: A, b: B) -> Self {
+        PhaseSwitch {
+            a,
+            b,
+            switched: AtomicBool::new(false),
+            a_start: AtomicUsize::new(usize::MAX),
+            a_end: AtomicUsize::new(0),
+        }
+    }
+
+    /// Makes all future allocations use `B`.
+    ///
+    /// # Safety
+    ///
+    /// `B` must never return memory in the range of addresses allocated by `A` before the switch.
+    /// This holds if both grow the same memory (like the wasm heap), since `A` does not grow after the switch.
+    pub unsafe fn switch(&self) {
+        self.switched.store(true, Ordering::Release);
+    }
+
+    /// If [PhaseSwitch::switch] has been called.
+    pub fn is_switched(&self) -> bool {
+        self.switched.load(Ordering::Acquire)
+    }
+
+    /// The first allocator.
+    pub fn a(&self) -> &A {
+        &self.a
+    }
+
+    /// The allocator used after the switch.
+    pub fn b(&self) -> &B {
+        &self.b
+    }
+
+    /// Records that `A` allocated `size` bytes at `ptr`.
+    fn record_a(&self, ptr: *mut u8, size: usize) {
+        let address = ptr as usize;
+        self.a_start.fetch_min(address, Ordering::Relaxed);
+        self.a_end.fetch_max(address + size, Ordering::Relaxed);
+    }
+
+    /// If `ptr` was allocated by `A`.
+    fn owned_by_a(&self, ptr: *mut u8) -> bool {
+        let address = ptr as usize;
+        self.a_start.load(Ordering::Relaxed) <= address
+            && address < self.a_end.load(Ordering::Relaxed)
+    }
+}
+
+unsafe impl<A: GlobalAlloc, B: GlobalAlloc> GlobalAlloc for PhaseSwitch<A, B> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        nonzero_size_assert!(layout.size());
+        if self.is_switched() {
+            return self.b.alloc(layout);
+        }
+        let ptr = self.a.alloc(layout);
+        if !ptr.is_null() {
+            self.record_a(ptr, layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        nonzero_size_assert!(layout.size());
+        if self.owned_by_a(ptr) {
+            self.a.dealloc(ptr, layout);
+        } else {
+            self.b.dealloc(ptr, layout);
+        }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        nonzero_size_assert!(layout.size(), new_size);
+        if !self.owned_by_a(ptr) {
+            return self.b.realloc(ptr, layout, new_size);
+        }
+        if !self.is_switched() {
+            let new_ptr = self.a.realloc(ptr, layout, new_size);
+            if !new_ptr.is_null() {
+                // Record the new allocation's range too.
+                self.record_a(new_ptr, new_size);
+            }
+            return new_ptr;
+        }
+        // Crossing the phase boundary: move the allocation to `B`.
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        let new_ptr = self.b.alloc(new_layout);
+        if !new_ptr.is_null() {
+            ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+            self.a.dealloc(ptr, layout);
+        }
+        new_ptr
+    }
+}
+
+/// Sums the statistics of both phases.
+/// `peak_bytes` is unknown, since the sum of each phase's peak overestimates it.
+impl<A: AllocatorStats, B: AllocatorStats> AllocatorStats for PhaseSwitch<A, B> {
+    fn stats(&self) -> Stats {
+        let (a, b) = (self.a.stats(), self.b.stats());
+        let sum = |a: Option<usize>, b: Option<usize>| Some(a? + b?);
+        Stats {
+            live_bytes: sum(a.live_bytes, b.live_bytes),
+            live_allocations: sum(a.live_allocations, b.live_allocations),
+            free_bytes: sum(a.free_bytes, b.free_bytes),
+            free_blocks: sum(a.free_blocks, b.free_blocks),
+            heap_bytes: sum(a.heap_bytes, b.heap_bytes),
+            peak_bytes: None,
+            failed_allocations: sum(a.failed_allocations, b.failed_allocations),
+        }
+    }
+}
+
+impl<A: AllocWithExcess, B: AllocWithExcess> AllocWithExcess for PhaseSwitch<A, B> {
+    unsafe fn alloc_with_excess(&self, layout: Layout) -> (*mut u8, usize) {
+        if self.is_switched() {
+            return self.b.alloc_with_excess(layout);
+        }
+        let (ptr, usable) = self.a.alloc_with_excess(layout);
+        if !ptr.is_null() {
+            self.record_a(ptr, layout.size());
+        }
+        (ptr, usable)
+    }
+}
+
+impl<A: CountsAllocations, B: CountsAllocations> CountsAllocations for PhaseSwitch<A, B> {}
+
+#[cfg(test)]
+mod tests {
+    use super::PhaseSwitch;
+    use crate::{
+        free_list_allocator::FreeListAllocator, testing::MockGrower, AllocWithExcess,
+        AllocatorStats, LeakingAllocator,
+    };
+    use alloc::vec::Vec;
+    use core::alloc::{GlobalAlloc, Layout};
+
+    #[test]
+    fn frees_after_switch() {
+        let grower = MockGrower::new();
+        let allocator = PhaseSwitch::new(
+            LeakingAllocator::new_in(&grower),
+            FreeListAllocator::new_in(&grower),
+        );
+        crate::testing::conformance(&allocator);
+        let layout = Layout::from_size_align(100, 8).unwrap();
+        unsafe {
+            let startup: Vec<*mut u8> = (0..10).map(|_| allocator.alloc(layout)).collect();
+            allocator.dealloc(startup[0], layout);
+            assert_eq!(allocator.b().stats().free_bytes, Some(0));
+            assert!(!allocator.is_switched());
+
+            allocator.switch();
+            let ptr = allocator.alloc(layout);
+            assert!(!startup.contains(&ptr));
+            let free_bytes = allocator.b().stats().free_bytes;
+            // Frees of startup allocations go to the LeakingAllocator, which ignores them.
+            for &startup_ptr in &startup[1..] {
+                allocator.dealloc(startup_ptr, layout);
+                assert!(!allocator.b().is_free(startup_ptr, 1));
+            }
+            assert_eq!(allocator.b().stats().free_bytes, free_bytes);
+            allocator.b().validate_heap();
+
+            allocator.dealloc(ptr, layout);
+            assert!(allocator.b().is_free(ptr, layout.size()));
+        }
+        // Each phase's stats are available, and summed for the whole allocator.
+        let (a, b) = (allocator.a().stats(), allocator.b().stats());
+        assert_eq!(
+            allocator.stats().free_bytes,
+            Some(a.free_bytes.unwrap() + b.free_bytes.unwrap())
+        );
+        assert_eq!(allocator.stats().peak_bytes, None);
+    }
+
+    #[test]
+    fn forwards_frees_to_a() {
+        let grower = MockGrower::new();
+        let allocator = PhaseSwitch::new(
+            FreeListAllocator::new_in(&grower),
+            FreeListAllocator::new_in(&grower),
+        );
+        let layout = Layout::from_size_align(64, 16).unwrap();
+        unsafe {
+            let startup = allocator.alloc(layout);
+            allocator.switch();
+            let ptr = allocator.alloc(layout);
+            allocator.dealloc(startup, layout);
+            assert!(allocator.a().is_free(startup, layout.size()));
+            assert!(!allocator.b().is_free(startup, 1));
+
+            // The memory freed to A is not reused.
+            let next = allocator.alloc(layout);
+            assert_ne!(next, startup);
+            allocator.dealloc(next, layout);
+            allocator.dealloc(ptr, layout);
+            assert!(allocator.b().is_free(ptr, layout.size()));
+        }
+    }
+
+    #[test]
+    fn excess_in_both_phases() {
+        let grower = MockGrower::new();
+        let allocator = PhaseSwitch::new(
+            FreeListAllocator::new_in(&grower),
+            FreeListAllocator::new_in(&grower),
+        );
+        crate::testing::excess_conformance(&allocator);
+        let layout = Layout::from_size_align(1, 1).unwrap();
+        unsafe {
+            let (startup, usable) = allocator.alloc_with_excess(layout);
+            allocator.switch();
+            crate::testing::excess_conformance(&allocator);
+            // Freeing with the usable size still goes to A.
+            allocator.dealloc(startup, Layout::from_size_align(usable, 1).unwrap());
+            assert!(allocator.a().is_free(startup, usable));
+        }
+        allocator.a().validate_heap();
+        allocator.b().validate_heap();
+    }
+
+    #[test]
+    fn realloc_across_switch() {
+        let grower = MockGrower::new();
+        let allocator = PhaseSwitch::new(
+            LeakingAllocator::new_in(&grower),
+            FreeListAllocator::new_in(&grower),
+        );
+        let layout = Layout::from_size_align(16, 8).unwrap();
+        unsafe {
+            let ptr = allocator.alloc(layout);
+            ptr.write_bytes(1, 16);
+            // Before the switch, reallocating stays in A.
+            let ptr = allocator.realloc(ptr, layout, 32);
+            let layout = Layout::from_size_align(32, 8).unwrap();
+            ptr.add(16).write_bytes(2, 16);
+            allocator.switch();
+            assert_eq!(allocator.b().stats().free_bytes, Some(0));
+
+            // After the switch, reallocating moves to B.
+            let moved = allocator.realloc(ptr, layout, 1000);
+            let bytes = core::slice::from_raw_parts(moved, 32);
+            assert_eq!(bytes[..16], [1; 16]);
+            assert_eq!(bytes[16..], [2; 16]);
+            let layout = Layout::from_size_align(1000, 8).unwrap();
+            let shrunk = allocator.realloc(moved, layout, 8);
+            assert_eq!(*shrunk.add(7), 1);
+            allocator.dealloc(shrunk, Layout::from_size_align(8, 8).unwrap());
+            assert!(allocator.b().is_free(moved, 1000));
+            allocator.b().validate_heap();
+        }
+    }
+}
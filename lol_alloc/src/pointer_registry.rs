@@ -0,0 +1,203 @@
+use crate::{AllocatorStats, CountsAllocations, Stats};
+use core::alloc::{GlobalAlloc, Layout};
+
+#[cfg(debug_assertions)]
+use alloc::collections::BTreeMap;
+
+/// Which [RegisteredAllocator] (by id) each live allocation came from, by address.
+#[cfg(debug_assertions)]
+static REGISTRY: spin::Mutex<BTreeMap<usize, u8>> = spin::Mutex::new(BTreeMap::new());
+
+/// Records which [RegisteredAllocator] each live allocation came from, so freeing through the wrong one is caught.
+///
+/// Only exists in builds with `debug_assertions`: in release builds [RegisteredAllocator] just forwards to the allocator it wraps.
+///
+/// The registry's map allocates from the global allocator.
+/// When that is a [RegisteredAllocator], the map's own allocations are made while the registry is being updated,
+/// so they are not recorded, and neither are allocations made while another thread is updating it.
+/// Freeing an allocation which was not recorded is not checked.
+#[cfg(debug_assertions)]
+pub struct GlobalPointerRegistry;
+
+#[cfg(debug_assertions)]
+impl GlobalPointerRegistry {
+    /// Runs `f` on the map, unless it is already being updated.
+    fn with_map<R>(f: impl FnOnce(&mut BTreeMap<usize, u8>) -> R) -> Option<R> {
+        REGISTRY.try_lock().map(|mut map| f(&mut map))
+    }
+
+    /// The id of the allocator `ptr` was allocated by, if it is a recorded live allocation.
+    pub fn owner(ptr: *const u8) -> Option<u8> {
+        Self::with_map(|map| map.get(&(ptr as usize)).copied()).flatten()
+    }
+
+    /// Number of recorded live allocations from the allocator with id `id`.
+    pub fn live_allocations(id: u8) -> usize {
+        Self::with_map(|map| map.values().filter(|&&owner| owner == id).count()).unwrap_or(0)
+    }
+
+    fn insert(ptr: *mut u8, id: u8) {
+        if !ptr.is_null() {
+            Self::with_map(|map| map.insert(ptr as usize, id));
+        }
+    }
+
+    /// Removes `ptr`, which is being freed by allocator `id`.
+    ///
+    /// # Panics
+    ///
+    /// If `ptr` was recorded as allocated by a different allocator.
+    fn remove(ptr: *mut u8, id: u8) {
+        let owner = Self::with_map(|map| map.remove(&(ptr as usize)))
+            .flatten()
+            .filter(|&owner| owner != id);
+        // Panic after releasing the lock, since panicking may allocate.
+        if let Some(owner) = owner {
+            panic!("{ptr:?} freed by allocator {id}, but allocated by allocator {owner}");
+        }
+    }
+}
+
+/// Wraps an allocator, giving it an id which (in builds with `debug_assertions`) is recorded in the [GlobalPointerRegistry] for each of its allocations.
+///
+/// Freeing an allocation through a different `RegisteredAllocator` than the one which allocated it panics,
+/// which catches routing bugs when several allocators coexist (like the two in a [PhaseSwitch](crate::PhaseSwitch)).
+/// Each allocator must be given a different id.
+pub struct RegisteredAllocator<T> {
+    inner: T,
+    id: u8,
+}
+
+impl<T> RegisteredAllocator<T> {
+    pub const fn new(id: u8, inner: T) -> Self {
+        RegisteredAllocator { inner, id }
+    }
+
+    pub fn id(&self) -> u8 {
+        self.id
+    }
+
+    #[cfg(debug_assertions)]
+    fn record(&self, ptr: *mut u8) -> *mut u8 {
+        GlobalPointerRegistry::insert(ptr, self.id);
+        ptr
+    }
+
+    #[cfg(not(debug_assertions))]
+    #[inline(always)]
+    fn record(&self, ptr: *mut u8) -> *mut u8 {
+        ptr
+    }
+
+    #[cfg(debug_assertions)]
+    fn forget(&self, ptr: *mut u8) {
+        GlobalPointerRegistry::remove(ptr, self.id);
+    }
+
+    #[cfg(not(debug_assertions))]
+    #[inline(always)]
+    fn forget(&self, _ptr: *mut u8) {}
+}
+
+unsafe impl<T: GlobalAlloc> GlobalAlloc for RegisteredAllocator<T> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        nonzero_size_assert!(layout.size());
+        self.record(self.inner.alloc(layout))
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        nonzero_size_assert!(layout.size());
+        self.forget(ptr);
+        self.inner.dealloc(ptr, layout);
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        nonzero_size_assert!(layout.size());
+        self.record(self.inner.alloc_zeroed(layout))
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        nonzero_size_assert!(layout.size(), new_size);
+        self.forget(ptr);
+        let new_ptr = self.inner.realloc(ptr, layout, new_size);
+        // On failure the original allocation is still live.
+        self.record(if new_ptr.is_null() { ptr } else { new_ptr });
+        new_ptr
+    }
+}
+
+impl<T: AllocatorStats> AllocatorStats for RegisteredAllocator<T> {
+    fn stats(&self) -> Stats {
+        self.inner.stats()
+    }
+}
+
+impl<T: CountsAllocations> CountsAllocations for RegisteredAllocator<T> {}
+
+#[cfg(test)]
+mod tests {
+    use super::RegisteredAllocator;
+    use crate::{
+        free_list_allocator::FreeListAllocator,
+        testing::{conformance, MockGrower},
+    };
+
+    type Registered = RegisteredAllocator<FreeListAllocator<MockGrower>>;
+
+    fn allocator(id: u8) -> Registered {
+        RegisteredAllocator::new(id, FreeListAllocator::new_in(MockGrower::new()))
+    }
+
+    /// The registry is shared by all the tests, and skips recording while another thread is using it.
+    #[cfg(debug_assertions)]
+    static SERIAL: spin::Mutex<()> = spin::Mutex::new(());
+
+    #[test]
+    fn conforms() {
+        #[cfg(debug_assertions)]
+        let _serial = SERIAL.lock();
+        conformance(&allocator(1));
+    }
+
+    #[cfg(debug_assertions)]
+    mod registry {
+        use super::{allocator, SERIAL};
+        use crate::GlobalPointerRegistry;
+        use core::alloc::{GlobalAlloc, Layout};
+
+        #[test]
+        fn empty_after_freeing() {
+            let _serial = SERIAL.lock();
+            let a = allocator(2);
+            let b = allocator(3);
+            let layout = Layout::from_size_align(24, 8).unwrap();
+            unsafe {
+                let x = a.alloc(layout);
+                let y = b.alloc_zeroed(layout);
+                assert_eq!(GlobalPointerRegistry::owner(x), Some(2));
+                assert_eq!(GlobalPointerRegistry::owner(y), Some(3));
+                let x = a.realloc(x, layout, 1000);
+                assert_eq!(GlobalPointerRegistry::owner(x), Some(2));
+                assert_eq!(GlobalPointerRegistry::live_allocations(2), 1);
+                a.dealloc(x, Layout::from_size_align(1000, 8).unwrap());
+                b.dealloc(y, layout);
+                assert_eq!(GlobalPointerRegistry::owner(x), None);
+            }
+            assert_eq!(GlobalPointerRegistry::live_allocations(2), 0);
+            assert_eq!(GlobalPointerRegistry::live_allocations(3), 0);
+        }
+
+        #[test]
+        #[should_panic(expected = "freed by allocator 5, but allocated by allocator 4")]
+        fn cross_allocator_dealloc() {
+            let _serial = SERIAL.lock();
+            let a = allocator(4);
+            let b = allocator(5);
+            let layout = Layout::new::<u64>();
+            unsafe {
+                let ptr = a.alloc(layout);
+                b.dealloc(ptr, layout);
+            }
+        }
+    }
+}
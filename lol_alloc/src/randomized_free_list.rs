@@ -0,0 +1,121 @@
+use crate::{zero::zeroed, AllocatorStats, FreeListAllocator, MemoryGrower, Stats};
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    cell::UnsafeCell,
+};
+use rand_core::RngCore;
+
+/// A non-thread safe [FreeListAllocator] which, when more than one free block can serve an allocation, picks one of them at random.
+///
+/// Always allocating from the first block which fits makes addresses predictable,
+/// which makes some exploits (like heap spraying, or guessing where an object will be) easier.
+/// Picking among the blocks with `R` makes them less so,
+/// for example with `rand::rngs::SmallRng` seeded from a random value provided by the host.
+///
+/// Picking walks the whole free list (instead of stopping at the first fit) to count the blocks which fit, so allocation is slower.
+/// Memory is only grown when no block fits, as with [FreeListAllocator], so while newly grown memory is placed deterministically,
+/// the free list, and so the choice, depends on all earlier choices.
+/// Large allocations (with the `large-objects` feature) are not randomized.
+pub struct RandomizedFreeListAllocator<T, R> {
+    inner: FreeListAllocator<T>,
+    rng: UnsafeCell<R>,
+}
+
+#[cfg(target_arch = "wasm32")]
+impl<R> RandomizedFreeListAllocator<crate::DefaultGrower, R> {
+    pub const fn new(rng: R) -> Self {
+        RandomizedFreeListAllocator::new_in(crate::DefaultGrower, rng)
+    }
+}
+
+impl<T, R> RandomizedFreeListAllocator<T, R> {
+    /// Creates an allocator which gets its memory from `grower`, and picks among free blocks with `rng`.
+    pub const fn new_in(grower: T, rng: R) -> Self {
+        RandomizedFreeListAllocator {
+            inner: FreeListAllocator::new_in(grower),
+            rng: UnsafeCell::new(rng),
+        }
+    }
+}
+
+unsafe impl<T: MemoryGrower, R: RngCore> GlobalAlloc for RandomizedFreeListAllocator<T, R> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        nonzero_size_assert!(layout.size());
+        let rng = &mut *self.rng.get();
+        self.inner.alloc_picking(
+            layout,
+            Some(|count| (rng.next_u64() % count as u64) as usize),
+        )
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        nonzero_size_assert!(layout.size());
+        self.inner.dealloc(ptr, layout);
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        // Blocks from the free list may have been used before.
+        zeroed(self.alloc(layout), layout.size())
+    }
+}
+
+impl<T, R> AllocatorStats for RandomizedFreeListAllocator<T, R> {
+    fn stats(&self) -> Stats {
+        self.inner.stats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RandomizedFreeListAllocator;
+    use crate::testing::{conformance, MockGrower};
+    use alloc::vec::Vec;
+    use core::alloc::{GlobalAlloc, Layout};
+    use rand_pcg::Pcg32;
+
+    fn allocator(seed: u64) -> RandomizedFreeListAllocator<MockGrower, Pcg32> {
+        RandomizedFreeListAllocator::new_in(MockGrower::new(), Pcg32::new(seed, 0))
+    }
+
+    /// Allocates 64 blocks, frees every other one, then allocates 16 more.
+    /// Returns the offsets of the last 16 from the start of the heap.
+    fn workload(allocator: &RandomizedFreeListAllocator<MockGrower, Pcg32>) -> Vec<usize> {
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let base = allocator.inner.grower.base();
+        unsafe {
+            let ptrs: Vec<_> = (0..64).map(|_| allocator.alloc(layout)).collect();
+            for &ptr in ptrs.iter().step_by(2) {
+                allocator.dealloc(ptr, layout);
+            }
+            // The 32 freed blocks, and the rest of the page, all fit.
+            let reused: Vec<_> = (0..16)
+                .map(|_| allocator.alloc(layout) as usize - base)
+                .collect();
+            allocator.inner.validate_heap();
+            reused
+        }
+    }
+
+    #[test]
+    fn randomized_conformance() {
+        let allocator = allocator(1);
+        conformance(&allocator);
+        allocator.inner.validate_heap();
+    }
+
+    #[test]
+    fn same_seed_same_addresses() {
+        assert_eq!(workload(&allocator(7)), workload(&allocator(7)));
+    }
+
+    #[test]
+    fn different_seeds_different_addresses() {
+        let runs: Vec<_> = (0..8).map(|seed| workload(&allocator(seed))).collect();
+        // With 32 blocks to pick from for each of 16 allocations, matching runs are very unlikely.
+        for (i, run) in runs.iter().enumerate() {
+            for other in &runs[i + 1..] {
+                assert_ne!(run, other);
+            }
+        }
+    }
+}
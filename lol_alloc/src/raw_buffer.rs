@@ -0,0 +1,238 @@
+//! Byte buffers which remember their layout, for handing allocations across an FFI boundary, for the `raw-buffer` feature.
+//!
+//! The usual pattern is allocating in Rust, giving the host a pointer, and freeing it when the host hands it back.
+//! Tracking the layout by hand makes it easy to leak the buffer, to free it twice, or to free it with the wrong size.
+//! [RawBuffer] frees itself on drop with the layout it was allocated with,
+//! so only the crossing itself ([RawBuffer::into_raw_parts] and [RawBuffer::from_raw_parts]) needs care.
+
+use crate::safe_heap::dangling;
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    mem::ManuallyDrop,
+    ops::{Deref, DerefMut},
+    ptr::NonNull,
+};
+
+/// The global allocator, as a [GlobalAlloc] value, which [RawBuffer] allocates from by default.
+pub struct Global;
+
+/// The instance of [Global] buffers refer to.
+static GLOBAL: Global = Global;
+
+unsafe impl GlobalAlloc for Global {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        alloc::alloc::alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        alloc::alloc::dealloc(ptr, layout);
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        alloc::alloc::alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        alloc::alloc::realloc(ptr, layout, new_size)
+    }
+}
+
+/// A zeroed byte buffer, freed when dropped.
+///
+/// Allocates from the global allocator with [RawBuffer::alloc], or from another allocator with [RawBuffer::alloc_in].
+/// Zero length buffers do not allocate.
+pub struct RawBuffer<A: GlobalAlloc + 'static = Global> {
+    ptr: NonNull<u8>,
+    layout: Layout,
+    allocator: &'static A,
+}
+
+/// The buffer owns its allocation, like a `Box<[u8]>`.
+unsafe impl<A: GlobalAlloc + Sync + 'static> Send for RawBuffer<A> {}
+unsafe impl<A: GlobalAlloc + Sync + 'static> Sync for RawBuffer<A> {}
+
+impl RawBuffer {
+    /// Allocates `len` zeroed bytes aligned to `align` from the global allocator.
+    ///
+    /// Returns `None` if allocation fails, or if `align` is not a power of two (or `len` too large for it).
+    pub fn alloc(len: usize, align: usize) -> Option<Self> {
+        Self::alloc_in(len, align, &GLOBAL)
+    }
+
+    /// Reconstructs a buffer from [RawBuffer::into_raw_parts], so dropping it frees it.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` and `len` must be from `into_raw_parts` on a buffer allocated by [RawBuffer::alloc] with `align`,
+    /// and each buffer must only be reconstructed once.
+    pub unsafe fn from_raw_parts(ptr: *mut u8, len: usize, align: usize) -> Self {
+        Self::from_raw_parts_in(ptr, len, align, &GLOBAL)
+    }
+}
+
+impl<A: GlobalAlloc + 'static> RawBuffer<A> {
+    /// Allocates `len` zeroed bytes aligned to `align` from `allocator`.
+    ///
+    /// Returns `None` if allocation fails, or if `align` is not a power of two (or `len` too large for it).
+    pub fn alloc_in(len: usize, align: usize, allocator: &'static A) -> Option<Self> {
+        let layout = Layout::from_size_align(len, align).ok()?;
+        let ptr = if len == 0 {
+            dangling(layout)
+        } else {
+            NonNull::new(unsafe { allocator.alloc_zeroed(layout) })?
+        };
+        Some(RawBuffer {
+            ptr,
+            layout,
+            allocator,
+        })
+    }
+
+    /// Like [RawBuffer::from_raw_parts], for buffers from [RawBuffer::alloc_in] with `allocator`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` and `len` must be from `into_raw_parts` on a buffer allocated by `alloc_in` with `align` and `allocator`,
+    /// and each buffer must only be reconstructed once.
+    pub unsafe fn from_raw_parts_in(
+        ptr: *mut u8,
+        len: usize,
+        align: usize,
+        allocator: &'static A,
+    ) -> Self {
+        RawBuffer {
+            ptr: NonNull::new_unchecked(ptr),
+            layout: Layout::from_size_align_unchecked(len, align),
+            allocator,
+        }
+    }
+
+    /// Gives up ownership of the buffer, returning its pointer and length without freeing it.
+    ///
+    /// Free it by passing these (and its alignment) to [RawBuffer::from_raw_parts] and dropping the result.
+    pub fn into_raw_parts(self) -> (*mut u8, usize) {
+        let buffer = ManuallyDrop::new(self);
+        (buffer.ptr.as_ptr(), buffer.layout.size())
+    }
+
+    /// The layout the buffer was allocated with.
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+}
+
+impl<A: GlobalAlloc + 'static> Deref for RawBuffer<A> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // Safety: the buffer is `len` initialized (zeroed) bytes, owned by this.
+        unsafe { core::slice::from_raw_parts(self.ptr.as_ptr(), self.layout.size()) }
+    }
+}
+
+impl<A: GlobalAlloc + 'static> DerefMut for RawBuffer<A> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.layout.size()) }
+    }
+}
+
+impl<A: GlobalAlloc + 'static> Drop for RawBuffer<A> {
+    fn drop(&mut self) {
+        if self.layout.size() != 0 {
+            unsafe { self.allocator.dealloc(self.ptr.as_ptr(), self.layout) };
+        }
+    }
+}
+
+/// Allocates a zeroed buffer of `len` bytes aligned to `align` from the global allocator, for the host.
+///
+/// Returns null if allocation fails or `align` is not a power of two.
+/// Free it with [lol_alloc_buffer_free].
+#[cfg(feature = "buffer-exports")]
+#[unsafe(no_mangle)]
+pub extern "C" fn lol_alloc_buffer_alloc(len: usize, align: usize) -> *mut u8 {
+    RawBuffer::alloc(len, align).map_or(core::ptr::null_mut(), |buffer| buffer.into_raw_parts().0)
+}
+
+/// Frees a buffer from [lol_alloc_buffer_alloc].
+///
+/// # Safety
+///
+/// `ptr` must have been returned by `lol_alloc_buffer_alloc(len, align)`, and not freed since.
+#[cfg(feature = "buffer-exports")]
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lol_alloc_buffer_free(ptr: *mut u8, len: usize, align: usize) {
+    drop(RawBuffer::from_raw_parts(ptr, len, align));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RawBuffer;
+    use crate::testing::Counted;
+    use core::alloc::Layout;
+
+    #[test]
+    fn round_trip() {
+        let mut buffer = RawBuffer::alloc(100, 16).unwrap();
+        assert!(buffer.iter().all(|&b| b == 0));
+        assert_eq!(buffer.as_ptr() as usize % 16, 0);
+        buffer[99] = 7;
+        let (ptr, len) = buffer.into_raw_parts();
+        assert_eq!(len, 100);
+        let buffer = unsafe { RawBuffer::from_raw_parts(ptr, len, 16) };
+        assert_eq!(buffer[99], 7);
+        assert_eq!(buffer.layout(), Layout::from_size_align(100, 16).unwrap());
+    }
+
+    #[test]
+    fn frees_on_drop() {
+        static ALLOCATOR: Counted = Counted::new();
+        let buffer = RawBuffer::alloc_in(10, 1, &ALLOCATOR).unwrap();
+        let (ptr, len) = buffer.into_raw_parts();
+        // Handing out the raw parts does not free it.
+        assert_eq!(ALLOCATOR.live(), 1);
+        drop(unsafe { RawBuffer::from_raw_parts_in(ptr, len, 1, &ALLOCATOR) });
+        assert_eq!(ALLOCATOR.live(), 0);
+    }
+
+    #[test]
+    fn frees_on_panic() {
+        static ALLOCATOR: Counted = Counted::new();
+        let result = std::panic::catch_unwind(|| {
+            let buffer = RawBuffer::alloc_in(1000, 8, &ALLOCATOR).unwrap();
+            assert_eq!(ALLOCATOR.live(), 1);
+            panic!("failed while holding {} bytes", buffer.len());
+        });
+        assert!(result.is_err());
+        assert_eq!(ALLOCATOR.live(), 0);
+    }
+
+    #[test]
+    fn zero_length() {
+        static ALLOCATOR: Counted = Counted::new();
+        let buffer = RawBuffer::alloc_in(0, 64, &ALLOCATOR).unwrap();
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.as_ptr() as usize % 64, 0);
+        let (ptr, len) = buffer.into_raw_parts();
+        drop(unsafe { RawBuffer::from_raw_parts_in(ptr, len, 64, &ALLOCATOR) });
+        assert_eq!(ALLOCATOR.live(), 0);
+    }
+
+    #[test]
+    fn invalid_alignment() {
+        assert!(RawBuffer::alloc(10, 3).is_none());
+    }
+
+    #[cfg(feature = "buffer-exports")]
+    #[test]
+    fn exports() {
+        use super::{lol_alloc_buffer_alloc, lol_alloc_buffer_free};
+        let ptr = lol_alloc_buffer_alloc(32, 8);
+        assert!(!ptr.is_null());
+        unsafe {
+            ptr.write_bytes(1, 32);
+            lol_alloc_buffer_free(ptr, 32, 8);
+        }
+        assert!(lol_alloc_buffer_alloc(32, 3).is_null());
+    }
+}
@@ -0,0 +1,535 @@
+use crate::{
+    atomic::{AtomicPtr, AtomicU8, AtomicUsize, Ordering},
+    lock::Lock,
+    DefaultGrower, MemoryGrower, PageCount, ERROR_PAGE_COUNT, PAGE_SIZE,
+};
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    mem::{align_of, size_of},
+    ptr::null_mut,
+};
+
+/// Allocator whose live allocations can be enumerated by readers on other threads while it allocates and frees,
+/// using read-copy-update (RCU) style list management.
+///
+/// Memory is a linked list of chunks of whole pages, each holding a sequence of records: a small header, then an allocation.
+/// Allocation appends a record to the last chunk (or a new chunk linked onto the end of the list),
+/// and only makes it reachable once its header is written, so nothing a reader can see is ever modified (except a record's state).
+/// Readers ([RcuArenaAllocator::read]) walk the list without locking, and see a consistent view:
+/// every record they observe is fully written, and no memory they can reach is reused until they are done.
+/// Allocations are hidden from readers until [published](RcuArenaAllocator::publish), so they can be initialized first.
+///
+/// Freeing marks the record dead. Once a chunk has no live records, it is unlinked from the list,
+/// and reused for new chunks only after an epoch based grace period, once every reader which could have seen it has finished.
+/// Memory in chunks with any live record is not reused, so this suits arenas of similar lifetime objects, not general purpose heaps.
+///
+/// Allocation and freeing are serialized by a lock (like [LockedAllocator](crate::LockedAllocator)), but never wait for readers:
+/// if readers keep a grace period from ending, new chunks are grown instead.
+pub struct RcuArenaAllocator<T = DefaultGrower> {
+    /// The first chunk, from which readers walk the list.
+    head: AtomicPtr<Chunk>,
+    /// The current epoch. Readers register in the counter for its parity.
+    epoch: AtomicUsize,
+    readers: [AtomicUsize; 2],
+    writer: Lock<Writer>,
+    grower: T,
+}
+
+/// Everything in the list is only modified under the writer lock, and published with release stores.
+unsafe impl<T: Sync> Sync for RcuArenaAllocator<T> {}
+
+/// State only the writer (holding the lock) uses.
+struct Writer {
+    /// The last chunk in the list, which allocations are appended to.
+    tail: *mut Chunk,
+    /// Chunks unlinked from the list, waiting for their grace period, or ready for reuse.
+    retired: *mut Chunk,
+}
+
+/// The start of a chunk of memory from the grower.
+#[repr(C)]
+struct Chunk {
+    next: AtomicPtr<Chunk>,
+    /// Offset (from the chunk) of the end of the records. Records are only added, made reachable by a release store of this.
+    len: AtomicUsize,
+    /// Size of the chunk in bytes, including this header.
+    capacity: usize,
+    /// Number of live records. Writer only.
+    live: usize,
+    /// Epoch in which the chunk was unlinked from the list. Writer only.
+    retired_epoch: usize,
+    /// Next chunk in [Writer::retired]. Writer only.
+    next_retired: *mut Chunk,
+}
+
+/// Precedes each allocation in a chunk.
+#[repr(C)]
+struct Record {
+    /// Offset (from the chunk) of the next record.
+    end: usize,
+    /// Offset (from the chunk) of the allocation.
+    data: usize,
+    size: usize,
+    /// [PENDING], [PUBLISHED] or [FREED].
+    state: AtomicU8,
+}
+
+/// Allocated, but not yet visible to readers.
+const PENDING: u8 = 0;
+const PUBLISHED: u8 = 1;
+const FREED: u8 = 2;
+
+const WORD: usize = size_of::<usize>();
+
+/// Offset of the first record in a chunk.
+const FIRST_RECORD: usize = round_up(size_of::<Chunk>(), align_of::<Record>());
+
+const fn round_up(value: usize, align: usize) -> usize {
+    (value + align - 1) & !(align - 1)
+}
+
+#[cfg(target_arch = "wasm32")]
+impl RcuArenaAllocator<DefaultGrower> {
+    pub const fn new() -> Self {
+        RcuArenaAllocator::new_in(DefaultGrower)
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Default for RcuArenaAllocator<DefaultGrower> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> RcuArenaAllocator<T> {
+    /// Creates an allocator which gets its memory from `grower`.
+    pub const fn new_in(grower: T) -> Self {
+        RcuArenaAllocator {
+            head: AtomicPtr::new(null_mut()),
+            epoch: AtomicUsize::new(0),
+            readers: [AtomicUsize::new(0), AtomicUsize::new(0)],
+            writer: Lock::new(Writer {
+                tail: null_mut(),
+                retired: null_mut(),
+            }),
+            grower,
+        }
+    }
+
+    /// Starts reading the live allocations.
+    ///
+    /// Memory freed after this is not reused until the returned guard is dropped, so keep it short lived.
+    pub fn read(&self) -> RcuReadGuard<'_, T> {
+        loop {
+            let epoch = self.epoch.load(Ordering::SeqCst);
+            let readers = &self.readers[epoch & 1];
+            readers.fetch_add(1, Ordering::SeqCst);
+            // If the epoch advanced before registering, the writer may not have seen this reader: register in the new one.
+            if self.epoch.load(Ordering::SeqCst) == epoch {
+                return RcuReadGuard {
+                    allocator: self,
+                    readers,
+                };
+            }
+            readers.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    /// Makes an allocation visible to readers.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been allocated by this allocator (and not freed), and not published already.
+    /// Its contents must be initialized (or readers must not read them).
+    pub unsafe fn publish(&self, ptr: *mut u8) {
+        let record = *(ptr.sub(WORD) as *const *const Record);
+        let previous = (*record).state.swap(PUBLISHED, Ordering::Release);
+        lol_assert!(previous == PENDING, "Published allocation was not pending");
+    }
+
+    /// Advances the epoch if the readers from the previous one are done.
+    ///
+    /// A chunk retired in epoch `e` is unreachable by readers once the epoch is `e + 2`:
+    /// readers registered in `e` (or before) have finished, and later ones started after it was unlinked.
+    fn try_advance(&self) {
+        let epoch = self.epoch.load(Ordering::SeqCst);
+        if self.readers[(epoch + 1) & 1].load(Ordering::SeqCst) == 0 {
+            self.epoch.store(epoch + 1, Ordering::SeqCst);
+        }
+    }
+}
+
+impl<T> RcuArenaAllocator<T> {
+    /// Appends a record for `layout`, returning its (pending) allocation, or null if out of memory.
+    fn append(&self, layout: Layout) -> *mut u8
+    where
+        T: MemoryGrower,
+    {
+        // This assumes PAGE_SIZE is always a multiple of the required alignment, which should be true for all practical use.
+        strict_assert!(layout.align() <= PAGE_SIZE);
+
+        let mut writer = self.writer.lock();
+        self.try_advance();
+        unsafe {
+            let mut chunk = writer.tail;
+            let mut record = if chunk.is_null() {
+                None
+            } else {
+                place(chunk, layout)
+            };
+            if record.is_none() {
+                chunk = self.new_chunk(&mut writer, layout);
+                if chunk.is_null() {
+                    return null_mut();
+                }
+                record = place(chunk, layout);
+            }
+            // A new chunk always fits the allocation.
+            let (offset, data, end) = record.unwrap_unchecked();
+            let base = chunk as *mut u8;
+            (base.add(offset) as *mut Record).write(Record {
+                end,
+                data,
+                size: layout.size(),
+                state: AtomicU8::new(PENDING),
+            });
+            // The word before the allocation locates its record when freed.
+            (base.add(data - WORD) as *mut usize).write(base as usize + offset);
+            (*chunk).live += 1;
+            (*chunk).len.store(end, Ordering::Release);
+            base.add(data)
+        }
+    }
+
+    /// Appends a chunk at least large enough for `layout` to the list, reusing a retired one if possible.
+    unsafe fn new_chunk(&self, writer: &mut Writer, layout: Layout) -> *mut Chunk
+    where
+        T: MemoryGrower,
+    {
+        let needed = match FIRST_RECORD
+            .checked_add(size_of::<Record>() + WORD + layout.align() + align_of::<Record>())
+            .and_then(|size| size.checked_add(layout.size()))
+        {
+            Some(needed) => needed,
+            None => return null_mut(),
+        };
+        let chunk = self.reuse_chunk(writer, needed);
+        let chunk = if chunk.is_null() {
+            let pages = needed.div_ceil(PAGE_SIZE);
+            let previous = self.grower.memory_grow(PageCount(pages));
+            if previous == ERROR_PAGE_COUNT {
+                return null_mut();
+            }
+            let (Some(start), Some(capacity)) =
+                (previous.size_in_bytes(), PageCount(pages).size_in_bytes())
+            else {
+                return null_mut();
+            };
+            let chunk = start as *mut Chunk;
+            chunk.write(Chunk {
+                next: AtomicPtr::new(null_mut()),
+                len: AtomicUsize::new(FIRST_RECORD),
+                capacity,
+                live: 0,
+                retired_epoch: 0,
+                next_retired: null_mut(),
+            });
+            chunk
+        } else {
+            chunk
+        };
+        let previous_tail = writer.tail;
+        if previous_tail.is_null() {
+            self.head.store(chunk, Ordering::Release);
+        } else {
+            (*previous_tail).next.store(chunk, Ordering::Release);
+        }
+        writer.tail = chunk;
+        // The tail is never retired, so one emptied while it was the tail is retired once it no longer is.
+        if !previous_tail.is_null() && (*previous_tail).live == 0 {
+            self.retire(writer, previous_tail);
+        }
+        chunk
+    }
+
+    /// Removes a chunk of at least `needed` bytes whose grace period is over from the retired list,
+    /// reset to have no records, or returns null if there is none.
+    unsafe fn reuse_chunk(&self, writer: &mut Writer, needed: usize) -> *mut Chunk {
+        let epoch = self.epoch.load(Ordering::SeqCst);
+        let mut link: *mut *mut Chunk = &mut writer.retired;
+        while !(*link).is_null() {
+            let chunk = *link;
+            if (*chunk).capacity >= needed && epoch.wrapping_sub((*chunk).retired_epoch) >= 2 {
+                *link = (*chunk).next_retired;
+                (*chunk).next.store(null_mut(), Ordering::Relaxed);
+                (*chunk).len.store(FIRST_RECORD, Ordering::Relaxed);
+                (*chunk).next_retired = null_mut();
+                return chunk;
+            }
+            link = &mut (*chunk).next_retired;
+        }
+        null_mut()
+    }
+}
+
+/// Where a record for `layout` would go in `chunk`: offsets of the record, its allocation and its end.
+/// Returns `None` if it does not fit.
+unsafe fn place(chunk: *mut Chunk, layout: Layout) -> Option<(usize, usize, usize)> {
+    let base = chunk as usize;
+    let offset = (*chunk).len.load(Ordering::Relaxed);
+    // Align the address, not the offset, so chunks need not be aligned more than the record header.
+    let data = round_up(
+        base.checked_add(offset + size_of::<Record>() + WORD)?,
+        layout.align(),
+    ) - base;
+    let end = round_up(data.checked_add(layout.size())?, align_of::<Record>());
+    if end > (*chunk).capacity {
+        return None;
+    }
+    Some((offset, data, end))
+}
+
+unsafe impl<T: MemoryGrower> GlobalAlloc for RcuArenaAllocator<T> {
+    /// Allocates, without publishing the allocation to readers (see [RcuArenaAllocator::publish]).
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        nonzero_size_assert!(layout.size());
+        self.append(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        nonzero_size_assert!(layout.size());
+        let mut writer = self.writer.lock();
+        let record = *(ptr.sub(WORD) as *const *const Record);
+        let previous = (*record).state.swap(FREED, Ordering::Release);
+        lol_assert!(previous != FREED, "Freed allocation was already freed");
+        let chunk = ptr.sub((*record).data) as *mut Chunk;
+        (*chunk).live -= 1;
+        if (*chunk).live == 0 && chunk != writer.tail {
+            self.retire(&mut writer, chunk);
+        }
+        self.try_advance();
+    }
+}
+
+impl<T> RcuArenaAllocator<T> {
+    /// Unlinks `chunk` (which is not the tail) from the list, and adds it to the retired list.
+    unsafe fn retire(&self, writer: &mut Writer, chunk: *mut Chunk) {
+        let next = (*chunk).next.load(Ordering::Relaxed);
+        let mut link = &self.head;
+        loop {
+            let current = link.load(Ordering::Relaxed);
+            lol_assert!(!current.is_null(), "Retired chunk is not in the list");
+            if current == chunk {
+                break;
+            }
+            link = &(*current).next;
+        }
+        // Readers already past `link` may still walk into `chunk`: it is left intact, and links to the rest of the list.
+        link.store(next, Ordering::Release);
+        (*chunk).retired_epoch = self.epoch.load(Ordering::SeqCst);
+        (*chunk).next_retired = writer.retired;
+        writer.retired = chunk;
+    }
+}
+
+/// A reader of an [RcuArenaAllocator], from [RcuArenaAllocator::read].
+///
+/// Memory is not reused while any reader which could have seen it is alive.
+pub struct RcuReadGuard<'a, T> {
+    allocator: &'a RcuArenaAllocator<T>,
+    /// The counter this reader is registered in.
+    readers: &'a AtomicUsize,
+}
+
+impl<T> RcuReadGuard<'_, T> {
+    /// Calls `f` with the pointer and size of each published allocation which has not been freed.
+    ///
+    /// Allocations published or freed during the walk may or may not be included.
+    /// Allocations are only valid to read until this guard is dropped, and may be freed (but not reused) meanwhile.
+    pub fn for_each_live(&self, mut f: impl FnMut(*const u8, usize)) {
+        let mut chunk = self.allocator.head.load(Ordering::Acquire);
+        while !chunk.is_null() {
+            unsafe {
+                let base = chunk as *const u8;
+                let len = (*chunk).len.load(Ordering::Acquire);
+                let mut offset = FIRST_RECORD;
+                while offset < len {
+                    let record = &*(base.add(offset) as *const Record);
+                    if record.state.load(Ordering::Acquire) == PUBLISHED {
+                        f(base.add(record.data), record.size);
+                    }
+                    offset = record.end;
+                }
+                chunk = (*chunk).next.load(Ordering::Acquire);
+            }
+        }
+    }
+}
+
+impl<T> Drop for RcuReadGuard<'_, T> {
+    fn drop(&mut self) {
+        self.readers.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RcuArenaAllocator;
+    use crate::{
+        testing::{conformance, MockGrower},
+        GlobalArrayGrower, PAGE_SIZE,
+    };
+    use alloc::{collections::VecDeque, vec::Vec};
+    use core::{
+        alloc::{GlobalAlloc, Layout},
+        sync::atomic::{AtomicBool, Ordering},
+    };
+    use std::thread;
+
+    /// Collects the published allocations as (pointer, size).
+    fn live<T>(allocator: &RcuArenaAllocator<T>) -> Vec<(usize, usize)> {
+        let mut live = Vec::new();
+        allocator
+            .read()
+            .for_each_live(|ptr, size| live.push((ptr as usize, size)));
+        live
+    }
+
+    #[test]
+    fn conforms() {
+        let allocator = RcuArenaAllocator::new_in(MockGrower::new());
+        conformance(&allocator);
+    }
+
+    #[test]
+    fn enumerates_live() {
+        let allocator = RcuArenaAllocator::new_in(MockGrower::new());
+        let small = Layout::from_size_align(10, 4).unwrap();
+        let large = Layout::from_size_align(PAGE_SIZE * 2, 64).unwrap();
+        unsafe {
+            let a = allocator.alloc(small);
+            let b = allocator.alloc(large);
+            let c = allocator.alloc(small);
+            assert_eq!(b as usize % 64, 0);
+            // Nothing is visible until published.
+            assert_eq!(live(&allocator), []);
+            for ptr in [a, b, c] {
+                allocator.publish(ptr);
+            }
+            assert_eq!(
+                live(&allocator),
+                [
+                    (a as usize, 10),
+                    (b as usize, PAGE_SIZE * 2),
+                    (c as usize, 10)
+                ]
+            );
+            allocator.dealloc(b, large);
+            assert_eq!(live(&allocator), [(a as usize, 10), (c as usize, 10)]);
+        }
+    }
+
+    #[test]
+    fn reuse_waits_for_readers() {
+        let allocator = RcuArenaAllocator::new_in(MockGrower::new());
+        let layout = Layout::from_size_align(PAGE_SIZE / 2, 1).unwrap();
+        unsafe {
+            let first = allocator.alloc(layout);
+            // Start a second chunk, so the first can be retired.
+            let second = allocator.alloc(layout);
+            let reader = allocator.read();
+            allocator.dealloc(first, layout);
+            // Filling the second chunk needs a new one: the first can't be reused while the reader is alive.
+            let grown = allocator.grower.used_pages();
+            let third = allocator.alloc(layout);
+            assert_eq!(allocator.grower.used_pages(), grown + 1);
+            let grown = grown + 1;
+            reader.for_each_live(|_, _| {});
+            drop(reader);
+
+            allocator.dealloc(second, layout);
+            // With no readers, the epoch advances on each operation, so the retired chunks become reusable.
+            for _ in 0..4 {
+                allocator.dealloc(allocator.alloc(layout), layout);
+            }
+            assert_eq!(allocator.grower.used_pages(), grown);
+            allocator.dealloc(third, layout);
+        }
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic]
+    fn double_free() {
+        let allocator = RcuArenaAllocator::new_in(MockGrower::new());
+        let layout = Layout::from_size_align(8, 8).unwrap();
+        unsafe {
+            let ptr = allocator.alloc(layout);
+            allocator.dealloc(ptr, layout);
+            allocator.dealloc(ptr, layout);
+        }
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic]
+    fn publish_freed() {
+        let allocator = RcuArenaAllocator::new_in(MockGrower::new());
+        let layout = Layout::from_size_align(8, 8).unwrap();
+        unsafe {
+            let ptr = allocator.alloc(layout);
+            allocator.dealloc(ptr, layout);
+            allocator.publish(ptr);
+        }
+    }
+
+    /// One thread allocates and frees, filling each allocation with a byte derived from its size,
+    /// while others read: every allocation they see must be intact.
+    #[test]
+    fn concurrent_readers() {
+        // Far less memory than is allocated in total, so chunks have to be reused.
+        static GROWER: GlobalArrayGrower<32> = GlobalArrayGrower::new();
+        let allocator = RcuArenaAllocator::new_in(&GROWER);
+        let done = AtomicBool::new(false);
+        thread::scope(|scope| {
+            for _ in 0..3 {
+                scope.spawn(|| {
+                    while !done.load(Ordering::Relaxed) {
+                        allocator.read().for_each_live(|ptr, size| {
+                            assert!((1..=1000).contains(&size));
+                            let bytes = unsafe { core::slice::from_raw_parts(ptr, size) };
+                            assert!(bytes.iter().all(|&b| b == size as u8), "torn allocation");
+                        });
+                    }
+                });
+            }
+            let mut live = VecDeque::new();
+            for i in 0..50_000usize {
+                let size = i * 7919 % 1000 + 1;
+                let layout = Layout::from_size_align(size, 1 << (i % 4)).unwrap();
+                let ptr = loop {
+                    let ptr = unsafe { allocator.alloc(layout) };
+                    if !ptr.is_null() {
+                        break ptr;
+                    }
+                    // Out of memory until the readers finish with retired chunks.
+                    thread::yield_now();
+                };
+                unsafe {
+                    ptr.write_bytes(size as u8, size);
+                    allocator.publish(ptr);
+                }
+                live.push_back((ptr, layout));
+                if live.len() > 300 {
+                    let (ptr, layout) = live.pop_front().unwrap();
+                    unsafe { allocator.dealloc(ptr, layout) };
+                }
+            }
+            done.store(true, Ordering::Relaxed);
+            for (ptr, layout) in live {
+                unsafe { allocator.dealloc(ptr, layout) };
+            }
+        });
+    }
+}
@@ -0,0 +1,117 @@
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    ptr::NonNull,
+};
+
+/// Safe interface for allocating from an allocator,
+/// with the [Layout] of each allocation determined by its type.
+pub struct SafeHeap<'a, A: GlobalAlloc> {
+    allocator: &'a A,
+}
+
+impl<'a, A: GlobalAlloc> SafeHeap<'a, A> {
+    pub const fn new(allocator: &'a A) -> Self {
+        SafeHeap { allocator }
+    }
+
+    /// Allocates uninitialized space for a `T`.
+    /// Returns `None` if the allocation failed.
+    ///
+    /// Zero sized types get a dangling pointer without allocating.
+    pub fn allocate<T: Sized>(&self) -> Option<NonNull<T>> {
+        self.allocate_layout(Layout::new::<T>()).map(NonNull::cast)
+    }
+
+    /// Allocates uninitialized space for `count` `T`s.
+    /// Returns `None` if the allocation failed, or the size overflows.
+    ///
+    /// Zero sized slices get a dangling pointer without allocating.
+    pub fn allocate_slice<T>(&self, count: usize) -> Option<NonNull<[T]>> {
+        let ptr = self.allocate_layout(Layout::array::<T>(count).ok()?)?;
+        Some(NonNull::slice_from_raw_parts(ptr.cast(), count))
+    }
+
+    /// Frees a `T` allocated by [SafeHeap::allocate].
+    /// Does not drop the `T`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by `allocate::<T>` on a `SafeHeap` for the same allocator,
+    /// and not already have been freed.
+    pub unsafe fn deallocate<T: Sized>(&self, ptr: NonNull<T>) {
+        self.deallocate_layout(ptr.cast(), Layout::new::<T>());
+    }
+
+    /// Frees a slice allocated by [SafeHeap::allocate_slice].
+    /// Does not drop the contents.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by `allocate_slice::<T>` on a `SafeHeap` for the same allocator,
+    /// and not already have been freed.
+    pub unsafe fn deallocate_slice<T>(&self, ptr: NonNull<[T]>) {
+        let layout = Layout::array::<T>(ptr.len()).unwrap_unchecked();
+        self.deallocate_layout(ptr.cast(), layout);
+    }
+
+    fn allocate_layout(&self, layout: Layout) -> Option<NonNull<u8>> {
+        if layout.size() == 0 {
+            // GlobalAlloc does not allow zero sized allocations.
+            return Some(dangling(layout));
+        }
+        // Safety: layout was checked to be non-zero sized.
+        NonNull::new(unsafe { self.allocator.alloc(layout) })
+    }
+
+    unsafe fn deallocate_layout(&self, ptr: NonNull<u8>, layout: Layout) {
+        if layout.size() != 0 {
+            self.allocator.dealloc(ptr.as_ptr(), layout);
+        }
+    }
+}
+
+/// A non-null pointer aligned for `layout`, to use for zero sized allocations.
+pub(crate) fn dangling(layout: Layout) -> NonNull<u8> {
+    // Safety: alignments are never zero.
+    unsafe { NonNull::new_unchecked(layout.align() as *mut u8) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SafeHeap;
+    use crate::{free_list_allocator::FreeListAllocator, testing::MockGrower};
+
+    #[test]
+    fn round_trip() {
+        let allocator = FreeListAllocator::new_in(MockGrower::new());
+        let heap = SafeHeap::new(&allocator);
+
+        let a = heap.allocate::<u64>().unwrap();
+        let b = heap.allocate::<[u8; 256]>().unwrap();
+        assert_ne!(a.cast::<u8>(), b.cast::<u8>());
+        assert!(a.as_ptr().is_aligned());
+        let slice = heap.allocate_slice::<u32>(100).unwrap();
+        assert_eq!(slice.len(), 100);
+        assert!(slice.cast::<u32>().as_ptr().is_aligned());
+
+        let empty = heap.allocate_slice::<u32>(0).unwrap();
+        let unit = heap.allocate::<()>().unwrap();
+        unsafe {
+            heap.deallocate(a);
+            heap.deallocate(b);
+            heap.deallocate_slice(slice);
+            heap.deallocate_slice(empty);
+            heap.deallocate(unit);
+        }
+        // Everything was returned to the (one page) heap.
+        let base = allocator.grower.base() as *const u8;
+        assert!(allocator.is_free(base, crate::PAGE_SIZE));
+    }
+
+    #[test]
+    fn overflow() {
+        let allocator = FreeListAllocator::new_in(MockGrower::new());
+        let heap = SafeHeap::new(&allocator);
+        assert!(heap.allocate_slice::<u64>(usize::MAX / 4).is_none());
+    }
+}
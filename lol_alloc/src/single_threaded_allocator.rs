@@ -1,3 +1,4 @@
+use crate::{AllocWithExcess, AllocatorStats, CountsAllocations, Stats};
 use core::alloc::{GlobalAlloc, Layout};
 
 /// A non-thread safe allocator created by wrapping an allocator in a `Sync` implementation that assumes all use is from the same thread.
@@ -24,10 +25,82 @@ unsafe impl<T> Sync for AssumeSingleThreaded<T> {}
 
 unsafe impl<T: GlobalAlloc> GlobalAlloc for AssumeSingleThreaded<T> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        nonzero_size_assert!(layout.size());
         self.inner.alloc(layout)
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        nonzero_size_assert!(layout.size());
         self.inner.dealloc(ptr, layout);
     }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        nonzero_size_assert!(layout.size());
+        self.inner.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        nonzero_size_assert!(layout.size(), new_size);
+        self.inner.realloc(ptr, layout, new_size)
+    }
+}
+
+impl<T: AllocWithExcess> AllocWithExcess for AssumeSingleThreaded<T> {
+    unsafe fn alloc_with_excess(&self, layout: Layout) -> (*mut u8, usize) {
+        self.inner.alloc_with_excess(layout)
+    }
+}
+
+impl<T: AllocatorStats> AllocatorStats for AssumeSingleThreaded<T> {
+    fn stats(&self) -> Stats {
+        self.inner.stats()
+    }
+}
+
+impl<T: CountsAllocations> CountsAllocations for AssumeSingleThreaded<T> {}
+
+#[cfg(test)]
+mod tests {
+    use super::AssumeSingleThreaded;
+    use crate::{
+        free_list_allocator::NODE_SIZE,
+        testing::{block_layout, MockGrower},
+        AllocatorStats, FreeListAllocator,
+    };
+    use core::alloc::GlobalAlloc;
+
+    /// `realloc` is forwarded, so growing into a free neighbor keeps the allocation where it is.
+    #[test]
+    fn realloc_grows_in_place() {
+        let allocator =
+            unsafe { AssumeSingleThreaded::new(FreeListAllocator::new_in(MockGrower::new())) };
+        let layout = block_layout(NODE_SIZE * 2, 8);
+        let grown = block_layout(NODE_SIZE * 4, 8);
+        unsafe {
+            // `b` is placed just below `a`, so freeing `a` leaves a free block just after `b`.
+            let a = allocator.alloc(layout);
+            let b = allocator.alloc(layout);
+            allocator.dealloc(a, layout);
+            assert_eq!(allocator.realloc(b, layout, grown.size()), b);
+            allocator.dealloc(b, grown);
+        }
+        allocator.inner.validate_heap();
+    }
+
+    /// Shrinking through the wrapper keeps the allocation where it is, and frees its tail.
+    #[test]
+    fn realloc_shrinks_in_place() {
+        let allocator =
+            unsafe { AssumeSingleThreaded::new(FreeListAllocator::new_in(MockGrower::new())) };
+        let layout = block_layout(NODE_SIZE * 4, 8);
+        let shrunk = block_layout(NODE_SIZE * 2, 8);
+        unsafe {
+            let ptr = allocator.alloc(layout);
+            let free = allocator.stats().free_bytes.unwrap();
+            assert_eq!(allocator.realloc(ptr, layout, shrunk.size()), ptr);
+            assert_eq!(allocator.stats().free_bytes, Some(free + NODE_SIZE * 2));
+            allocator.dealloc(ptr, shrunk);
+        }
+        allocator.inner.validate_heap();
+    }
 }
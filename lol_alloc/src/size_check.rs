@@ -0,0 +1,93 @@
+//! Checking that [FreeListAllocator](crate::FreeListAllocator) and [LockedFreeListAllocator](crate::LockedFreeListAllocator) allocations are freed with the size they were allocated with, for the `dealloc-size-check` feature.
+//!
+//! Without the feature, or without `debug_assertions`, everything here is a no-op.
+//! With both, each allocation has a hidden one word prefix holding the size of the block allocated for it,
+//! which is checked against the block the layout it is freed with would free.
+
+use core::alloc::Layout;
+#[cfg(all(feature = "dealloc-size-check", debug_assertions))]
+use {crate::free_list_allocator::full_size, core::mem::size_of};
+
+/// If allocations have a size prefix.
+pub(crate) const ENABLED: bool = cfg!(all(feature = "dealloc-size-check", debug_assertions));
+
+/// Bytes [inner_layout] adds before allocations aligned to `align`:
+/// a multiple of both the alignment and the prefix's alignment, since alignments are powers of two.
+#[cfg(all(feature = "dealloc-size-check", debug_assertions))]
+pub(crate) fn prefix_space(align: usize) -> usize {
+    align.max(size_of::<usize>())
+}
+
+/// The layout to allocate for `layout`, with space for the prefix before it.
+#[cfg(all(feature = "dealloc-size-check", debug_assertions))]
+pub(crate) fn inner_layout(layout: Layout) -> Option<Layout> {
+    let size = layout.size().checked_add(prefix_space(layout.align()))?;
+    Layout::from_size_align(size, prefix_space(layout.align())).ok()
+}
+
+/// Size of the block allocated for `layout`, including the prefix.
+///
+/// # Safety
+///
+/// [inner_layout] must succeed for `layout`.
+#[cfg(all(feature = "dealloc-size-check", debug_assertions))]
+unsafe fn block_size(layout: Layout) -> usize {
+    full_size(inner_layout(layout).unwrap_unchecked())
+}
+
+/// Records the block size for `layout` in the prefix of `raw` (allocated with [inner_layout]),
+/// returning the pointer to give to the user (null if `raw` is null).
+#[cfg(all(feature = "dealloc-size-check", debug_assertions))]
+pub(crate) unsafe fn write(raw: *mut u8, layout: Layout) -> *mut u8 {
+    if raw.is_null() {
+        return raw;
+    }
+    let ptr = raw.add(prefix_space(layout.align()));
+    (ptr as *mut usize).sub(1).write(block_size(layout));
+    ptr
+}
+
+/// Checks the allocation at `ptr` is being freed with a size which frees the block it was allocated with,
+/// returning the pointer originally allocated with [inner_layout].
+///
+/// This compares whole blocks (not just [full_size]) since the prefix can round differently:
+/// for example, with 16 byte nodes 9 and 16 byte allocations have the same full size, but their prefixed blocks do not.
+///
+/// # Panics
+///
+/// If the block size for `layout` does not match the allocation's.
+#[cfg(all(feature = "dealloc-size-check", debug_assertions))]
+pub(crate) unsafe fn check(ptr: *mut u8, layout: Layout) -> *mut u8 {
+    let allocated = (ptr as *const usize).sub(1).read();
+    lol_assert!(
+        allocated == block_size(layout),
+        "Freed with size {} (block size {}), but allocated with block size {allocated}",
+        layout.size(),
+        block_size(layout)
+    );
+    ptr.sub(prefix_space(layout.align()))
+}
+
+#[cfg(not(all(feature = "dealloc-size-check", debug_assertions)))]
+#[inline(always)]
+pub(crate) fn prefix_space(_align: usize) -> usize {
+    0
+}
+
+#[cfg(not(all(feature = "dealloc-size-check", debug_assertions)))]
+#[inline(always)]
+pub(crate) fn inner_layout(layout: Layout) -> Option<Layout> {
+    Some(layout)
+}
+
+#[cfg(not(all(feature = "dealloc-size-check", debug_assertions)))]
+#[inline(always)]
+pub(crate) unsafe fn write(raw: *mut u8, _layout: Layout) -> *mut u8 {
+    raw
+}
+
+#[cfg(not(all(feature = "dealloc-size-check", debug_assertions)))]
+#[inline(always)]
+pub(crate) unsafe fn check(ptr: *mut u8, _layout: Layout) -> *mut u8 {
+    ptr
+}
@@ -0,0 +1,427 @@
+use crate::{
+    free_list_allocator::{multiple_below, pages_to_grow, round_up},
+    AllocatorStats, DefaultGrower, MemoryGrower, Stats, ERROR_PAGE_COUNT, PAGE_SIZE,
+};
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    cell::UnsafeCell,
+    ptr::null_mut,
+};
+
+/// Number of size classes: class `n` holds free blocks of at least `2^n` and less than `2^(n + 1)` bytes.
+const SIZE_CLASSES: usize = usize::BITS as usize;
+
+/// Stored at the beginning of each free block.
+///
+/// Each node is in two lists: the address ordered list of all free blocks, used to coalesce freed memory with its neighbors,
+/// and the list of blocks in its size class, used to find blocks large enough for an allocation.
+struct SkipNode {
+    /// Next lower free block.
+    next: *mut SkipNode,
+    /// Next higher free block.
+    previous: *mut SkipNode,
+    size: usize,
+    /// Next block in the same size class, in no particular order.
+    next_in_class: *mut SkipNode,
+}
+
+const NODE_SIZE: usize = core::mem::size_of::<SkipNode>();
+
+/// Marks the end of a list. Never a multiple of the node alignment, so it can never collide with a node.
+const EMPTY: *mut SkipNode = usize::MAX as *mut SkipNode;
+
+/// The size class of a free block of `size` bytes (which is not 0).
+fn size_class(size: usize) -> usize {
+    (usize::BITS - 1 - size.leading_zeros()) as usize
+}
+
+/// A non-thread safe free list allocator which also indexes its free blocks by size,
+/// so allocations skip over blocks too small for them.
+///
+/// Like [FreeListAllocator](crate::FreeListAllocator), free blocks are kept in a list sorted by address, and coalesced when freeing,
+/// so frees are O(length of free list).
+/// Each free block is also in one of [usize::BITS] size class lists (one for each power of two),
+/// and allocations only search the classes which can hold blocks large enough for them,
+/// so a heap fragmented into many small blocks does not slow down larger allocations.
+///
+/// Free blocks hold a four word node, so allocations are rounded up to a multiple of four words.
+pub struct SizeSkipFreeListAllocator<T = DefaultGrower> {
+    grower: T,
+    /// Highest free block.
+    free_list: UnsafeCell<*mut SkipNode>,
+    /// First free block in each size class.
+    classes: UnsafeCell<[*mut SkipNode; SIZE_CLASSES]>,
+    /// Nodes examined by allocations, so tests can check how many were skipped.
+    #[cfg(test)]
+    nodes_searched: core::cell::Cell<usize>,
+}
+
+#[cfg(target_arch = "wasm32")]
+impl SizeSkipFreeListAllocator<DefaultGrower> {
+    pub const fn new() -> Self {
+        SizeSkipFreeListAllocator::new_in(DefaultGrower)
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Default for SizeSkipFreeListAllocator<DefaultGrower> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> SizeSkipFreeListAllocator<T> {
+    /// Creates an allocator which gets its memory from `grower`.
+    pub const fn new_in(grower: T) -> Self {
+        SizeSkipFreeListAllocator {
+            grower,
+            free_list: UnsafeCell::new(EMPTY),
+            classes: UnsafeCell::new([EMPTY; SIZE_CLASSES]),
+            #[cfg(test)]
+            nodes_searched: core::cell::Cell::new(0),
+        }
+    }
+
+    /// Adds `node` to the list for its size class.
+    unsafe fn add_to_class(&self, node: *mut SkipNode) {
+        let first = &mut (*self.classes.get())[size_class((*node).size)];
+        (*node).next_in_class = *first;
+        *first = node;
+    }
+
+    /// Removes `node` from the list for its size class.
+    /// This is O(length of that list).
+    unsafe fn remove_from_class(&self, node: *mut SkipNode) {
+        let mut link: *mut *mut SkipNode = &mut (*self.classes.get())[size_class((*node).size)];
+        while *link != node {
+            lol_assert!(*link != EMPTY, "Free block missing from its size class");
+            link = &mut (**link).next_in_class;
+        }
+        *link = (*node).next_in_class;
+    }
+
+    /// Makes `node` take the place of `below` in the address ordered list, after `above`.
+    unsafe fn link_between(&self, above: *mut SkipNode, node: *mut SkipNode, below: *mut SkipNode) {
+        (*node).previous = above;
+        (*node).next = below;
+        if above == EMPTY {
+            *self.free_list.get() = node;
+        } else {
+            (*above).next = node;
+        }
+        if below != EMPTY {
+            (*below).previous = node;
+        }
+    }
+
+    /// Removes `node` from the address ordered list.
+    unsafe fn unlink(&self, node: *mut SkipNode) {
+        let (above, below) = ((*node).previous, (*node).next);
+        if above == EMPTY {
+            *self.free_list.get() = below;
+        } else {
+            (*above).next = below;
+        }
+        if below != EMPTY {
+            (*below).previous = above;
+        }
+    }
+
+    /// Allocates `size` bytes aligned to `alignment` from a free block, or returns null if none can fit it.
+    ///
+    /// Only searches the size classes which can hold blocks of at least `size` bytes.
+    unsafe fn search(&self, size: usize, alignment: usize) -> *mut u8 {
+        for class in size_class(size)..SIZE_CLASSES {
+            let mut link: *mut *mut SkipNode = &mut (*self.classes.get())[class];
+            while *link != EMPTY {
+                let node = *link;
+                #[cfg(test)]
+                self.nodes_searched.set(self.nodes_searched.get() + 1);
+                let start_of_block = node as usize;
+                let end_of_block = start_of_block + (*node).size;
+                if size <= end_of_block - start_of_block {
+                    // Allocate from the end of the block, like FreeListAllocator.
+                    let position = multiple_below(end_of_block - size, alignment);
+                    if position >= start_of_block {
+                        *link = (*node).next_in_class;
+                        self.carve(node, position, position + size);
+                        return position as *mut u8;
+                    }
+                }
+                link = &mut (*node).next_in_class;
+            }
+        }
+        null_mut()
+    }
+
+    /// Removes `start..end` from the free block `node`, which has already been removed from its size class.
+    unsafe fn carve(&self, node: *mut SkipNode, start: usize, end: usize) {
+        let start_of_block = node as usize;
+        let end_of_block = start_of_block + (*node).size;
+        if end < end_of_block {
+            // Space left after the allocation due to alignment.
+            let after = end as *mut SkipNode;
+            (*after).size = end_of_block - end;
+            self.link_between((*node).previous, after, node);
+            self.add_to_class(after);
+        }
+        if start == start_of_block {
+            self.unlink(node);
+        } else {
+            (*node).size = start - start_of_block;
+            self.add_to_class(node);
+        }
+    }
+
+    /// Inserts the `size` bytes at `ptr` into the free lists, coalescing them with adjacent free blocks.
+    /// `ptr` and `size` must be multiples of [NODE_SIZE].
+    unsafe fn free(&self, ptr: *mut u8, size: usize) {
+        strict_assert!((ptr as usize).is_multiple_of(NODE_SIZE));
+        let node = ptr as *mut SkipNode;
+        let mut above = EMPTY;
+        let mut below = *self.free_list.get();
+        while below != EMPTY && below > node {
+            above = below;
+            below = (*below).next;
+        }
+        let merge_above = above != EMPTY && node as usize + size == above as usize;
+        let merge_below = below != EMPTY && below as usize + (*below).size == node as usize;
+        match (merge_above, merge_below) {
+            (true, true) => {
+                self.remove_from_class(above);
+                self.remove_from_class(below);
+                (*below).size += size + (*above).size;
+                self.unlink(above);
+                self.add_to_class(below);
+            }
+            (false, true) => {
+                self.remove_from_class(below);
+                (*below).size += size;
+                self.add_to_class(below);
+            }
+            (true, false) => {
+                self.remove_from_class(above);
+                (*node).size = size + (*above).size;
+                self.link_between((*above).previous, node, below);
+                self.add_to_class(node);
+            }
+            (false, false) => {
+                (*node).size = size;
+                self.link_between(above, node, below);
+                self.add_to_class(node);
+            }
+        }
+    }
+}
+
+/// Size of the free block used by an allocation for `layout`.
+fn full_size(layout: Layout) -> usize {
+    round_up(layout.size().max(NODE_SIZE), NODE_SIZE)
+}
+
+// Safety: No one besides us has the raw pointers, so we can safely transfer the
+// allocator to another thread.
+unsafe impl<T> Send for SizeSkipFreeListAllocator<T> {}
+
+unsafe impl<T: MemoryGrower> GlobalAlloc for SizeSkipFreeListAllocator<T> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        nonzero_size_assert!(layout.size());
+        // Unlike FreeListAllocator, this assumes the alignment does not exceed PAGE_SIZE.
+        strict_assert!(layout.align() <= PAGE_SIZE);
+        let size = full_size(layout);
+        let alignment = layout.align().max(NODE_SIZE);
+        let ptr = self.search(size, alignment);
+        if !ptr.is_null() {
+            return ptr;
+        }
+
+        // Grow enough for the allocation, and allocate from that.
+        // The grown pages are aligned to PAGE_SIZE, so the allocation fits in them even if they are not coalesced with anything.
+        let pages = pages_to_grow(size);
+        let first_page = self.grower.memory_grow(pages);
+        if first_page == ERROR_PAGE_COUNT {
+            return null_mut();
+        }
+        let (Some(start), Some(grown_size)) = (first_page.size_in_bytes(), pages.size_in_bytes())
+        else {
+            return null_mut();
+        };
+        self.free(start as *mut u8, grown_size);
+        self.search(size, alignment)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        nonzero_size_assert!(layout.size());
+        self.free(ptr, full_size(layout));
+    }
+}
+
+impl<T> AllocatorStats for SizeSkipFreeListAllocator<T> {
+    fn stats(&self) -> Stats {
+        let (mut bytes, mut blocks) = (0, 0);
+        unsafe {
+            let mut list = *self.free_list.get();
+            while list != EMPTY {
+                bytes += (*list).size;
+                blocks += 1;
+                list = (*list).next;
+            }
+        }
+        Stats {
+            free_bytes: Some(bytes),
+            free_blocks: Some(blocks),
+            ..Stats::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{size_class, SizeSkipFreeListAllocator, SkipNode, EMPTY, NODE_SIZE};
+    use crate::{
+        testing::{conformance, MockGrower},
+        AllocatorStats, PAGE_SIZE,
+    };
+    use alloc::vec::Vec;
+    use core::alloc::{GlobalAlloc, Layout};
+    use rand::{Rng, SeedableRng};
+    use rand_pcg::Pcg32;
+
+    type Allocator = SizeSkipFreeListAllocator<MockGrower>;
+
+    fn allocator() -> Allocator {
+        SizeSkipFreeListAllocator::new_in(MockGrower::new())
+    }
+
+    /// The free blocks (start and size), highest first,
+    /// after checking the address ordered list and the size classes agree.
+    fn free_blocks(allocator: &Allocator) -> Vec<(usize, usize)> {
+        let mut blocks = vec![];
+        unsafe {
+            let mut previous = EMPTY;
+            let mut list = *allocator.free_list.get();
+            while list != EMPTY {
+                assert_eq!((*list).previous, previous);
+                let (start, size) = (list as usize, (*list).size);
+                assert!(size >= NODE_SIZE && size.is_multiple_of(NODE_SIZE));
+                if let Some(&(above, _)) = blocks.last() {
+                    assert!(
+                        start + size < above,
+                        "Free blocks should not overlap or be adjacent"
+                    );
+                }
+                blocks.push((start, size));
+                previous = list;
+                list = (*list).next;
+            }
+            let mut in_classes = vec![];
+            for (class, &first) in (*allocator.classes.get()).iter().enumerate() {
+                let mut node: *mut SkipNode = first;
+                while node != EMPTY {
+                    assert_eq!(size_class((*node).size), class);
+                    in_classes.push((node as usize, (*node).size));
+                    node = (*node).next_in_class;
+                }
+            }
+            in_classes.sort_by(|a, b| b.cmp(a));
+            assert_eq!(in_classes, blocks);
+        }
+        blocks
+    }
+
+    #[test]
+    fn size_classes() {
+        let classes = [1, 2, 3, 4, 31, 32, 33, usize::MAX].map(size_class);
+        assert_eq!(classes, [0, 1, 1, 2, 4, 5, 5, usize::BITS as usize - 1]);
+    }
+
+    #[test]
+    fn conforms() {
+        let allocator = allocator();
+        conformance(&allocator);
+        assert_eq!(free_blocks(&allocator).len(), 1);
+    }
+
+    #[test]
+    fn coalesces() {
+        let allocator = allocator();
+        let layout = Layout::from_size_align(NODE_SIZE, 1).unwrap();
+        unsafe {
+            let ptrs: Vec<*mut u8> = (0..5).map(|_| allocator.alloc(layout)).collect();
+            // Free the middle one, then its neighbors on either side, then join the rest.
+            for i in [2, 1, 3, 0, 4] {
+                allocator.dealloc(ptrs[i], layout);
+                free_blocks(&allocator);
+            }
+        }
+        let base = allocator.grower.base();
+        assert_eq!(free_blocks(&allocator), [(base, PAGE_SIZE)]);
+    }
+
+    /// Small free blocks in front of (at higher addresses than) the free space a large allocation uses are not searched.
+    #[test]
+    fn skips_small_blocks() {
+        let allocator = allocator();
+        let small = Layout::from_size_align(NODE_SIZE, 1).unwrap();
+        let large = Layout::from_size_align(NODE_SIZE * 100, 1).unwrap();
+        unsafe {
+            let ptrs: Vec<*mut u8> = (0..1000).map(|_| allocator.alloc(small)).collect();
+            // Free every other allocation, leaving 500 blocks too small for the large allocation.
+            for &ptr in ptrs.iter().step_by(2) {
+                allocator.dealloc(ptr, small);
+            }
+            assert_eq!(allocator.stats().free_blocks, Some(501));
+            allocator.nodes_searched.set(0);
+            let ptr = allocator.alloc(large);
+            assert!(!ptr.is_null());
+            // Only the remainder of the first page is large enough.
+            assert_eq!(allocator.nodes_searched.get(), 1);
+            allocator.dealloc(ptr, large);
+        }
+        free_blocks(&allocator);
+    }
+
+    #[test]
+    fn aligned_allocations() {
+        let allocator = allocator();
+        unsafe {
+            for align in [1, NODE_SIZE * 2, 256, PAGE_SIZE] {
+                let layout = Layout::from_size_align(NODE_SIZE * 3, align).unwrap();
+                let ptr = allocator.alloc(layout);
+                assert_eq!(ptr as usize % align, 0);
+                free_blocks(&allocator);
+                allocator.dealloc(ptr, layout);
+            }
+        }
+        assert_eq!(free_blocks(&allocator).len(), 1);
+    }
+
+    #[test]
+    fn randomized() {
+        let allocator = allocator();
+        let mut rng = Pcg32::seed_from_u64(0);
+        let mut live: Vec<(*mut u8, Layout)> = vec![];
+        unsafe {
+            for _ in 0..2000 {
+                if live.is_empty() || rng.gen_bool(0.6) {
+                    let layout =
+                        Layout::from_size_align(rng.gen_range(1..2000), 1 << rng.gen_range(0..8))
+                            .unwrap();
+                    let ptr = allocator.alloc(layout);
+                    assert!(!ptr.is_null());
+                    assert_eq!(ptr as usize % layout.align(), 0);
+                    ptr.write_bytes(0xFF, layout.size());
+                    live.push((ptr, layout));
+                } else {
+                    let (ptr, layout) = live.swap_remove(rng.gen_range(0..live.len()));
+                    allocator.dealloc(ptr, layout);
+                }
+            }
+            free_blocks(&allocator);
+            for (ptr, layout) in live {
+                allocator.dealloc(ptr, layout);
+            }
+        }
+        assert_eq!(free_blocks(&allocator).len(), 1);
+    }
+}
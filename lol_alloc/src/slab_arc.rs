@@ -0,0 +1,198 @@
+use crate::atomic::{fence, AtomicUsize, Ordering};
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    marker::PhantomData,
+    ops::Deref,
+    ptr::NonNull,
+};
+
+/// What a [SlabArc] points to: the value and the number of references to it, in one slot from the pool.
+struct Slot<T> {
+    value: T,
+    count: AtomicUsize,
+}
+
+/// A reference counted pointer, like `Arc<T>`, whose value lives in a slot allocated from `pool` instead of the global allocator.
+///
+/// For shared ownership in programs which do not want `Arc`'s dependency on the global allocator,
+/// or which want shared values kept in a separate pool (like a [FreeListAllocator](crate::FreeListAllocator) growing into its own [GlobalArrayGrower](crate::GlobalArrayGrower)).
+/// Each value takes one allocation of `T` plus a word for its count.
+/// Cloning increments the count, and dropping the last clone drops the value and frees its slot.
+pub struct SlabArc<T, A: GlobalAlloc + 'static> {
+    slot: NonNull<Slot<T>>,
+    pool: &'static A,
+    /// The value is owned (and dropped) by the last clone.
+    marker: PhantomData<Slot<T>>,
+}
+
+/// Like `Arc<T>`: clones on other threads can access and drop the value, and free its slot.
+unsafe impl<T: Send + Sync, A: GlobalAlloc + Sync + 'static> Send for SlabArc<T, A> {}
+unsafe impl<T: Send + Sync, A: GlobalAlloc + Sync + 'static> Sync for SlabArc<T, A> {}
+
+impl<T, A: GlobalAlloc + 'static> SlabArc<T, A> {
+    /// Moves `value` into a slot allocated from `pool`, with a count of 1.
+    ///
+    /// Returns `None` (dropping `value`) if allocation fails.
+    pub fn new(value: T, pool: &'static A) -> Option<Self> {
+        let slot = unsafe { pool.alloc(Layout::new::<Slot<T>>()) } as *mut Slot<T>;
+        let slot = NonNull::new(slot)?;
+        unsafe {
+            slot.as_ptr().write(Slot {
+                value,
+                count: AtomicUsize::new(1),
+            })
+        };
+        Some(SlabArc {
+            slot,
+            pool,
+            marker: PhantomData,
+        })
+    }
+
+    /// Number of clones of `this` (including itself) which have not been dropped.
+    ///
+    /// Other threads may change it at any time.
+    pub fn count(this: &Self) -> usize {
+        this.slot().count.load(Ordering::Relaxed)
+    }
+
+    /// Whether `a` and `b` are clones of each other (so share one value).
+    pub fn ptr_eq(a: &Self, b: &Self) -> bool {
+        a.slot == b.slot
+    }
+
+    fn slot(&self) -> &Slot<T> {
+        // Safety: the slot stays allocated while this clone exists.
+        unsafe { self.slot.as_ref() }
+    }
+}
+
+impl<T, A: GlobalAlloc + 'static> Clone for SlabArc<T, A> {
+    fn clone(&self) -> Self {
+        // Like `Arc`, the new clone is made from an existing one, so needs no synchronization.
+        let previous = self.slot().count.fetch_add(1, Ordering::Relaxed);
+        // A count this large could only come from leaking clones (with `mem::forget`), and would soon overflow.
+        if previous > isize::MAX as usize {
+            count_overflow();
+        }
+        SlabArc {
+            slot: self.slot,
+            pool: self.pool,
+            marker: PhantomData,
+        }
+    }
+}
+
+/// Stops execution when a [SlabArc] has been cloned too many times.
+#[cold]
+fn count_overflow() -> ! {
+    #[cfg(target_arch = "wasm32")]
+    core::arch::wasm32::unreachable();
+    #[cfg(not(target_arch = "wasm32"))]
+    panic!("SlabArc count overflow");
+}
+
+impl<T, A: GlobalAlloc + 'static> Deref for SlabArc<T, A> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.slot().value
+    }
+}
+
+impl<T, A: GlobalAlloc + 'static> Drop for SlabArc<T, A> {
+    fn drop(&mut self) {
+        if self.slot().count.fetch_sub(1, Ordering::Release) != 1 {
+            return;
+        }
+        // Synchronize with the other clones' drops, so their uses of the value happen before it is dropped.
+        fence(Ordering::Acquire);
+        unsafe {
+            core::ptr::drop_in_place(self.slot.as_ptr());
+            self.pool
+                .dealloc(self.slot.as_ptr() as *mut u8, Layout::new::<Slot<T>>());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SlabArc;
+    use crate::{
+        atomic::{AtomicUsize, Ordering},
+        testing::{Counted, MockGrower},
+        AllocatorStats, FreeListAllocator,
+    };
+    use alloc::{boxed::Box, vec::Vec};
+
+    /// Counts how many times it has been dropped.
+    struct DropCounter<'a>(&'a AtomicUsize);
+
+    impl Drop for DropCounter<'_> {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn drops_once_after_last_clone() {
+        static POOL: Counted = Counted::new();
+        let drops = AtomicUsize::new(0);
+        let first = SlabArc::new(DropCounter(&drops), &POOL).unwrap();
+        let second = first.clone();
+        assert!(SlabArc::ptr_eq(&first, &second));
+        assert_eq!(SlabArc::count(&first), 2);
+        assert_eq!(POOL.live(), 1);
+        drop(first);
+        assert_eq!(drops.load(Ordering::Relaxed), 0);
+        assert_eq!(SlabArc::count(&second), 1);
+        drop(second);
+        assert_eq!(drops.load(Ordering::Relaxed), 1);
+        assert_eq!(POOL.live(), 0);
+    }
+
+    #[test]
+    fn slot_is_reused() {
+        let pool = Box::leak(Box::new(FreeListAllocator::new_in(MockGrower::new())));
+        let first = SlabArc::new([7u64; 4], pool).unwrap();
+        assert_eq!(*first, [7; 4]);
+        let address = &*first as *const [u64; 4];
+        drop(first);
+        let second = SlabArc::new([8u64; 4], pool).unwrap();
+        assert_eq!(&*second as *const [u64; 4], address);
+        drop(second);
+        pool.validate_heap();
+        assert_eq!(pool.stats().free_blocks, Some(1));
+    }
+
+    #[test]
+    fn allocation_failure() {
+        static POOL: crate::FailAllocator = crate::FailAllocator;
+        let drops = AtomicUsize::new(0);
+        assert!(SlabArc::new(DropCounter(&drops), &POOL).is_none());
+        assert_eq!(drops.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn shared_between_threads() {
+        static POOL: Counted = Counted::new();
+        static DROPS: AtomicUsize = AtomicUsize::new(0);
+        let shared = SlabArc::new(DropCounter(&DROPS), &POOL).unwrap();
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let shared = shared.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..1000 {
+                        drop(shared.clone());
+                    }
+                })
+            })
+            .collect();
+        drop(shared);
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(DROPS.load(Ordering::Relaxed), 1);
+        assert_eq!(POOL.live(), 0);
+    }
+}
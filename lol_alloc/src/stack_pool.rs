@@ -0,0 +1,161 @@
+use crate::atomic::{AtomicU64, Ordering};
+use core::{
+    cell::UnsafeCell,
+    mem::{size_of, MaybeUninit},
+    ptr::NonNull,
+};
+
+/// A pool of up to 64 slots for values of type `T`, stored inline (so on the stack, or in a static), which never grows.
+///
+/// For code where growing the heap is impossible, like interrupt handlers or early boot code.
+/// A bitmap tracks which slots are in use, so allocating and freeing are O(1) and lock free.
+///
+/// This is not a `GlobalAlloc`: it only allocates `T`s, with [StackPoolAllocator::allocate].
+pub struct StackPoolAllocator<T, const N: usize> {
+    slots: UnsafeCell<[MaybeUninit<T>; N]>,
+    /// Bit `i` is set while slot `i` is allocated.
+    used: AtomicU64,
+}
+
+/// Slots are only accessed through the pointers handed out, which each refer to a different slot.
+unsafe impl<T: Send, const N: usize> Sync for StackPoolAllocator<T, N> {}
+
+impl<T, const N: usize> StackPoolAllocator<T, N> {
+    /// Creates a pool with all `N` slots free.
+    ///
+    /// Fails to compile if `N` is more than 64.
+    pub const fn new() -> Self {
+        const { assert!(N <= 64, "StackPoolAllocator supports at most 64 slots") };
+        StackPoolAllocator {
+            slots: UnsafeCell::new([const { MaybeUninit::uninit() }; N]),
+            used: AtomicU64::new(0),
+        }
+    }
+
+    /// Allocates an uninitialized slot, or returns `None` if all slots are in use.
+    pub fn allocate(&self) -> Option<NonNull<T>> {
+        let mut used = self.used.load(Ordering::Relaxed);
+        loop {
+            let index = used.trailing_ones() as usize;
+            if index >= N {
+                return None;
+            }
+            match self.used.compare_exchange_weak(
+                used,
+                used | 1 << index,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return Some(self.slot(index)),
+                Err(current) => used = current,
+            }
+        }
+    }
+
+    /// Frees a slot from [StackPoolAllocator::allocate].
+    ///
+    /// This does not drop the value in it.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by `allocate` on this pool, and not freed since.
+    pub unsafe fn deallocate(&self, ptr: NonNull<T>) {
+        let index = if size_of::<T>() == 0 {
+            // Every slot has the same address, and any one is as good as another.
+            63usize.wrapping_sub(self.used.load(Ordering::Relaxed).leading_zeros() as usize)
+        } else {
+            (ptr.as_ptr() as usize).wrapping_sub(self.slot(0).as_ptr() as usize) / size_of::<T>()
+        };
+        lol_assert!(
+            index < N && self.slot(index) == ptr,
+            "Freed pointer is not from this pool"
+        );
+        let bit = 1u64.wrapping_shl(index as u32);
+        let previous = self.used.fetch_and(!bit, Ordering::Release);
+        lol_assert!(previous & bit != 0, "Freed slot was not allocated");
+    }
+
+    /// Number of slots not in use.
+    pub fn available(&self) -> usize {
+        N - self.used.load(Ordering::Relaxed).count_ones() as usize
+    }
+
+    fn slot(&self, index: usize) -> NonNull<T> {
+        // Safety: a pointer into the array is never null.
+        unsafe { NonNull::new_unchecked((self.slots.get() as *mut T).wrapping_add(index)) }
+    }
+}
+
+impl<T, const N: usize> Default for StackPoolAllocator<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StackPoolAllocator;
+    use crate::atomic::Ordering;
+    use alloc::vec::Vec;
+    use core::ptr::NonNull;
+
+    #[test]
+    fn tracks_64_slots() {
+        let pool = StackPoolAllocator::<u64, 64>::new();
+        let slots: Vec<NonNull<u64>> = (0..64).map(|_| pool.allocate().unwrap()).collect();
+        assert_eq!(pool.used.load(Ordering::Relaxed), u64::MAX);
+        assert_eq!(pool.available(), 0);
+        assert_eq!(pool.allocate(), None);
+        for (i, &slot) in slots.iter().enumerate() {
+            unsafe { slot.as_ptr().write(i as u64) };
+        }
+        for (i, &slot) in slots.iter().enumerate() {
+            assert_eq!(unsafe { slot.as_ptr().read() }, i as u64);
+        }
+
+        // Freeing a slot clears only its bit, and it is the next one allocated.
+        unsafe { pool.deallocate(slots[37]) };
+        assert_eq!(pool.used.load(Ordering::Relaxed), !(1 << 37));
+        assert_eq!(pool.available(), 1);
+        assert_eq!(pool.allocate(), Some(slots[37]));
+
+        for slot in slots {
+            unsafe { pool.deallocate(slot) };
+        }
+        assert_eq!(pool.used.load(Ordering::Relaxed), 0);
+        assert_eq!(pool.available(), 64);
+    }
+
+    #[test]
+    fn fewer_slots() {
+        let pool = StackPoolAllocator::<[u8; 3], 5>::new();
+        let slots: Vec<NonNull<[u8; 3]>> = (0..5).map(|_| pool.allocate().unwrap()).collect();
+        assert_eq!(pool.allocate(), None);
+        assert_eq!(pool.used.load(Ordering::Relaxed), 0b11111);
+        unsafe { pool.deallocate(slots[0]) };
+        assert_eq!(pool.allocate(), Some(slots[0]));
+    }
+
+    #[test]
+    fn zero_sized() {
+        let pool = StackPoolAllocator::<(), 3>::new();
+        let slots: Vec<NonNull<()>> = (0..3).map(|_| pool.allocate().unwrap()).collect();
+        assert_eq!(pool.allocate(), None);
+        for slot in slots {
+            unsafe { pool.deallocate(slot) };
+        }
+        assert_eq!(pool.available(), 3);
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic]
+    fn double_free() {
+        let pool = StackPoolAllocator::<u32, 2>::new();
+        let slot = pool.allocate().unwrap();
+        unsafe {
+            pool.deallocate(slot);
+            pool.deallocate(slot);
+        }
+    }
+}
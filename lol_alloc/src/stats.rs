@@ -0,0 +1,52 @@
+/// Statistics about the memory use of an allocator.
+///
+/// Fields an allocator can't know are `None`.
+/// Wrapping allocators merge their own statistics with those of the allocator they wrap.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Stats {
+    /// Bytes requested by allocations which have not been freed.
+    pub live_bytes: Option<usize>,
+    /// Number of allocations which have not been freed.
+    pub live_allocations: Option<usize>,
+    /// Bytes available for allocation without growing memory.
+    pub free_bytes: Option<usize>,
+    /// Number of separate blocks `free_bytes` is split into. For free list allocators, the length of the free list.
+    pub free_blocks: Option<usize>,
+    /// Bytes of memory obtained for the heap.
+    pub heap_bytes: Option<usize>,
+    /// Largest value `live_bytes` has had.
+    pub peak_bytes: Option<usize>,
+    /// Number of allocations which failed (returned null).
+    pub failed_allocations: Option<usize>,
+}
+
+impl Stats {
+    /// Combines two sets of statistics, preferring the values from `self` when both are known.
+    pub fn or(self, other: Stats) -> Stats {
+        Stats {
+            live_bytes: self.live_bytes.or(other.live_bytes),
+            live_allocations: self.live_allocations.or(other.live_allocations),
+            free_bytes: self.free_bytes.or(other.free_bytes),
+            free_blocks: self.free_blocks.or(other.free_blocks),
+            heap_bytes: self.heap_bytes.or(other.heap_bytes),
+            peak_bytes: self.peak_bytes.or(other.peak_bytes),
+            failed_allocations: self.failed_allocations.or(other.failed_allocations),
+        }
+    }
+}
+
+/// An allocator which can report [Stats] about its memory use.
+pub trait AllocatorStats {
+    fn stats(&self) -> Stats;
+}
+
+/// An allocator whose [Stats] always include [Stats::live_bytes] and [Stats::live_allocations],
+/// like one wrapped in `lol_alloc_diagnostics::CountingAllocator`.
+///
+/// Wrappers implement this if the allocator they wrap does.
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` does not count allocations",
+    note = "wrap the allocator in `lol_alloc_diagnostics::CountingAllocator` to count its allocations"
+)]
+pub trait CountsAllocations: AllocatorStats {}
@@ -0,0 +1,106 @@
+//! Shared helpers for unit tests which are not part of [crate::testing], since they need `libc`.
+
+use crate::{testing::TestGrower, MemoryGrower, PageCount, ERROR_PAGE_COUNT, PAGE_SIZE};
+
+/// Number of pages in a [Reservation]: all of the 4 GiB wasm32 address space.
+pub(crate) const RESERVATION_PAGES: usize = 1 << 16;
+
+/// Test implementation of [MemoryGrower] which grows into a 4 GiB reservation of address space, like a wasm32 memory.
+///
+/// Unlike [MockGrower](crate::testing::MockGrower), memory is only committed once it is grown, so the whole wasm32 address space can be used,
+/// and memory which has not been grown can't be accessed, like memory past the end of a wasm memory.
+/// [Reservation::offset] and [Reservation::address] translate between addresses and wasm-like offsets from the start of the reservation.
+pub(crate) struct Reservation {
+    /// The mapping, which is larger than the reservation so the reservation can be page aligned.
+    mapping: *mut libc::c_void,
+    base: usize,
+    used_pages: core::cell::Cell<usize>,
+}
+
+impl Reservation {
+    const MAPPING_SIZE: usize = (RESERVATION_PAGES + 1) * PAGE_SIZE;
+
+    pub(crate) fn new() -> Self {
+        let mapping = unsafe {
+            libc::mmap(
+                core::ptr::null_mut(),
+                Self::MAPPING_SIZE,
+                libc::PROT_NONE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        assert_ne!(mapping, libc::MAP_FAILED, "failed to reserve address space");
+        Reservation {
+            mapping,
+            base: (mapping as usize).next_multiple_of(PAGE_SIZE),
+            used_pages: core::cell::Cell::new(0),
+        }
+    }
+
+    /// Marks the next `pages` pages as used without making them accessible, like other code growing memory.
+    pub(crate) fn skip_pages(&self, pages: usize) {
+        assert!(self.used_pages.get() + pages <= RESERVATION_PAGES);
+        self.used_pages.set(self.used_pages.get() + pages);
+    }
+
+    /// Offset of `ptr` from the start of the reservation: the address it would have in a wasm32 memory.
+    pub(crate) fn offset(&self, ptr: *const u8) -> usize {
+        let offset = (ptr as usize).wrapping_sub(self.base);
+        assert!(
+            offset < RESERVATION_PAGES * PAGE_SIZE,
+            "{ptr:?} is outside the reservation"
+        );
+        offset
+    }
+
+    /// The address `offset` bytes from the start of the reservation.
+    pub(crate) fn address(&self, offset: usize) -> *mut u8 {
+        assert!(offset < RESERVATION_PAGES * PAGE_SIZE);
+        (self.base + offset) as *mut u8
+    }
+}
+
+impl MemoryGrower for Reservation {
+    fn memory_grow(&self, delta: PageCount) -> PageCount {
+        let used = self.used_pages.get();
+        if used + delta.0 > RESERVATION_PAGES {
+            return ERROR_PAGE_COUNT;
+        }
+        let start = self.base + used * PAGE_SIZE;
+        if delta.0 != 0 {
+            let result = unsafe {
+                libc::mprotect(
+                    start as *mut libc::c_void,
+                    delta.size_in_bytes().unwrap(),
+                    libc::PROT_READ | libc::PROT_WRITE,
+                )
+            };
+            assert_eq!(result, 0, "failed to commit reserved memory");
+        }
+        self.used_pages.set(used + delta.0);
+        PageCount(start / PAGE_SIZE)
+    }
+
+    /// Anonymous mappings start zeroed.
+    fn grows_zeroed(&self) -> bool {
+        true
+    }
+}
+
+impl TestGrower for Reservation {
+    fn base(&self) -> usize {
+        self.base
+    }
+
+    fn used_pages(&self) -> usize {
+        self.used_pages.get()
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.mapping, Self::MAPPING_SIZE) };
+    }
+}
@@ -0,0 +1,962 @@
+//! Helpers for testing allocators, for use in downstream crate tests (with the `testing` feature).
+//!
+//! These are what lol_alloc's own tests use:
+//! - [MockGrower] provides memory from an ordinary heap allocation, so allocators can be tested on the host.
+//! - [conformance] and [excess_conformance] are checks every allocator should pass.
+//! - [AllocationTracker] makes allocations, checking they are aligned and never overlap, and that nothing writes to them.
+//! - [assert_free_list] and [run_alloc_scenario] check the exact state of a [FreeListAllocator]'s free list.
+//! - [grower_conformance] (or the [check_grower!](crate::check_grower) test it defines) checks a custom [MemoryGrower] follows the contract allocators rely on.
+//!
+//! They panic when a check fails, and are not optimized: they are not intended for use outside tests.
+
+use crate::{
+    free_list_allocator::full_size, AllocWithExcess, FreeListAllocator, MemoryGrower, PageCount,
+    ERROR_PAGE_COUNT, PAGE_SIZE,
+};
+use alloc::{collections::BTreeMap, vec, vec::Vec};
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    cell::UnsafeCell,
+};
+use spin::Mutex;
+
+#[derive(Clone, Copy)]
+#[repr(C, align(65536))] // align does not appear to work with the PAGE_SIZE constant
+struct Page([u8; PAGE_SIZE]);
+
+/// Grower which hands out pages from a fixed size array it allocates (from the global allocator) when created.
+///
+/// Use it in place of the default grower to test allocators on the host, for example with `FreeListAllocator::new_in(MockGrower::new())`.
+/// Pages are handed out in order, so all memory grown is one contiguous region starting at [MockGrower::base].
+pub struct MockGrower {
+    pages: Vec<UnsafeCell<Page>>,
+    used_pages: Mutex<usize>,
+}
+
+/// The pages are only ever accessed through the pointers handed out by `memory_grow`.
+unsafe impl Sync for MockGrower {}
+
+impl MockGrower {
+    /// A grower with 1000 pages (about 64 MiB).
+    pub fn new() -> Self {
+        Self::with_pages(1000)
+    }
+
+    /// A grower which runs out of memory after `pages` pages.
+    pub fn with_pages(pages: usize) -> Self {
+        let mut memory = Vec::with_capacity(pages);
+        memory.resize_with(pages, || UnsafeCell::new(Page([0; PAGE_SIZE])));
+        MockGrower {
+            pages: memory,
+            used_pages: Mutex::new(0),
+        }
+    }
+
+    /// Address of the first page.
+    pub fn base(&self) -> usize {
+        self.pages.as_ptr() as usize
+    }
+
+    /// Number of pages which have been grown.
+    pub fn used_pages(&self) -> usize {
+        *self.used_pages.lock()
+    }
+
+    /// Pretends `pages` pages have been grown, so tests can free memory into an allocator which never grew it.
+    #[cfg(test)]
+    pub(crate) fn set_used_pages(&self, pages: usize) {
+        assert!(pages <= self.pages.len());
+        *self.used_pages.lock() = pages;
+    }
+}
+
+impl Default for MockGrower {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryGrower for MockGrower {
+    fn memory_grow(&self, delta: PageCount) -> PageCount {
+        let mut used_pages = self.used_pages.lock();
+        if used_pages
+            .checked_add(delta.0)
+            .is_none_or(|total| total > self.pages.len())
+        {
+            return ERROR_PAGE_COUNT;
+        }
+        let first = self.base() + *used_pages * PAGE_SIZE;
+        *used_pages += delta.0;
+        PageCount(first / PAGE_SIZE)
+    }
+
+    fn grows_zeroed(&self) -> bool {
+        true
+    }
+}
+
+/// A grower which grows into a single region of memory, so tests can describe memory by offsets into it.
+pub trait TestGrower {
+    /// Address of the start of the region.
+    fn base(&self) -> usize;
+    /// Number of pages of the region which have been grown.
+    fn used_pages(&self) -> usize;
+}
+
+impl TestGrower for MockGrower {
+    fn base(&self) -> usize {
+        MockGrower::base(self)
+    }
+
+    fn used_pages(&self) -> usize {
+        MockGrower::used_pages(self)
+    }
+}
+
+/// An allocation made by an [AllocationTracker].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Allocation {
+    pub ptr: *mut u8,
+    pub layout: Layout,
+    /// What the first and last bytes were set to.
+    stamp: u8,
+}
+
+/// Allocates from an allocator, keeping track of the live allocations, and checking them:
+/// allocations must be aligned, and must not overlap each other.
+///
+/// The first and last bytes of each allocation are set to a value derived from its address,
+/// and checked when it is freed (and by [AllocationTracker::check]), to catch the allocator writing to live allocations.
+pub struct AllocationTracker<'a, A> {
+    allocator: &'a A,
+    /// Live allocations, by address.
+    live: BTreeMap<usize, Allocation>,
+    /// Live zero sized allocations, which can share an address with anything, so are kept separately.
+    zero_sized: Vec<Allocation>,
+}
+
+impl<'a, A: GlobalAlloc> AllocationTracker<'a, A> {
+    pub fn new(allocator: &'a A) -> Self {
+        AllocationTracker {
+            allocator,
+            live: BTreeMap::new(),
+            zero_sized: vec![],
+        }
+    }
+
+    /// Allocates `layout`, panicking if the allocation fails, is misaligned or overlaps a live allocation.
+    pub fn alloc(&mut self, layout: Layout) -> Allocation {
+        let ptr = unsafe { self.allocator.alloc(layout) };
+        self.insert(ptr, layout)
+    }
+
+    /// Like [AllocationTracker::alloc], but allocates with [AllocWithExcess::alloc_with_excess],
+    /// and tracks the whole usable size (which is then what the allocation is freed with).
+    pub fn alloc_with_excess(&mut self, layout: Layout) -> Allocation
+    where
+        A: AllocWithExcess,
+    {
+        let (ptr, usable) = unsafe { self.allocator.alloc_with_excess(layout) };
+        assert!(
+            usable >= layout.size(),
+            "usable size is smaller than requested"
+        );
+        self.insert(
+            ptr,
+            Layout::from_size_align(usable, layout.align()).unwrap(),
+        )
+    }
+
+    fn insert(&mut self, ptr: *mut u8, layout: Layout) -> Allocation {
+        assert!(!ptr.is_null(), "allocation of {layout:?} failed");
+        assert_eq!(
+            ptr as usize % layout.align(),
+            0,
+            "{ptr:?} is misaligned for {layout:?}"
+        );
+        let start = ptr as usize;
+        // Live allocations do not overlap, so only the last one starting before this one ends can overlap it.
+        if let Some((&previous, allocation)) = self.live.range(..start + layout.size()).next_back()
+        {
+            assert!(
+                layout.size() == 0 || previous + allocation.layout.size() <= start,
+                "{layout:?} at {ptr:?} overlaps {allocation:?}"
+            );
+        }
+        let allocation = Allocation {
+            ptr,
+            layout,
+            stamp: (start >> 3) as u8 ^ 0x5A,
+        };
+        if layout.size() == 0 {
+            self.zero_sized.push(allocation);
+        } else {
+            unsafe {
+                ptr.write(allocation.stamp);
+                ptr.add(layout.size() - 1).write(allocation.stamp);
+            }
+            self.live.insert(start, allocation);
+        }
+        allocation
+    }
+
+    /// The live non zero sized allocations, in address order.
+    pub fn live(&self) -> impl Iterator<Item = &Allocation> {
+        self.live.values()
+    }
+
+    /// Number of live allocations.
+    pub fn len(&self) -> usize {
+        self.live.len() + self.zero_sized.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Checks no live allocation has been written to.
+    pub fn check(&self) {
+        for allocation in self.live.values() {
+            Self::check_stamp(allocation);
+        }
+    }
+
+    fn check_stamp(allocation: &Allocation) {
+        let size = allocation.layout.size();
+        unsafe {
+            assert_eq!(
+                *allocation.ptr, allocation.stamp,
+                "{allocation:?} was overwritten"
+            );
+            assert_eq!(
+                *allocation.ptr.add(size - 1),
+                allocation.stamp,
+                "{allocation:?} was overwritten"
+            );
+        }
+    }
+
+    /// Frees `allocation`, after checking it has not been written to.
+    pub fn free(&mut self, allocation: Allocation) {
+        if allocation.layout.size() == 0 {
+            let index = self.zero_sized.iter().position(|&a| a == allocation);
+            self.zero_sized
+                .swap_remove(index.unwrap_or_else(|| panic!("{allocation:?} is not live")));
+        } else {
+            let tracked = self.live.remove(&(allocation.ptr as usize));
+            assert_eq!(tracked, Some(allocation), "{allocation:?} is not live");
+            Self::check_stamp(&allocation);
+        }
+        unsafe { self.allocator.dealloc(allocation.ptr, allocation.layout) };
+    }
+
+    /// Frees all live allocations.
+    pub fn free_all(&mut self) {
+        for (_, allocation) in core::mem::take(&mut self.live) {
+            Self::check_stamp(&allocation);
+            unsafe { self.allocator.dealloc(allocation.ptr, allocation.layout) };
+        }
+        for allocation in core::mem::take(&mut self.zero_sized) {
+            unsafe { self.allocator.dealloc(allocation.ptr, allocation.layout) };
+        }
+    }
+}
+
+/// The system allocator, counting live allocations.
+///
+/// A reference to it is an allocator too, so wrapping allocators which own their inner allocator can be checked after they are dropped.
+#[cfg(test)]
+pub(crate) struct Counted {
+    live: crate::atomic::AtomicUsize,
+}
+
+#[cfg(test)]
+impl Counted {
+    pub(crate) const fn new() -> Self {
+        Counted {
+            live: crate::atomic::AtomicUsize::new(0),
+        }
+    }
+
+    pub(crate) fn live(&self) -> usize {
+        self.live.load(crate::atomic::Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+unsafe impl GlobalAlloc for Counted {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.live.fetch_add(1, crate::atomic::Ordering::Relaxed);
+        std::alloc::System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.live.fetch_sub(1, crate::atomic::Ordering::Relaxed);
+        std::alloc::System.dealloc(ptr, layout);
+    }
+}
+
+#[cfg(test)]
+unsafe impl GlobalAlloc for &Counted {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        (**self).alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        (**self).dealloc(ptr, layout)
+    }
+}
+
+/// Size of the free list block used by an allocation for `layout`, including the `dealloc-size-check` prefix if there is one.
+#[cfg(test)]
+pub(crate) fn block_size(layout: Layout) -> usize {
+    full_size(crate::size_check::inner_layout(layout).unwrap())
+}
+
+/// A layout aligned to `align` whose allocation uses a free list block of exactly `size` bytes (a multiple of [NODE_SIZE](crate::free_list_allocator::NODE_SIZE)),
+/// leaving room for the `dealloc-size-check` prefix if there is one.
+#[cfg(test)]
+pub(crate) fn block_layout(size: usize, align: usize) -> Layout {
+    let layout =
+        Layout::from_size_align(size - crate::size_check::prefix_space(align), align).unwrap();
+    assert_eq!(block_size(layout), size);
+    layout
+}
+
+/// Start of the free list block used by the allocation at `ptr` for `layout`, which is before the `dealloc-size-check` prefix if there is one.
+#[cfg(test)]
+pub(crate) fn block_start(ptr: *mut u8, layout: Layout) -> *mut u8 {
+    ptr.wrapping_sub(crate::size_check::prefix_space(layout.align()))
+}
+
+/// Fills the first two pages a fresh [MockGrower] grows (at `base`) with 0xFF, as if it had not zeroed them,
+/// then allocates them zeroed from `allocator` (a free list which gets its memory from that grower), returning how many of the bytes are zero.
+///
+/// Allocators which skip zeroing memory never used since it was grown only zero the free list node written when it was grown,
+/// so this counts the bytes which were zeroed.
+#[cfg(test)]
+pub(crate) unsafe fn fresh_zeroed_bytes<A: GlobalAlloc>(allocator: &A, base: *mut u8) -> usize {
+    base.write_bytes(0xFF, PAGE_SIZE * 2);
+    let layout = block_layout(PAGE_SIZE * 2, 8);
+    let ptr = allocator.alloc_zeroed(layout);
+    assert_eq!(block_start(ptr, layout), base);
+    let zeroed = core::slice::from_raw_parts(ptr, layout.size())
+        .iter()
+        .filter(|&&byte| byte == 0)
+        .count();
+    allocator.dealloc(ptr, layout);
+    zeroed
+}
+
+/// Sizes and alignments [conformance] and [excess_conformance] allocate.
+const CONFORMANCE_SIZES: [usize; 7] = [1, 7, 16, 100, 4096, PAGE_SIZE, PAGE_SIZE + 1];
+const CONFORMANCE_ALIGNS: [usize; 4] = [1, 8, 64, 4096];
+
+/// Shared checks every allocator should pass.
+///
+/// Makes allocations of a range of sizes and alignments,
+/// checks they are aligned and do not overlap, then frees them.
+pub fn conformance<A: GlobalAlloc>(allocator: &A) {
+    let mut tracker = AllocationTracker::new(allocator);
+    for size in CONFORMANCE_SIZES {
+        for align in CONFORMANCE_ALIGNS {
+            tracker.alloc(Layout::from_size_align(size, align).unwrap());
+        }
+    }
+    fill_check_and_free(tracker);
+}
+
+/// Checks the usable sizes reported by an [AllocWithExcess] are real.
+///
+/// Like [conformance], but uses all of each allocation's usable size, freeing it with that size.
+pub fn excess_conformance<A: AllocWithExcess>(allocator: &A) {
+    let mut tracker = AllocationTracker::new(allocator);
+    for size in CONFORMANCE_SIZES {
+        for align in CONFORMANCE_ALIGNS {
+            tracker.alloc_with_excess(Layout::from_size_align(size, align).unwrap());
+        }
+    }
+    fill_check_and_free(tracker);
+}
+
+fn fill_check_and_free<A: GlobalAlloc>(mut tracker: AllocationTracker<A>) {
+    // Fill all of each allocation (not just the stamped ends), in case the middle overlaps memory the allocator uses.
+    for allocation in tracker.live() {
+        unsafe {
+            allocation
+                .ptr
+                .write_bytes(allocation.stamp, allocation.layout.size())
+        };
+    }
+    for allocation in tracker.live() {
+        let bytes =
+            unsafe { core::slice::from_raw_parts(allocation.ptr, allocation.layout.size()) };
+        assert!(
+            bytes.iter().all(|&b| b == allocation.stamp),
+            "{allocation:?} was overwritten"
+        );
+    }
+    tracker.free_all();
+}
+
+/// Number of grows [grower_conformance] makes after its scripted ones, with pseudo-random sizes.
+const RANDOM_GROWS: usize = 24;
+
+/// Checks `grower` follows the [MemoryGrower] contract, then runs a small [FreeListAllocator] workload on it.
+///
+/// Growers hand out pages allocators write their data structures into, so a grower breaking the contract
+/// shows up as baffling heap corruption. This catches it directly, checking scripted and pseudo-random grows (including partial and zero page ones):
+/// - Growing zero pages succeeds, returning the current size, and [MemoryGrower::current_pages] agrees.
+/// - Each grant starts at or after the end of the previous one, fits in the address space, and is within [MemoryGrower::current_pages].
+/// - Granted pages are real memory, and no grant hands out memory from an earlier one (the first and last bytes of each are written, and checked at the end).
+///   If [MemoryGrower::grows_zeroed], those bytes must be zero before they are written.
+/// - Requests which can't be granted return [ERROR_PAGE_COUNT] and grant nothing (the current size is unchanged), and partial grants grant at most what was asked.
+///
+/// The workload runs first, so growers with a small limit can still serve it. It needs about one page, and is skipped if the grower can't grow at all.
+/// Nothing else may grow memory while this runs. See [check_grower!](crate::check_grower) for making a test of it.
+pub fn grower_conformance<G: MemoryGrower>(grower: &G) {
+    grower_workload(grower);
+
+    let current = grower.current_pages();
+    assert_eq!(
+        grower.memory_grow(PageCount(0)),
+        current,
+        "growing zero pages must return the current size"
+    );
+    let mut regions: Vec<(PageCount, usize)> = Vec::with_capacity(8 + RANDOM_GROWS);
+    let mut check_grant = |first: PageCount, pages: usize| {
+        assert_ne!(first, ERROR_PAGE_COUNT);
+        let end = first
+            .0
+            .checked_add(pages)
+            .filter(|end| end.checked_mul(PAGE_SIZE).is_some())
+            .unwrap_or_else(|| {
+                panic!("{pages} pages at {first:?} do not fit in the address space")
+            });
+        let previous_end = regions.last().map_or(0, |&(start, pages)| start.0 + pages);
+        assert!(
+            first.0 >= previous_end,
+            "{pages} pages granted at {first:?}, before the end of the previous grant (page {previous_end})"
+        );
+        assert!(
+            grower.current_pages().0 >= end,
+            "{pages} pages granted at {first:?}, beyond the current size {:?}",
+            grower.current_pages()
+        );
+        if pages > 0 {
+            let stamp = regions.len() as u8 ^ 0xA5;
+            unsafe {
+                if grower.grows_zeroed() {
+                    assert!(
+                        *(first.size_in_bytes().unwrap() as *const u8) == 0
+                            && *((end * PAGE_SIZE - 1) as *const u8) == 0,
+                        "{pages} pages granted at {first:?} are not zeroed"
+                    );
+                }
+                (first.size_in_bytes().unwrap() as *mut u8).write(stamp);
+                ((end * PAGE_SIZE - 1) as *mut u8).write(stamp);
+            }
+            regions.push((first, pages));
+        }
+    };
+    let mut check_grow = |pages: usize, partial: bool| {
+        let before = grower.current_pages();
+        let (first, granted) = if partial {
+            let (first, granted) = grower.memory_grow_partial(PageCount(pages));
+            assert!(granted.0 <= pages, "granted {granted:?} of {pages} pages");
+            (first, granted.0)
+        } else {
+            let first = grower.memory_grow(PageCount(pages));
+            (first, if first == ERROR_PAGE_COUNT { 0 } else { pages })
+        };
+        if granted == 0 && (partial || first == ERROR_PAGE_COUNT) {
+            assert_eq!(
+                grower.current_pages(),
+                before,
+                "failing to grow {pages} pages changed the current size"
+            );
+        } else {
+            check_grant(first, granted);
+        }
+    };
+    for (pages, partial) in [
+        (1, false),
+        (0, false),
+        (2, false),
+        (1, true),
+        (3, false),
+        (0, true),
+    ] {
+        check_grow(pages, partial);
+    }
+    // xorshift, for reproducible sizes.
+    let mut state: u32 = 0x9E37_79B9;
+    for _ in 0..RANDOM_GROWS {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        check_grow(state as usize % 4, state & 0x100 != 0);
+    }
+    let before = grower.current_pages();
+    assert_eq!(
+        grower.memory_grow(PageCount(usize::MAX)),
+        ERROR_PAGE_COUNT,
+        "growing usize::MAX pages must fail"
+    );
+    assert_eq!(grower.current_pages(), before);
+
+    for (i, &(first, pages)) in regions.iter().enumerate() {
+        let stamp = i as u8 ^ 0xA5;
+        let start = first.size_in_bytes().unwrap() as *const u8;
+        unsafe {
+            assert_eq!(
+                *start, stamp,
+                "pages granted at {first:?} were granted again"
+            );
+            assert_eq!(
+                *start.add(pages * PAGE_SIZE - 1),
+                stamp,
+                "pages granted at {first:?} were granted again"
+            );
+        }
+    }
+}
+
+/// Allocates and frees a mix of sizes and alignments from a [FreeListAllocator] using `grower`, stopping early if it runs out of memory.
+fn grower_workload<G: MemoryGrower>(grower: &G) {
+    let allocator = FreeListAllocator::new_in(grower);
+    let mut tracker = AllocationTracker::new(&allocator);
+    for i in 0..200 {
+        let layout = Layout::from_size_align(i * 37 % 1000 + 1, 1 << (i % 5)).unwrap();
+        let ptr = unsafe { allocator.alloc(layout) };
+        if ptr.is_null() {
+            break;
+        }
+        tracker.insert(ptr, layout);
+        if i % 3 == 0 {
+            let oldest = *tracker.live().next().unwrap();
+            tracker.free(oldest);
+        }
+    }
+    tracker.check();
+    tracker.free_all();
+    allocator.validate_heap();
+}
+
+/// Defines a test named `$name` checking the grower `$grower` with [grower_conformance](crate::testing::grower_conformance).
+///
+/// ```ignore
+/// lol_alloc::check_grower!(my_grower_conformance, MyGrower::new());
+/// ```
+#[macro_export]
+macro_rules! check_grower {
+    ($name:ident, $grower:expr) => {
+        #[test]
+        fn $name() {
+            $crate::testing::grower_conformance(&$grower);
+        }
+    };
+}
+
+/// A block in a [FreeListAllocator]'s free list.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FreeBlock {
+    /// Offset from [TestGrower::base].
+    pub offset: usize,
+    pub size: usize,
+}
+
+/// The blocks in the free list of `allocator`, in the order it keeps them (descending address), after validating the heap.
+///
+/// # Panics
+///
+/// If the free list is corrupt, or has blocks outside the memory grown.
+pub fn free_blocks<
+    G: TestGrower,
+    const SENTINEL: usize,
+    const SKIP_WASTEFUL: bool,
+    const STATIC_CHUNK: usize,
+>(
+    allocator: &FreeListAllocator<G, SENTINEL, SKIP_WASTEFUL, STATIC_CHUNK>,
+) -> Vec<FreeBlock> {
+    allocator.validate_heap();
+    let base = allocator.grower.base();
+    let grown = allocator.grower.used_pages() * PAGE_SIZE;
+    allocator
+        .free_list_blocks()
+        .into_iter()
+        .map(|(address, size)| {
+            assert!(
+                address >= base && address - base + size <= grown,
+                "free block at {address:#x} is outside the memory grown"
+            );
+            FreeBlock {
+                offset: address - base,
+                size,
+            }
+        })
+        .collect()
+}
+
+/// Asserts the free list of `allocator` is exactly `expected` (see [free_blocks]).
+#[track_caller]
+pub fn assert_free_list<
+    G: TestGrower,
+    const SENTINEL: usize,
+    const SKIP_WASTEFUL: bool,
+    const STATIC_CHUNK: usize,
+>(
+    allocator: &FreeListAllocator<G, SENTINEL, SKIP_WASTEFUL, STATIC_CHUNK>,
+    expected: &[FreeBlock],
+) {
+    assert_eq!(free_blocks(allocator), expected);
+}
+
+/// Access to an allocator's memory, for checking the expected states in an [AllocScenario].
+pub trait ScenarioAllocator: GlobalAlloc {
+    /// Address of the start of the allocator's memory, which offsets in scenarios are relative to.
+    fn base(&self) -> usize;
+    /// Number of pages of memory grown.
+    fn used_pages(&self) -> usize;
+    /// The `(offset, size)` of each free block, in the order the allocator keeps them.
+    fn free_list(&self) -> Vec<(usize, usize)>;
+
+    /// Frees `size` bytes at `ptr` which were never allocated, for [AllocScenario::free_range].
+    ///
+    /// # Safety
+    ///
+    /// The memory must not be in use, and must be within the allocator's memory.
+    unsafe fn free_range(&self, ptr: *mut u8, size: usize) {
+        self.dealloc(ptr, Layout::from_size_align(size, 1).unwrap());
+    }
+}
+
+impl<G: TestGrower + MemoryGrower, const SENTINEL: usize, const SKIP_WASTEFUL: bool>
+    ScenarioAllocator for FreeListAllocator<G, SENTINEL, SKIP_WASTEFUL>
+{
+    fn base(&self) -> usize {
+        self.grower.base()
+    }
+
+    fn used_pages(&self) -> usize {
+        self.grower.used_pages()
+    }
+
+    fn free_list(&self) -> Vec<(usize, usize)> {
+        free_blocks(self)
+            .into_iter()
+            .map(|block| (block.offset, block.size))
+            .collect()
+    }
+
+    /// Frees directly into the free list, since `dealloc` would check for a `dealloc-size-check` prefix.
+    unsafe fn free_range(&self, ptr: *mut u8, size: usize) {
+        self.free(ptr, full_size(Layout::from_size_align(size, 1).unwrap()));
+    }
+}
+
+enum Step {
+    Alloc(Layout),
+    Free(usize),
+    FreeRange { offset: usize, size: usize },
+    ExpectFreeList(Vec<(usize, usize)>),
+    ExpectPages(usize),
+}
+
+/// A scripted sequence of allocations and frees, and the states expected after them,
+/// which can be run against any [ScenarioAllocator] by [run_alloc_scenario].
+#[derive(Default)]
+pub struct AllocScenario {
+    steps: Vec<Step>,
+    allocations: usize,
+}
+
+impl AllocScenario {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allocates `size` bytes aligned to `align`, returning an id for [Self::free].
+    pub fn alloc(&mut self, size: usize, align: usize) -> usize {
+        self.steps
+            .push(Step::Alloc(Layout::from_size_align(size, align).unwrap()));
+        self.allocations += 1;
+        self.allocations - 1
+    }
+
+    /// Frees the allocation `id` returned by [Self::alloc].
+    pub fn free(&mut self, id: usize) {
+        self.steps.push(Step::Free(id));
+    }
+
+    /// Frees `size` bytes at `offset` which were never allocated, to set up free lists directly.
+    pub fn free_range(&mut self, offset: usize, size: usize) {
+        self.steps.push(Step::FreeRange { offset, size });
+    }
+
+    /// Expects the free list to be exactly `blocks`, as `(offset, size)` pairs.
+    pub fn expect_free_list(&mut self, blocks: Vec<(usize, usize)>) {
+        self.steps.push(Step::ExpectFreeList(blocks));
+    }
+
+    /// Expects `pages` pages to have been grown.
+    pub fn expect_pages(&mut self, pages: usize) {
+        self.steps.push(Step::ExpectPages(pages));
+    }
+}
+
+/// Runs `scenario` on `allocator`, panicking (with the index of the step) if any expectation is not met.
+pub fn run_alloc_scenario<A: ScenarioAllocator>(allocator: &A, scenario: &AllocScenario) {
+    let mut allocations: Vec<Option<(*mut u8, Layout)>> = vec![];
+    for (step, operation) in scenario.steps.iter().enumerate() {
+        match *operation {
+            Step::Alloc(layout) => {
+                let ptr = unsafe { allocator.alloc(layout) };
+                assert!(!ptr.is_null(), "step {step}: allocation failed");
+                assert_eq!(ptr as usize % layout.align(), 0, "step {step}: misaligned");
+                allocations.push(Some((ptr, layout)));
+            }
+            Step::Free(id) => {
+                let (ptr, layout) = allocations[id]
+                    .take()
+                    .unwrap_or_else(|| panic!("step {step}: {id} already freed"));
+                unsafe { allocator.dealloc(ptr, layout) };
+            }
+            Step::FreeRange { offset, size } => unsafe {
+                allocator.free_range((allocator.base() + offset) as *mut u8, size)
+            },
+            Step::ExpectFreeList(ref blocks) => {
+                assert_eq!(&allocator.free_list(), blocks, "step {step}: free list");
+            }
+            Step::ExpectPages(pages) => {
+                assert_eq!(allocator.used_pages(), pages, "step {step}: pages");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{assert_free_list, grower_conformance, AllocationTracker, FreeBlock, MockGrower};
+    use crate::{FreeListAllocator, MemoryGrower, PageCount, ERROR_PAGE_COUNT, PAGE_SIZE};
+    use core::{
+        alloc::{GlobalAlloc, Layout},
+        cell::Cell,
+    };
+
+    /// Checks every `GlobalAlloc` method of `allocator` rejects zero sizes (with debug assertions, which callers must check are enabled):
+    /// allocating (and allocating zeroed) and freeing zero bytes, and reallocating to and from zero bytes.
+    #[cfg(debug_assertions)]
+    fn assert_rejects_zero_sizes(name: &str, allocator: &dyn GlobalAlloc) {
+        use crate::safe_heap::dangling;
+        use std::panic::{catch_unwind, AssertUnwindSafe};
+
+        /// If `f` panics.
+        fn rejects<R>(f: impl FnOnce() -> R) -> bool {
+            catch_unwind(AssertUnwindSafe(f)).is_err()
+        }
+
+        let zero = Layout::from_size_align(0, 8).unwrap();
+        let layout = Layout::from_size_align(16, 8).unwrap();
+        let zero_ptr = dangling(zero).as_ptr();
+        unsafe {
+            assert!(rejects(|| allocator.alloc(zero)), "alloc: {name}");
+            assert!(
+                rejects(|| allocator.alloc_zeroed(zero)),
+                "alloc_zeroed: {name}"
+            );
+            assert!(
+                rejects(|| allocator.dealloc(zero_ptr, zero)),
+                "dealloc: {name}"
+            );
+            assert!(
+                rejects(|| allocator.realloc(zero_ptr, zero, 16)),
+                "realloc from zero: {name}"
+            );
+            let ptr = allocator.alloc(layout);
+            assert!(!ptr.is_null());
+            // Rejected before anything is freed, so `ptr` is still live.
+            assert!(
+                rejects(|| allocator.realloc(ptr, layout, 0)),
+                "realloc to zero: {name}"
+            );
+            allocator.dealloc(ptr, layout);
+        }
+    }
+
+    /// `GlobalAlloc` forbids zero sizes, which debug builds check (with `nonzero_size_assert!`) in every allocator.
+    #[cfg(debug_assertions)]
+    #[test]
+    fn allocators_reject_zero_sizes() {
+        use crate::*;
+        use alloc::{boxed::Box, vec, vec::Vec};
+
+        fn free_list() -> FreeListAllocator<MockGrower> {
+            FreeListAllocator::new_in(MockGrower::new())
+        }
+
+        // Only pushed to on some targets and with some features.
+        #[allow(unused_mut)]
+        let mut allocators: Vec<(&str, Box<dyn GlobalAlloc>)> = vec![
+            ("FreeListAllocator", Box::new(free_list())),
+            (
+                "SizeSkipFreeListAllocator",
+                Box::new(SizeSkipFreeListAllocator::new_in(MockGrower::new())),
+            ),
+            (
+                "RcuArenaAllocator",
+                Box::new(RcuArenaAllocator::new_in(MockGrower::new())),
+            ),
+            (
+                "LeakingPageAllocator",
+                Box::new(LeakingPageAllocator::new_in(MockGrower::new())),
+            ),
+            (
+                "LeakingAllocator",
+                Box::new(LeakingAllocator::new_in(MockGrower::new())),
+            ),
+            (
+                "LockedAllocator",
+                Box::new(LockedAllocator::new(free_list())),
+            ),
+            (
+                "LockedFreeListAllocator",
+                Box::new(LockedFreeListAllocator::new_in(MockGrower::new())),
+            ),
+            (
+                "AllocRef",
+                Box::new(Box::leak(Box::new(LockedAllocator::new(free_list()))).alloc_ref()),
+            ),
+            (
+                "AssumeSingleThreaded",
+                Box::new(unsafe { AssumeSingleThreaded::new(free_list()) }),
+            ),
+            (
+                "BudgetAllocator",
+                Box::new(BudgetAllocator::new(free_list(), 1000)),
+            ),
+            (
+                "CacheIsolatedAllocator",
+                Box::new(CacheIsolatedAllocator::<_>::new(free_list())),
+            ),
+            (
+                "DeferredFreeAllocator",
+                Box::new(DeferredFreeAllocator::<_, 4>::new(free_list())),
+            ),
+            (
+                "MpscFreeAllocator",
+                Box::new(unsafe { MpscFreeAllocator::new(free_list()) }),
+            ),
+            (
+                "NaturallyAlignedAllocator",
+                Box::new(NaturallyAlignedAllocator::new(free_list())),
+            ),
+            (
+                "PhaseSwitch",
+                Box::new(PhaseSwitch::new(free_list(), free_list())),
+            ),
+            (
+                "RecoveringAllocator",
+                Box::new(RecoveringAllocator::<_, 1>::new(free_list())),
+            ),
+        ];
+        #[cfg(unix)]
+        allocators.push((
+            "ExternAllocator",
+            Box::new(unsafe { ExternAllocator::new(libc::malloc, libc::free) }),
+        ));
+        #[cfg(feature = "std")]
+        allocators.push(("HostAllocator", Box::new(HostAllocator)));
+        #[cfg(feature = "pointer-registry")]
+        allocators.push((
+            "RegisteredAllocator",
+            Box::new(RegisteredAllocator::new(1, free_list())),
+        ));
+        #[cfg(feature = "randomized")]
+        allocators.push((
+            "RandomizedFreeListAllocator",
+            Box::new(RandomizedFreeListAllocator::new_in(
+                MockGrower::new(),
+                rand_pcg::Pcg32::new(1, 0),
+            )),
+        ));
+        for (name, allocator) in &allocators {
+            assert_rejects_zero_sizes(name, &**allocator);
+        }
+    }
+
+    #[test]
+    fn mock_grower_runs_out() {
+        let grower = MockGrower::with_pages(2);
+        assert_eq!(
+            grower.memory_grow(PageCount(1)).size_in_bytes().unwrap(),
+            grower.base()
+        );
+        assert_eq!(
+            grower.memory_grow(PageCount(1)).size_in_bytes().unwrap(),
+            grower.base() + PAGE_SIZE
+        );
+        assert_eq!(grower.memory_grow(PageCount(1)), ERROR_PAGE_COUNT);
+        assert_eq!(grower.used_pages(), 2);
+    }
+
+    #[test]
+    fn free_list_after_free() {
+        let allocator = FreeListAllocator::new_in(MockGrower::new());
+        let mut tracker = AllocationTracker::new(&allocator);
+        let allocation = tracker.alloc(Layout::from_size_align(PAGE_SIZE, 1).unwrap());
+        tracker.free(allocation);
+        assert_free_list(
+            &allocator,
+            &[FreeBlock {
+                offset: 0,
+                size: allocator.grower.used_pages() * PAGE_SIZE,
+            }],
+        );
+    }
+
+    /// Broken allocator which hands out the same memory every time.
+    struct Repeating(Cell<[u64; 4]>);
+
+    unsafe impl GlobalAlloc for Repeating {
+        unsafe fn alloc(&self, _layout: Layout) -> *mut u8 {
+            self.0.as_ptr() as *mut u8
+        }
+
+        unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {}
+    }
+
+    crate::check_grower!(mock_grower_conformance, MockGrower::with_pages(64));
+    crate::check_grower!(no_grower_conformance, crate::NoGrower);
+
+    /// Broken grower which fails to grow zero pages, instead of returning the current size.
+    struct ZeroFails(MockGrower);
+
+    impl MemoryGrower for ZeroFails {
+        fn memory_grow(&self, delta: PageCount) -> PageCount {
+            if delta.0 == 0 {
+                return ERROR_PAGE_COUNT;
+            }
+            self.0.memory_grow(delta)
+        }
+
+        fn current_pages(&self) -> PageCount {
+            self.0.current_pages()
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "growing zero pages must return the current size")]
+    fn grower_conformance_catches_zero_failing() {
+        grower_conformance(&ZeroFails(MockGrower::with_pages(64)));
+    }
+
+    #[test]
+    #[should_panic(expected = "overlaps")]
+    fn tracker_catches_overlap() {
+        let allocator = Repeating(Cell::new([0; 4]));
+        let mut tracker = AllocationTracker::new(&allocator);
+        tracker.alloc(Layout::new::<u64>());
+        tracker.alloc(Layout::new::<u32>());
+    }
+}
@@ -1,4 +1,7 @@
-use crate::{DefaultGrower, MemoryGrower, PageCount, ERROR_PAGE_COUNT, PAGE_SIZE};
+use crate::{
+    zero::zeroed, AllocError, AllocWithExcess, AllocatorStats, DefaultGrower, MemoryGrower,
+    NoGrower, PageCount, Stats, ERROR_PAGE_COUNT, PAGE_SIZE,
+};
 use core::{
     alloc::{GlobalAlloc, Layout},
     cell::UnsafeCell,
@@ -16,30 +19,102 @@ unsafe impl GlobalAlloc for FailAllocator {
     unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {}
 }
 
+impl AllocWithExcess for FailAllocator {
+    unsafe fn alloc_with_excess(&self, _layout: Layout) -> (*mut u8, usize) {
+        (null_mut(), 0)
+    }
+}
+
+impl AllocatorStats for FailAllocator {
+    fn stats(&self) -> Stats {
+        Stats {
+            live_bytes: Some(0),
+            live_allocations: Some(0),
+            free_bytes: Some(0),
+            free_blocks: Some(0),
+            heap_bytes: Some(0),
+            peak_bytes: Some(0),
+            failed_allocations: None,
+        }
+    }
+}
+
 /// Allocator that allocates whole pages for each allocation.
 /// Very wasteful for small allocations.
 /// Does not free or reuse memory.
+pub struct LeakingPageAllocator<T = DefaultGrower> {
+    grower: T,
+}
+
+/// Allows `LeakingPageAllocator` to be used as a value, like it could before it took a grower.
 #[cfg(target_arch = "wasm32")]
-pub struct LeakingPageAllocator;
+#[allow(non_upper_case_globals)]
+pub const LeakingPageAllocator: LeakingPageAllocator = LeakingPageAllocator::new();
 
 #[cfg(target_arch = "wasm32")]
-unsafe impl GlobalAlloc for LeakingPageAllocator {
+impl LeakingPageAllocator<DefaultGrower> {
+    pub const fn new() -> Self {
+        LeakingPageAllocator::new_in(DefaultGrower)
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Default for LeakingPageAllocator<DefaultGrower> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> LeakingPageAllocator<T> {
+    /// Creates an allocator which gets its memory from `grower`.
+    pub const fn new_in(grower: T) -> Self {
+        LeakingPageAllocator { grower }
+    }
+}
+
+unsafe impl<T: MemoryGrower> GlobalAlloc for LeakingPageAllocator<T> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        // This assumes PAGE_SIZE is always a multiple of the required alignment, which should be true for all practical use.
-        debug_assert!(PAGE_SIZE % layout.align() == 0);
+        nonzero_size_assert!(layout.size());
+        // Grown memory is only page aligned.
+        if layout.align() > PAGE_SIZE {
+            return null_mut();
+        }
 
-        let requested_pages = (layout.size() + PAGE_SIZE - 1) / PAGE_SIZE;
-        let previous_page_count = DefaultGrower.memory_grow(PageCount(requested_pages));
+        let requested_pages = layout.size().div_ceil(PAGE_SIZE);
+        let previous_page_count = self.grower.memory_grow(PageCount(requested_pages));
         if previous_page_count == ERROR_PAGE_COUNT {
             return null_mut();
         }
 
-        let ptr = previous_page_count.size_in_bytes() as *mut u8;
-        debug_assert!(ptr.align_offset(layout.align()) == 0);
+        let Some(start) = previous_page_count.size_in_bytes() else {
+            return null_mut();
+        };
+        let ptr = start as *mut u8;
+        lol_assert!(ptr.align_offset(layout.align()) == 0);
         ptr
     }
 
-    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {}
+    unsafe fn dealloc(&self, _ptr: *mut u8, layout: Layout) {
+        nonzero_size_assert!(layout.size());
+    }
+}
+
+/// Reports the whole pages grown for the allocation.
+impl<T: MemoryGrower> AllocWithExcess for LeakingPageAllocator<T> {
+    unsafe fn alloc_with_excess(&self, layout: Layout) -> (*mut u8, usize) {
+        let ptr = self.alloc(layout);
+        if ptr.is_null() {
+            return (ptr, 0);
+        }
+        (ptr, layout.size().div_ceil(PAGE_SIZE) * PAGE_SIZE)
+    }
+}
+
+/// Keeps no state, so knows nothing about its memory use.
+impl<T> AllocatorStats for LeakingPageAllocator<T> {
+    fn stats(&self) -> Stats {
+        Stats::default()
+    }
 }
 
 /// A non-thread safe bump-pointer allocator.
@@ -47,63 +122,684 @@ unsafe impl GlobalAlloc for LeakingPageAllocator {
 /// Efficient for small allocations.
 /// Does tolerate concurrent callers of wasm::memory_grow,
 /// but not concurrent use of this allocator.
+///
+/// When other code grows memory between its growths, the new memory is not contiguous with the old,
+/// so allocation moves to the new region. The end of the old region is kept in one of [LEFTOVER_SLOTS] slots,
+/// and later allocations which fit in one are bumped from the smallest which fits, before the main region.
+/// Once the slots are full, only the largest leftovers are kept, and the rest is lost.
 pub struct LeakingAllocator<T = DefaultGrower> {
     used: UnsafeCell<usize>, // bytes
     size: UnsafeCell<usize>, // bytes
+    /// Unused `(start, end)` ends of regions allocation moved away from. Empty slots have `start == end`.
+    leftovers: UnsafeCell<[(usize, usize); LEFTOVER_SLOTS]>,
     grower: T,
 }
 
+/// How many leftover regions a [LeakingAllocator] keeps.
+const LEFTOVER_SLOTS: usize = 4;
+
 #[cfg(target_arch = "wasm32")]
 impl LeakingAllocator<DefaultGrower> {
     pub const fn new() -> Self {
+        LeakingAllocator::new_in(DefaultGrower)
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Default for LeakingAllocator<DefaultGrower> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> LeakingAllocator<T> {
+    /// Creates an allocator which gets its memory from `grower`.
+    pub const fn new_in(grower: T) -> Self {
         LeakingAllocator {
             used: UnsafeCell::new(0),
             size: UnsafeCell::new(0),
-            grower: DefaultGrower,
+            leftovers: UnsafeCell::new([(0, 0); LEFTOVER_SLOTS]),
+            grower,
+        }
+    }
+}
+
+impl LeakingAllocator<NoGrower> {
+    /// Creates an allocator which allocates from `buffer`, and never grows.
+    /// Once `buffer` is used up, allocations fail.
+    ///
+    /// `buffer` must be aligned to `PAGE_SIZE` (this is checked in debug builds).
+    pub fn from_slice(buffer: &'static mut [u8]) -> Self {
+        let start = buffer.as_mut_ptr() as usize;
+        lol_assert!(
+            start.is_multiple_of(PAGE_SIZE),
+            "buffer must be aligned to PAGE_SIZE"
+        );
+        LeakingAllocator {
+            used: UnsafeCell::new(start),
+            size: UnsafeCell::new(start + buffer.len()),
+            leftovers: UnsafeCell::new([(0, 0); LEFTOVER_SLOTS]),
+            grower: NoGrower,
+        }
+    }
+}
+
+impl<T: MemoryGrower> LeakingAllocator<T> {
+    /// Grows memory now, so that the following allocations, up to `bytes` of them, do not grow it:
+    /// for example before a latency sensitive phase, or one where growing would be a problem.
+    ///
+    /// This holds for allocations with alignment at most `max_align`, whose sizes (each rounded up to a multiple of `max_align`) total at most `bytes`,
+    /// which covers the padding bumping them can need.
+    /// Only what is missing from the current region is grown, in whole pages.
+    ///
+    /// If other code grows memory while this does (so the new memory is not contiguous with the current region),
+    /// the current region is kept as a leftover and the reservation is made in newly grown memory instead,
+    /// so an `Ok` result always holds, however memory is grown afterwards.
+    ///
+    /// Fails if growing fails (leaving any memory grown for later allocations), if `max_align` is not a power of two no larger than `PAGE_SIZE`, or on overflow.
+    pub fn grow_by(&self, bytes: usize, max_align: usize) -> Result<(), AllocError> {
+        if !max_align.is_power_of_two() || max_align > PAGE_SIZE {
+            return Err(AllocError);
+        }
+        // Safety: this allocator is not used concurrently.
+        let (size, used, leftovers) = unsafe {
+            (
+                &mut *self.size.get(),
+                &mut *self.used.get(),
+                &mut *self.leftovers.get(),
+            )
+        };
+        // Once growth has been interfered with, grow the whole reservation, since the result may not be contiguous again.
+        let mut whole = false;
+        loop {
+            // Each allocation starts at most at this plus the rounded sizes of those before it.
+            let end = used
+                .checked_next_multiple_of(max_align)
+                .and_then(|start| start.checked_add(bytes))
+                .ok_or(AllocError)?;
+            if end <= *size {
+                return Ok(());
+            }
+            let missing = if whole { bytes } else { end - *size };
+            let requested_pages = missing.div_ceil(PAGE_SIZE);
+            let previous_page_count = self.grower.memory_grow(PageCount(requested_pages));
+            if previous_page_count == ERROR_PAGE_COUNT {
+                return Err(AllocError);
+            }
+            let (Some(previous_size), Some(new_size)) = (
+                previous_page_count.size_in_bytes(),
+                PageCount(previous_page_count.0 + requested_pages).size_in_bytes(),
+            ) else {
+                return Err(AllocError);
+            };
+            if previous_size != *size {
+                keep_leftover(leftovers, (*used, *size));
+                *used = previous_size;
+                whole = true;
+            }
+            *size = new_size;
         }
     }
 }
 
 unsafe impl<T: MemoryGrower> GlobalAlloc for LeakingAllocator<T> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        nonzero_size_assert!(layout.size());
         let size: &mut usize = &mut *self.size.get();
         let used: &mut usize = &mut *self.used.get();
-        // This assumes PAGE_SIZE is always a multiple of the required alignment, which should be true for all practical use.
-        // If this is not true, this could go past size.
-        let alignment = layout.align();
-        let offset = *used % alignment;
-        if offset != 0 {
-            *used += alignment - offset;
+        let leftovers = &mut *self.leftovers.get();
+        if let Some(ptr) = alloc_from_leftovers(leftovers, layout) {
+            return ptr;
         }
+        let alignment = layout.align();
+        // These only overflow near the end of the address space, where the allocation can't fit anyway.
+        // `used` is only updated once the allocation succeeds, so failing does not waste the padding.
+        let Some(mut start) = used.checked_next_multiple_of(alignment) else {
+            return null_mut();
+        };
 
         let requested_size = layout.size();
-        let new_total = *used + requested_size;
-        if new_total > *size {
+        if start
+            .checked_add(requested_size)
+            .is_none_or(|new_total| new_total > *size)
+        {
+            // Grown memory is only page aligned, so may not be aligned enough if it is not contiguous.
+            if alignment > PAGE_SIZE {
+                return null_mut();
+            }
             // Request enough new space for this allocation, even if we have some space left over from the last one incase they end up non-contiguous.
             // Round up to a number of pages
-            let requested_pages = (requested_size + PAGE_SIZE - 1) / PAGE_SIZE;
+            let requested_pages = requested_size.div_ceil(PAGE_SIZE);
             let previous_page_count = self.grower.memory_grow(PageCount(requested_pages));
             if previous_page_count == ERROR_PAGE_COUNT {
                 return null_mut();
             }
 
-            let previous_size = previous_page_count.size_in_bytes();
+            let (Some(previous_size), Some(new_size)) = (
+                previous_page_count.size_in_bytes(),
+                PageCount(previous_page_count.0 + requested_pages).size_in_bytes(),
+            ) else {
+                return null_mut();
+            };
             if previous_size != *size {
                 // New memory is not contiguous with old: something else allocated in-between.
-                // TODO: is handling this case necessary? Maybe make it optional behind a feature?
+                keep_leftover(leftovers, (*used, *size));
                 // This assumes PAGE_SIZE is always a multiple of the required alignment, which should be true for all practical use.
-                *used = previous_size;
+                start = previous_size;
                 // TODO: in free mode, have minimum alignment used is rounded up to and is maxed with alignment so we can ensure there is either:
                 // 1. no space at the end of the page
                 // 2. enough space we can add it to the free list
             }
-            *size = previous_size + requested_pages * PAGE_SIZE;
+            *size = new_size;
         }
 
-        let start = *used;
-        *used += requested_size;
+        *used = start + requested_size;
         start as *mut u8
     }
 
-    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {}
+    unsafe fn dealloc(&self, _ptr: *mut u8, layout: Layout) {
+        nonzero_size_assert!(layout.size());
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        // Memory is never reused, but buffers from `from_slice` (or other growers) may not start zeroed.
+        zeroed(self.alloc(layout), layout.size())
+    }
+}
+
+/// Bumps an allocation for `layout` from the smallest leftover region it fits in, if any.
+fn alloc_from_leftovers(
+    leftovers: &mut [(usize, usize); LEFTOVER_SLOTS],
+    layout: Layout,
+) -> Option<*mut u8> {
+    let mut best: Option<(&mut (usize, usize), usize)> = None;
+    for slot in leftovers.iter_mut() {
+        let (start, end) = *slot;
+        let Some(aligned) = start.checked_next_multiple_of(layout.align()) else {
+            continue;
+        };
+        let fits = aligned <= end && end - aligned >= layout.size();
+        if fits
+            && best
+                .as_ref()
+                .is_none_or(|(best, _)| end - start < best.1 - best.0)
+        {
+            best = Some((slot, aligned));
+        }
+    }
+    let (slot, aligned) = best?;
+    slot.0 = aligned + layout.size();
+    Some(aligned as *mut u8)
+}
+
+/// Keeps `region` in an empty slot, or in place of the smallest leftover if it is larger.
+fn keep_leftover(leftovers: &mut [(usize, usize); LEFTOVER_SLOTS], region: (usize, usize)) {
+    let len = |(start, end): (usize, usize)| end.saturating_sub(start);
+    let smallest = leftovers.iter_mut().min_by_key(|slot| len(**slot)).unwrap();
+    if len(region) > len(*smallest) {
+        *smallest = region;
+    }
+}
+
+/// Bump allocations have no excess: the next allocation starts right after this one.
+impl<T: MemoryGrower> AllocWithExcess for LeakingAllocator<T> {
+    unsafe fn alloc_with_excess(&self, layout: Layout) -> (*mut u8, usize) {
+        let ptr = self.alloc(layout);
+        if ptr.is_null() {
+            return (ptr, 0);
+        }
+        (ptr, layout.size())
+    }
+}
+
+impl<T> AllocatorStats for LeakingAllocator<T> {
+    fn stats(&self) -> Stats {
+        let (used, size, leftovers) =
+            unsafe { (*self.used.get(), *self.size.get(), &*self.leftovers.get()) };
+        let leftover_bytes: usize = leftovers.iter().map(|&(start, end)| end - start).sum();
+        Stats {
+            // Only counts the remaining space in the current region and the leftovers kept.
+            free_bytes: Some(size.saturating_sub(used) + leftover_bytes),
+            ..Stats::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LeakingAllocator, LeakingPageAllocator};
+    use crate::{
+        testing::{conformance, excess_conformance, MockGrower},
+        AllocError, AllocWithExcess, AllocatorStats, MemoryGrower, NoGrower, PageCount,
+        ERROR_PAGE_COUNT, PAGE_SIZE,
+    };
+    use alloc::{boxed::Box, vec::Vec};
+    use core::{
+        alloc::{GlobalAlloc, Layout},
+        cell::Cell,
+    };
+
+    /// Leaks a page aligned buffer of `pages` pages.
+    fn leak_buffer(pages: usize) -> &'static mut [u8] {
+        let grower = Box::leak(Box::new(MockGrower::with_pages(pages)));
+        unsafe { core::slice::from_raw_parts_mut(grower.base() as *mut u8, pages * PAGE_SIZE) }
+    }
+
+    #[test]
+    fn leaking_allocator_conformance() {
+        conformance(&LeakingAllocator::new_in(MockGrower::new()));
+    }
+
+    #[test]
+    fn leaking_allocator_out_of_memory() {
+        let allocator = LeakingAllocator::new_in(NoGrower);
+        unsafe {
+            assert!(allocator
+                .alloc(Layout::from_size_align(1, 1).unwrap())
+                .is_null());
+        }
+    }
+
+    #[test]
+    fn leaking_allocator_from_slice() {
+        let buffer = leak_buffer(2);
+        let start = buffer.as_ptr() as usize;
+        let len = buffer.len();
+        let allocator = LeakingAllocator::from_slice(buffer);
+        let layout = Layout::from_size_align(48, 16).unwrap();
+        let mut count = 0;
+        unsafe {
+            loop {
+                let ptr = allocator.alloc(layout);
+                if ptr.is_null() {
+                    break;
+                }
+                assert_eq!(ptr as usize, start + count * layout.size());
+                count += 1;
+            }
+            // Fails without using up the remaining space.
+            let remaining = len - count * layout.size();
+            assert!(allocator
+                .alloc(Layout::from_size_align(remaining + 1, 1).unwrap())
+                .is_null());
+            assert!(!allocator
+                .alloc(Layout::from_size_align(remaining, 1).unwrap())
+                .is_null());
+            assert!(allocator
+                .alloc(Layout::from_size_align(1, 1).unwrap())
+                .is_null());
+        }
+        assert_eq!(count, len / layout.size());
+    }
+
+    #[test]
+    fn leaking_allocator_from_slice_whole_buffer() {
+        let buffer = leak_buffer(1);
+        let allocator = LeakingAllocator::from_slice(buffer);
+        unsafe {
+            assert!(!allocator
+                .alloc(Layout::from_size_align(PAGE_SIZE, PAGE_SIZE).unwrap())
+                .is_null());
+        }
+    }
+
+    #[test]
+    fn leaking_allocator_alloc_zeroed() {
+        let buffer = leak_buffer(1);
+        buffer.fill(0xFF);
+        let allocator = LeakingAllocator::from_slice(buffer);
+        for size in [1, 15, 16, 17, 100, 1000] {
+            let layout = Layout::from_size_align(size, 8).unwrap();
+            let bytes =
+                unsafe { core::slice::from_raw_parts(allocator.alloc_zeroed(layout), size) };
+            assert!(bytes.iter().all(|&b| b == 0));
+        }
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic]
+    fn leaking_allocator_from_slice_unaligned() {
+        LeakingAllocator::from_slice(&mut leak_buffer(1)[1..]);
+    }
+
+    /// Grower where something else grows a page right after each growth, so no two growths are contiguous.
+    struct Interfering(MockGrower);
+
+    impl MemoryGrower for &Interfering {
+        fn memory_grow(&self, delta: PageCount) -> PageCount {
+            let previous = self.0.memory_grow(delta);
+            if previous != ERROR_PAGE_COUNT {
+                self.0.memory_grow(PageCount(1));
+            }
+            previous
+        }
+    }
+
+    #[test]
+    fn leaking_allocator_reuses_leftovers() {
+        let grower = Interfering(MockGrower::new());
+        let allocator = LeakingAllocator::new_in(&grower);
+        let large = Layout::from_size_align(PAGE_SIZE * 3 / 5, 1).unwrap();
+        let small = Layout::from_size_align(PAGE_SIZE / 4, 1).unwrap();
+        unsafe {
+            // Each needs a new page, leaving the end of the last one.
+            let large_ptrs: Vec<_> = (0..4).map(|_| allocator.alloc(large) as usize).collect();
+            let pages = grower.0.used_pages();
+            assert_eq!(pages, 8);
+            assert_eq!(
+                allocator.stats().free_bytes,
+                Some(4 * (PAGE_SIZE - large.size()))
+            );
+
+            // Each fits after a large allocation, so none grow.
+            for _ in 0..4 {
+                let ptr = allocator.alloc(small) as usize;
+                assert!(large_ptrs.contains(&(ptr - large.size())));
+            }
+            assert_eq!(grower.0.used_pages(), pages);
+            assert_eq!(
+                allocator.stats().free_bytes,
+                Some(4 * (PAGE_SIZE - large.size() - small.size()))
+            );
+        }
+    }
+
+    #[test]
+    fn leaking_allocator_leftover_slots_full() {
+        let grower = Interfering(MockGrower::new());
+        let allocator = LeakingAllocator::new_in(&grower);
+        let layout = |size| Layout::from_size_align(size, 1).unwrap();
+        unsafe {
+            // Leaves 1000, 2000, ... 7000 bytes at the end of each page, more than fit in the slots.
+            let ptrs: Vec<_> = (1..=7)
+                .map(|i| allocator.alloc(layout(PAGE_SIZE - 1000 * i)) as usize)
+                .collect();
+            // The smallest leftovers are dropped.
+            assert_eq!(
+                allocator.stats().free_bytes,
+                Some(3000 + 4000 + 5000 + 6000 + 7000)
+            );
+            assert_eq!(
+                allocator.alloc(layout(2500)) as usize,
+                ptrs[2] + PAGE_SIZE - 3000
+            );
+            assert_eq!(
+                allocator.alloc(layout(1000)) as usize,
+                ptrs[3] + PAGE_SIZE - 4000
+            );
+
+            // Allocations which fit nowhere still grow.
+            let pages = grower.0.used_pages();
+            assert!(!allocator.alloc(layout(PAGE_SIZE)).is_null());
+            assert_eq!(grower.0.used_pages(), pages + 2);
+        }
+    }
+
+    /// Grower counting its growths.
+    struct Counting<G> {
+        inner: G,
+        grows: Cell<usize>,
+    }
+
+    impl<G: MemoryGrower> MemoryGrower for &Counting<G> {
+        fn memory_grow(&self, delta: PageCount) -> PageCount {
+            self.grows.set(self.grows.get() + 1);
+            self.inner.memory_grow(delta)
+        }
+    }
+
+    /// Allocates with alignments up to `max_align`, and sizes which rounded up to multiples of it total at most `bytes`,
+    /// checking the allocations are aligned and do not overlap.
+    fn reserved_phase<T: MemoryGrower>(
+        allocator: &LeakingAllocator<T>,
+        bytes: usize,
+        max_align: usize,
+    ) {
+        let sizes = [1, 3, max_align, 17, 2 * max_align + 1];
+        let mut remaining = bytes;
+        let mut allocations = Vec::new();
+        for i in 0.. {
+            let mut size = sizes[i % sizes.len()];
+            if size.next_multiple_of(max_align) > remaining {
+                // Use up the rest.
+                size = remaining / max_align * max_align;
+                if size == 0 {
+                    break;
+                }
+            }
+            let align = max_align >> (i % (max_align.trailing_zeros() as usize + 1));
+            remaining -= size.next_multiple_of(max_align);
+            let ptr = unsafe { allocator.alloc(Layout::from_size_align(size, align).unwrap()) };
+            assert!(!ptr.is_null());
+            assert_eq!(ptr as usize % align, 0);
+            allocations.push((ptr as usize, size));
+        }
+        allocations.sort();
+        for pair in allocations.windows(2) {
+            assert!(pair[0].0 + pair[0].1 <= pair[1].0);
+        }
+    }
+
+    #[test]
+    fn leaking_allocator_grow_by() {
+        for bytes in [1, 7, 100, PAGE_SIZE - 1, PAGE_SIZE, 3 * PAGE_SIZE + 5] {
+            for max_align in [1, 2, 8, 64, 4096, PAGE_SIZE] {
+                let grower = Counting {
+                    inner: MockGrower::new(),
+                    grows: Cell::new(0),
+                };
+                let allocator = LeakingAllocator::new_in(&grower);
+                // Leave the region unaligned.
+                unsafe { allocator.alloc(Layout::from_size_align(3, 1).unwrap()) };
+                allocator.grow_by(bytes, max_align).unwrap();
+                let grows = grower.grows.get();
+                reserved_phase(&allocator, bytes, max_align);
+                assert_eq!(
+                    grower.grows.get(),
+                    grows,
+                    "{bytes} bytes, aligned to {max_align}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn leaking_allocator_grow_by_only_grows_missing() {
+        let grower = Counting {
+            inner: MockGrower::new(),
+            grows: Cell::new(0),
+        };
+        let allocator = LeakingAllocator::new_in(&grower);
+        unsafe { allocator.alloc(Layout::from_size_align(100, 1).unwrap()) };
+        assert_eq!(grower.grows.get(), 1);
+        // Fits in the rest of the page.
+        allocator.grow_by(PAGE_SIZE - 100, 1).unwrap();
+        assert_eq!(grower.grows.get(), 1);
+        allocator.grow_by(PAGE_SIZE, 1).unwrap();
+        assert_eq!(grower.grows.get(), 2);
+        assert_eq!(grower.inner.used_pages(), 2);
+    }
+
+    /// Growth by other code during `grow_by` moves the reservation to new memory, where it still holds.
+    #[test]
+    fn leaking_allocator_grow_by_interfered() {
+        let grower = Interfering(MockGrower::new());
+        let allocator = LeakingAllocator::new_in(&grower);
+        unsafe { allocator.alloc(Layout::from_size_align(100, 1).unwrap()) };
+        let bytes = 2 * PAGE_SIZE;
+        allocator.grow_by(bytes, 16).unwrap();
+        // The end of the first page was kept, and the reservation is in the two pages after the interfering one.
+        assert_eq!(allocator.stats().free_bytes, Some(PAGE_SIZE - 100 + bytes));
+        let pages = grower.0.used_pages();
+        reserved_phase(&allocator, bytes, 16);
+        assert_eq!(grower.0.used_pages(), pages);
+    }
+
+    #[test]
+    fn leaking_allocator_grow_by_errors() {
+        let allocator = LeakingAllocator::new_in(MockGrower::with_pages(1));
+        assert_eq!(allocator.grow_by(1, 3), Err(AllocError));
+        assert_eq!(allocator.grow_by(1, 2 * PAGE_SIZE), Err(AllocError));
+        assert_eq!(allocator.grow_by(usize::MAX, 1), Err(AllocError));
+        assert_eq!(allocator.grow_by(2 * PAGE_SIZE, 1), Err(AllocError));
+        allocator.grow_by(PAGE_SIZE, 1).unwrap();
+    }
+
+    #[test]
+    fn leaking_page_allocator_conformance() {
+        conformance(&LeakingPageAllocator::new_in(MockGrower::new()));
+    }
+
+    #[test]
+    fn leaking_page_allocator_excess() {
+        let allocator = LeakingPageAllocator::new_in(MockGrower::new());
+        excess_conformance(&allocator);
+        unsafe {
+            let (a, usable) = allocator.alloc_with_excess(Layout::from_size_align(1, 1).unwrap());
+            assert_eq!(usable, PAGE_SIZE);
+            a.write_bytes(1, usable);
+            let (b, usable) =
+                allocator.alloc_with_excess(Layout::from_size_align(PAGE_SIZE + 1, 1).unwrap());
+            assert_eq!(usable, 2 * PAGE_SIZE);
+            assert_eq!(b as usize - a as usize, PAGE_SIZE);
+        }
+    }
+
+    #[test]
+    fn leaking_page_allocator_rounds_to_pages() {
+        let allocator = LeakingPageAllocator::new_in(MockGrower::new());
+        unsafe {
+            let a = allocator.alloc(Layout::from_size_align(1, 1).unwrap());
+            let b = allocator.alloc(Layout::from_size_align(PAGE_SIZE, 1).unwrap());
+            let c = allocator.alloc(Layout::from_size_align(2 * PAGE_SIZE + 1, 1).unwrap());
+            let d = allocator.alloc(Layout::from_size_align(1, 1).unwrap());
+            assert_eq!(b as usize - a as usize, PAGE_SIZE);
+            assert_eq!(c as usize - b as usize, PAGE_SIZE);
+            assert_eq!(d as usize - c as usize, 3 * PAGE_SIZE);
+        }
+        assert_eq!(allocator.grower.used_pages(), 6);
+    }
+
+    #[test]
+    fn leaking_page_allocator_alignment() {
+        let allocator = LeakingPageAllocator::new_in(MockGrower::new());
+        for align in [1, 8, 4096, PAGE_SIZE] {
+            let ptr = unsafe { allocator.alloc(Layout::from_size_align(1, align).unwrap()) };
+            assert_eq!(ptr as usize % PAGE_SIZE, 0);
+        }
+    }
+
+    /// Alignments larger than a page fail, instead of returning misaligned memory.
+    #[test]
+    fn leaking_page_allocator_alignment_too_large() {
+        let allocator = LeakingPageAllocator::new_in(MockGrower::new());
+        unsafe {
+            assert!(allocator
+                .alloc(Layout::from_size_align(1, PAGE_SIZE * 2).unwrap())
+                .is_null());
+        }
+        assert_eq!(allocator.grower.used_pages(), 0);
+    }
+
+    #[test]
+    fn leaking_page_allocator_quota() {
+        let allocator = LeakingPageAllocator::new_in(MockGrower::with_pages(3));
+        unsafe {
+            assert!(!allocator
+                .alloc(Layout::from_size_align(2 * PAGE_SIZE, 1).unwrap())
+                .is_null());
+            // Does not fit in the one remaining page.
+            assert!(allocator
+                .alloc(Layout::from_size_align(PAGE_SIZE + 1, 1).unwrap())
+                .is_null());
+            assert!(!allocator
+                .alloc(Layout::from_size_align(PAGE_SIZE, 1).unwrap())
+                .is_null());
+            assert!(allocator
+                .alloc(Layout::from_size_align(1, 1).unwrap())
+                .is_null());
+        }
+    }
+
+    /// Rounding the largest sizes up to whole pages must not overflow.
+    #[test]
+    fn leaking_page_allocator_huge_size() {
+        let allocator = LeakingPageAllocator::new_in(MockGrower::new());
+        for size in [isize::MAX as usize - PAGE_SIZE + 2, isize::MAX as usize] {
+            unsafe {
+                assert!(allocator
+                    .alloc(Layout::from_size_align(size, 1).unwrap())
+                    .is_null());
+            }
+        }
+    }
+
+    /// The largest sizes a [Layout] can have fail cleanly, both when they need to grow and when memory left from earlier grows is checked.
+    #[test]
+    fn leaking_allocator_huge_size() {
+        let allocator = LeakingAllocator::new_in(MockGrower::new());
+        let max = isize::MAX as usize;
+        unsafe {
+            let small = Layout::from_size_align(100, 1).unwrap();
+            assert!(!allocator.alloc(small).is_null());
+            for (size, align) in [(max, 1), (max - PAGE_SIZE + 1, PAGE_SIZE)] {
+                assert!(allocator
+                    .alloc(Layout::from_size_align(size, align).unwrap())
+                    .is_null());
+            }
+            assert_eq!(allocator.grower.used_pages(), 1);
+            // The rest of the page is still used.
+            let ptr = allocator.alloc(small);
+            assert!(!ptr.is_null());
+            assert_eq!(allocator.grower.used_pages(), 1);
+        }
+    }
+
+    #[test]
+    fn page_count_size_overflow() {
+        assert_eq!(PageCount(usize::MAX / PAGE_SIZE + 1).size_in_bytes(), None);
+        assert_eq!(
+            PageCount(usize::MAX / PAGE_SIZE).size_in_bytes(),
+            Some(usize::MAX / PAGE_SIZE * PAGE_SIZE)
+        );
+    }
+
+    /// A grower which claims to have grown the last pages of a 64 bit address space, whose addresses overflow.
+    struct OverflowingGrower;
+
+    impl MemoryGrower for OverflowingGrower {
+        fn memory_grow(&self, _delta: PageCount) -> PageCount {
+            PageCount(usize::MAX / PAGE_SIZE + 1)
+        }
+    }
+
+    /// Allocators using the grown pages' address return null when it overflows, instead of panicking out of `alloc`.
+    #[test]
+    fn grown_address_overflow() {
+        let layout = Layout::from_size_align(100, 8).unwrap();
+        let allocators: [&dyn GlobalAlloc; 6] = [
+            &LeakingPageAllocator::new_in(OverflowingGrower),
+            &LeakingAllocator::new_in(OverflowingGrower),
+            &crate::FreeListAllocator::new_in(OverflowingGrower),
+            &crate::LockedFreeListAllocator::new_in(OverflowingGrower),
+            &crate::SizeSkipFreeListAllocator::new_in(OverflowingGrower),
+            &crate::RcuArenaAllocator::new_in(OverflowingGrower),
+        ];
+        for allocator in allocators {
+            assert!(unsafe { allocator.alloc(layout) }.is_null());
+        }
+    }
+
+    /// Alignments larger than a page fail when they need to grow memory, instead of returning misaligned memory.
+    #[test]
+    fn leaking_allocator_alignment_too_large() {
+        let allocator = LeakingAllocator::new_in(MockGrower::new());
+        unsafe {
+            assert!(allocator
+                .alloc(Layout::from_size_align(1, crate::PAGE_SIZE * 2).unwrap())
+                .is_null());
+        }
+        assert_eq!(allocator.grower.used_pages(), 0);
+    }
 }
@@ -0,0 +1,63 @@
+//! Zeroing memory for `alloc_zeroed`.
+//!
+//! With wasm SIMD (`target_feature = "simd128"`) regions of at least 16 bytes are zeroed with `v128.store`, 16 bytes per store.
+//! Otherwise this is `write_bytes`, which becomes `memory.fill` with the wasm bulk memory feature, or a byte loop without it.
+
+/// Zeroes the `len` bytes at `ptr` (if it is not null), and returns it.
+///
+/// # Safety
+///
+/// If not null, `ptr` must be valid for writing `len` bytes.
+pub(crate) unsafe fn zeroed(ptr: *mut u8, len: usize) -> *mut u8 {
+    if !ptr.is_null() {
+        zero(ptr, len);
+    }
+    ptr
+}
+
+#[cfg(all(target_arch = "wasm32", target_feature = "simd128"))]
+unsafe fn zero(ptr: *mut u8, len: usize) {
+    use core::arch::wasm32::{u8x16_splat, v128, v128_store};
+    if len < 16 {
+        ptr.write_bytes(0, len);
+        return;
+    }
+    // v128_store does not require alignment.
+    let zeros = u8x16_splat(0);
+    let mut offset = 0;
+    while offset + 16 <= len {
+        v128_store(ptr.add(offset) as *mut v128, zeros);
+        offset += 16;
+    }
+    if offset != len {
+        // The last store overlaps the previous one instead of handling the remainder byte by byte.
+        v128_store(ptr.add(len - 16) as *mut v128, zeros);
+    }
+}
+
+#[cfg(not(all(target_arch = "wasm32", target_feature = "simd128")))]
+#[inline(always)]
+unsafe fn zero(ptr: *mut u8, len: usize) {
+    ptr.write_bytes(0, len);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::zeroed;
+
+    #[test]
+    fn zeroes_exactly() {
+        let mut buffer = [0xFFu8; 100];
+        for start in [0, 1, 7, 16] {
+            for len in [0, 1, 15, 16, 17, 31, 32, 33, 64] {
+                buffer.fill(0xFF);
+                let ptr = unsafe { zeroed(buffer.as_mut_ptr().add(start), len) };
+                assert_eq!(ptr, buffer[start..].as_mut_ptr());
+                assert_eq!(buffer[..start], [0xFF; 100][..start]);
+                assert_eq!(buffer[start..start + len], [0; 100][..len]);
+                assert_eq!(buffer[start + len..], [0xFF; 100][start + len..]);
+            }
+        }
+        assert!(unsafe { zeroed(core::ptr::null_mut(), 100) }.is_null());
+    }
+}
@@ -0,0 +1,82 @@
+//! The `fallible` helpers, with a global allocator whose memory can be limited.
+//!
+//! This is a single test so no other tests allocate while the limit is low.
+#![cfg(all(feature = "fallible", not(target_arch = "wasm32")))]
+
+use lol_alloc::{
+    try_box, try_string_with_capacity, try_vec_with_capacity, AllocError, FreeListAllocator,
+    GlobalArrayGrower, LockedAllocator, MaxPagesGrower,
+};
+
+const PAGES: usize = 64;
+const PAGE_SIZE: usize = 65536;
+
+static GROWER: MaxPagesGrower<GlobalArrayGrower<PAGES>> =
+    MaxPagesGrower::new(GlobalArrayGrower::new(), PAGES);
+
+#[global_allocator]
+static ALLOCATOR: LockedAllocator<FreeListAllocator<&MaxPagesGrower<GlobalArrayGrower<PAGES>>>> =
+    LockedAllocator::new(FreeListAllocator::new_in(&GROWER));
+
+#[test]
+fn fails_exactly_when_over_quota() {
+    // Stop growing, so only what is already in the free list can be allocated.
+    let limit = GROWER.set_limit_pages(GROWER.grown_pages());
+    let available = ALLOCATOR.lock().largest_allocatable_block(1);
+    assert!(available > 0);
+
+    assert_eq!(
+        try_vec_with_capacity::<u8>(available + 1).err(),
+        Some(AllocError)
+    );
+    assert_eq!(
+        try_string_with_capacity(available + 1).err(),
+        Some(AllocError)
+    );
+    assert_eq!(
+        try_vec_with_capacity::<u64>(usize::MAX).err(),
+        Some(AllocError)
+    );
+
+    // Holds the allocations using up the free list, allocated first so pushing to it does not allocate.
+    let mut used = try_vec_with_capacity::<Vec<u8>>(256).unwrap();
+    let available = ALLOCATOR.lock().largest_allocatable_block(1);
+    let mut bytes = try_vec_with_capacity::<u8>(available).unwrap();
+    assert_eq!(bytes.capacity(), available);
+    bytes.extend(core::iter::repeat_n(7, available));
+    assert!(bytes.iter().all(|&b| b == 7));
+    used.push(bytes);
+    loop {
+        let size = ALLOCATOR.lock().largest_allocatable_block(1);
+        if size == 0 {
+            break;
+        }
+        used.push(try_vec_with_capacity::<u8>(size).unwrap());
+        assert!(used.len() < used.capacity());
+    }
+    // Everything is in use.
+    assert_eq!(try_box(1u8).err(), Some(AllocError));
+    assert_eq!(try_string_with_capacity(1).err(), Some(AllocError));
+    // One more page of quota allows exactly one more page of allocations.
+    GROWER.set_limit_pages(GROWER.grown_pages() + 1);
+    used.push(try_vec_with_capacity::<u8>(PAGE_SIZE).unwrap());
+    assert_eq!(try_box(1u8).err(), Some(AllocError));
+    drop(used);
+
+    // Freed memory can be used again, and the results work like normal collections.
+    let mut boxed = try_box(5u64).unwrap();
+    *boxed += 1;
+    assert_eq!(*boxed, 6);
+    let mut string = try_string_with_capacity(10).unwrap();
+    string.push_str("lol_alloc");
+    assert_eq!(string, "lol_alloc");
+    let mut numbers = try_vec_with_capacity::<u32>(3).unwrap();
+    numbers.extend([1, 2, 3]);
+    numbers.push(4);
+    assert_eq!(numbers, [1, 2, 3, 4]);
+    assert!(try_box(()).is_ok());
+    assert!(try_vec_with_capacity::<()>(usize::MAX).is_ok());
+    assert_eq!(try_vec_with_capacity::<u8>(0).unwrap().capacity(), 0);
+
+    GROWER.set_limit_pages(limit);
+}
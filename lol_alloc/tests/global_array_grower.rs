@@ -0,0 +1,21 @@
+//! Uses `lol_alloc` as the global allocator for a whole native program,
+//! with memory coming from a static array instead of `memory.grow`.
+//! This is how it would be used on targets with no operating system.
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use lol_alloc::{FreeListAllocator, GlobalArrayGrower, LockedAllocator};
+
+lol_alloc::define_global! {
+    static ALLOCATOR: LockedAllocator<FreeListAllocator<GlobalArrayGrower<16>>> =
+        LockedAllocator::new(FreeListAllocator::new_in(GlobalArrayGrower::new()));
+}
+
+#[test]
+fn vec_sum() {
+    let v: Vec<u32> = (0..1000).collect();
+    assert_eq!(v.iter().sum::<u32>(), 499500);
+    drop(v);
+    ALLOCATOR.lock().validate_heap();
+}
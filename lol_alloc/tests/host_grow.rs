@@ -0,0 +1,149 @@
+//! The host growing the module's memory from JavaScript, which the allocators never see a `memory.grow` result for.
+//!
+//! Memory the host grew is the host's: the allocators must not hand it out,
+//! and must not assume their next grow is adjacent to their previous one.
+#![cfg(target_arch = "wasm32")]
+
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    ops::Range,
+};
+use lol_alloc::{FreeListAllocator, LeakingAllocator, LockedAllocator};
+use wasm_bindgen::{prelude::*, JsCast};
+use wasm_bindgen_test::*;
+
+lol_alloc::define_global! {
+    static ALLOCATOR: LockedAllocator<FreeListAllocator> =
+        LockedAllocator::new(FreeListAllocator::new());
+}
+
+const PAGE_SIZE: usize = 65536;
+/// What the host fills the memory it grows with.
+const HOST_BYTE: u8 = 0xAB;
+
+#[wasm_bindgen(inline_js = "
+export function grow_between(memory, pages, rounds, workload) {
+    for (let i = 0; i < rounds; i++) {
+        workload();
+        memory.grow(pages);
+    }
+    workload();
+}
+")]
+extern "C" {
+    /// Runs `workload` `rounds + 1` times, with JavaScript growing `memory` by `pages` in between.
+    fn grow_between(memory: &JsValue, pages: u32, rounds: u32, workload: &Closure<dyn FnMut()>);
+}
+
+fn memory() -> js_sys::WebAssembly::Memory {
+    wasm_bindgen::memory().unchecked_into()
+}
+
+/// Grows memory by `pages` through the JavaScript API, like a host would, and fills the new pages with [HOST_BYTE].
+/// Returns the range of memory grown.
+fn host_grow(pages: u32) -> Range<usize> {
+    let previous = memory().grow(pages) as usize * PAGE_SIZE;
+    let grown = previous..previous + pages as usize * PAGE_SIZE;
+    unsafe { (grown.start as *mut u8).write_bytes(HOST_BYTE, grown.len()) };
+    grown
+}
+
+/// Checks nothing wrote to memory the host grew.
+fn assert_untouched(grown: &Range<usize>) {
+    let bytes = unsafe { core::slice::from_raw_parts(grown.start as *const u8, grown.len()) };
+    assert!(bytes.iter().all(|&b| b == HOST_BYTE));
+}
+
+/// Allocates `layout` from `allocator`, checking it is outside `grown`, and filling it with `fill`.
+unsafe fn alloc_outside(
+    allocator: &impl GlobalAlloc,
+    layout: Layout,
+    grown: &Range<usize>,
+    fill: u8,
+) -> *mut u8 {
+    let ptr = allocator.alloc(layout);
+    assert!(!ptr.is_null());
+    let start = ptr as usize;
+    assert!(start + layout.size() <= grown.start || start >= grown.end);
+    ptr.write_bytes(fill, layout.size());
+    ptr
+}
+
+unsafe fn assert_filled(ptr: *const u8, layout: Layout, fill: u8) {
+    let bytes = core::slice::from_raw_parts(ptr, layout.size());
+    assert!(bytes.iter().all(|&b| b == fill));
+}
+
+#[wasm_bindgen_test]
+fn before_first_allocation() {
+    let free_list = FreeListAllocator::new();
+    let leaking = LeakingAllocator::new();
+    let grown = host_grow(3);
+    let layout = Layout::from_size_align(1000, 8).unwrap();
+    unsafe {
+        let a = alloc_outside(&free_list, layout, &grown, 1);
+        let b = alloc_outside(&leaking, layout, &grown, 2);
+        assert_untouched(&grown);
+        assert_filled(a, layout, 1);
+        assert_filled(b, layout, 2);
+        free_list.dealloc(a, layout);
+    }
+    free_list.validate_heap();
+}
+
+#[wasm_bindgen_test]
+fn between_allocations() {
+    let free_list = FreeListAllocator::new();
+    let leaking = LeakingAllocator::new();
+    let small = Layout::from_size_align(100, 8).unwrap();
+    // More than is left of the first allocation's page, so the next allocation has to grow again.
+    let large = Layout::from_size_align(PAGE_SIZE, 64).unwrap();
+    unsafe {
+        let a = free_list.alloc(small);
+        a.write_bytes(1, small.size());
+        let b = leaking.alloc(small);
+        b.write_bytes(2, small.size());
+        let grown = host_grow(2);
+        let c = alloc_outside(&free_list, large, &grown, 3);
+        let d = alloc_outside(&leaking, large, &grown, 4);
+        // Small allocations may still use what was left before the host grew.
+        let e = alloc_outside(&free_list, small, &grown, 5);
+        let f = alloc_outside(&leaking, small, &grown, 6);
+        assert_untouched(&grown);
+        for (ptr, layout, fill) in [
+            (a, small, 1),
+            (b, small, 2),
+            (c, large, 3),
+            (d, large, 4),
+            (e, small, 5),
+            (f, small, 6),
+        ] {
+            assert_filled(ptr, layout, fill);
+        }
+        free_list.dealloc(a, small);
+        free_list.dealloc(c, large);
+        free_list.dealloc(e, small);
+    }
+    free_list.validate_heap();
+}
+
+/// The host grows memory from a JavaScript loop calling back into a workload using the global allocator.
+#[wasm_bindgen_test]
+fn during_workload() {
+    let mut live: Vec<Vec<u8>> = vec![];
+    let mut round = 0u8;
+    let workload = Closure::new(move || {
+        round += 1;
+        // Large enough to need new memory each round.
+        live.push(vec![round; 3 * PAGE_SIZE]);
+        drop(vec![round; 1000]);
+        for (i, buffer) in live.iter().enumerate() {
+            assert!(buffer.iter().all(|&b| b == i as u8 + 1));
+        }
+    });
+    let before = memory().grow(0) as usize * PAGE_SIZE;
+    grow_between(&wasm_bindgen::memory(), 2, 5, &workload);
+    drop(workload);
+    ALLOCATOR.lock().validate_heap();
+    assert!(memory().grow(0) as usize * PAGE_SIZE >= before + 5 * 2 * PAGE_SIZE);
+}
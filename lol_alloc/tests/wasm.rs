@@ -3,11 +3,28 @@
 use std::mem::swap;
 
 use lol_alloc::{FreeListAllocator, LockedAllocator};
+use rand::Rng;
+use rand_core::SeedableRng;
+use rand_pcg::Pcg32;
 use wasm_bindgen_test::*;
 
-#[global_allocator]
-static ALLOCATOR: LockedAllocator<FreeListAllocator> =
-    LockedAllocator::new(FreeListAllocator::new());
+lol_alloc::define_global! {
+    static ALLOCATOR: LockedAllocator<FreeListAllocator> =
+        LockedAllocator::new(FreeListAllocator::new());
+}
+
+/// Without threads, the lock is only a flag.
+#[cfg(not(target_feature = "atomics"))]
+#[wasm_bindgen_test]
+fn flag_lock_without_atomics() {
+    assert!(std::any::type_name::<lol_alloc::LockGuard<'static, u8>>().contains("FlagGuard"));
+}
+
+#[cfg(target_feature = "atomics")]
+#[wasm_bindgen_test]
+fn spin_lock_with_atomics() {
+    assert!(std::any::type_name::<lol_alloc::LockGuard<'static, u8>>().starts_with("spin::"));
+}
 
 #[wasm_bindgen_test]
 fn minimal() {
@@ -22,6 +39,16 @@ fn small_allocations() {
     assert_eq!(*b, 2);
 }
 
+/// Zeroed allocations reusing freed memory are zeroed (with `v128.store` when built with `-C target-feature=+simd128`).
+#[wasm_bindgen_test]
+fn zeroed_allocations() {
+    for len in [1, 15, 16, 17, 100, 100_000] {
+        drop(vec![0xFFu8; len]);
+        let zeroed = vec![0u8; len];
+        assert!(zeroed.iter().all(|&b| b == 0));
+    }
+}
+
 #[wasm_bindgen_test]
 fn many_allocations() {
     let mut v = vec![];
@@ -34,3 +61,86 @@ fn many_allocations() {
     v.reserve(1000000);
     drop(v);
 }
+
+/// Allocates and frees random sized buffers for 30 seconds,
+/// to catch timing dependent and platform specific issues the short tests miss.
+///
+/// Ignored by default due to its run time.
+/// Run with `wasm-pack test --node lol_alloc -- --include-ignored`.
+#[wasm_bindgen_test]
+#[ignore]
+fn randomized_stress() {
+    let mut rng = Pcg32::seed_from_u64(0);
+    let start = js_sys::Date::now();
+    let mut live: Vec<Vec<u8>> = vec![];
+    while js_sys::Date::now() - start < 30_000.0 {
+        for _ in 0..1000 {
+            if !live.is_empty() && rng.gen_bool(0.5) {
+                let buffer = live.swap_remove(rng.gen_range(0..live.len()));
+                // Detect overlapping allocations.
+                assert!(buffer.iter().all(|&b| b == buffer.len() as u8));
+            } else {
+                let size = if rng.gen_bool(0.01) {
+                    rng.gen_range(0..300_000)
+                } else {
+                    rng.gen_range(0..300)
+                };
+                let mut buffer = Vec::new();
+                buffer
+                    .try_reserve_exact(size)
+                    .expect("allocation of reasonable size should not fail");
+                buffer.resize(size, size as u8);
+                live.push(buffer);
+            }
+        }
+    }
+    drop(live);
+    ALLOCATOR.lock().validate_heap();
+}
+
+#[wasm_bindgen::prelude::wasm_bindgen]
+extern "C" {
+    /// `performance.now()`: milliseconds, with sub-millisecond precision.
+    /// Called through the global `performance` (instead of `window().performance()`), which browsers, workers and Node all provide.
+    #[wasm_bindgen(js_namespace = performance, js_name = now)]
+    fn performance_now() -> f64;
+}
+
+/// Measures `Box::new(42u64)` latency, as a baseline for comparing runs in real wasm engines.
+///
+/// Timer precision is coarse (and deliberately reduced by some browsers), so allocations are timed in batches,
+/// and the median batch gives the latency per allocation.
+/// Timing varies too much between machines to fail on, so exceeding the threshold only logs a warning.
+#[wasm_bindgen_test]
+fn allocation_latency() {
+    const BATCHES: usize = 100;
+    const BATCH: usize = 100;
+    let mut boxes = Vec::with_capacity(BATCHES * BATCH);
+    let mut per_allocation: Vec<f64> = (0..BATCHES)
+        .map(|_| {
+            let start = performance_now();
+            for _ in 0..BATCH {
+                boxes.push(Box::new(42u64));
+            }
+            (performance_now() - start) / BATCH as f64
+        })
+        .collect();
+    assert!(boxes.iter().all(|b| **b == 42));
+    drop(boxes);
+    per_allocation.sort_by(f64::total_cmp);
+    let median_us = per_allocation[BATCHES / 2] * 1000.0;
+    console_log!(
+        "median allocation latency: {median_us:.3} µs ({} allocations)",
+        BATCHES * BATCH
+    );
+    if median_us > 1.0 {
+        console_log!("warning: median allocation latency {median_us:.3} µs exceeds 1 µs");
+    }
+}
+
+/// The default grower follows the `MemoryGrower` contract, sharing memory with the global allocator.
+#[cfg(feature = "testing")]
+#[wasm_bindgen_test]
+fn default_grower_conformance() {
+    lol_alloc::testing::grower_conformance(&lol_alloc::DefaultGrower);
+}
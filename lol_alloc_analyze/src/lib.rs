@@ -0,0 +1,154 @@
+//! Summarizes allocation logs written by `lol_alloc::WasiLogAllocator`,
+//! and decodes reports written by `lol_alloc_diagnostics::ProfileAllocator`.
+
+use lol_alloc_diagnostics::{LogKind, LogRecord, ProfileCell};
+use std::{collections::BTreeMap, fmt};
+
+/// Statistics about an allocation log.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Analysis {
+    /// Sum of the sizes of all successful allocations.
+    pub total_allocated_bytes: u64,
+    pub allocations: usize,
+    pub failed_allocations: usize,
+    pub deallocations: usize,
+    /// Number of successful allocations by size class: the smallest power of two at least the size.
+    pub allocations_by_size_class: BTreeMap<u32, usize>,
+    /// Number of successful allocations of each `(size, align)`, most common first.
+    pub top_patterns: Vec<((u32, u32), usize)>,
+}
+
+/// Number of entries kept in [Analysis::top_patterns].
+pub const TOP_PATTERNS: usize = 10;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The log does not contain a whole number of records.
+    Truncated { len: usize },
+    /// The record at `index` is invalid.
+    InvalidRecord { index: usize },
+    /// The profile report does not contain a whole number of cells.
+    TruncatedProfile { len: usize },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Truncated { len } => write!(
+                f,
+                "log length {len} is not a multiple of the record size {}",
+                LogRecord::SIZE
+            ),
+            Error::InvalidRecord { index } => write!(f, "record {index} is invalid"),
+            Error::TruncatedProfile { len } => write!(
+                f,
+                "profile length {len} is not a multiple of the cell size {}",
+                ProfileCell::SIZE
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Parses a log into its records.
+pub fn parse(log: &[u8]) -> Result<Vec<LogRecord>, Error> {
+    let chunks = log.chunks_exact(LogRecord::SIZE);
+    if !chunks.remainder().is_empty() {
+        return Err(Error::Truncated { len: log.len() });
+    }
+    chunks
+        .enumerate()
+        .map(|(index, chunk)| {
+            LogRecord::from_bytes(chunk.try_into().unwrap()).ok_or(Error::InvalidRecord { index })
+        })
+        .collect()
+}
+
+/// Computes statistics for a log.
+pub fn analyze(log: &[u8]) -> Result<Analysis, Error> {
+    let mut analysis = Analysis::default();
+    let mut patterns: BTreeMap<(u32, u32), usize> = BTreeMap::new();
+    for record in parse(log)? {
+        match record.kind {
+            LogKind::Alloc if record.ptr == 0 => analysis.failed_allocations += 1,
+            LogKind::Alloc => {
+                analysis.allocations += 1;
+                analysis.total_allocated_bytes += u64::from(record.size);
+                *analysis
+                    .allocations_by_size_class
+                    .entry(record.size.next_power_of_two())
+                    .or_default() += 1;
+                *patterns.entry((record.size, record.align)).or_default() += 1;
+            }
+            LogKind::Dealloc => analysis.deallocations += 1,
+        }
+    }
+    let mut patterns: Vec<_> = patterns.into_iter().collect();
+    // Stable, so ties stay ordered by size then alignment.
+    patterns.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+    patterns.truncate(TOP_PATTERNS);
+    analysis.top_patterns = patterns;
+    Ok(analysis)
+}
+
+impl fmt::Display for Analysis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Total allocated bytes: {}", self.total_allocated_bytes)?;
+        writeln!(
+            f,
+            "Allocations: {} ({} failed), deallocations: {}",
+            self.allocations, self.failed_allocations, self.deallocations
+        )?;
+        writeln!(f, "Allocations by size class:")?;
+        for (class, count) in &self.allocations_by_size_class {
+            writeln!(f, "  <= {class}: {count}")?;
+        }
+        writeln!(f, "Top allocation patterns:")?;
+        for ((size, align), count) in &self.top_patterns {
+            writeln!(f, "  size {size}, align {align}: {count}")?;
+        }
+        Ok(())
+    }
+}
+
+/// A report written by `ProfileAllocator::write_report`.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Profile {
+    /// Cells which have had allocations, ordered by tag then size class.
+    pub cells: Vec<ProfileCell>,
+}
+
+/// Decodes a profile report.
+pub fn parse_profile(report: &[u8]) -> Result<Profile, Error> {
+    let chunks = report.chunks_exact(ProfileCell::SIZE);
+    if !chunks.remainder().is_empty() {
+        return Err(Error::TruncatedProfile { len: report.len() });
+    }
+    Ok(Profile {
+        cells: chunks
+            .map(|chunk| ProfileCell::from_bytes(chunk.try_into().unwrap()))
+            .collect(),
+    })
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut tag = None;
+        for cell in &self.cells {
+            if tag != Some(cell.tag) {
+                writeln!(f, "Tag {}:", cell.tag)?;
+                tag = Some(cell.tag);
+            }
+            writeln!(
+                f,
+                "  <= {}: {} live ({} bytes), {} allocated",
+                1u128 << cell.size_class,
+                cell.live_count,
+                cell.live_bytes,
+                cell.allocations
+            )?;
+        }
+        Ok(())
+    }
+}
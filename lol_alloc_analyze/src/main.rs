@@ -0,0 +1,36 @@
+use std::{env, fs, process::ExitCode};
+
+fn main() -> ExitCode {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    // With `--profile`, the file is a report from `ProfileAllocator::write_report` instead of a log.
+    let profile = args.first().is_some_and(|arg| arg == "--profile");
+    if profile {
+        args.remove(0);
+    }
+    let path = args
+        .into_iter()
+        .next()
+        .unwrap_or_else(|| "lol_alloc.log".into());
+    let log = match fs::read(&path) {
+        Ok(log) => log,
+        Err(error) => {
+            eprintln!("Failed to read {path}: {error}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let summary = if profile {
+        lol_alloc_analyze::parse_profile(&log).map(|profile| profile.to_string())
+    } else {
+        lol_alloc_analyze::analyze(&log).map(|analysis| analysis.to_string())
+    };
+    match summary {
+        Ok(summary) => {
+            print!("{summary}");
+            ExitCode::SUCCESS
+        }
+        Err(error) => {
+            eprintln!("Invalid log {path}: {error}");
+            ExitCode::FAILURE
+        }
+    }
+}
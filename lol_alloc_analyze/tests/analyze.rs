@@ -0,0 +1,65 @@
+use lol_alloc_analyze::{analyze, parse, Error};
+use lol_alloc_diagnostics::{LogKind, LogRecord};
+
+fn record(kind: LogKind, ptr: u32, size: u32, align: u32) -> LogRecord {
+    LogRecord {
+        kind,
+        ptr,
+        size,
+        align,
+    }
+}
+
+fn encode(records: &[LogRecord]) -> Vec<u8> {
+    records.iter().flat_map(LogRecord::to_bytes).collect()
+}
+
+#[test]
+fn parses_records() {
+    let records = [
+        record(LogKind::Alloc, 0x1000, 24, 8),
+        record(LogKind::Dealloc, 0x1000, 24, 8),
+    ];
+    assert_eq!(parse(&encode(&records)), Ok(records.to_vec()));
+}
+
+#[test]
+fn statistics() {
+    let log = encode(&[
+        record(LogKind::Alloc, 0x1000, 24, 8),
+        record(LogKind::Alloc, 0x2000, 24, 8),
+        record(LogKind::Alloc, 0x3000, 100, 4),
+        record(LogKind::Alloc, 0, 1 << 30, 1),
+        record(LogKind::Dealloc, 0x2000, 24, 8),
+        record(LogKind::Alloc, 0x4000, 32, 16),
+    ]);
+    let analysis = analyze(&log).unwrap();
+    assert_eq!(analysis.total_allocated_bytes, 24 + 24 + 100 + 32);
+    assert_eq!(analysis.allocations, 4);
+    assert_eq!(analysis.failed_allocations, 1);
+    assert_eq!(analysis.deallocations, 1);
+    assert_eq!(
+        analysis
+            .allocations_by_size_class
+            .into_iter()
+            .collect::<Vec<_>>(),
+        [(32, 3), (128, 1)]
+    );
+    assert_eq!(
+        analysis.top_patterns,
+        [((24, 8), 2), ((32, 16), 1), ((100, 4), 1)]
+    );
+}
+
+#[test]
+fn invalid_logs() {
+    let mut log = encode(&[record(LogKind::Alloc, 0x1000, 24, 8)]);
+    assert_eq!(
+        analyze(&log[..LogRecord::SIZE - 1]),
+        Err(Error::Truncated {
+            len: LogRecord::SIZE - 1
+        })
+    );
+    log[0] = 7;
+    assert_eq!(analyze(&log), Err(Error::InvalidRecord { index: 0 }));
+}
@@ -0,0 +1,76 @@
+use lol_alloc::HostAllocator;
+use lol_alloc_analyze::{parse_profile, Error};
+use lol_alloc_diagnostics::{size_class, ProfileAllocator, ProfileCell};
+use std::alloc::{GlobalAlloc, Layout};
+
+fn report<T, const TAGS: usize>(allocator: &ProfileAllocator<T, TAGS>) -> Vec<u8> {
+    let mut report = vec![0; allocator.write_report(&mut [])];
+    allocator.write_report(&mut report);
+    report
+}
+
+#[test]
+fn round_trip() {
+    let allocator = ProfileAllocator::<_, 4>::new(HostAllocator);
+    let small = Layout::from_size_align(24, 8).unwrap();
+    let large = Layout::from_size_align(1000, 16).unwrap();
+    let c = unsafe {
+        let a = allocator.alloc(small);
+        allocator.set_tag(3);
+        let b = allocator.alloc(small);
+        let c = allocator.alloc(large);
+        allocator.dealloc(a, small);
+        allocator.dealloc(b, small);
+        c
+    };
+    let profile = parse_profile(&report(&allocator)).unwrap();
+    assert_eq!(
+        profile.cells,
+        [
+            ProfileCell {
+                tag: 0,
+                size_class: size_class(24) as u8,
+                live_count: 0,
+                live_bytes: 0,
+                allocations: 1,
+            },
+            ProfileCell {
+                tag: 3,
+                size_class: size_class(24) as u8,
+                live_count: 0,
+                live_bytes: 0,
+                allocations: 1,
+            },
+            ProfileCell {
+                tag: 3,
+                size_class: size_class(1000) as u8,
+                live_count: 1,
+                live_bytes: 1000,
+                allocations: 1,
+            },
+        ]
+    );
+    assert_eq!(
+        profile.to_string(),
+        "Tag 0:\n  <= 32: 0 live (0 bytes), 1 allocated\n\
+         Tag 3:\n  <= 32: 0 live (0 bytes), 1 allocated\n  <= 1024: 1 live (1000 bytes), 1 allocated\n"
+    );
+    unsafe { allocator.dealloc(c, large) };
+}
+
+#[test]
+fn empty_profile() {
+    let allocator = ProfileAllocator::<_, 1>::new(HostAllocator);
+    assert_eq!(parse_profile(&report(&allocator)).unwrap().cells, []);
+}
+
+#[test]
+fn truncated_profile() {
+    let bytes = [0; ProfileCell::SIZE + 1];
+    assert_eq!(
+        parse_profile(&bytes),
+        Err(Error::TruncatedProfile {
+            len: ProfileCell::SIZE + 1
+        })
+    );
+}
@@ -0,0 +1,326 @@
+use crate::atomic::{AtomicU32, Ordering};
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    mem::size_of,
+    ptr::null_mut,
+};
+use lol_alloc::{AllocatorStats, CountsAllocations, Stats};
+
+/// An operation made through an [IdAllocator], passed to its callback.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum IdEvent {
+    /// Allocated `layout` at `ptr`, as allocation `id`.
+    Alloc {
+        id: u32,
+        ptr: *mut u8,
+        layout: Layout,
+    },
+    /// Freed allocation `id` at `ptr`, which was allocated with `layout`.
+    Dealloc {
+        id: u32,
+        ptr: *mut u8,
+        layout: Layout,
+    },
+    /// Reallocated allocation `id` at `ptr` (allocated with `layout`) to `new_size` bytes at `new_ptr`, as allocation `new_id`.
+    /// If it failed, `new_ptr` is null and `new_id` is `id`, which is still live.
+    Realloc {
+        id: u32,
+        ptr: *mut u8,
+        layout: Layout,
+        new_id: u32,
+        new_ptr: *mut u8,
+        new_size: usize,
+    },
+}
+
+/// Wraps an allocator, giving each allocation an id and reporting every operation with it.
+///
+/// Addresses are reused as soon as they are freed, so a trace of pointers can't always tell which allocation a free is of.
+/// Ids are never reused (until they wrap), so events can be paired by id instead:
+/// each allocation's id is stored in a hidden header before it, and passed to the callback with every operation on it.
+/// Reallocating gives the allocation a new id, and reports both.
+///
+/// Ids count up from 0 in order of allocation, wrapping after `u32::MAX`.
+/// So after 4 billion allocations an id may match more than one live allocation: traces that long are accepted to be ambiguous.
+///
+/// Each allocation's header takes its alignment (and at least 4 bytes) before it,
+/// and allocations are aligned to at least 4.
+pub struct IdAllocator<T> {
+    inner: T,
+    next_id: AtomicU32,
+    on_event: fn(IdEvent),
+}
+
+impl<T> IdAllocator<T> {
+    /// Wraps `inner`, calling `on_event` after each successful operation.
+    ///
+    /// `on_event` runs inside the allocator, so must not allocate from it.
+    pub const fn new(inner: T, on_event: fn(IdEvent)) -> Self {
+        IdAllocator {
+            inner,
+            next_id: AtomicU32::new(0),
+            on_event,
+        }
+    }
+
+    /// The id of a live allocation made by this allocator.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be a live allocation from this allocator.
+    pub unsafe fn id_of(&self, ptr: *const u8) -> u32 {
+        *header(ptr)
+    }
+
+    /// Takes the next id, wrapping after `u32::MAX`.
+    fn take_id(&self) -> u32 {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+/// The header holding the id of the allocation returned at `ptr`.
+fn header(ptr: *const u8) -> *mut u32 {
+    ptr.wrapping_sub(size_of::<u32>()) as *mut u32
+}
+
+/// The inner allocation's layout for `layout`, with space for the header before it.
+fn inner_layout(layout: Layout) -> Option<Layout> {
+    // Alignments are powers of two, so a prefix of `align` bytes ends with an aligned place for the header.
+    let align = layout.align().max(size_of::<u32>());
+    Layout::from_size_align(layout.size().checked_add(align)?, align).ok()
+}
+
+unsafe impl<T: GlobalAlloc> GlobalAlloc for IdAllocator<T> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let Some(inner_layout) = inner_layout(layout) else {
+            return null_mut();
+        };
+        let raw = self.inner.alloc(inner_layout);
+        if raw.is_null() {
+            return raw;
+        }
+        let ptr = raw.add(inner_layout.align());
+        let id = self.take_id();
+        header(ptr).write(id);
+        (self.on_event)(IdEvent::Alloc { id, ptr, layout });
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // Layout was valid when allocated, so this is too.
+        let inner_layout = inner_layout(layout).unwrap_unchecked();
+        let id = *header(ptr);
+        self.inner
+            .dealloc(ptr.sub(inner_layout.align()), inner_layout);
+        (self.on_event)(IdEvent::Dealloc { id, ptr, layout });
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let inner_layout = inner_layout(layout).unwrap_unchecked();
+        let prefix = inner_layout.align();
+        let id = *header(ptr);
+        let new_ptr = match new_size.checked_add(prefix) {
+            // The header moves with the contents, so stays the same distance before them.
+            Some(new_inner_size) => {
+                let raw = self
+                    .inner
+                    .realloc(ptr.sub(prefix), inner_layout, new_inner_size);
+                if raw.is_null() {
+                    raw
+                } else {
+                    raw.add(prefix)
+                }
+            }
+            None => null_mut(),
+        };
+        let new_id = if new_ptr.is_null() {
+            id
+        } else {
+            let new_id = self.take_id();
+            header(new_ptr).write(new_id);
+            new_id
+        };
+        (self.on_event)(IdEvent::Realloc {
+            id,
+            ptr,
+            layout,
+            new_id,
+            new_ptr,
+            new_size,
+        });
+        new_ptr
+    }
+}
+
+impl<T: AllocatorStats> AllocatorStats for IdAllocator<T> {
+    fn stats(&self) -> Stats {
+        self.inner.stats()
+    }
+}
+
+impl<T: CountsAllocations> CountsAllocations for IdAllocator<T> {}
+
+#[cfg(test)]
+mod tests {
+    use super::{IdAllocator, IdEvent};
+    use alloc::{collections::BTreeMap, vec::Vec};
+    use core::{
+        alloc::{GlobalAlloc, Layout},
+        cell::RefCell,
+    };
+    use lol_alloc::{
+        testing::{conformance, MockGrower},
+        FreeListAllocator, HostAllocator,
+    };
+
+    std::thread_local! {
+        /// Events passed to [record].
+        static EVENTS: RefCell<Vec<IdEvent>> = const { RefCell::new(Vec::new()) };
+    }
+
+    fn record(event: IdEvent) {
+        EVENTS.with_borrow_mut(|events| events.push(event));
+    }
+
+    fn take_events() -> Vec<IdEvent> {
+        EVENTS.take()
+    }
+
+    #[test]
+    fn ids_conformance() {
+        let allocator = IdAllocator::new(HostAllocator, record);
+        conformance(&allocator);
+        take_events();
+    }
+
+    #[test]
+    fn ids_count_up() {
+        let allocator = IdAllocator::new(HostAllocator, record);
+        let layouts = [
+            Layout::from_size_align(1, 1).unwrap(),
+            Layout::from_size_align(100, 8).unwrap(),
+            Layout::from_size_align(32, 64).unwrap(),
+        ];
+        unsafe {
+            let ptrs: Vec<_> = layouts.iter().map(|&l| allocator.alloc(l)).collect();
+            for (i, (&ptr, layout)) in ptrs.iter().zip(layouts).enumerate() {
+                assert_eq!(allocator.id_of(ptr), i as u32);
+                assert_eq!(ptr as usize % layout.align(), 0);
+                ptr.write_bytes(0xFF, layout.size());
+            }
+            let moved = allocator.realloc(ptrs[1], layouts[1], 4096);
+            assert_eq!(allocator.id_of(moved), 3);
+            allocator.dealloc(moved, Layout::from_size_align(4096, 8).unwrap());
+            allocator.dealloc(ptrs[0], layouts[0]);
+            allocator.dealloc(ptrs[2], layouts[2]);
+            let events = take_events();
+            assert_eq!(
+                events[3],
+                IdEvent::Realloc {
+                    id: 1,
+                    ptr: ptrs[1],
+                    layout: layouts[1],
+                    new_id: 3,
+                    new_ptr: moved,
+                    new_size: 4096,
+                }
+            );
+            assert_eq!(
+                events[5],
+                IdEvent::Dealloc {
+                    id: 0,
+                    ptr: ptrs[0],
+                    layout: layouts[0]
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn ids_wrap() {
+        let allocator = IdAllocator::new(HostAllocator, record);
+        allocator.next_id.store(u32::MAX, super::Ordering::Relaxed);
+        let layout = Layout::new::<u64>();
+        unsafe {
+            let last = allocator.alloc(layout);
+            let first = allocator.alloc(layout);
+            assert_eq!(allocator.id_of(last), u32::MAX);
+            assert_eq!(allocator.id_of(first), 0);
+            allocator.dealloc(last, layout);
+            allocator.dealloc(first, layout);
+        }
+        take_events();
+    }
+
+    /// Pairing events by id matches each free to its allocation, even when addresses are reused.
+    #[test]
+    fn ids_pair_with_address_reuse() {
+        let allocator = IdAllocator::new(FreeListAllocator::new_in(MockGrower::new()), record);
+        let layout = Layout::from_size_align(24, 8).unwrap();
+        let grown = Layout::from_size_align(48, 8).unwrap();
+        unsafe {
+            let mut live: Vec<(*mut u8, Layout)> = Vec::new();
+            for i in 0..200usize {
+                // Free the oldest, newest or a middle allocation, so freed memory is reused in varying orders.
+                if live.len() >= 4 {
+                    let (ptr, layout) = live.remove([0, live.len() - 1, live.len() / 2][i % 3]);
+                    allocator.dealloc(ptr, layout);
+                }
+                live.push((allocator.alloc(layout), layout));
+                if i % 5 == 0 {
+                    let (ptr, layout) = live.remove(0);
+                    let size = if layout == grown { 24 } else { 48 };
+                    let moved = allocator.realloc(ptr, layout, size);
+                    assert!(!moved.is_null());
+                    live.push((moved, Layout::from_size_align(size, 8).unwrap()));
+                }
+            }
+            for (ptr, layout) in live {
+                allocator.dealloc(ptr, layout);
+            }
+        }
+        let events = take_events();
+
+        // Each event as allocations and frees of (id, pointer), with a reallocation freeing one and allocating the other.
+        let mut ops: Vec<(bool, u32, *mut u8)> = Vec::new();
+        for event in events {
+            match event {
+                IdEvent::Alloc { id, ptr, .. } => ops.push((true, id, ptr)),
+                IdEvent::Dealloc { id, ptr, .. } => ops.push((false, id, ptr)),
+                IdEvent::Realloc {
+                    id,
+                    ptr,
+                    new_id,
+                    new_ptr,
+                    ..
+                } => ops.extend([(false, id, ptr), (true, new_id, new_ptr)]),
+            }
+        }
+
+        // Live allocations by id, and every id allocated at each address.
+        let mut by_id: BTreeMap<u32, *mut u8> = BTreeMap::new();
+        let mut by_address: BTreeMap<*mut u8, Vec<u32>> = BTreeMap::new();
+        let (mut allocs, mut frees, mut ambiguous_frees) = (0, 0, 0);
+        for (is_alloc, id, ptr) in ops {
+            if is_alloc {
+                allocs += 1;
+                assert!(by_id.insert(id, ptr).is_none(), "id {id} reused");
+                by_address.entry(ptr).or_default().push(id);
+            } else {
+                frees += 1;
+                // Exactly one live allocation has each id.
+                assert_eq!(by_id.remove(&id), Some(ptr));
+                // Every allocation ever made at the address is a candidate when matching by address.
+                if by_address[&ptr].len() > 1 {
+                    ambiguous_frees += 1;
+                }
+            }
+        }
+        // Every allocation was freed exactly once.
+        assert!(by_id.is_empty());
+        assert_eq!(allocs, frees);
+        assert!(allocs > 200);
+        // Matching by address alone would have had several candidates for most frees.
+        assert!(ambiguous_frees > frees / 2, "{ambiguous_frees} of {frees}");
+    }
+}
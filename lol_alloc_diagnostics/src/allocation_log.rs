@@ -0,0 +1,206 @@
+/// Kind of operation a [LogRecord] records.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(u8)]
+pub enum LogKind {
+    Alloc = 0,
+    Dealloc = 1,
+}
+
+/// One allocator operation, in a binary log of allocations for offline heap profiling.
+///
+/// A log is a sequence of [LogRecord::SIZE] byte records (see [LogRecord::to_bytes]), with no header.
+/// Pointers and sizes are 32 bits, since this is intended for wasm32 heaps.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct LogRecord {
+    pub kind: LogKind,
+    /// The pointer allocated or freed. Null for failed allocations.
+    pub ptr: u32,
+    pub size: u32,
+    pub align: u32,
+}
+
+impl LogRecord {
+    /// Size of an encoded record, in bytes.
+    pub const SIZE: usize = 13;
+
+    /// Encodes as `kind`, then `ptr`, `size` and `align` as little endian `u32`s.
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        bytes[0] = self.kind as u8;
+        bytes[1..5].copy_from_slice(&self.ptr.to_le_bytes());
+        bytes[5..9].copy_from_slice(&self.size.to_le_bytes());
+        bytes[9..13].copy_from_slice(&self.align.to_le_bytes());
+        bytes
+    }
+
+    /// Decodes a record encoded by [LogRecord::to_bytes].
+    /// Returns `None` if `kind` is invalid.
+    pub fn from_bytes(bytes: &[u8; Self::SIZE]) -> Option<Self> {
+        let kind = match bytes[0] {
+            0 => LogKind::Alloc,
+            1 => LogKind::Dealloc,
+            _ => return None,
+        };
+        let word =
+            |i: usize| u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+        Some(LogRecord {
+            kind,
+            ptr: word(1),
+            size: word(5),
+            align: word(9),
+        })
+    }
+}
+
+#[cfg(all(feature = "wasi", target_os = "wasi"))]
+pub use wasi::{WasiLogAllocator, WasiLogHooks};
+
+#[cfg(all(feature = "wasi", target_os = "wasi"))]
+mod wasi {
+    use super::{LogKind, LogRecord};
+    use crate::{
+        atomic::{AtomicUsize, Ordering},
+        AllocHooks, Hooked,
+    };
+    use core::alloc::Layout;
+    use lol_alloc::CountsAllocations;
+
+    #[repr(C)]
+    struct Ciovec {
+        buf: *const u8,
+        buf_len: usize,
+    }
+
+    #[link(wasm_import_module = "wasi_snapshot_preview1")]
+    extern "C" {
+        fn path_open(
+            fd: u32,
+            dirflags: u32,
+            path: *const u8,
+            path_len: usize,
+            oflags: u16,
+            fs_rights_base: u64,
+            fs_rights_inheriting: u64,
+            fdflags: u16,
+            opened_fd: *mut u32,
+        ) -> u16;
+        fn fd_write(fd: u32, iovs: *const Ciovec, iovs_len: usize, nwritten: *mut usize) -> u16;
+    }
+
+    /// The first preopened directory.
+    const PREOPEN_FD: u32 = 3;
+    const OFLAGS_CREAT: u16 = 1;
+    const OFLAGS_TRUNC: u16 = 8;
+    const FDFLAGS_APPEND: u16 = 1;
+    const RIGHTS_FD_WRITE: u64 = 1 << 6;
+    const LOG_PATH: &[u8] = b"lol_alloc.log";
+
+    /// `fd` value before the log has been opened.
+    const UNOPENED: usize = usize::MAX;
+    /// `fd` value if opening the log failed.
+    const FAILED: usize = usize::MAX - 1;
+
+    /// Wraps an allocator, appending a [LogRecord] to `lol_alloc.log` (in the first preopened directory) for every operation.
+    ///
+    /// The file is created (or truncated) on first use.
+    /// If it can't be opened, nothing is logged.
+    /// Logging does not allocate.
+    pub type WasiLogAllocator<T> = Hooked<T, WasiLogHooks>;
+
+    /// The [AllocHooks] of a [WasiLogAllocator].
+    pub struct WasiLogHooks {
+        fd: AtomicUsize,
+    }
+
+    impl<T> WasiLogAllocator<T> {
+        pub const fn new(inner: T) -> Self {
+            Hooked::with_hooks(
+                inner,
+                WasiLogHooks {
+                    fd: AtomicUsize::new(UNOPENED),
+                },
+            )
+        }
+    }
+
+    impl WasiLogHooks {
+        fn log(&self, kind: LogKind, ptr: *mut u8, layout: Layout) {
+            let fd = match self.fd.load(Ordering::Relaxed) {
+                UNOPENED => {
+                    let fd = open_log().map_or(FAILED, |fd| fd as usize);
+                    self.fd.store(fd, Ordering::Relaxed);
+                    fd
+                }
+                fd => fd,
+            };
+            if fd == FAILED {
+                return;
+            }
+            let record = LogRecord {
+                kind,
+                ptr: ptr as u32,
+                size: layout.size() as u32,
+                align: layout.align() as u32,
+            }
+            .to_bytes();
+            let iov = Ciovec {
+                buf: record.as_ptr(),
+                buf_len: record.len(),
+            };
+            let mut written = 0;
+            // Errors are ignored: there is nowhere to report them.
+            unsafe { fd_write(fd as u32, &iov, 1, &mut written) };
+        }
+    }
+
+    fn open_log() -> Option<u32> {
+        let mut fd = 0;
+        let errno = unsafe {
+            path_open(
+                PREOPEN_FD,
+                0,
+                LOG_PATH.as_ptr(),
+                LOG_PATH.len(),
+                OFLAGS_CREAT | OFLAGS_TRUNC,
+                RIGHTS_FD_WRITE,
+                0,
+                FDFLAGS_APPEND,
+                &mut fd,
+            )
+        };
+        (errno == 0).then_some(fd)
+    }
+
+    impl AllocHooks for WasiLogHooks {
+        fn on_alloc(&self, layout: Layout, result: *mut u8) {
+            self.log(LogKind::Alloc, result, layout);
+        }
+
+        fn on_dealloc(&self, ptr: *mut u8, layout: Layout) {
+            self.log(LogKind::Dealloc, ptr, layout);
+        }
+    }
+
+    impl<T: CountsAllocations> CountsAllocations for WasiLogAllocator<T> {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LogKind, LogRecord};
+
+    #[test]
+    fn round_trip() {
+        let record = LogRecord {
+            kind: LogKind::Dealloc,
+            ptr: 0x1234_5678,
+            size: 100,
+            align: 8,
+        };
+        let bytes = record.to_bytes();
+        assert_eq!(bytes, [1, 0x78, 0x56, 0x34, 0x12, 100, 0, 0, 0, 8, 0, 0, 0]);
+        assert_eq!(LogRecord::from_bytes(&bytes), Some(record));
+        let mut invalid = bytes;
+        invalid[0] = 2;
+        assert_eq!(LogRecord::from_bytes(&invalid), None);
+    }
+}
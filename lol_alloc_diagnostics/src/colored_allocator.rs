@@ -0,0 +1,202 @@
+use crate::atomic::{AtomicUsize, Ordering};
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    ptr::null_mut,
+};
+use lol_alloc::{AllocatorStats, CountsAllocations, Stats};
+
+/// Hidden prefix stored immediately before each allocation made by [ColoredAllocator].
+#[repr(C, align(16))]
+struct Header {
+    /// [MAGIC] while the allocation is live.
+    magic: u32,
+    color: u32,
+    /// Bytes from the start of the header to the end of the allocation.
+    size: usize,
+}
+
+/// Marks live [Header]s.
+const MAGIC: u32 = 0x6C6F_6C21;
+
+/// Size of the units [ColoredAllocator::visualize_heap] reports on.
+pub const GRANULE: usize = core::mem::size_of::<Header>();
+
+/// Debugging wrapper for an allocator, which colors each allocation to allow visualizing the heap layout.
+///
+/// Colors are assigned in order of allocation, wrapping after `COLORS` (which must be between 1 and 255).
+/// Each allocation gets a hidden [GRANULE] sized prefix holding its color,
+/// and is aligned to at least [GRANULE].
+pub struct ColoredAllocator<T, const COLORS: usize> {
+    inner: T,
+    next_color: AtomicUsize,
+}
+
+impl<T, const COLORS: usize> ColoredAllocator<T, COLORS> {
+    const VALID_COLORS: () = assert!(COLORS > 0 && COLORS < 256, "COLORS must be in 1..=255");
+
+    pub const fn new(inner: T) -> Self {
+        let () = Self::VALID_COLORS;
+        ColoredAllocator {
+            inner,
+            next_color: AtomicUsize::new(0),
+        }
+    }
+
+    /// The color of a live allocation made by this allocator.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be a live allocation from this allocator.
+    pub unsafe fn color_of(&self, ptr: *const u8) -> u8 {
+        (*header(ptr)).color as u8
+    }
+
+    /// Fills `out` with the color of each [GRANULE] of the `total` bytes starting at `base`:
+    /// `color + 1` for granules in live allocations with that color (including their prefix), and 0 for the rest.
+    ///
+    /// Only fills as many granules as fit in `out`.
+    /// Each byte can be rendered as one cell of an ASCII grid.
+    ///
+    /// # Safety
+    ///
+    /// `base` must be aligned to [GRANULE], and the `total` bytes starting at `base` must be readable.
+    pub unsafe fn visualize_heap(&self, base: *const u8, total: usize, out: &mut [u8]) {
+        let granules = (total / GRANULE).min(out.len());
+        out[..granules].fill(0);
+        let mut i = 0;
+        while i < granules {
+            let header = base.add(i * GRANULE) as *const Header;
+            if (*header).magic == MAGIC {
+                let end = (i + (*header).size.div_ceil(GRANULE)).min(granules);
+                out[i..end].fill((*header).color as u8 + 1);
+                i = end;
+            } else {
+                i += 1;
+            }
+        }
+    }
+}
+
+/// The header for the allocation returned at `ptr`.
+fn header(ptr: *const u8) -> *mut Header {
+    ptr.wrapping_sub(GRANULE) as *mut Header
+}
+
+/// The inner allocation's layout for `layout`, with space for the header before it.
+fn inner_layout(layout: Layout) -> Option<Layout> {
+    // Alignments are powers of two, so any alignment at least GRANULE is a multiple of it,
+    // and the prefix of `align` bytes ends with space for the header.
+    let align = layout.align().max(GRANULE);
+    Layout::from_size_align(layout.size().checked_add(align)?, align).ok()
+}
+
+unsafe impl<T: GlobalAlloc, const COLORS: usize> GlobalAlloc for ColoredAllocator<T, COLORS> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let Some(inner_layout) = inner_layout(layout) else {
+            return null_mut();
+        };
+        let raw = self.inner.alloc(inner_layout);
+        if raw.is_null() {
+            return raw;
+        }
+        let ptr = raw.add(inner_layout.align());
+        let color = self.next_color.fetch_add(1, Ordering::Relaxed) % COLORS;
+        header(ptr).write(Header {
+            magic: MAGIC,
+            color: color as u32,
+            size: GRANULE + layout.size(),
+        });
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // Layout was valid when allocated, so this is too.
+        let inner_layout = inner_layout(layout).unwrap_unchecked();
+        (*header(ptr)).magic = 0;
+        self.inner
+            .dealloc(ptr.sub(inner_layout.align()), inner_layout);
+    }
+}
+
+impl<T: AllocatorStats, const COLORS: usize> AllocatorStats for ColoredAllocator<T, COLORS> {
+    fn stats(&self) -> Stats {
+        self.inner.stats()
+    }
+}
+
+impl<T: CountsAllocations, const COLORS: usize> CountsAllocations for ColoredAllocator<T, COLORS> {}
+
+#[cfg(test)]
+mod tests {
+    use super::{ColoredAllocator, GRANULE};
+    use alloc::vec::Vec;
+    use core::alloc::{GlobalAlloc, Layout};
+    use lol_alloc::testing::conformance;
+    use lol_alloc::{
+        FreeListAllocator, GlobalArrayGrower, HostAllocator, LockedAllocator, PAGE_SIZE,
+    };
+
+    #[test]
+    fn colors() {
+        conformance(&ColoredAllocator::<_, 3>::new(HostAllocator::new()));
+        let allocator: ColoredAllocator<_, 3> = ColoredAllocator::new(HostAllocator::new());
+        let layouts = [
+            Layout::from_size_align(1, 1).unwrap(),
+            Layout::from_size_align(100, 8).unwrap(),
+            Layout::from_size_align(32, 64).unwrap(),
+            Layout::from_size_align(GRANULE, GRANULE).unwrap(),
+            Layout::from_size_align(7, 2).unwrap(),
+        ];
+        unsafe {
+            let ptrs: Vec<*mut u8> = layouts.iter().map(|&l| allocator.alloc(l)).collect();
+            for (i, (&ptr, layout)) in ptrs.iter().zip(layouts).enumerate() {
+                assert_eq!(allocator.color_of(ptr), (i % 3) as u8);
+                assert_eq!(ptr as usize % layout.align(), 0);
+                ptr.write_bytes(0xFF, layout.size());
+            }
+            for (&ptr, layout) in ptrs.iter().zip(layouts) {
+                allocator.dealloc(ptr, layout);
+            }
+        }
+    }
+
+    #[test]
+    fn visualize_heap() {
+        static ALLOCATOR: ColoredAllocator<
+            LockedAllocator<FreeListAllocator<GlobalArrayGrower<1>>>,
+            2,
+        > = ColoredAllocator::new(LockedAllocator::new(FreeListAllocator::new_in(
+            GlobalArrayGrower::new(),
+        )));
+        let allocator = &ALLOCATOR;
+        let small = Layout::from_size_align(1, 1).unwrap();
+        let large = Layout::from_size_align(GRANULE * 3, GRANULE).unwrap();
+        let mut out = vec![0xAA; PAGE_SIZE / GRANULE];
+        unsafe {
+            // The free list allocates from the end of its only page downwards.
+            let a = allocator.alloc(small);
+            let base = a.add(GRANULE).sub(PAGE_SIZE) as *const u8;
+            assert_eq!(base as usize % PAGE_SIZE, 0);
+            let b = allocator.alloc(large);
+            let c = allocator.alloc(small);
+            allocator.visualize_heap(base, PAGE_SIZE, &mut out);
+            let end = out.len();
+            // Each allocation is its header followed by its content.
+            let mut expected = vec![0; end];
+            expected[end - 2..].copy_from_slice(&[1, 1]);
+            expected[end - 6..end - 2].fill(2);
+            expected[end - 8..end - 6].copy_from_slice(&[1, 1]);
+            assert_eq!(out, expected);
+
+            allocator.dealloc(b, large);
+            allocator.visualize_heap(base, PAGE_SIZE, &mut out);
+            expected[end - 6..end - 2].fill(0);
+            assert_eq!(out, expected);
+
+            allocator.dealloc(a, small);
+            allocator.dealloc(c, small);
+            allocator.visualize_heap(base, PAGE_SIZE, &mut out);
+            assert!(out.iter().all(|&color| color == 0));
+        }
+    }
+}
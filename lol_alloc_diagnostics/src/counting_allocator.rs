@@ -0,0 +1,145 @@
+use crate::{
+    atomic::{AtomicUsize, Ordering},
+    AllocHooks, Hooked,
+};
+use core::alloc::Layout;
+use lol_alloc::{AllocatorStats, CountsAllocations, Stats};
+
+/// Wraps an allocator, counting allocations to provide [Stats].
+pub type CountingAllocator<T> = Hooked<T, CountingHooks>;
+
+/// The [AllocHooks] of a [CountingAllocator].
+pub struct CountingHooks {
+    live_bytes: AtomicUsize,
+    live_allocations: AtomicUsize,
+    peak_bytes: AtomicUsize,
+    failed_allocations: AtomicUsize,
+}
+
+impl CountingHooks {
+    pub const fn new() -> Self {
+        CountingHooks {
+            live_bytes: AtomicUsize::new(0),
+            live_allocations: AtomicUsize::new(0),
+            peak_bytes: AtomicUsize::new(0),
+            failed_allocations: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl Default for CountingHooks {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> CountingAllocator<T> {
+    pub const fn new(inner: T) -> Self {
+        Hooked::with_hooks(inner, CountingHooks::new())
+    }
+}
+
+impl AllocHooks for CountingHooks {
+    fn on_alloc(&self, layout: Layout, result: *mut u8) {
+        if result.is_null() {
+            self.failed_allocations.fetch_add(1, Ordering::Relaxed);
+        } else {
+            let live = self.live_bytes.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+            self.peak_bytes.fetch_max(live, Ordering::Relaxed);
+            self.live_allocations.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn on_dealloc(&self, _ptr: *mut u8, layout: Layout) {
+        self.live_bytes.fetch_sub(layout.size(), Ordering::Relaxed);
+        self.live_allocations.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    fn stats(&self, inner: Stats) -> Stats {
+        Stats {
+            live_bytes: Some(self.live_bytes.load(Ordering::Relaxed)),
+            live_allocations: Some(self.live_allocations.load(Ordering::Relaxed)),
+            peak_bytes: Some(self.peak_bytes.load(Ordering::Relaxed)),
+            failed_allocations: Some(self.failed_allocations.load(Ordering::Relaxed)),
+            ..Stats::default()
+        }
+        .or(inner)
+    }
+}
+
+/// The counts are always known.
+impl<T: AllocatorStats> CountsAllocations for CountingAllocator<T> {}
+
+#[cfg(test)]
+mod tests {
+    use super::CountingAllocator;
+    use alloc::vec::Vec;
+    use core::alloc::{GlobalAlloc, Layout};
+    use lol_alloc::{
+        AllocatorStats, FreeListAllocator, GlobalArrayGrower, LockedAllocator, PAGE_SIZE,
+    };
+
+    /// Size of the free list nodes `FreeListAllocator` rounds allocations up to.
+    const NODE_SIZE: usize = core::mem::size_of::<usize>() * 2;
+
+    #[test]
+    fn stats_through_stack() {
+        static ALLOCATOR: LockedAllocator<
+            CountingAllocator<FreeListAllocator<GlobalArrayGrower<16>>>,
+        > = LockedAllocator::new(CountingAllocator::new(FreeListAllocator::new_in(
+            GlobalArrayGrower::new(),
+        )));
+        let allocator = &ALLOCATOR;
+        let mut live: Vec<(*mut u8, Layout)> = vec![];
+        let mut peak = 0;
+        let mut failed = 0;
+        unsafe {
+            for i in 1..200 {
+                let layout = Layout::from_size_align(i * 7, 1 << (i % 5)).unwrap();
+                live.push((allocator.alloc(layout), layout));
+                if i % 3 == 0 {
+                    let (ptr, layout) = live.swap_remove(i % live.len());
+                    allocator.dealloc(ptr, layout);
+                }
+                peak = peak.max(live.iter().map(|(_, layout)| layout.size()).sum());
+                if i % 50 == 0 {
+                    // Larger than the grower.
+                    let ptr = allocator.alloc(Layout::from_size_align(PAGE_SIZE * 32, 1).unwrap());
+                    assert!(ptr.is_null());
+                    failed += 1;
+                }
+            }
+        }
+        let stats = allocator.stats();
+        assert_eq!(
+            stats.live_bytes,
+            Some(live.iter().map(|(_, layout)| layout.size()).sum())
+        );
+        assert_eq!(stats.live_allocations, Some(live.len()));
+        assert_eq!(stats.peak_bytes, Some(peak));
+        assert_eq!(stats.failed_allocations, Some(failed));
+        // The free list accounts for all of the grown pages not used by live allocations.
+        let used: usize = live
+            .iter()
+            .map(|(_, layout)| layout.size().max(NODE_SIZE).next_multiple_of(NODE_SIZE))
+            .sum();
+        let grown = stats.free_bytes.unwrap() + used;
+        assert!(grown > 0);
+        assert_eq!(grown % PAGE_SIZE, 0);
+    }
+
+    #[test]
+    fn counts_realloc() {
+        let allocator = CountingAllocator::new(lol_alloc::HostAllocator);
+        let layout = Layout::new::<u64>();
+        unsafe {
+            let ptr = allocator.alloc(layout);
+            let ptr = allocator.realloc(ptr, layout, 100);
+            let stats = allocator.stats();
+            assert_eq!(stats.live_bytes, Some(100));
+            assert_eq!(stats.live_allocations, Some(1));
+            allocator.dealloc(ptr, Layout::from_size_align(100, 8).unwrap());
+        }
+        assert_eq!(allocator.stats().live_bytes, Some(0));
+    }
+}
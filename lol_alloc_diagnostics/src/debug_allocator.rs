@@ -0,0 +1,455 @@
+use crate::{
+    atomic::{AtomicUsize, Ordering},
+    CountingAllocator,
+};
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    mem::{align_of, size_of},
+    ptr::null_mut,
+};
+use lol_alloc::{AllocatorStats, CountsAllocations, Stats};
+
+/// Byte new allocations are filled with, so reads of uninitialized memory stand out.
+pub const ALLOC_POISON: u8 = 0xAA;
+/// Byte freed allocations are filled with, so use after free stands out.
+pub const FREE_POISON: u8 = 0xDD;
+/// Byte the redzones around each allocation are filled with.
+pub const REDZONE_BYTE: u8 = 0xFD;
+/// Bytes of redzone after each allocation (and, at least, before it).
+pub const REDZONE: usize = 16;
+
+/// Marks the layout header of a live allocation.
+const LIVE: usize = 0x11FE_A110;
+
+/// Wraps an allocator with all the debugging checks in this crate, for dropping in during development.
+///
+/// This detects:
+/// - Writes just past either end of an allocation, using [REDZONE] bytes of [REDZONE_BYTE] on each side, checked when it is freed.
+/// - Allocations freed with a different layout than they were allocated with.
+/// - Double frees (and frees of pointers that were never allocated), which are not forwarded to the wrapped allocator.
+/// - Use of uninitialized or freed memory, which is filled with [ALLOC_POISON] or [FREE_POISON].
+///
+/// Problems are counted rather than panicking (which would allocate), and read with [DebugAllocator::report].
+/// Allocations are also counted, so [DebugReport::stats] shows leaks and peak usage.
+///
+/// The checks are layered, from the outside in:
+/// 1. Counting: outermost, so it counts the sizes the program asked for, not the padding the other layers add.
+/// 2. Redzones: outside the layout checks, so an underflow hits the redzone before it can corrupt the layout header,
+///    and is reported as the overflow it is.
+///    The redzones are only checked for live allocations, since a freed allocation's are poisoned.
+/// 3. Layout checks: a header before each allocation recording its layout and if it is live.
+///    Since this sees the layouts the redzones add, and those only depend on the layout asked for, any mismatch is still caught.
+/// 4. Poison: innermost, so it fills the whole block, including the other layers' headers and redzones.
+///    That way a freed block's header never looks live, which is what catches double frees.
+///
+/// Freeing a pointer whose allocation was freed and then reused by a new allocation can't be detected.
+/// Freeing with the wrong alignment moves where the header is looked for, so may be reported as an invalid free instead of a layout mismatch.
+pub struct DebugAllocator<T> {
+    inner: CountingAllocator<Redzones<T>>,
+}
+
+/// Problems found by a [DebugAllocator], from [DebugAllocator::report].
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct DebugReport {
+    /// Counts from the outermost layer, in the sizes the program allocated.
+    pub stats: Stats,
+    /// Allocations freed with a damaged redzone.
+    pub redzone_violations: usize,
+    /// Allocations freed with a different layout than they were allocated with.
+    pub layout_mismatches: usize,
+    /// Frees of pointers which were not live allocations, like double frees.
+    pub invalid_frees: usize,
+}
+
+impl DebugReport {
+    /// If no problems were found.
+    /// Leaks are not counted as problems: check [DebugReport::stats] for those.
+    pub fn is_clean(&self) -> bool {
+        self.redzone_violations == 0 && self.layout_mismatches == 0 && self.invalid_frees == 0
+    }
+}
+
+impl<T> DebugAllocator<T> {
+    pub const fn new(inner: T) -> Self {
+        DebugAllocator {
+            inner: CountingAllocator::new(Redzones {
+                inner: LayoutChecks {
+                    inner: Poison { inner },
+                    layout_mismatches: AtomicUsize::new(0),
+                    invalid_frees: AtomicUsize::new(0),
+                },
+                violations: AtomicUsize::new(0),
+            }),
+        }
+    }
+
+    /// Problems found so far, and the allocation counts.
+    pub fn report(&self) -> DebugReport
+    where
+        T: AllocatorStats,
+    {
+        let redzones = self.inner.inner();
+        let layout_checks = &redzones.inner;
+        DebugReport {
+            stats: self.inner.stats(),
+            redzone_violations: redzones.violations.load(Ordering::Relaxed),
+            layout_mismatches: layout_checks.layout_mismatches.load(Ordering::Relaxed),
+            invalid_frees: layout_checks.invalid_frees.load(Ordering::Relaxed),
+        }
+    }
+}
+
+unsafe impl<T: GlobalAlloc> GlobalAlloc for DebugAllocator<T> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.inner.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.inner.dealloc(ptr, layout);
+    }
+}
+
+impl<T: AllocatorStats> AllocatorStats for DebugAllocator<T> {
+    fn stats(&self) -> Stats {
+        self.inner.stats()
+    }
+}
+
+/// The counts are always known.
+impl<T: AllocatorStats> CountsAllocations for DebugAllocator<T> {}
+
+/// Surrounds allocations with redzones, checking them when freed.
+///
+/// The first word of the front redzone holds the size allocated, so the back redzone can be found even if freed with the wrong size.
+struct Redzones<T> {
+    inner: LayoutChecks<T>,
+    violations: AtomicUsize,
+}
+
+/// Bytes of redzone before allocations aligned to `align`: a multiple of the alignment.
+fn front(align: usize) -> usize {
+    align.max(REDZONE)
+}
+
+/// The layout to allocate from [LayoutChecks] for `layout`, with redzones on each side.
+fn redzoned_layout(layout: Layout) -> Option<Layout> {
+    let size = layout.size().checked_add(front(layout.align()) + REDZONE)?;
+    Layout::from_size_align(size, layout.align()).ok()
+}
+
+/// If the `len` bytes at `ptr` are all `byte`.
+unsafe fn filled_with(ptr: *const u8, len: usize, byte: u8) -> bool {
+    core::slice::from_raw_parts(ptr, len)
+        .iter()
+        .all(|&b| b == byte)
+}
+
+unsafe impl<T: GlobalAlloc> GlobalAlloc for Redzones<T> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let Some(outer) = redzoned_layout(layout) else {
+            return null_mut();
+        };
+        let raw = self.inner.alloc(outer);
+        if raw.is_null() {
+            return raw;
+        }
+        let front = front(layout.align());
+        (raw as *mut usize).write_unaligned(layout.size());
+        let size_word = size_of::<usize>();
+        raw.add(size_word)
+            .write_bytes(REDZONE_BYTE, front - size_word);
+        let ptr = raw.add(front);
+        ptr.add(layout.size()).write_bytes(REDZONE_BYTE, REDZONE);
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let front = front(layout.align());
+        let raw = ptr.sub(front);
+        if self.inner.is_live(raw) {
+            let size_word = size_of::<usize>();
+            let front_intact = filled_with(raw.add(size_word), front - size_word, REDZONE_BYTE);
+            // A damaged front redzone may have overwritten the size too.
+            let size = if front_intact {
+                (raw as *const usize).read_unaligned()
+            } else {
+                layout.size()
+            };
+            if !front_intact || !filled_with(ptr.add(size), REDZONE, REDZONE_BYTE) {
+                self.violations.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        // The layout checks catch `layout` being wrong, so pass along what it implies.
+        if let Some(outer) = redzoned_layout(layout) {
+            self.inner.dealloc(raw, outer);
+        }
+    }
+}
+
+impl<T: AllocatorStats> AllocatorStats for Redzones<T> {
+    fn stats(&self) -> Stats {
+        self.inner.stats()
+    }
+}
+
+/// Records each allocation's layout in a header before it, checking it when freed.
+struct LayoutChecks<T> {
+    inner: Poison<T>,
+    layout_mismatches: AtomicUsize,
+    invalid_frees: AtomicUsize,
+}
+
+#[repr(C)]
+struct Header {
+    /// [LIVE] while allocated.
+    state: usize,
+    size: usize,
+    align: usize,
+}
+
+/// Bytes before allocations aligned to `align`, ending with the [Header]:
+/// a multiple of both the alignment and the header's alignment.
+fn prefix(align: usize) -> usize {
+    size_of::<Header>().next_multiple_of(align.max(align_of::<Header>()))
+}
+
+/// The layout to allocate from [Poison] for `layout`, with space for the header before it.
+fn headed_layout(layout: Layout) -> Option<Layout> {
+    let size = layout.size().checked_add(prefix(layout.align()))?;
+    Layout::from_size_align(size, layout.align().max(align_of::<Header>())).ok()
+}
+
+/// The header for the allocation returned at `ptr`.
+fn header(ptr: *const u8) -> *mut Header {
+    ptr.wrapping_sub(size_of::<Header>()) as *mut Header
+}
+
+impl<T> LayoutChecks<T> {
+    /// If `ptr` looks like a live allocation from this.
+    ///
+    /// # Safety
+    ///
+    /// The header before `ptr` must be readable.
+    unsafe fn is_live(&self, ptr: *const u8) -> bool {
+        (*header(ptr)).state == LIVE
+    }
+}
+
+unsafe impl<T: GlobalAlloc> GlobalAlloc for LayoutChecks<T> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let Some(inner_layout) = headed_layout(layout) else {
+            return null_mut();
+        };
+        let raw = self.inner.alloc(inner_layout);
+        if raw.is_null() {
+            return raw;
+        }
+        let ptr = raw.add(prefix(layout.align()));
+        header(ptr).write(Header {
+            state: LIVE,
+            size: layout.size(),
+            align: layout.align(),
+        });
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if !self.is_live(ptr) {
+            // Freeing it again could corrupt the wrapped allocator, so leak it.
+            self.invalid_frees.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        let header = header(ptr);
+        // The header was written from a valid layout.
+        let allocated = Layout::from_size_align_unchecked((*header).size, (*header).align);
+        if allocated != layout {
+            self.layout_mismatches.fetch_add(1, Ordering::Relaxed);
+        }
+        (*header).state = 0;
+        // Free what was actually allocated, so the wrapped allocator stays consistent.
+        self.inner.dealloc(
+            ptr.sub(prefix(allocated.align())),
+            headed_layout(allocated).unwrap_unchecked(),
+        );
+    }
+}
+
+impl<T: AllocatorStats> AllocatorStats for LayoutChecks<T> {
+    fn stats(&self) -> Stats {
+        self.inner.stats()
+    }
+}
+
+/// Fills new allocations with [ALLOC_POISON], and freed ones with [FREE_POISON].
+struct Poison<T> {
+    inner: T,
+}
+
+unsafe impl<T: GlobalAlloc> GlobalAlloc for Poison<T> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.inner.alloc(layout);
+        if !ptr.is_null() {
+            ptr.write_bytes(ALLOC_POISON, layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        ptr.write_bytes(FREE_POISON, layout.size());
+        self.inner.dealloc(ptr, layout);
+    }
+}
+
+impl<T: AllocatorStats> AllocatorStats for Poison<T> {
+    fn stats(&self) -> Stats {
+        self.inner.stats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DebugAllocator, ALLOC_POISON, FREE_POISON, REDZONE};
+    use core::alloc::{GlobalAlloc, Layout};
+    use lol_alloc::testing::conformance;
+    use lol_alloc::{
+        FreeListAllocator, GlobalArrayGrower, HostAllocator, LockedAllocator, RawBuffer,
+    };
+
+    /// A heap which is never returned to the host, so freed memory can still be read.
+    type Heap = FreeListAllocator<&'static GlobalArrayGrower<4>>;
+
+    fn debug_allocator(grower: &'static GlobalArrayGrower<4>) -> DebugAllocator<Heap> {
+        DebugAllocator::new(FreeListAllocator::new_in(grower))
+    }
+
+    #[test]
+    fn conforms() {
+        let allocator = DebugAllocator::new(HostAllocator);
+        conformance(&allocator);
+        let report = allocator.report();
+        assert!(report.is_clean());
+        assert_eq!(report.stats.live_allocations, Some(0));
+    }
+
+    #[test]
+    fn overflow() {
+        let allocator = DebugAllocator::new(HostAllocator);
+        let layout = Layout::from_size_align(10, 2).unwrap();
+        unsafe {
+            let ptr = allocator.alloc(layout);
+            ptr.add(10).write(0);
+            allocator.dealloc(ptr, layout);
+            // Writes within the allocation are fine.
+            let ptr = allocator.alloc(layout);
+            ptr.write_bytes(0, 10);
+            allocator.dealloc(ptr, layout);
+        }
+        assert_eq!(allocator.report().redzone_violations, 1);
+    }
+
+    #[test]
+    fn underflow() {
+        let allocator = DebugAllocator::new(HostAllocator);
+        let layout = Layout::from_size_align(100, 64).unwrap();
+        unsafe {
+            let ptr = allocator.alloc(layout);
+            ptr.sub(1).write(0);
+            allocator.dealloc(ptr, layout);
+        }
+        let report = allocator.report();
+        assert_eq!(report.redzone_violations, 1);
+        // The layout header was protected by the redzone.
+        assert_eq!(report.layout_mismatches, 0);
+    }
+
+    #[test]
+    fn layout_mismatch() {
+        let allocator = DebugAllocator::new(HostAllocator);
+        let layout = Layout::from_size_align(100, 8).unwrap();
+        unsafe {
+            let ptr = allocator.alloc(layout);
+            ptr.write_bytes(1, 100);
+            allocator.dealloc(ptr, Layout::from_size_align(50, 8).unwrap());
+        }
+        let report = allocator.report();
+        assert_eq!(report.layout_mismatches, 1);
+        // The redzone was found from the size allocated, so is intact.
+        assert_eq!((report.redzone_violations, report.invalid_frees), (0, 0));
+    }
+
+    #[test]
+    fn double_free() {
+        static GROWER: GlobalArrayGrower<4> = GlobalArrayGrower::new();
+        let allocator = debug_allocator(&GROWER);
+        let layout = Layout::new::<[u64; 4]>();
+        unsafe {
+            let ptr = allocator.alloc(layout);
+            allocator.dealloc(ptr, layout);
+            allocator.dealloc(ptr, layout);
+        }
+        let report = allocator.report();
+        assert_eq!(report.invalid_frees, 1);
+        assert_eq!(report.redzone_violations, 0);
+    }
+
+    #[test]
+    fn poison() {
+        static GROWER: GlobalArrayGrower<4> = GlobalArrayGrower::new();
+        let allocator = debug_allocator(&GROWER);
+        let layout = Layout::from_size_align(REDZONE, 8).unwrap();
+        unsafe {
+            let ptr = allocator.alloc(layout);
+            let bytes = core::slice::from_raw_parts(ptr, layout.size());
+            assert!(bytes.iter().all(|&b| b == ALLOC_POISON));
+            ptr.write_bytes(1, layout.size());
+            allocator.dealloc(ptr, layout);
+            // The free list only writes to the start of the block, where the header is.
+            let bytes = core::slice::from_raw_parts(ptr, layout.size());
+            assert!(bytes.iter().all(|&b| b == FREE_POISON));
+        }
+        assert!(allocator.report().is_clean());
+    }
+
+    /// Reconstructing a `RawBuffer` with the wrong length, or twice, is caught.
+    #[test]
+    fn raw_buffer_misuse() {
+        static GROWER: GlobalArrayGrower<4> = GlobalArrayGrower::new();
+        static ALLOCATOR: DebugAllocator<LockedAllocator<Heap>> =
+            DebugAllocator::new(LockedAllocator::new(FreeListAllocator::new_in(&GROWER)));
+        let (ptr, len) = RawBuffer::alloc_in(64, 8, &ALLOCATOR)
+            .unwrap()
+            .into_raw_parts();
+        drop(unsafe { RawBuffer::from_raw_parts_in(ptr, len / 2, 8, &ALLOCATOR) });
+        assert_eq!(ALLOCATOR.report().layout_mismatches, 1);
+
+        let (ptr, len) = RawBuffer::alloc_in(64, 8, &ALLOCATOR)
+            .unwrap()
+            .into_raw_parts();
+        drop(unsafe { RawBuffer::from_raw_parts_in(ptr, len, 8, &ALLOCATOR) });
+        drop(unsafe { RawBuffer::from_raw_parts_in(ptr, len, 8, &ALLOCATOR) });
+        let report = ALLOCATOR.report();
+        assert_eq!((report.layout_mismatches, report.invalid_frees), (1, 1));
+    }
+
+    #[test]
+    fn counts_requested_sizes() {
+        let allocator = DebugAllocator::new(HostAllocator);
+        let layout = Layout::from_size_align(24, 8).unwrap();
+        unsafe {
+            let ptr = allocator.alloc(layout);
+            let stats = allocator.report().stats;
+            assert_eq!(
+                (stats.live_bytes, stats.live_allocations),
+                (Some(24), Some(1))
+            );
+            // Reallocating goes through all the checks.
+            let ptr = allocator.realloc(ptr, layout, 1000);
+            ptr.write_bytes(1, 1000);
+            allocator.dealloc(ptr, Layout::from_size_align(1000, 8).unwrap());
+        }
+        let report = allocator.report();
+        assert!(report.is_clean());
+        assert_eq!(report.stats.live_bytes, Some(0));
+        assert_eq!(report.stats.peak_bytes, Some(1024));
+    }
+}
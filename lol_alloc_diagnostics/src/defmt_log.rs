@@ -0,0 +1,58 @@
+use crate::{AllocHooks, Hooked};
+use core::alloc::Layout;
+use lol_alloc::CountsAllocations;
+
+/// Kind of operation a [DefmtAllocator] event records: the first value of each event.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(u8)]
+pub enum DefmtOp {
+    /// An allocation, with the pointer returned (null if it failed).
+    Alloc = 0,
+    Dealloc = 1,
+}
+
+/// Wraps an allocator, logging every operation with `defmt`, through the application's `#[defmt::global_logger]`.
+///
+/// Each event is compact: the [DefmtOp] code, then the pointer, size and alignment as `u32`s
+/// (which is what `defmt` encodes `usize`s as), with the format string interned like all `defmt` strings.
+/// Reallocating is logged as freeing the old pointer then allocating the new one.
+/// Events are logged with `defmt::println!`, so are not filtered by `DEFMT_LOG`: wrapping an allocator in this is what turns them on.
+///
+/// Logging does not allocate, but the global logger is provided by the application, and might.
+/// So operations the logger makes through this allocator (when it is the global allocator) are not logged, instead of recursing.
+pub type DefmtAllocator<T> = Hooked<T, DefmtHooks>;
+
+/// The [AllocHooks] of a [DefmtAllocator].
+pub struct DefmtHooks;
+
+impl<T> DefmtAllocator<T> {
+    pub const fn new(inner: T) -> Self {
+        Hooked::with_hooks(inner, DefmtHooks)
+    }
+}
+
+impl DefmtHooks {
+    fn log(&self, op: DefmtOp, ptr: *mut u8, layout: Layout) {
+        defmt::println!(
+            "{=u8} {=u32} {=u32} {=u32}",
+            op as u8,
+            ptr as u32,
+            layout.size() as u32,
+            layout.align() as u32
+        );
+    }
+}
+
+impl AllocHooks for DefmtHooks {
+    const ALLOCATES: bool = true;
+
+    fn on_alloc(&self, layout: Layout, result: *mut u8) {
+        self.log(DefmtOp::Alloc, result, layout);
+    }
+
+    fn on_dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.log(DefmtOp::Dealloc, ptr, layout);
+    }
+}
+
+impl<T: CountsAllocations> CountsAllocations for DefmtAllocator<T> {}
@@ -0,0 +1,203 @@
+use crate::atomic::{AtomicBool, Ordering};
+use core::alloc::{GlobalAlloc, Layout};
+use lol_alloc::{AllocatorStats, Stats};
+
+/// Instrumentation run by [Hooked] around the operations of the allocator it wraps.
+///
+/// Implementing this is enough to write an instrumentation wrapper:
+/// [Hooked] forwards every `GlobalAlloc` method (including `alloc_zeroed` and `realloc`) to the wrapped allocator,
+/// then calls the matching hook.
+pub trait AllocHooks {
+    /// If the hooks may allocate through the [Hooked] allocator running them (for example when it is the global allocator).
+    ///
+    /// If so, [Hooked] does not run the hooks for operations made while a hook is running,
+    /// which stops a hook recursing into itself.
+    /// There is no way to tell which thread an operation is from without `std`,
+    /// so this also skips the hooks for operations other threads make while a hook is running.
+    const ALLOCATES: bool = false;
+
+    /// Called after allocating `layout`, with the pointer returned (null if the allocation failed).
+    fn on_alloc(&self, _layout: Layout, _result: *mut u8) {}
+
+    /// Called after freeing `ptr`, which was allocated with `layout`.
+    fn on_dealloc(&self, _ptr: *mut u8, _layout: Layout) {}
+
+    /// Called after reallocating `ptr` (allocated with `layout`) to `new_size`, with the pointer returned (null if it failed, in which case `ptr` is still live).
+    ///
+    /// By default this is reported as freeing `ptr` then allocating the new size, or as a failed allocation.
+    fn on_realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize, result: *mut u8) {
+        // Safety: reallocating requires the new size to be valid for the alignment.
+        let new_layout = unsafe { Layout::from_size_align_unchecked(new_size, layout.align()) };
+        if !result.is_null() {
+            self.on_dealloc(ptr, layout);
+        }
+        self.on_alloc(new_layout, result);
+    }
+
+    /// Merges statistics the hooks collect into `inner`, the [Stats] of the wrapped allocator.
+    fn stats(&self, inner: Stats) -> Stats {
+        inner
+    }
+}
+
+/// Wraps an allocator, calling an [AllocHooks] implementation after each operation.
+///
+/// This implements `GlobalAlloc` once for all instrumentation wrappers, like [CountingAllocator](crate::CountingAllocator), which are [Hooked] with their own hooks.
+///
+/// [CountsAllocations](lol_alloc::CountsAllocations) is implemented for each hooks type separately,
+/// since hooks which count allocations provide it whatever they wrap.
+/// To count the allocations of an allocator with other hooks, wrap it in a [CountingAllocator](crate::CountingAllocator).
+pub struct Hooked<T, H> {
+    inner: T,
+    hooks: H,
+    /// Set while a hook is running, if [AllocHooks::ALLOCATES].
+    in_hook: AtomicBool,
+}
+
+impl<T, H> Hooked<T, H> {
+    pub const fn with_hooks(inner: T, hooks: H) -> Self {
+        Hooked {
+            inner,
+            hooks,
+            in_hook: AtomicBool::new(false),
+        }
+    }
+
+    pub fn hooks(&self) -> &H {
+        &self.hooks
+    }
+
+    /// The wrapped allocator.
+    pub(crate) fn inner(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<T, H: AllocHooks> Hooked<T, H> {
+    /// Runs `hook`, unless hooks may allocate and one is already running.
+    fn run(&self, hook: impl FnOnce(&H)) {
+        if !H::ALLOCATES {
+            hook(&self.hooks);
+        } else if !self.in_hook.swap(true, Ordering::Acquire) {
+            hook(&self.hooks);
+            self.in_hook.store(false, Ordering::Release);
+        }
+    }
+}
+
+unsafe impl<T: GlobalAlloc, H: AllocHooks> GlobalAlloc for Hooked<T, H> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.inner.alloc(layout);
+        self.run(|hooks| hooks.on_alloc(layout, ptr));
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.inner.dealloc(ptr, layout);
+        self.run(|hooks| hooks.on_dealloc(ptr, layout));
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.inner.alloc_zeroed(layout);
+        self.run(|hooks| hooks.on_alloc(layout, ptr));
+        ptr
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = self.inner.realloc(ptr, layout, new_size);
+        self.run(|hooks| hooks.on_realloc(ptr, layout, new_size, new_ptr));
+        new_ptr
+    }
+}
+
+impl<T: AllocatorStats, H: AllocHooks> AllocatorStats for Hooked<T, H> {
+    fn stats(&self) -> Stats {
+        self.hooks.stats(self.inner.stats())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AllocHooks, Hooked};
+    use crate::atomic::AtomicUsize;
+    use core::{
+        alloc::{GlobalAlloc, Layout},
+        sync::atomic::Ordering,
+    };
+    use lol_alloc::testing::conformance;
+    use lol_alloc::HostAllocator;
+
+    /// Records the largest allocation, and the number of frees.
+    #[derive(Default)]
+    struct Largest {
+        largest: AtomicUsize,
+        frees: AtomicUsize,
+    }
+
+    impl AllocHooks for Largest {
+        fn on_alloc(&self, layout: Layout, result: *mut u8) {
+            if !result.is_null() {
+                self.largest.fetch_max(layout.size(), Ordering::Relaxed);
+            }
+        }
+
+        fn on_dealloc(&self, _ptr: *mut u8, _layout: Layout) {
+            self.frees.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn user_hooks() {
+        let allocator = Hooked::with_hooks(HostAllocator, Largest::default());
+        conformance(&allocator);
+        let hooks = allocator.hooks();
+        assert_eq!(hooks.largest.load(Ordering::Relaxed), 65537);
+        assert_eq!(hooks.frees.load(Ordering::Relaxed), 28);
+        // Reallocating is reported as a free and an allocation.
+        let layout = Layout::new::<u64>();
+        unsafe {
+            let ptr = allocator.alloc_zeroed(layout);
+            let ptr = allocator.realloc(ptr, layout, 100_000);
+            allocator.dealloc(ptr, Layout::from_size_align(100_000, 8).unwrap());
+        }
+        assert_eq!(hooks.largest.load(Ordering::Relaxed), 100_000);
+        assert_eq!(hooks.frees.load(Ordering::Relaxed), 30);
+    }
+
+    /// Hooks which allocate and free through [RECURSIVE] each time they are called.
+    struct Recursive {
+        calls: AtomicUsize,
+    }
+
+    impl AllocHooks for Recursive {
+        const ALLOCATES: bool = true;
+
+        fn on_alloc(&self, layout: Layout, _result: *mut u8) {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+            unsafe { RECURSIVE.dealloc(RECURSIVE.alloc(layout), layout) };
+        }
+
+        fn on_dealloc(&self, _ptr: *mut u8, layout: Layout) {
+            self.on_alloc(layout, core::ptr::null_mut());
+        }
+    }
+
+    static RECURSIVE: Hooked<HostAllocator, Recursive> = Hooked::with_hooks(
+        HostAllocator,
+        Recursive {
+            calls: AtomicUsize::new(0),
+        },
+    );
+
+    #[test]
+    fn reentrancy_guard() {
+        let layout = Layout::new::<u32>();
+        unsafe {
+            let ptr = RECURSIVE.alloc(layout);
+            assert!(!ptr.is_null());
+            RECURSIVE.dealloc(ptr, layout);
+        }
+        // Without the guard, each hook would recurse forever.
+        assert_eq!(RECURSIVE.hooks().calls.load(Ordering::Relaxed), 2);
+    }
+}
@@ -0,0 +1,221 @@
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    cell::Cell,
+    panic::Location,
+    ptr::NonNull,
+};
+use lol_alloc::{AllocatorStats, CountsAllocations, Stats};
+use std::{collections::HashMap, eprintln, sync::Mutex, vec::Vec};
+
+std::thread_local! {
+    /// Set while this thread is updating a side table, so the table's own allocations are not tracked.
+    static IN_TRACKER: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Where live allocations came from, by address.
+type Table = HashMap<usize, (Layout, Option<&'static Location<'static>>)>;
+
+/// Wraps an allocator, recording every live allocation in a side table so leaks can be reported with [LeakTracker::report_leaks].
+///
+/// Allocations made with [LeakTracker::try_alloc] record the location of their caller.
+/// `GlobalAlloc` can't forward its caller's location, so other allocations (like ones from `Box` when this is the global allocator) are reported with an unknown location.
+///
+/// The side table uses `std`, which allocates: when this is the global allocator, the table's own allocations bypass tracking.
+/// This is intended for running code natively in tests, not for wasm.
+pub struct LeakTracker<T> {
+    inner: T,
+    table: Mutex<Option<Table>>,
+}
+
+/// Allocations leaked from one call site, from [LeakTracker::leaks].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct LeakSite {
+    /// Where the allocations were made, if known.
+    pub location: Option<&'static Location<'static>>,
+    pub allocations: usize,
+    pub bytes: usize,
+}
+
+impl<T> LeakTracker<T> {
+    pub const fn new(inner: T) -> Self {
+        LeakTracker {
+            inner,
+            table: Mutex::new(None),
+        }
+    }
+
+    /// Runs `f` on the side table, unless this thread is already inside the tracker.
+    fn with_table<R>(&self, f: impl FnOnce(&mut Table) -> R) -> Option<R> {
+        if IN_TRACKER.with(|inside| inside.replace(true)) {
+            return None;
+        }
+        let result = f(self
+            .table
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .get_or_insert_with(HashMap::new));
+        IN_TRACKER.with(|inside| inside.set(false));
+        Some(result)
+    }
+
+    /// Live allocations, grouped by where they were allocated, with the most allocations first.
+    pub fn leaks(&self) -> Vec<LeakSite> {
+        let mut sites: Vec<LeakSite> = self
+            .with_table(|table| {
+                let mut sites: Vec<LeakSite> = Vec::new();
+                for &(layout, location) in table.values() {
+                    match sites.iter_mut().find(|site| site.location == location) {
+                        Some(site) => {
+                            site.allocations += 1;
+                            site.bytes += layout.size();
+                        }
+                        None => sites.push(LeakSite {
+                            location,
+                            allocations: 1,
+                            bytes: layout.size(),
+                        }),
+                    }
+                }
+                sites
+            })
+            .unwrap_or_default();
+        sites.sort_by_key(|site| {
+            (
+                core::cmp::Reverse(site.allocations),
+                site.location
+                    .map(|location| (location.file(), location.line())),
+            )
+        });
+        sites
+    }
+
+    /// Prints the live allocations to stderr, grouped by where they were allocated, and returns how many there are.
+    ///
+    /// Call this at the end of a test, once everything should have been freed.
+    pub fn report_leaks(&self) -> usize {
+        let leaks = self.leaks();
+        let total = leaks.iter().map(|site| site.allocations).sum();
+        if total != 0 {
+            eprintln!("{total} leaked allocations:");
+        }
+        for site in leaks {
+            match site.location {
+                Some(location) => eprint_site(&location, site),
+                None => eprint_site(&"unknown location", site),
+            }
+        }
+        total
+    }
+}
+
+fn eprint_site(location: &dyn core::fmt::Display, site: LeakSite) {
+    eprintln!(
+        "  {location}: {} allocations, {} bytes",
+        site.allocations, site.bytes
+    );
+}
+
+impl<T: GlobalAlloc> LeakTracker<T> {
+    /// Allocates like [GlobalAlloc::alloc], recording the caller's location for leak reports.
+    /// Returns `None` if the allocation fails.
+    ///
+    /// Free the allocation with [GlobalAlloc::dealloc].
+    #[track_caller]
+    pub fn try_alloc(&self, layout: Layout) -> Option<NonNull<u8>> {
+        self.alloc_at(layout, Some(Location::caller()))
+    }
+
+    fn alloc_at(
+        &self,
+        layout: Layout,
+        location: Option<&'static Location<'static>>,
+    ) -> Option<NonNull<u8>> {
+        let ptr = NonNull::new(unsafe { self.inner.alloc(layout) })?;
+        self.with_table(|table| table.insert(ptr.as_ptr() as usize, (layout, location)));
+        Some(ptr)
+    }
+}
+
+unsafe impl<T: GlobalAlloc> GlobalAlloc for LeakTracker<T> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.alloc_at(layout, None)
+            .map_or(core::ptr::null_mut(), NonNull::as_ptr)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.with_table(|table| table.remove(&(ptr as usize)));
+        self.inner.dealloc(ptr, layout);
+    }
+}
+
+impl<T: AllocatorStats> AllocatorStats for LeakTracker<T> {
+    fn stats(&self) -> Stats {
+        self.inner.stats()
+    }
+}
+
+impl<T: CountsAllocations> CountsAllocations for LeakTracker<T> {}
+
+#[cfg(test)]
+mod tests {
+    use super::LeakTracker;
+    use core::alloc::{GlobalAlloc, Layout};
+    use lol_alloc::testing::conformance;
+    use lol_alloc::HostAllocator;
+
+    /// Leaks an allocation, returning the line it was allocated on.
+    fn leak_small(tracker: &LeakTracker<HostAllocator>) -> u32 {
+        let (ptr, line) = (tracker.try_alloc(Layout::new::<u64>()), line!());
+        assert!(ptr.is_some());
+        line
+    }
+
+    fn leak_large(tracker: &LeakTracker<HostAllocator>) -> u32 {
+        let (ptr, line) = (tracker.try_alloc(Layout::new::<[u8; 100]>()), line!());
+        assert!(ptr.is_some());
+        line
+    }
+
+    #[test]
+    fn conforms() {
+        let tracker = LeakTracker::new(HostAllocator);
+        conformance(&tracker);
+        assert_eq!(tracker.leaks(), []);
+    }
+
+    #[test]
+    fn reports_call_sites() {
+        let tracker = LeakTracker::new(HostAllocator);
+        let small = leak_small(&tracker);
+        leak_small(&tracker);
+        let large = leak_large(&tracker);
+        // Freed allocations are not reported.
+        let layout = Layout::new::<u32>();
+        let freed = tracker.try_alloc(layout).unwrap();
+        unsafe {
+            tracker.dealloc(freed.as_ptr(), layout);
+            tracker.alloc(layout);
+        }
+
+        let leaks = tracker.leaks();
+        let sites: std::vec::Vec<_> = leaks
+            .iter()
+            .map(|site| {
+                site.location
+                    .map(|location| (location.file(), location.line()))
+            })
+            .collect();
+        assert_eq!(
+            sites,
+            [Some((file!(), small)), None, Some((file!(), large))]
+        );
+        assert_eq!(
+            leaks
+                .iter()
+                .map(|site| (site.allocations, site.bytes))
+                .collect::<std::vec::Vec<_>>(),
+            [(2, 16), (1, 4), (1, 100)]
+        );
+        assert_eq!(tracker.report_leaks(), 4);
+    }
+}
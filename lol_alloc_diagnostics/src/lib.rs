@@ -0,0 +1,60 @@
+//! Instrumentation wrappers and debugging tools for [lol_alloc] allocators.
+//!
+//! The traits the wrappers implement (like [lol_alloc::AllocatorStats]) are defined in `lol_alloc`,
+//! so this crate depends on it and not the other way around.
+
+#![no_std]
+
+#[cfg(any(test, feature = "trace"))]
+#[cfg_attr(test, macro_use)]
+extern crate alloc;
+
+#[cfg(any(test, feature = "leak-report"))]
+extern crate std;
+
+/// Atomics used by the wrappers (but not the tests).
+/// All uses go through here so the implementation can be changed in one place, like in `lol_alloc`.
+mod atomic {
+    #[cfg(feature = "allocation-ids")]
+    pub(crate) use core::sync::atomic::AtomicU32;
+    pub(crate) use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+}
+
+#[cfg(feature = "allocation-ids")]
+mod allocation_ids;
+#[cfg(feature = "log-to-file")]
+mod allocation_log;
+mod colored_allocator;
+mod counting_allocator;
+mod debug_allocator;
+#[cfg(feature = "defmt")]
+mod defmt_log;
+mod hooks;
+#[cfg(feature = "leak-report")]
+mod leak_tracker;
+mod pprof_profile;
+mod profile_allocator;
+#[cfg(feature = "trace")]
+mod trace_replay;
+mod watch_allocator;
+#[cfg(feature = "allocation-ids")]
+pub use crate::allocation_ids::{IdAllocator, IdEvent};
+#[cfg(feature = "log-to-file")]
+pub use crate::allocation_log::{LogKind, LogRecord};
+#[cfg(all(feature = "wasi", target_os = "wasi"))]
+pub use crate::allocation_log::{WasiLogAllocator, WasiLogHooks};
+pub use crate::colored_allocator::{ColoredAllocator, GRANULE};
+pub use crate::counting_allocator::{CountingAllocator, CountingHooks};
+pub use crate::debug_allocator::{
+    DebugAllocator, DebugReport, ALLOC_POISON, FREE_POISON, REDZONE, REDZONE_BYTE,
+};
+#[cfg(feature = "defmt")]
+pub use crate::defmt_log::{DefmtAllocator, DefmtHooks, DefmtOp};
+pub use crate::hooks::{AllocHooks, Hooked};
+#[cfg(feature = "leak-report")]
+pub use crate::leak_tracker::{LeakSite, LeakTracker};
+pub use crate::pprof_profile::BufferTooSmall;
+pub use crate::profile_allocator::{size_class, ProfileAllocator, ProfileCell, SIZE_CLASSES};
+#[cfg(feature = "trace")]
+pub use crate::trace_replay::{allocation_trace_to_wat, TraceEntry, TraceOp};
+pub use crate::watch_allocator::{WatchAllocator, WatchHooks, WatchOp};
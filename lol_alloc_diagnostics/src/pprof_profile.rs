@@ -0,0 +1,421 @@
+use crate::{ProfileAllocator, SIZE_CLASSES};
+
+/// Returned by [ProfileAllocator::write_pprof] when the profile does not fit in the buffer.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct BufferTooSmall {
+    /// Size of the whole profile, in bytes.
+    pub needed: usize,
+}
+
+/// Strings every profile starts its string table with, in order, so they can be referred to by index.
+/// pprof requires the first to be empty.
+const STRINGS: [&str; 6] = [
+    "",
+    "alloc_objects",
+    "count",
+    "inuse_objects",
+    "inuse_space",
+    "bytes",
+];
+
+/// The `(type, unit)` string indexes of the values in each sample: allocations, live allocations and live bytes.
+const SAMPLE_TYPES: [(u64, u64); 3] = [(1, 2), (3, 2), (4, 5)];
+
+impl<T, const TAGS: usize> ProfileAllocator<T, TAGS> {
+    /// Writes the aggregated allocations into `buffer` as a [pprof](https://github.com/google/pprof) profile (an uncompressed `profile.proto` message),
+    /// returning its size, for viewing the heap in pprof's tools (or speedscope, through a converter).
+    ///
+    /// Each tag and size class which has had an allocation is a sample, with a two frame stack:
+    /// a function named for the tag (like `tag 3`), calling one named for the size class (like `size class 7 (up to 2^7 bytes)`).
+    /// Its values are the number of allocations ever made (`alloc_objects`), and the number and size of those still live (`inuse_objects` and `inuse_space`).
+    ///
+    /// If the profile does not fit, returns the size needed, so the caller can retry with a larger buffer.
+    /// See [export_pprof!](crate::export_pprof) for exporting this to the host.
+    pub fn write_pprof(&self, buffer: &mut [u8]) -> Result<usize, BufferTooSmall> {
+        let mut writer = Writer { buffer, len: 0 };
+        self.encode(&mut writer);
+        if writer.len > writer.buffer.len() {
+            return Err(BufferTooSmall { needed: writer.len });
+        }
+        Ok(writer.len)
+    }
+
+    /// Encodes the whole profile.
+    fn encode(&self, w: &mut Writer) {
+        let used_tags = (0..TAGS).filter(|&tag| self.tag_used(tag)).count();
+        for (kind, unit) in SAMPLE_TYPES {
+            w.message(1, |w| {
+                w.uint(1, kind);
+                w.uint(2, unit);
+            });
+        }
+        for tag in 0..TAGS {
+            for size_class in 0..SIZE_CLASSES {
+                let Some(cell) = self.record(tag, size_class) else {
+                    continue;
+                };
+                w.message(2, |w| {
+                    // Leaf first.
+                    w.packed(
+                        1,
+                        [
+                            (used_tags + self.class_rank(size_class)) as u64 + 1,
+                            self.tag_rank(tag) as u64 + 1,
+                        ],
+                    );
+                    w.packed(
+                        2,
+                        [cell.allocations, cell.live_count, cell.live_bytes].map(u64::from),
+                    );
+                });
+            }
+        }
+        // One location and function for each used tag, then each used size class, with ids (and name indexes) in that order from 1.
+        let names = (0..TAGS)
+            .filter(|&tag| self.tag_used(tag))
+            .map(Name::Tag)
+            .chain(
+                (0..SIZE_CLASSES)
+                    .filter(|&size_class| self.class_used(size_class))
+                    .map(Name::SizeClass),
+            );
+        for (i, _) in names.clone().enumerate() {
+            let id = i as u64 + 1;
+            w.message(4, |w| {
+                w.uint(1, id);
+                w.message(4, |w| w.uint(1, id));
+            });
+            w.message(5, |w| {
+                w.uint(1, id);
+                w.uint(2, (STRINGS.len() + i) as u64);
+            });
+        }
+        for string in STRINGS {
+            w.bytes(6, string.as_bytes());
+        }
+        for name in names {
+            w.message(6, |w| name.write(w));
+        }
+    }
+
+    fn tag_used(&self, tag: usize) -> bool {
+        (0..SIZE_CLASSES).any(|size_class| self.record(tag, size_class).is_some())
+    }
+
+    fn class_used(&self, size_class: usize) -> bool {
+        (0..TAGS).any(|tag| self.record(tag, size_class).is_some())
+    }
+
+    /// Number of used tags before `tag`.
+    fn tag_rank(&self, tag: usize) -> usize {
+        (0..tag).filter(|&tag| self.tag_used(tag)).count()
+    }
+
+    /// Number of used size classes before `size_class`.
+    fn class_rank(&self, size_class: usize) -> usize {
+        (0..size_class)
+            .filter(|&size_class| self.class_used(size_class))
+            .count()
+    }
+}
+
+/// The name of a synthetic function.
+#[derive(Clone, Copy)]
+enum Name {
+    Tag(usize),
+    SizeClass(usize),
+}
+
+impl Name {
+    /// Writes the name's bytes (without a field key or length).
+    fn write(self, w: &mut Writer) {
+        match self {
+            Name::Tag(tag) => {
+                w.raw(b"tag ");
+                w.decimal(tag);
+            }
+            Name::SizeClass(size_class) => {
+                w.raw(b"size class ");
+                w.decimal(size_class);
+                w.raw(b" (up to 2^");
+                w.decimal(size_class);
+                w.raw(b" bytes)");
+            }
+        }
+    }
+}
+
+/// Writes protobuf encoded data into a buffer, counting (but not writing) what does not fit.
+struct Writer<'a> {
+    buffer: &'a mut [u8],
+    len: usize,
+}
+
+impl Writer<'_> {
+    fn raw(&mut self, bytes: &[u8]) {
+        if let Some(out) = self.buffer.get_mut(self.len..self.len + bytes.len()) {
+            out.copy_from_slice(bytes);
+        }
+        self.len += bytes.len();
+    }
+
+    fn varint(&mut self, mut value: u64) {
+        loop {
+            let byte = (value & 0x7F) as u8;
+            value >>= 7;
+            if value == 0 {
+                self.raw(&[byte]);
+                return;
+            }
+            self.raw(&[byte | 0x80]);
+        }
+    }
+
+    fn decimal(&mut self, value: usize) {
+        let mut digits = [0; 20];
+        let mut start = digits.len();
+        let mut rest = value;
+        loop {
+            start -= 1;
+            digits[start] = b'0' + (rest % 10) as u8;
+            rest /= 10;
+            if rest == 0 {
+                break;
+            }
+        }
+        self.raw(&digits[start..]);
+    }
+
+    /// A varint field.
+    fn uint(&mut self, field: u64, value: u64) {
+        self.varint(field << 3);
+        self.varint(value);
+    }
+
+    /// A length delimited field holding `bytes`.
+    fn bytes(&mut self, field: u64, bytes: &[u8]) {
+        self.varint(field << 3 | 2);
+        self.varint(bytes.len() as u64);
+        self.raw(bytes);
+    }
+
+    /// A length delimited field holding what `contents` writes.
+    ///
+    /// Runs `contents` twice: first to measure it, then to write it after its length.
+    fn message(&mut self, field: u64, contents: impl Fn(&mut Writer)) {
+        let mut measure = Writer {
+            buffer: &mut [],
+            len: 0,
+        };
+        contents(&mut measure);
+        self.varint(field << 3 | 2);
+        self.varint(measure.len as u64);
+        contents(self);
+    }
+
+    /// A packed repeated varint field.
+    fn packed<const N: usize>(&mut self, field: u64, values: [u64; N]) {
+        self.message(field, |w| {
+            for value in values {
+                w.varint(value);
+            }
+        });
+    }
+}
+
+/// Exports a `lol_alloc_write_pprof(ptr: *mut u8, len: usize) -> usize` function,
+/// which the host can call to have a [ProfileAllocator]'s profile written (as by [ProfileAllocator::write_pprof])
+/// into the `len` bytes of wasm memory at `ptr`.
+///
+/// Returns the size of the profile: if it is more than `len`, what was written is incomplete, and the host should retry with a larger buffer.
+///
+/// Takes an expression evaluating to the `ProfileAllocator`, for example:
+///
+/// ```ignore
+/// #[global_allocator]
+/// static ALLOCATOR: ProfileAllocator<LockedAllocator<FreeListAllocator>, 8> =
+///     ProfileAllocator::new(LockedAllocator::new(FreeListAllocator::new()));
+///
+/// lol_alloc_diagnostics::export_pprof!(ALLOCATOR);
+/// ```
+#[macro_export]
+macro_rules! export_pprof {
+    ($allocator:expr) => {
+        /// Writes a pprof heap profile into the `len` bytes at `ptr`, returning its size.
+        /// If that is more than `len`, retry with a larger buffer.
+        ///
+        /// # Safety
+        ///
+        /// The `len` bytes at `ptr` must be writable, and not used by anything else.
+        #[unsafe(no_mangle)]
+        pub unsafe extern "C" fn lol_alloc_write_pprof(ptr: *mut u8, len: usize) -> usize {
+            let buffer: &mut [u8] = if len == 0 {
+                &mut []
+            } else {
+                ::core::slice::from_raw_parts_mut(ptr, len)
+            };
+            match $allocator.write_pprof(buffer) {
+                Ok(len) => len,
+                Err(too_small) => too_small.needed,
+            }
+        }
+    };
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::BufferTooSmall;
+    use crate::{size_class, ProfileAllocator};
+    use alloc::{collections::BTreeMap, string::String, vec::Vec};
+    use core::alloc::{GlobalAlloc, Layout};
+    use lol_alloc::HostAllocator;
+    use pprof::protos::{Message, Profile};
+
+    fn write(allocator: &ProfileAllocator<HostAllocator, 4>) -> Vec<u8> {
+        let needed = match allocator.write_pprof(&mut [0; 8]) {
+            Err(BufferTooSmall { needed }) => needed,
+            Ok(len) => return vec![0; len],
+        };
+        let mut profile = vec![0; needed];
+        assert_eq!(allocator.write_pprof(&mut profile), Ok(needed));
+        profile
+    }
+
+    /// Each sample's stack, as function names from the root, with its values.
+    fn samples(profile: &Profile) -> BTreeMap<Vec<String>, Vec<i64>> {
+        let string = |index: i64| profile.string_table[index as usize].clone();
+        let function_name = |location_id: u64| {
+            let location = profile
+                .location
+                .iter()
+                .find(|l| l.id == location_id)
+                .unwrap();
+            let function_id = location.line[0].function_id;
+            let function = profile
+                .function
+                .iter()
+                .find(|f| f.id == function_id)
+                .unwrap();
+            string(function.name)
+        };
+        profile
+            .sample
+            .iter()
+            .map(|sample| {
+                let stack = sample
+                    .location_id
+                    .iter()
+                    .rev()
+                    .map(|&id| function_name(id))
+                    .collect();
+                (stack, sample.value.clone())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn pprof_empty() {
+        let allocator = ProfileAllocator::<_, 4>::new(HostAllocator);
+        let profile = Profile::decode(write(&allocator).as_slice()).unwrap();
+        assert!(profile.sample.is_empty());
+        assert_eq!(profile.string_table[0], "");
+    }
+
+    #[test]
+    fn pprof_matches_shadow() {
+        let allocator = ProfileAllocator::<_, 4>::new(HostAllocator);
+        // (tag, size class) to (allocations, live count, live bytes).
+        let mut shadow: BTreeMap<(usize, usize), [i64; 3]> = BTreeMap::new();
+        let mut live = Vec::new();
+        unsafe {
+            for i in 0..200usize {
+                // Tag 1 is never used.
+                let tag = [0, 2, 3][i % 3];
+                allocator.set_tag(tag);
+                let layout = Layout::from_size_align(i * 37 % 3000 + 1, 8).unwrap();
+                live.push((allocator.alloc(layout), layout, tag));
+                let entry = shadow.entry((tag, size_class(layout.size()))).or_default();
+                entry[0] += 1;
+                entry[1] += 1;
+                entry[2] += layout.size() as i64;
+                if i % 4 == 0 {
+                    let (ptr, layout, tag) = live.swap_remove(i * 7 % live.len());
+                    allocator.dealloc(ptr, layout);
+                    let entry = shadow.get_mut(&(tag, size_class(layout.size()))).unwrap();
+                    entry[1] -= 1;
+                    entry[2] -= layout.size() as i64;
+                }
+            }
+        }
+
+        let profile = Profile::decode(write(&allocator).as_slice()).unwrap();
+        let types: Vec<_> = profile
+            .sample_type
+            .iter()
+            .map(|t| {
+                (
+                    profile.string_table[t.ty as usize].as_str(),
+                    profile.string_table[t.unit as usize].as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            types,
+            [
+                ("alloc_objects", "count"),
+                ("inuse_objects", "count"),
+                ("inuse_space", "bytes")
+            ]
+        );
+        let expected: BTreeMap<Vec<String>, Vec<i64>> = shadow
+            .into_iter()
+            .map(|((tag, size_class), values)| {
+                (
+                    vec![
+                        format!("tag {tag}"),
+                        format!("size class {size_class} (up to 2^{size_class} bytes)"),
+                    ],
+                    values.to_vec(),
+                )
+            })
+            .collect();
+        assert_eq!(samples(&profile), expected);
+
+        for (ptr, layout, _) in live {
+            unsafe { allocator.dealloc(ptr, layout) };
+        }
+    }
+
+    #[test]
+    fn pprof_buffer_too_small() {
+        let allocator = ProfileAllocator::<_, 4>::new(HostAllocator);
+        let layout = Layout::new::<u64>();
+        unsafe { allocator.dealloc(allocator.alloc(layout), layout) };
+        let len = write(&allocator).len();
+        let mut buffer = vec![0; len - 1];
+        assert_eq!(
+            allocator.write_pprof(&mut buffer),
+            Err(BufferTooSmall { needed: len })
+        );
+    }
+
+    static EXPORTED: ProfileAllocator<HostAllocator, 1> = ProfileAllocator::new(HostAllocator);
+
+    crate::export_pprof!(EXPORTED);
+
+    #[test]
+    fn pprof_exported() {
+        let layout = Layout::new::<u64>();
+        let ptr = unsafe { EXPORTED.alloc(layout) };
+        let needed = unsafe { lol_alloc_write_pprof(core::ptr::null_mut(), 0) };
+        let mut profile = vec![0; needed];
+        assert_eq!(
+            unsafe { lol_alloc_write_pprof(profile.as_mut_ptr(), needed) },
+            needed
+        );
+        let profile = Profile::decode(profile.as_slice()).unwrap();
+        assert_eq!(profile.sample.len(), 1);
+        assert_eq!(profile.sample[0].value, [1, 1, 8]);
+        unsafe { EXPORTED.dealloc(ptr, layout) };
+    }
+}
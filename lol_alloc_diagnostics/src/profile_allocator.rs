@@ -0,0 +1,325 @@
+use crate::atomic::{AtomicUsize, Ordering};
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    mem::{align_of, size_of},
+};
+use lol_alloc::{AllocatorStats, CountsAllocations, Stats};
+
+/// Number of size classes: class `n` holds allocations of at most `2^n` bytes.
+pub const SIZE_CLASSES: usize = usize::BITS as usize + 1;
+
+/// The size class of an allocation of `size` bytes: the smallest `n` where `2^n` is at least `size`.
+pub fn size_class(size: usize) -> usize {
+    (usize::BITS - size.saturating_sub(1).leading_zeros()) as usize
+}
+
+/// Counters for one tag and size class.
+struct Cell {
+    live_count: AtomicUsize,
+    live_bytes: AtomicUsize,
+    allocations: AtomicUsize,
+}
+
+impl Cell {
+    const fn new() -> Self {
+        Cell {
+            live_count: AtomicUsize::new(0),
+            live_bytes: AtomicUsize::new(0),
+            allocations: AtomicUsize::new(0),
+        }
+    }
+}
+
+/// Wraps an allocator, aggregating live and cumulative allocations by tag and size class into a table,
+/// which [ProfileAllocator::write_report] (or [ProfileAllocator::write_pprof], as a pprof profile) serializes for a host to fetch.
+///
+/// This is a much smaller amount of data than a log of every operation, so it can be collected from production.
+/// Allocations are tagged with the tag most recently set by [ProfileAllocator::set_tag], which must be less than `TAGS`.
+/// The tag is stored in a hidden one word (or alignment, if larger) header before each allocation, so frees update the right cell.
+/// Updates are O(1) and do not allocate.
+pub struct ProfileAllocator<T, const TAGS: usize> {
+    inner: T,
+    tag: AtomicUsize,
+    cells: [[Cell; SIZE_CLASSES]; TAGS],
+}
+
+impl<T, const TAGS: usize> ProfileAllocator<T, TAGS> {
+    pub const fn new(inner: T) -> Self {
+        assert!(TAGS > 0 && TAGS <= 1 << 16, "TAGS must fit in a u16");
+        ProfileAllocator {
+            inner,
+            tag: AtomicUsize::new(0),
+            cells: [const { [const { Cell::new() }; SIZE_CLASSES] }; TAGS],
+        }
+    }
+
+    /// Sets the tag for future allocations.
+    ///
+    /// # Panics
+    ///
+    /// If `tag` is not less than `TAGS`.
+    pub fn set_tag(&self, tag: usize) {
+        assert!(tag < TAGS, "tag {tag} is not less than {TAGS}");
+        self.tag.store(tag, Ordering::Relaxed);
+    }
+
+    /// The tag future allocations will get.
+    pub fn tag(&self) -> usize {
+        self.tag.load(Ordering::Relaxed)
+    }
+
+    /// Writes a [ProfileCell] record for every tag and size class which has had an allocation into `buffer`,
+    /// and returns the size of the whole report, in bytes.
+    ///
+    /// The report is a sequence of [ProfileCell::SIZE] byte records (see [ProfileCell::to_bytes]), with no header,
+    /// ordered by tag then size class.
+    /// If `buffer` is too small, only the records which fit are written,
+    /// so callers should retry with a larger buffer if the result is more than `buffer.len()`.
+    pub fn write_report(&self, buffer: &mut [u8]) -> usize {
+        let mut len = 0;
+        for tag in 0..TAGS {
+            for size_class in 0..SIZE_CLASSES {
+                let Some(record) = self.record(tag, size_class) else {
+                    continue;
+                };
+                if let Some(out) = buffer.get_mut(len..len + ProfileCell::SIZE) {
+                    out.copy_from_slice(&record.to_bytes());
+                }
+                len += ProfileCell::SIZE;
+            }
+        }
+        len
+    }
+
+    /// The counts for `tag` and `size_class`, if there has been an allocation with them.
+    pub(crate) fn record(&self, tag: usize, size_class: usize) -> Option<ProfileCell> {
+        let cell = &self.cells[tag][size_class];
+        let allocations = cell.allocations.load(Ordering::Relaxed);
+        if allocations == 0 {
+            return None;
+        }
+        Some(ProfileCell {
+            tag: tag as u16,
+            size_class: size_class as u8,
+            live_count: cell.live_count.load(Ordering::Relaxed) as u32,
+            live_bytes: cell.live_bytes.load(Ordering::Relaxed) as u32,
+            allocations: allocations as u32,
+        })
+    }
+}
+
+/// Layout of the allocation requested from the inner allocator, and the offset of the allocation in it.
+fn with_header(layout: Layout) -> Option<(Layout, usize)> {
+    let header = layout.align().max(size_of::<usize>());
+    let inner = Layout::from_size_align(
+        layout.size().checked_add(header)?,
+        layout.align().max(align_of::<usize>()),
+    )
+    .ok()?;
+    Some((inner, header))
+}
+
+unsafe impl<T: GlobalAlloc, const TAGS: usize> GlobalAlloc for ProfileAllocator<T, TAGS> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let Some((inner_layout, header)) = with_header(layout) else {
+            return core::ptr::null_mut();
+        };
+        let ptr = self.inner.alloc(inner_layout);
+        if ptr.is_null() {
+            return ptr;
+        }
+        let ptr = ptr.add(header);
+        let tag = self.tag();
+        (ptr.sub(size_of::<usize>()) as *mut usize).write(tag);
+        let cell = &self.cells[tag][size_class(layout.size())];
+        cell.live_count.fetch_add(1, Ordering::Relaxed);
+        cell.live_bytes.fetch_add(layout.size(), Ordering::Relaxed);
+        cell.allocations.fetch_add(1, Ordering::Relaxed);
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // Layout was valid when allocated, so this is too.
+        let (inner_layout, header) = with_header(layout).unwrap_unchecked();
+        let tag = *(ptr.sub(size_of::<usize>()) as *const usize);
+        let cell = &self.cells[tag][size_class(layout.size())];
+        cell.live_count.fetch_sub(1, Ordering::Relaxed);
+        cell.live_bytes.fetch_sub(layout.size(), Ordering::Relaxed);
+        self.inner.dealloc(ptr.sub(header), inner_layout);
+    }
+}
+
+impl<T: AllocatorStats, const TAGS: usize> AllocatorStats for ProfileAllocator<T, TAGS> {
+    fn stats(&self) -> Stats {
+        let cells = self.cells.iter().flatten();
+        Stats {
+            live_bytes: Some(
+                cells
+                    .clone()
+                    .map(|cell| cell.live_bytes.load(Ordering::Relaxed))
+                    .sum(),
+            ),
+            live_allocations: Some(
+                cells
+                    .map(|cell| cell.live_count.load(Ordering::Relaxed))
+                    .sum(),
+            ),
+            ..Stats::default()
+        }
+        .or(self.inner.stats())
+    }
+}
+
+/// The counts are always known.
+impl<T: AllocatorStats, const TAGS: usize> CountsAllocations for ProfileAllocator<T, TAGS> {}
+
+/// Aggregated allocations for one tag and size class, in a report written by [ProfileAllocator::write_report].
+///
+/// Counts are 32 bits, since this is intended for wasm32 heaps.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ProfileCell {
+    pub tag: u16,
+    /// See [size_class].
+    pub size_class: u8,
+    /// Number of live allocations.
+    pub live_count: u32,
+    /// Total size of the live allocations.
+    pub live_bytes: u32,
+    /// Number of allocations ever made, including freed ones.
+    pub allocations: u32,
+}
+
+impl ProfileCell {
+    /// Size of an encoded record, in bytes.
+    pub const SIZE: usize = 15;
+
+    /// Encodes as `tag` as a little endian `u16`, `size_class`, then `live_count`, `live_bytes` and `allocations` as little endian `u32`s.
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        bytes[0..2].copy_from_slice(&self.tag.to_le_bytes());
+        bytes[2] = self.size_class;
+        bytes[3..7].copy_from_slice(&self.live_count.to_le_bytes());
+        bytes[7..11].copy_from_slice(&self.live_bytes.to_le_bytes());
+        bytes[11..15].copy_from_slice(&self.allocations.to_le_bytes());
+        bytes
+    }
+
+    /// Decodes a record encoded by [ProfileCell::to_bytes].
+    pub fn from_bytes(bytes: &[u8; Self::SIZE]) -> Self {
+        let word =
+            |i: usize| u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+        ProfileCell {
+            tag: u16::from_le_bytes([bytes[0], bytes[1]]),
+            size_class: bytes[2],
+            live_count: word(3),
+            live_bytes: word(7),
+            allocations: word(11),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{size_class, ProfileAllocator, ProfileCell};
+    use alloc::{collections::BTreeMap, vec::Vec};
+    use core::alloc::{GlobalAlloc, Layout};
+    use lol_alloc::testing::conformance;
+    use lol_alloc::{AllocatorStats, HostAllocator};
+
+    #[test]
+    fn size_classes() {
+        let classes = [0, 1, 2, 3, 4, 5, 8, 9, 1024, 1025, usize::MAX].map(size_class);
+        assert_eq!(
+            classes,
+            [0, 0, 1, 2, 2, 3, 3, 4, 10, 11, usize::BITS as usize]
+        );
+    }
+
+    #[test]
+    fn conforms() {
+        conformance(&ProfileAllocator::<_, 1>::new(HostAllocator));
+    }
+
+    #[test]
+    fn cell_round_trip() {
+        let cell = ProfileCell {
+            tag: 0x1234,
+            size_class: 7,
+            live_count: 1,
+            live_bytes: 0xDEAD_BEEF,
+            allocations: u32::MAX,
+        };
+        assert_eq!(ProfileCell::from_bytes(&cell.to_bytes()), cell);
+    }
+
+    fn parse(report: &[u8]) -> Vec<ProfileCell> {
+        report
+            .chunks_exact(ProfileCell::SIZE)
+            .map(|chunk| ProfileCell::from_bytes(chunk.try_into().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn matches_shadow() {
+        let allocator = ProfileAllocator::<_, 3>::new(HostAllocator);
+        // (tag, size class) to (live count, live bytes, allocations).
+        let mut shadow: BTreeMap<(u16, u8), (u32, u32, u32)> = BTreeMap::new();
+        let mut live: Vec<(*mut u8, Layout, usize)> = vec![];
+        unsafe {
+            for i in 0..300 {
+                let tag = i % 7 % 3;
+                allocator.set_tag(tag);
+                let layout = Layout::from_size_align(i * 13 % 500, 1 << (i % 6)).unwrap();
+                let ptr = allocator.alloc(layout);
+                assert!(!ptr.is_null());
+                assert_eq!(ptr as usize % layout.align(), 0);
+                live.push((ptr, layout, tag));
+                let entry = shadow
+                    .entry((tag as u16, size_class(layout.size()) as u8))
+                    .or_default();
+                entry.0 += 1;
+                entry.1 += layout.size() as u32;
+                entry.2 += 1;
+                if i % 3 == 0 {
+                    // Freeing uses the tag the allocation was made with, not the current one.
+                    let (ptr, layout, tag) = live.swap_remove(i * 5 % live.len());
+                    allocator.dealloc(ptr, layout);
+                    let entry = shadow
+                        .get_mut(&(tag as u16, size_class(layout.size()) as u8))
+                        .unwrap();
+                    entry.0 -= 1;
+                    entry.1 -= layout.size() as u32;
+                }
+            }
+        }
+        let mut report = vec![0; 4];
+        let len = allocator.write_report(&mut report);
+        assert_eq!(len, shadow.len() * ProfileCell::SIZE);
+        report.resize(len, 0);
+        assert_eq!(allocator.write_report(&mut report), len);
+        let expected: Vec<ProfileCell> = shadow
+            .into_iter()
+            .map(
+                |((tag, size_class), (live_count, live_bytes, allocations))| ProfileCell {
+                    tag,
+                    size_class,
+                    live_count,
+                    live_bytes,
+                    allocations,
+                },
+            )
+            .collect();
+        assert_eq!(parse(&report), expected);
+        assert_eq!(allocator.stats().live_allocations, Some(live.len()));
+        for (ptr, layout, _) in live {
+            unsafe { allocator.dealloc(ptr, layout) };
+        }
+        assert_eq!(allocator.stats().live_bytes, Some(0));
+    }
+
+    #[test]
+    #[should_panic(expected = "tag 2 is not less than 2")]
+    fn tag_out_of_range() {
+        ProfileAllocator::<_, 2>::new(HostAllocator).set_tag(2);
+    }
+}
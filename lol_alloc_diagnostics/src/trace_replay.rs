@@ -0,0 +1,375 @@
+//! Replaying allocation traces as WebAssembly modules, for debugging allocators in a wasm context.
+
+use alloc::{string::String, vec::Vec};
+use core::fmt::Write;
+
+/// Kind of a [TraceEntry].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TraceOp {
+    Alloc,
+    Dealloc,
+}
+
+/// One recorded allocator operation.
+///
+/// For [TraceOp::Alloc], `ptr` is the pointer the allocation returned.
+/// For [TraceOp::Dealloc], `ptr` is the pointer freed, which must have been returned by an earlier [TraceOp::Alloc] in the same trace.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct TraceEntry {
+    pub op: TraceOp,
+    pub ptr: usize,
+    pub size: usize,
+    pub align: usize,
+}
+
+/// Generates a WebAssembly text format module which replays `trace`.
+///
+/// The module imports `env.malloc`, with parameters `(size, align)` returning the pointer,
+/// and `env.free`, with parameters `(ptr, size, align)`.
+/// Its exported `replay` function calls them in the same sequence as `trace`,
+/// freeing the pointers returned during the replay in place of the recorded ones.
+///
+/// # Panics
+///
+/// If `trace` contains a [TraceOp::Dealloc] of a pointer which is not live at that point in the trace.
+pub fn allocation_trace_to_wat(trace: &[TraceEntry]) -> String {
+    // Recorded pointer of each live allocation, and the local holding its replayed pointer.
+    let mut live: Vec<(usize, usize)> = Vec::new();
+    let mut locals = 0;
+    let mut body = String::new();
+    for entry in trace {
+        match entry.op {
+            TraceOp::Alloc => {
+                writeln!(
+                    body,
+                    "    (local.set $p{locals} (call $malloc (i32.const {}) (i32.const {})))",
+                    entry.size, entry.align
+                )
+                .unwrap();
+                live.push((entry.ptr, locals));
+                locals += 1;
+            }
+            TraceOp::Dealloc => {
+                let index = live
+                    .iter()
+                    .rposition(|&(ptr, _)| ptr == entry.ptr)
+                    .expect("Dealloc of pointer which is not live");
+                let (_, local) = live.swap_remove(index);
+                writeln!(
+                    body,
+                    "    (call $free (local.get $p{local}) (i32.const {}) (i32.const {}))",
+                    entry.size, entry.align
+                )
+                .unwrap();
+            }
+        }
+    }
+
+    let mut wat = String::from(
+        "(module\n  (import \"env\" \"malloc\" (func $malloc (param i32 i32) (result i32)))\n  (import \"env\" \"free\" (func $free (param i32 i32 i32)))\n  (func (export \"replay\")\n",
+    );
+    for local in 0..locals {
+        writeln!(wat, "    (local $p{local} i32)").unwrap();
+    }
+    wat.push_str(&body);
+    wat.push_str("  )\n)\n");
+    wat
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{allocation_trace_to_wat, TraceEntry, TraceOp};
+    use alloc::{format, string::String, vec, vec::Vec};
+
+    fn entry(op: TraceOp, ptr: usize, size: usize) -> TraceEntry {
+        TraceEntry {
+            op,
+            ptr,
+            size,
+            align: 8,
+        }
+    }
+
+    /// A parsed WebAssembly text format S-expression.
+    #[derive(Debug, PartialEq)]
+    enum Sexp {
+        Atom(String),
+        List(Vec<Sexp>),
+    }
+
+    impl Sexp {
+        fn atom(&self) -> Option<&str> {
+            match self {
+                Sexp::Atom(atom) => Some(atom),
+                Sexp::List(_) => None,
+            }
+        }
+
+        /// The list's keyword (its first item) and the rest of its items.
+        fn form(&self) -> Result<(&str, &[Sexp]), String> {
+            match self {
+                Sexp::List(items) => match items.split_first() {
+                    Some((keyword, rest)) => {
+                        Ok((keyword.atom().ok_or("list without keyword")?, rest))
+                    }
+                    None => Err("empty list".into()),
+                },
+                Sexp::Atom(atom) => Err(format!("expected a list, found {atom}")),
+            }
+        }
+    }
+
+    /// Parses `wat` as a single S-expression (with string atoms kept quoted).
+    fn parse(wat: &str) -> Result<Sexp, String> {
+        let mut stack: Vec<Vec<Sexp>> = vec![Vec::new()];
+        let mut chars = wat.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '(' => stack.push(Vec::new()),
+                ')' => {
+                    let list = stack
+                        .pop()
+                        .filter(|_| !stack.is_empty())
+                        .ok_or("unbalanced )")?;
+                    stack.last_mut().unwrap().push(Sexp::List(list));
+                }
+                c if c.is_whitespace() => {}
+                '"' => {
+                    let mut atom = String::from('"');
+                    loop {
+                        let c = chars.next().ok_or("unterminated string")?;
+                        atom.push(c);
+                        if c == '"' {
+                            break;
+                        }
+                    }
+                    stack.last_mut().unwrap().push(Sexp::Atom(atom));
+                }
+                c => {
+                    let mut atom = String::from(c);
+                    while let Some(&c) = chars
+                        .peek()
+                        .filter(|&&c| !"()\"".contains(c) && !c.is_whitespace())
+                    {
+                        atom.push(c);
+                        chars.next();
+                    }
+                    stack.last_mut().unwrap().push(Sexp::Atom(atom));
+                }
+            }
+        }
+        let mut top = stack
+            .pop()
+            .filter(|_| stack.is_empty())
+            .ok_or("unbalanced (")?;
+        match (top.pop(), top.is_empty()) {
+            (Some(module), true) => Ok(module),
+            _ => Err("expected exactly one top level expression".into()),
+        }
+    }
+
+    /// Checks `wat` is a well formed module of the form [allocation_trace_to_wat] generates, returning the number of calls to each imported function.
+    ///
+    /// This is a structural check of the subset of the text format used (not a full validator):
+    /// functions are imported with `i32` parameters and results, the `replay` function declares `i32` locals before its instructions,
+    /// every function and local referenced is declared (once), and every folded instruction has operands producing exactly the values it takes
+    /// (with the body leaving nothing on the stack).
+    fn validate(wat: &str) -> Result<Vec<(String, usize)>, String> {
+        let module = parse(wat)?;
+        let (keyword, fields) = module.form()?;
+        if keyword != "module" {
+            return Err(format!("expected module, found {keyword}"));
+        }
+        // Imported functions: name, parameter count, result count, and calls.
+        let mut funcs: Vec<(String, usize, usize, usize)> = Vec::new();
+        let mut replay = None;
+        for field in fields {
+            match field.form()? {
+                ("import", [module, name, func])
+                    if module.atom().is_some_and(|a| a.starts_with('"'))
+                        && name.atom().is_some_and(|a| a.starts_with('"')) =>
+                {
+                    let ("func", [id, signature @ ..]) = func.form()? else {
+                        return Err("import is not a function".into());
+                    };
+                    let id = id
+                        .atom()
+                        .filter(|id| id.starts_with('$'))
+                        .ok_or("import without id")?;
+                    let (mut params, mut results) = (0, 0);
+                    for part in signature {
+                        let (kind, types) = part.form()?;
+                        if types.iter().any(|t| t.atom() != Some("i32")) {
+                            return Err(format!("{id} has a type other than i32"));
+                        }
+                        match kind {
+                            "param" if results == 0 => params += types.len(),
+                            "result" => results += types.len(),
+                            _ => return Err(format!("unexpected {kind} in {id}'s signature")),
+                        }
+                    }
+                    if funcs.iter().any(|(name, ..)| name == id) || results > 1 {
+                        return Err(format!("invalid import {id}"));
+                    }
+                    funcs.push((id.into(), params, results, 0));
+                }
+                ("func", [export, body @ ..])
+                    if export.form()? == ("export", &[Sexp::Atom("\"replay\"".into())][..])
+                        && replay.is_none() =>
+                {
+                    replay = Some(body);
+                }
+                (keyword, _) => return Err(format!("unexpected module field {keyword}")),
+            }
+        }
+        let body = replay.ok_or("no replay function")?;
+
+        let mut locals: Vec<&str> = Vec::new();
+        let instructions = body
+            .iter()
+            .skip_while(|item| matches!(item.form(), Ok(("local", _))))
+            .collect::<Vec<_>>();
+        for local in &body[..body.len() - instructions.len()] {
+            let [id, ty] = local.form()?.1 else {
+                return Err("local must have an id and a type".into());
+            };
+            let id = id
+                .atom()
+                .filter(|id| id.starts_with('$') && !locals.contains(id))
+                .ok_or("invalid local id")?;
+            if ty.atom() != Some("i32") {
+                return Err(format!("local {id} is not i32"));
+            }
+            locals.push(id);
+        }
+
+        /// Number of values `instruction` leaves on the stack, checking its operands.
+        fn values(
+            instruction: &Sexp,
+            locals: &[&str],
+            funcs: &mut [(String, usize, usize, usize)],
+        ) -> Result<usize, String> {
+            let (keyword, operands) = instruction.form()?;
+            let local = |operand: &Sexp| {
+                operand
+                    .atom()
+                    .filter(|id| locals.contains(id))
+                    .map(|_| ())
+                    .ok_or(format!("undeclared local in {keyword}"))
+            };
+            match (keyword, operands) {
+                ("i32.const", [n]) => n
+                    .atom()
+                    .and_then(|n| n.parse::<u32>().ok())
+                    .map(|_| 1)
+                    .ok_or("invalid i32.const".into()),
+                ("local.get", [id]) => local(id).map(|_| 1),
+                ("local.set", [id, value]) => {
+                    local(id)?;
+                    match values(value, locals, funcs)? {
+                        1 => Ok(0),
+                        n => Err(format!("local.set of {n} values")),
+                    }
+                }
+                ("call", [id, args @ ..]) => {
+                    for arg in args {
+                        if values(arg, locals, funcs)? != 1 {
+                            return Err("call argument is not one value".into());
+                        }
+                    }
+                    let id = id.atom().ok_or("call without id")?;
+                    let func = funcs
+                        .iter_mut()
+                        .find(|(name, ..)| name == id)
+                        .ok_or(format!("undeclared function {id}"))?;
+                    if func.1 != args.len() {
+                        return Err(format!(
+                            "{id} takes {} arguments, not {}",
+                            func.1,
+                            args.len()
+                        ));
+                    }
+                    func.3 += 1;
+                    Ok(func.2)
+                }
+                _ => Err(format!("invalid instruction {keyword}")),
+            }
+        }
+        for instruction in instructions {
+            if values(instruction, &locals, &mut funcs)? != 0 {
+                return Err("instruction leaves a value on the stack".into());
+            }
+        }
+        Ok(funcs
+            .into_iter()
+            .map(|(name, _, _, calls)| (name, calls))
+            .collect())
+    }
+
+    /// Calls of `$malloc` and `$free` in a module which `validate` accepted.
+    fn calls(wat: &str) -> (usize, usize) {
+        let calls = validate(wat).unwrap_or_else(|error| panic!("{error}:\n{wat}"));
+        let count = |name: &str| calls.iter().find(|(n, _)| n == name).unwrap().1;
+        (count("$malloc"), count("$free"))
+    }
+
+    #[test]
+    fn replays_trace() {
+        let trace = [
+            entry(TraceOp::Alloc, 100, 16),
+            entry(TraceOp::Alloc, 200, 32),
+            entry(TraceOp::Dealloc, 100, 16),
+            // Reuses the freed address.
+            entry(TraceOp::Alloc, 100, 8),
+            entry(TraceOp::Dealloc, 200, 32),
+            entry(TraceOp::Dealloc, 100, 8),
+        ];
+        let wat = allocation_trace_to_wat(&trace);
+        assert_eq!(calls(&wat), (3, 3));
+        assert!(wat.contains("(call $free (local.get $p0) (i32.const 16) (i32.const 8))"));
+        assert!(wat.contains("(call $free (local.get $p1) (i32.const 32) (i32.const 8))"));
+        assert!(wat.contains("(call $free (local.get $p2) (i32.const 8) (i32.const 8))"));
+    }
+
+    #[test]
+    fn empty_trace() {
+        assert_eq!(calls(&allocation_trace_to_wat(&[])), (0, 0));
+    }
+
+    /// The check rejects malformed modules, not just unbalanced ones.
+    #[test]
+    fn validate_rejects_malformed() {
+        let wat = allocation_trace_to_wat(&[
+            entry(TraceOp::Alloc, 100, 16),
+            entry(TraceOp::Dealloc, 100, 16),
+        ]);
+        assert!(validate(&wat).is_ok());
+        for (from, to) in [
+            // Undeclared local.
+            ("(local.get $p0)", "(local.get $p1)"),
+            // Undeclared function.
+            ("(call $free", "(call $release"),
+            // Wrong number of arguments.
+            ("(i32.const 16) (i32.const 8)))", "(i32.const 16)))"),
+            // A value left on the stack.
+            (
+                "(local.set $p0 (call $malloc (i32.const 16) (i32.const 8)))",
+                "(call $malloc (i32.const 16) (i32.const 8))",
+            ),
+            // Unknown instruction.
+            ("local.set", "local.tee"),
+            // Local declared after instructions.
+            ("  )\n)", "    (local $p1 i32)\n  )\n)"),
+        ] {
+            assert!(wat.contains(from), "{from}");
+            let malformed = wat.replace(from, to);
+            assert!(validate(&malformed).is_err(), "{malformed}");
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Dealloc of pointer which is not live")]
+    fn unknown_dealloc() {
+        allocation_trace_to_wat(&[entry(TraceOp::Dealloc, 100, 16)]);
+    }
+}
@@ -0,0 +1,339 @@
+use crate::{
+    atomic::{AtomicUsize, Ordering},
+    AllocHooks, Hooked,
+};
+use core::alloc::Layout;
+use lol_alloc::CountsAllocations;
+
+/// Wraps an allocator, calling a function whenever an operation touches a watched address range.
+///
+/// For finding who allocates or frees memory which gets corrupted: watch its address with [WatchHooks::watch]
+/// (or from the host or a debugger, through [export_watch!](crate::export_watch)),
+/// rerun the workload, and every `alloc`, `realloc` or `dealloc` of a block overlapping a watched range calls the callback,
+/// which by default stops the program (see [WatchAllocator::new]).
+///
+/// Up to `N` ranges can be watched at once.
+/// With none watched, each operation only costs an atomic load.
+pub type WatchAllocator<T, const N: usize = 4> = Hooked<T, WatchHooks<N>>;
+
+/// An operation which touched a watched range, passed to the callback of a [WatchAllocator].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum WatchOp {
+    /// Allocated `layout` at `ptr`.
+    Alloc { ptr: *mut u8, layout: Layout },
+    /// Freed `ptr`, which was allocated with `layout`.
+    Dealloc { ptr: *mut u8, layout: Layout },
+    /// Reallocated `ptr` (allocated with `layout`) to `new_size` bytes at `new_ptr`.
+    /// Either block may be the one overlapping the watched range. `new_ptr` is null if it failed.
+    Realloc {
+        ptr: *mut u8,
+        layout: Layout,
+        new_ptr: *mut u8,
+        new_size: usize,
+    },
+}
+
+/// The [AllocHooks] of a [WatchAllocator].
+pub struct WatchHooks<const N: usize> {
+    /// Start address of each watched range.
+    starts: [AtomicUsize; N],
+    /// Length of each watched range: 0 if the slot is unused, and [CLAIMED] while it is being set.
+    lens: [AtomicUsize; N],
+    /// Number of ranges watched, so operations can skip checking when there are none.
+    watching: AtomicUsize,
+    on_watched: fn(WatchOp),
+}
+
+/// Length of a range slot which [WatchHooks::watch] is setting.
+const CLAIMED: usize = usize::MAX;
+
+impl<T, const N: usize> WatchAllocator<T, N> {
+    /// Wraps `inner`, trapping when a watched range is touched.
+    ///
+    /// On wasm32 this is the `unreachable` instruction, so a debugger stops there with the operation in the wrapper's locals.
+    /// Other targets panic with the operation.
+    pub const fn new(inner: T) -> Self {
+        Self::with_callback(inner, watched_trap)
+    }
+
+    /// Wraps `inner`, calling `on_watched` with each operation which touches a watched range.
+    ///
+    /// `on_watched` runs inside the allocator, so must not allocate from it.
+    pub const fn with_callback(inner: T, on_watched: fn(WatchOp)) -> Self {
+        Hooked::with_hooks(
+            inner,
+            WatchHooks {
+                starts: [const { AtomicUsize::new(0) }; N],
+                lens: [const { AtomicUsize::new(0) }; N],
+                watching: AtomicUsize::new(0),
+                on_watched,
+            },
+        )
+    }
+}
+
+impl<const N: usize> WatchHooks<N> {
+    /// Watches the `len` bytes starting at `address`.
+    ///
+    /// Returns false (watching nothing new) if `N` ranges are already watched, or `len` is 0.
+    pub fn watch(&self, address: usize, len: usize) -> bool {
+        if len == 0 || len == CLAIMED {
+            return false;
+        }
+        for (start, slot_len) in self.starts.iter().zip(&self.lens) {
+            if slot_len
+                .compare_exchange(0, CLAIMED, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+            {
+                start.store(address, Ordering::Relaxed);
+                slot_len.store(len, Ordering::Release);
+                self.watching.fetch_add(1, Ordering::Release);
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Stops watching all ranges.
+    pub fn clear(&self) {
+        for slot_len in &self.lens {
+            if slot_len.swap(0, Ordering::Relaxed) != 0 {
+                self.watching.fetch_sub(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// If the `size` bytes at `ptr` overlap a watched range.
+    fn is_watched(&self, ptr: *mut u8, size: usize) -> bool {
+        if ptr.is_null() || self.watching.load(Ordering::Acquire) == 0 {
+            return false;
+        }
+        let address = ptr as usize;
+        // Zero sized blocks still report touching the address they are at.
+        let end = address.saturating_add(size.max(1));
+        self.starts.iter().zip(&self.lens).any(|(start, len)| {
+            let len = len.load(Ordering::Acquire);
+            if len == 0 || len == CLAIMED {
+                return false;
+            }
+            let start = start.load(Ordering::Relaxed);
+            address < start.saturating_add(len) && start < end
+        })
+    }
+}
+
+impl<const N: usize> AllocHooks for WatchHooks<N> {
+    fn on_alloc(&self, layout: Layout, result: *mut u8) {
+        if self.is_watched(result, layout.size()) {
+            (self.on_watched)(WatchOp::Alloc {
+                ptr: result,
+                layout,
+            });
+        }
+    }
+
+    fn on_dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if self.is_watched(ptr, layout.size()) {
+            (self.on_watched)(WatchOp::Dealloc { ptr, layout });
+        }
+    }
+
+    fn on_realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize, result: *mut u8) {
+        if self.is_watched(ptr, layout.size()) || self.is_watched(result, new_size) {
+            (self.on_watched)(WatchOp::Realloc {
+                ptr,
+                layout,
+                new_ptr: result,
+                new_size,
+            });
+        }
+    }
+}
+
+/// The default callback of a [WatchAllocator].
+#[cold]
+fn watched_trap(op: WatchOp) {
+    #[cfg(target_arch = "wasm32")]
+    {
+        let _ = op;
+        core::arch::wasm32::unreachable();
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    panic!("watched memory touched: {op:?}");
+}
+
+/// Counts when what it wraps does.
+impl<T: CountsAllocations, const N: usize> CountsAllocations for WatchAllocator<T, N> {}
+
+/// Exports a `lol_alloc_watch(address: usize, len: usize) -> u32` function,
+/// which the host (or a debugger) can call to watch a range of addresses in a [WatchAllocator],
+/// returning 1 if it is now watched and 0 if too many ranges already are.
+///
+/// Takes an expression evaluating to the `WatchAllocator`, for example:
+///
+/// ```ignore
+/// #[global_allocator]
+/// static ALLOCATOR: WatchAllocator<LockedAllocator<FreeListAllocator>> =
+///     WatchAllocator::new(LockedAllocator::new(FreeListAllocator::new()));
+///
+/// lol_alloc_diagnostics::export_watch!(ALLOCATOR);
+/// ```
+#[macro_export]
+macro_rules! export_watch {
+    ($allocator:expr) => {
+        /// Watches the `len` bytes at `address`, stopping whenever an allocation or free touches them.
+        /// Returns 1 if they are now watched.
+        #[unsafe(no_mangle)]
+        pub extern "C" fn lol_alloc_watch(address: usize, len: usize) -> u32 {
+            $allocator.hooks().watch(address, len) as u32
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{WatchAllocator, WatchOp};
+    use alloc::vec::Vec;
+    use core::{
+        alloc::{GlobalAlloc, Layout},
+        cell::RefCell,
+    };
+    use lol_alloc::{
+        testing::{conformance, MockGrower},
+        FreeListAllocator, HostAllocator,
+    };
+
+    std::thread_local! {
+        /// Operations passed to [record].
+        static WATCHED: RefCell<Vec<WatchOp>> = const { RefCell::new(Vec::new()) };
+    }
+
+    fn record(op: WatchOp) {
+        WATCHED.with_borrow_mut(|watched| watched.push(op));
+    }
+
+    fn take_watched() -> Vec<WatchOp> {
+        WATCHED.take()
+    }
+
+    /// Allocates 8 blocks, frees the fourth, reallocates the sixth, allocates another block, then frees everything.
+    /// Returns the 8 original pointers.
+    unsafe fn workload(allocator: &impl GlobalAlloc) -> Vec<*mut u8> {
+        let layout = Layout::from_size_align(32, 8).unwrap();
+        let ptrs: Vec<_> = (0..8).map(|_| allocator.alloc(layout)).collect();
+        let mut live = ptrs.clone();
+        allocator.dealloc(live[3], layout);
+        live[5] = allocator.realloc(live[5], layout, 64);
+        live[3] = allocator.alloc(layout);
+        for (i, &ptr) in live.iter().enumerate() {
+            let size = if i == 5 { 64 } else { 32 };
+            allocator.dealloc(ptr, Layout::from_size_align(size, 8).unwrap());
+        }
+        ptrs
+    }
+
+    #[test]
+    fn watch_conformance() {
+        let allocator = WatchAllocator::<_>::with_callback(HostAllocator, record);
+        conformance(&allocator);
+        // Nothing is watched.
+        assert!(take_watched().is_empty());
+    }
+
+    #[test]
+    fn watch_rerun_workload() {
+        // Find the addresses, as offsets from the start of the heap.
+        let grower = MockGrower::new();
+        let first_run = unsafe { workload(&FreeListAllocator::new_in(&grower)) };
+        let offsets: Vec<_> = first_run
+            .iter()
+            .map(|&ptr| ptr as usize - grower.base())
+            .collect();
+
+        // Rerun with a watch in the middle of the fourth block, which is freed then reused.
+        let grower = MockGrower::new();
+        let allocator =
+            WatchAllocator::<_>::with_callback(FreeListAllocator::new_in(&grower), record);
+        assert!(allocator.hooks().watch(grower.base() + offsets[3] + 8, 4));
+        let ptrs = unsafe { workload(&allocator) };
+        let layout = Layout::from_size_align(32, 8).unwrap();
+        let fourth = ptrs[3];
+        assert_eq!(fourth as usize - grower.base(), offsets[3]);
+        assert_eq!(
+            take_watched(),
+            [
+                WatchOp::Alloc {
+                    ptr: fourth,
+                    layout
+                },
+                WatchOp::Dealloc {
+                    ptr: fourth,
+                    layout
+                },
+                // The next allocation of the same size reuses its memory.
+                WatchOp::Alloc {
+                    ptr: fourth,
+                    layout
+                },
+                WatchOp::Dealloc {
+                    ptr: fourth,
+                    layout
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn watch_realloc() {
+        let allocator = WatchAllocator::<_, 1>::with_callback(HostAllocator, record);
+        let layout = Layout::from_size_align(32, 8).unwrap();
+        unsafe {
+            let ptr = allocator.alloc(layout);
+            assert!(allocator.hooks().watch(ptr as usize + 31, 1));
+            // Only one range fits.
+            assert!(!allocator.hooks().watch(ptr as usize, 1));
+            let new_ptr = allocator.realloc(ptr, layout, 4096);
+            assert_eq!(
+                take_watched(),
+                [WatchOp::Realloc {
+                    ptr,
+                    layout,
+                    new_ptr,
+                    new_size: 4096
+                }]
+            );
+            allocator.hooks().clear();
+            allocator.dealloc(new_ptr, Layout::from_size_align(4096, 8).unwrap());
+            assert!(take_watched().is_empty());
+            assert!(allocator.hooks().watch(ptr as usize, 1));
+        }
+    }
+
+    static EXPORTED: WatchAllocator<HostAllocator, 1> =
+        WatchAllocator::with_callback(HostAllocator, record);
+
+    crate::export_watch!(EXPORTED);
+
+    #[test]
+    fn watch_exported() {
+        let layout = Layout::new::<u64>();
+        let ptr = unsafe { EXPORTED.alloc(layout) };
+        assert_eq!(lol_alloc_watch(ptr as usize, 8), 1);
+        // Only one range fits.
+        assert_eq!(lol_alloc_watch(ptr as usize, 8), 0);
+        unsafe { EXPORTED.dealloc(ptr, layout) };
+        assert_eq!(take_watched(), [WatchOp::Dealloc { ptr, layout }]);
+    }
+
+    #[test]
+    #[should_panic(expected = "watched memory touched: Dealloc")]
+    fn watch_traps() {
+        let allocator = WatchAllocator::<_>::new(HostAllocator);
+        let layout = Layout::new::<u64>();
+        unsafe {
+            let ptr = allocator.alloc(layout);
+            allocator.hooks().watch(ptr as usize, 8);
+            allocator.dealloc(ptr, layout);
+        }
+    }
+}
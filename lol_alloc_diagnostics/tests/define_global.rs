@@ -0,0 +1,10 @@
+//! Tests for `lol_alloc::define_global!`.
+//!
+//! These are here rather than in `lol_alloc` since exporting stats needs a counting wrapper from this crate.
+
+#[test]
+fn define_global() {
+    let cases = trybuild::TestCases::new();
+    cases.pass("tests/define_global/pass_*.rs");
+    cases.compile_fail("tests/define_global/fail_*.rs");
+}
@@ -0,0 +1,11 @@
+use lol_alloc::{FreeListAllocator, GlobalArrayGrower, LockedAllocator};
+
+static GROWER: GlobalArrayGrower<16> = GlobalArrayGrower::new();
+
+lol_alloc::define_global! {
+    static ALLOCATOR: LockedAllocator<FreeListAllocator<&'static GlobalArrayGrower<16>>> =
+        LockedAllocator::new(FreeListAllocator::new_in(&GROWER));
+    export set_limit(GROWER);
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use lol_alloc::{FreeListAllocator, GlobalArrayGrower, LockedAllocator};
+
+lol_alloc::define_global! {
+    static ALLOCATOR: LockedAllocator<FreeListAllocator<GlobalArrayGrower<16>>> =
+        LockedAllocator::new(FreeListAllocator::new_in(GlobalArrayGrower::new()));
+    export stats;
+}
+
+fn main() {}
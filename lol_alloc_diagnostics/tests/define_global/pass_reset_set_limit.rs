@@ -0,0 +1,18 @@
+use lol_alloc::{FreeListAllocator, GlobalArrayGrower, LockedAllocator, MaxPagesGrower};
+
+static GROWER: MaxPagesGrower<GlobalArrayGrower<16>> =
+    MaxPagesGrower::new(GlobalArrayGrower::new(), 16);
+
+lol_alloc::define_global! {
+    static ALLOCATOR: LockedAllocator<FreeListAllocator<&'static MaxPagesGrower<GlobalArrayGrower<16>>>> =
+        LockedAllocator::new(FreeListAllocator::new_in(&GROWER));
+    export reset(ALLOCATOR.lock());
+    export set_limit(GROWER);
+}
+
+fn main() {
+    assert_eq!(lol_alloc_set_limit_pages(12), 16);
+    assert_eq!(lol_alloc_set_limit_pages(16), 12);
+    // The standard library has live allocations, so the heap can't actually be reset.
+    let _reset: unsafe extern "C" fn() = lol_alloc_reset_heap;
+}
@@ -0,0 +1,25 @@
+use lol_alloc::{FreeListAllocator, GlobalArrayGrower, LockedAllocator};
+use lol_alloc_diagnostics::CountingAllocator;
+
+lol_alloc::define_global! {
+    /// Attributes apply to the static.
+    static ALLOCATOR: LockedAllocator<CountingAllocator<FreeListAllocator<GlobalArrayGrower<16>>>> =
+        LockedAllocator::new(CountingAllocator::new(FreeListAllocator::new_in(GlobalArrayGrower::new())));
+    export stats;
+}
+
+fn main() {
+    let live_bytes = lol_alloc_live_bytes();
+    let live_allocations = lol_alloc_live_allocations();
+    let v: Vec<u8> = Vec::with_capacity(100);
+    assert_eq!(lol_alloc_live_bytes(), live_bytes + 100);
+    assert_eq!(lol_alloc_live_allocations(), live_allocations + 1);
+    assert!(lol_alloc_peak_bytes() >= live_bytes + 100);
+    assert_eq!(lol_alloc_failed_allocations(), 0);
+    assert_ne!(lol_alloc_free_bytes(), usize::MAX);
+    assert_ne!(lol_alloc_free_blocks(), usize::MAX);
+    // FreeListAllocator does not know how much memory it has grown.
+    assert_eq!(lol_alloc_heap_bytes(), usize::MAX);
+    drop(v);
+    assert_eq!(lol_alloc_live_bytes(), live_bytes);
+}
@@ -0,0 +1,149 @@
+//! Logs through a DefmtAllocator into a test global logger, which checks nothing allocates while an event is being written.
+#![cfg(feature = "defmt")]
+
+use core::cell::Cell;
+use lol_alloc::{HostAllocator, LiveInfo, Stats};
+use lol_alloc_diagnostics::{AllocHooks, DefmtAllocator, DefmtOp, Hooked};
+use std::{
+    alloc::{GlobalAlloc, Layout},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+std::thread_local! {
+    /// Set while this thread holds the logger.
+    static LOGGING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Number of allocations made while logging.
+static ALLOCATIONS_WHILE_LOGGING: AtomicUsize = AtomicUsize::new(0);
+
+/// Counts allocations made while logging.
+struct WhileLogging;
+
+impl AllocHooks for WhileLogging {
+    fn on_alloc(&self, _layout: Layout, _result: *mut u8) {
+        if LOGGING.with(Cell::get) {
+            ALLOCATIONS_WHILE_LOGGING.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Hooked<HostAllocator, WhileLogging> =
+    Hooked::with_hooks(HostAllocator, WhileLogging);
+
+/// Bytes of the events logged, without the interned format string index each starts with.
+static EVENTS: Mutex<[u8; 256]> = Mutex::new([0; 256]);
+static EVENTS_LEN: AtomicUsize = AtomicUsize::new(0);
+/// The index of the next byte of the current event, so the format string index (its first 2 bytes) can be skipped.
+static EVENT_BYTE: AtomicUsize = AtomicUsize::new(0);
+/// Serializes the tests, since they share the logged events.
+static LOGGER_TEST: Mutex<()> = Mutex::new(());
+
+#[defmt::global_logger]
+struct Logger;
+
+unsafe impl defmt::Logger for Logger {
+    fn acquire() {
+        LOGGING.with(|logging| logging.set(true));
+        EVENT_BYTE.store(0, Ordering::Relaxed);
+    }
+
+    unsafe fn flush() {}
+
+    unsafe fn release() {
+        LOGGING.with(|logging| logging.set(false));
+    }
+
+    unsafe fn write(bytes: &[u8]) {
+        let mut events = EVENTS.lock().unwrap();
+        for &byte in bytes {
+            if EVENT_BYTE.fetch_add(1, Ordering::Relaxed) >= 2 {
+                let len = EVENTS_LEN.fetch_add(1, Ordering::Relaxed);
+                events[len] = byte;
+            }
+        }
+    }
+}
+
+// Events carry no timestamp.
+defmt::timestamp!("");
+
+/// Takes the bytes logged so far.
+fn take_events() -> Vec<u8> {
+    let events = EVENTS.lock().unwrap();
+    events[..EVENTS_LEN.swap(0, Ordering::Relaxed)].to_vec()
+}
+
+/// Decodes events of [DefmtAllocator]: an op code byte, then the pointer, size and alignment as little endian `u32`s.
+fn decode(bytes: &[u8]) -> Vec<(u8, u32, u32, u32)> {
+    let word = |bytes: &[u8]| u32::from_le_bytes(bytes.try_into().unwrap());
+    bytes
+        .chunks(13)
+        .map(|event| {
+            (
+                event[0],
+                word(&event[1..5]),
+                word(&event[5..9]),
+                word(&event[9..13]),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn logs_operations() {
+    let _serial = LOGGER_TEST.lock().unwrap();
+    take_events();
+    let allocator = DefmtAllocator::new(HostAllocator);
+    let layout = Layout::from_size_align(300, 8).unwrap();
+    let ptr = unsafe { allocator.alloc(layout) };
+    let grown = unsafe { allocator.realloc(ptr, layout, 1000) };
+    unsafe { allocator.dealloc(grown, Layout::from_size_align(1000, 8).unwrap()) };
+    let events = decode(&take_events());
+    assert_eq!(
+        events,
+        [
+            (DefmtOp::Alloc as u8, ptr as u32, 300, 8),
+            (DefmtOp::Dealloc as u8, ptr as u32, 300, 8),
+            (DefmtOp::Alloc as u8, grown as u32, 1000, 8),
+            (DefmtOp::Dealloc as u8, grown as u32, 1000, 8),
+        ]
+    );
+    assert_eq!(ALLOCATIONS_WHILE_LOGGING.load(Ordering::Relaxed), 0);
+}
+
+/// Failed allocations are logged with a null pointer.
+#[test]
+fn logs_failures() {
+    let _serial = LOGGER_TEST.lock().unwrap();
+    take_events();
+    let allocator = DefmtAllocator::new(lol_alloc::FailAllocator);
+    let layout = Layout::new::<u64>();
+    assert!(unsafe { allocator.alloc(layout) }.is_null());
+    assert_eq!(decode(&take_events()), [(DefmtOp::Alloc as u8, 0, 8, 8)]);
+}
+
+/// The lol_alloc types logged alongside allocator events implement `defmt::Format`.
+#[test]
+fn formats_stats_and_live_info() {
+    let _serial = LOGGER_TEST.lock().unwrap();
+    take_events();
+    let stats = Stats {
+        live_allocations: Some(3),
+        ..Stats::default()
+    };
+    defmt::println!("{}", stats);
+    assert!(!take_events().is_empty());
+    let info = LiveInfo {
+        ptr: core::ptr::null(),
+        usable_size: Some(64),
+        is_in_free_list: false,
+    };
+    defmt::println!("{}", info);
+    assert!(!take_events().is_empty());
+    assert_eq!(ALLOCATIONS_WHILE_LOGGING.load(Ordering::Relaxed), 0);
+}
@@ -0,0 +1,31 @@
+//! Uses a LeakTracker as the global allocator, so the side table's own allocations go through it too.
+#![cfg(feature = "leak-report")]
+
+use lol_alloc::HostAllocator;
+use lol_alloc_diagnostics::LeakTracker;
+use std::alloc::Layout;
+
+#[global_allocator]
+static ALLOCATOR: LeakTracker<HostAllocator> = LeakTracker::new(HostAllocator);
+
+#[test]
+fn global_allocator() {
+    let leaked = Box::leak(Box::new([0u8; 1000]));
+    let tracked = ALLOCATOR.try_alloc(Layout::new::<u64>()).unwrap();
+    drop(vec![1, 2, 3]);
+    let leaks = ALLOCATOR.leaks();
+    let site = leaks
+        .iter()
+        .find(|site| {
+            site.location
+                .is_some_and(|location| location.file() == file!())
+        })
+        .unwrap();
+    assert_eq!((site.allocations, site.bytes), (1, 8));
+    // Allocations through GlobalAlloc don't know their location.
+    assert!(leaks
+        .iter()
+        .any(|site| site.location.is_none() && site.bytes >= leaked.len()));
+    assert!(ALLOCATOR.report_leaks() >= 2);
+    unsafe { std::alloc::dealloc(tracked.as_ptr(), Layout::new::<u64>()) };
+}
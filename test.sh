@@ -2,7 +2,76 @@
 set -eux -o pipefail
 
 cargo test
+cargo test --features strict-checks -p lol_alloc
+cargo test --features peak-free-list-len -p lol_alloc
+cargo test --features tiny-asserts -p lol_alloc
+cargo test --features trace -p lol_alloc_diagnostics
+cargo test --features defmt -p lol_alloc_diagnostics
+cargo test --features leak-report -p lol_alloc_diagnostics
+cargo test --features allocation-ids -p lol_alloc_diagnostics
+cargo test --features reset-heap -p lol_alloc
+cargo test --features jitter -p lol_alloc
+cargo test --features large-objects -p lol_alloc
+cargo test --features std -p lol_alloc
+cargo test --features live-blocks -p lol_alloc
+cargo test --features pointer-registry -p lol_alloc
+cargo test --features node-checksums -p lol_alloc
+cargo test --features dealloc-size-check -p lol_alloc
+cargo test --features fallible -p lol_alloc
+cargo test --features raw-buffer -p lol_alloc
+cargo test --features buffer-exports -p lol_alloc
+cargo test --features testing -p lol_alloc
+cargo test --features unchecked-lock -p lol_alloc
+cargo test --features randomized -p lol_alloc
+# The integration tests would need the ASAN runtime, so only the unit tests (which provide test versions of its functions) run with asan.
+cargo test --features asan -p lol_alloc --lib
+# The embedded benchmark is its own workspace, building for thumbv7m-none-eabi: check it builds, and run it if QEMU is installed.
+# It also has vec_sum, which checks lol_alloc works as the global allocator of a bare-metal program (failing QEMU's exit status if not).
+(cd bench-embedded && cargo build --release --bins)
+if command -v qemu-system-arm > /dev/null; then
+    (cd bench-embedded && cargo run --release)
+    (cd bench-embedded && cargo run --release --bin vec_sum)
+fi
 wasm-pack test --node lol_alloc
+wasm-pack test --node lol_alloc --features strict-checks
+wasm-pack test --node lol_alloc --features unchecked-lock
+wasm-pack test --node lol_alloc --features testing
+RUSTFLAGS="-C target-feature=+simd128" wasm-pack test --node lol_alloc
+wasm-pack test --node example --features wasm-bindgen -- --example raf_sampler
+wasm-pack test --node examples/wasm-bindgen-ts
+wasm-pack test --node examples/workloads
+wasm-pack test --node examples/lru-cache
+# All workspace members built together for wasm32, so a member's features (unified into the others) can't conflict with them.
+cargo build --target wasm32-unknown-unknown --workspace
+# The demos are tested and measured separately, including single-threaded-free-list, which is its own workspace since it enables auto-global.
+for demo in examples/demos/*/; do
+    if [ "$demo" != examples/demos/support/ ]; then
+        wasm-pack test --node "$demo"
+    fi
+done
+./examples/wasm-bindgen-ts/build.sh
 wasm-pack build --release example
 
-wc -c example/pkg/lol_alloc_example_bg.wasm
\ No newline at end of file
+wc -c example/pkg/lol_alloc_example_bg.wasm
+
+# Size cost of the strict-checks feature.
+wasm-pack build --release --out-dir pkg-strict-checks example -- --features strict-checks
+wc -c example/pkg-strict-checks/lol_alloc_example_bg.wasm
+
+# Size cost of checking for recursive locking.
+wasm-pack build --release --out-dir pkg-unchecked-lock example -- --features unchecked-lock
+wc -c example/pkg-unchecked-lock/lol_alloc_example_bg.wasm
+
+# Size of debug assertion enabled builds, with and without tiny-asserts.
+RUSTFLAGS="-C debug-assertions" wasm-pack build --release --out-dir pkg-debug-assertions example
+wc -c example/pkg-debug-assertions/lol_alloc_example_bg.wasm
+RUSTFLAGS="-C debug-assertions" wasm-pack build --release --out-dir pkg-tiny-asserts example -- --features tiny-asserts
+wc -c example/pkg-tiny-asserts/lol_alloc_example_bg.wasm
+
+# Size of each allocator used with wasm-bindgen, from the demos.
+for demo in examples/demos/*/; do
+    if [ "$demo" != examples/demos/support/ ]; then
+        wasm-pack build --release "$demo"
+        wc -c "$demo"pkg/*_bg.wasm
+    fi
+done